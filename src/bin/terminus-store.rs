@@ -0,0 +1,751 @@
+//! Command-line administration tool for terminus-store stores.
+//!
+//! Run `terminus-store --help` for the list of commands.
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::UNIX_EPOCH;
+
+use rug::Integer;
+use tdb_succinct::{
+    Base64Binary, DateTimeStamp, DayTimeDuration, Decimal, Duration, GDay, GMonth, GMonthDay,
+    GYear, GYearMonth, HexBinary, NegativeInteger, NonNegativeInteger, NonPositiveInteger,
+    PositiveInteger, TypedDictEntry, YearMonthDuration,
+};
+use tdb_succinct::{Datatype, Date};
+use terminus_store::layer::{Layer, ObjectType, ValueTriple};
+use terminus_store::storage::{name_to_string, pack_layer_parents, string_to_name};
+use terminus_store::store::{
+    self, open_archive_store_checked, open_directory_store_checked, open_raw_archive_store_checked,
+    read_store_version, upgrade_store, Store, StoreLayer, StoreVersion,
+};
+
+const USAGE: &str = "usage: terminus-store [--kind directory|archive|raw-archive] <store> <command>
+
+commands:
+  labels                              list labels with their version and head layer
+  layer info <layer>                  show parent, rollup, counts and section sizes of a layer
+  stack <label>                       list the layers of the stack a label points at
+  dump <label>                        write the triples of a label as N-Triples
+  squash <label> [--temp-dir <dir>]   squash the head of a label into a single base layer
+  rollup <label> [--upto <layer>]     roll up the head of a label
+  export <label> <pack>               write all layers of a label to a pack file
+  import <pack> [<label>]             import all layers of a pack, optionally pointing a label at its top layer
+  check [<label>...]                  verify that all layers reachable from the given labels load and resolve
+  upgrade                             convert the store to the format of the given store kind
+
+The store kind defaults to archive.";
+
+const ARCHIVE_CACHE_SIZE: usize = 512;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum StoreKind {
+    Directory,
+    Archive,
+    RawArchive,
+}
+
+impl StoreKind {
+    fn parse(kind: &str) -> io::Result<Self> {
+        match kind {
+            "directory" => Ok(Self::Directory),
+            "archive" => Ok(Self::Archive),
+            "raw-archive" => Ok(Self::RawArchive),
+            _ => Err(usage_error(format!("unknown store kind {}", kind))),
+        }
+    }
+
+    async fn open(self, path: &Path) -> io::Result<Store> {
+        match self {
+            Self::Directory => open_directory_store_checked(path).await,
+            Self::Archive => open_archive_store_checked(path, ARCHIVE_CACHE_SIZE).await,
+            Self::RawArchive => open_raw_archive_store_checked(path).await,
+        }
+    }
+
+    fn version(self) -> StoreVersion {
+        match self {
+            Self::Directory => StoreVersion::current(store::StoreKind::Directory),
+            Self::Archive | Self::RawArchive => StoreVersion::current(store::StoreKind::Archive),
+        }
+    }
+}
+
+struct Cli {
+    store: Store,
+}
+
+fn usage_error<S: Into<String>>(msg: S) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg.into())
+}
+
+fn parse_layer_name(name: &str) -> io::Result<[u32; 5]> {
+    string_to_name(name).map_err(|_| usage_error(format!("invalid layer id {}", name)))
+}
+
+/// Split `--option value` pairs from the positional arguments.
+fn parse_options<'a>(
+    args: &'a [String],
+    options: &[&str],
+) -> io::Result<(Vec<&'a str>, HashMap<String, &'a str>)> {
+    let mut positional = Vec::new();
+    let mut found = HashMap::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if let Some(option) = arg.strip_prefix("--") {
+            if !options.contains(&option) {
+                return Err(usage_error(format!("unknown option {}", arg)));
+            }
+            let value = iter
+                .next()
+                .ok_or_else(|| usage_error(format!("option {} requires a value", arg)))?;
+            found.insert(option.to_string(), value.as_str());
+        } else {
+            positional.push(arg.as_str());
+        }
+    }
+
+    Ok((positional, found))
+}
+
+/// Like `println!`, but returns write errors such as a closed pipe instead of panicking.
+macro_rules! out {
+    ($($arg:tt)*) => {
+        writeln!(io::stdout(), $($arg)*)?
+    };
+}
+
+impl Cli {
+    async fn head(&self, label: &str) -> io::Result<(StoreLayer, u64)> {
+        let graph = self
+            .store
+            .open(label)
+            .await?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "label not found"))?;
+        match graph.head_version().await? {
+            (Some(layer), version) => Ok((layer, version)),
+            (None, _) => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "label does not point at a layer",
+            )),
+        }
+    }
+
+    async fn labels(&self) -> io::Result<()> {
+        let mut labels = self.store.labels().await?;
+        labels.sort();
+        for label in labels {
+            if let Some(graph) = self.store.open(&label).await? {
+                let (head, version) = graph.head_version().await?;
+                let head = head
+                    .map(|layer| name_to_string(layer.name()))
+                    .unwrap_or_else(|| "-".to_string());
+                out!("{}\t{}\t{}", label, version, head);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn layer_info(&self, name: &str) -> io::Result<()> {
+        let name = parse_layer_name(name)?;
+        let layer = self
+            .store
+            .get_layer_from_id(name)
+            .await?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "layer not found"))?;
+        let info = self
+            .store
+            .layer_info(name)
+            .await?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "layer not found"))?;
+
+        out!("layer: {}", name_to_string(name));
+        out!("kind: {:?}", info.kind);
+        match info.parent {
+            Some(parent) => out!("parent: {}", name_to_string(parent)),
+            None => out!("parent: -"),
+        }
+        out!("depth: {}", info.depth);
+        match self.store.layer_rollup_info(name).await? {
+            Some(rollup) => out!(
+                "rollup: {} (upto {})",
+                name_to_string(rollup.rollup),
+                rollup
+                    .upto
+                    .map(name_to_string)
+                    .unwrap_or_else(|| "base".to_string())
+            ),
+            None => out!("rollup: -"),
+        }
+        match info.created.and_then(|c| c.duration_since(UNIX_EPOCH).ok()) {
+            Some(created) => out!("created: {} seconds since the epoch", created.as_secs()),
+            None => out!("created: -"),
+        }
+        out!("nodes and values: {}", layer.node_and_value_count());
+        out!("predicates: {}", layer.predicate_count());
+        out!(
+            "triple additions: {}",
+            layer.triple_layer_addition_count().await?
+        );
+        out!(
+            "triple removals: {}",
+            layer.triple_layer_removal_count().await?
+        );
+        out!("triples: {}", layer.triple_count());
+        out!("size: {}", info.size);
+        out!("sections:");
+        for (section, size) in info.sections {
+            out!("  {:?}\t{}", section, size);
+        }
+
+        Ok(())
+    }
+
+    async fn stack(&self, label: &str) -> io::Result<()> {
+        let (head, _) = self.head(label).await?;
+        let mut current = Some(head);
+        while let Some(layer) = current {
+            let rollup = match self.store.layer_rollup_info(layer.name()).await? {
+                Some(rollup) => format!("\trollup {}", name_to_string(rollup.rollup)),
+                None => String::new(),
+            };
+            out!(
+                "{}\t{}\t+{}\t-{}{}",
+                name_to_string(layer.name()),
+                if layer.parent_name().is_some() {
+                    "child"
+                } else {
+                    "base"
+                },
+                layer.triple_layer_addition_count().await?,
+                layer.triple_layer_removal_count().await?,
+                rollup
+            );
+            current = layer.parent().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn dump(&self, label: &str) -> io::Result<()> {
+        let (head, _) = self.head(label).await?;
+        let stdout = io::stdout();
+        let mut out = BufWriter::new(stdout.lock());
+        for triple in head.triples() {
+            let triple = head.id_triple_to_string(&triple).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "triple could not be resolved")
+            })?;
+            writeln!(out, "{}", ntriple(&triple))?;
+        }
+
+        out.flush()
+    }
+
+    async fn squash(&self, label: &str, temp_dir: Option<&str>) -> io::Result<()> {
+        let graph = self
+            .store
+            .open(label)
+            .await?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "label not found"))?;
+        let (head, version) = self.head(label).await?;
+        let squashed = match temp_dir {
+            Some(temp_dir) => head.squash_external(Path::new(temp_dir)).await?,
+            None => head.squash().await?,
+        };
+        if !graph.force_set_head_version(&squashed, version).await? {
+            return Err(io::Error::other("label was changed while squashing"));
+        }
+        out!("{}", name_to_string(squashed.name()));
+
+        Ok(())
+    }
+
+    async fn rollup(&self, label: &str, upto: Option<&str>) -> io::Result<()> {
+        let (head, _) = self.head(label).await?;
+        match upto {
+            Some(upto) => {
+                let upto = head
+                    .ancestor_by_name(parse_layer_name(upto)?)
+                    .await?
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::NotFound, "upto layer not in stack")
+                    })?;
+                head.rollup_upto(&upto).await?;
+            }
+            None => head.rollup().await?,
+        }
+        if let Some(rollup) = self.store.layer_rollup_info(head.name()).await? {
+            out!("{}", name_to_string(rollup.rollup));
+        }
+
+        Ok(())
+    }
+
+    async fn export(&self, label: &str, pack_path: &str) -> io::Result<()> {
+        let (head, _) = self.head(label).await?;
+        let layers = head.retrieve_layer_stack_names().await?;
+        let count = layers.len();
+        let pack = self
+            .store
+            .export_layers(Box::new(layers.into_iter()))
+            .await?;
+        tokio::fs::write(pack_path, pack).await?;
+        out!("exported {} layers", count);
+
+        Ok(())
+    }
+
+    async fn import(&self, pack_path: &str, label: Option<&str>) -> io::Result<()> {
+        let pack = tokio::fs::read(pack_path).await?;
+        let parents = pack_layer_parents(&pack[..])
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        let layers: Vec<_> = parents.keys().cloned().collect();
+        self.store
+            .import_layers(&pack, Box::new(layers.clone().into_iter()))
+            .await?;
+        out!("imported {} layers", layers.len());
+
+        if let Some(label) = label {
+            let parent_names: HashSet<_> = parents.values().flatten().collect();
+            let tops: Vec<_> = layers
+                .iter()
+                .filter(|layer| !parent_names.contains(layer))
+                .collect();
+            if tops.len() != 1 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "pack does not contain a single layer stack",
+                ));
+            }
+            let top = self
+                .store
+                .get_layer_from_id(*tops[0])
+                .await?
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "layer not found"))?;
+            let graph = match self.store.open(label).await? {
+                Some(graph) => graph,
+                None => self.store.create(label).await?,
+            };
+            if !graph.set_head(&top).await? {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "label points at a layer that is not an ancestor of the imported stack",
+                ));
+            }
+            out!("{}\t{}", label, name_to_string(top.name()));
+        }
+
+        Ok(())
+    }
+
+    /// Check a single layer, returning a description of every problem found.
+    async fn check_layer(&self, layer: &StoreLayer) -> io::Result<Vec<String>> {
+        let mut problems = Vec::new();
+        let changes = [
+            ("addition", layer.triple_additions().await?),
+            ("removal", layer.triple_removals().await?),
+        ];
+        for (kind, triples) in changes {
+            let unresolved = triples
+                .filter(|t| layer.id_triple_to_string(t).is_none())
+                .count();
+            if unresolved != 0 {
+                problems.push(format!("{} triple {}s do not resolve", unresolved, kind));
+            }
+        }
+        let count = layer.triples().count();
+        if count != layer.triple_count() {
+            problems.push(format!(
+                "triple count is {} but {} triples were found",
+                layer.triple_count(),
+                count
+            ));
+        }
+        if let Some(rollup) = self.store.layer_rollup_info(layer.name()).await? {
+            if self.store.get_layer_from_id(rollup.rollup).await?.is_none() {
+                problems.push(format!(
+                    "rollup {} is missing",
+                    name_to_string(rollup.rollup)
+                ));
+            }
+        }
+
+        Ok(problems)
+    }
+
+    /// Check every layer reachable from the given labels, returning whether they were all fine.
+    async fn check(&self, labels: &[&str]) -> io::Result<bool> {
+        let labels: Vec<String> = if labels.is_empty() {
+            let mut labels = self.store.labels().await?;
+            labels.sort();
+            labels
+        } else {
+            labels.iter().map(|l| l.to_string()).collect()
+        };
+
+        let mut checked = HashSet::new();
+        let mut problem_count = 0;
+        for label in labels {
+            let mut current = match self.store.open(&label).await {
+                Ok(Some(graph)) => graph.head().await,
+                Ok(None) => Err(io::Error::new(io::ErrorKind::NotFound, "label not found")),
+                Err(e) => Err(e),
+            };
+            loop {
+                let layer = match current {
+                    Ok(Some(layer)) => layer,
+                    Ok(None) => break,
+                    Err(e) => {
+                        out!("{}: {}", label, e);
+                        problem_count += 1;
+                        break;
+                    }
+                };
+                if !checked.insert(layer.name()) {
+                    break;
+                }
+                let name = name_to_string(layer.name());
+                let problems = match self.check_layer(&layer).await {
+                    Ok(problems) => problems,
+                    Err(e) => vec![e.to_string()],
+                };
+                if problems.is_empty() {
+                    out!("{}: ok", name);
+                }
+                for problem in problems {
+                    out!("{}: {}", name, problem);
+                    problem_count += 1;
+                }
+                current = layer.parent().await;
+            }
+        }
+
+        out!(
+            "checked {} layers, found {} problems",
+            checked.len(),
+            problem_count
+        );
+
+        Ok(problem_count == 0)
+    }
+}
+
+fn escape_literal(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '"' => result.push_str("\\\""),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c => result.push(c),
+        }
+    }
+
+    result
+}
+
+fn ntriple_node(node: &str) -> String {
+    if node.starts_with("_:") {
+        return node.to_string();
+    }
+
+    let mut result = String::with_capacity(node.len() + 2);
+    result.push('<');
+    for c in node.chars() {
+        if c <= ' ' || "<>\"{}|^`\\".contains(c) {
+            result.push_str(&format!("\\u{:04X}", c as u32));
+        } else {
+            result.push(c);
+        }
+    }
+    result.push('>');
+
+    result
+}
+
+fn xsd_float(f: f64) -> String {
+    if f.is_nan() {
+        "NaN".to_string()
+    } else if f.is_infinite() {
+        if f > 0.0 { "INF" } else { "-INF" }.to_string()
+    } else {
+        f.to_string()
+    }
+}
+
+/// The lexical form of a value, as used in N-Triples.
+fn lexical_form(value: &TypedDictEntry) -> String {
+    match value.datatype() {
+        Datatype::UInt8 => value.as_val::<u8, u8>().to_string(),
+        Datatype::Int8 => value.as_val::<i8, i8>().to_string(),
+        Datatype::UInt16 => value.as_val::<u16, u16>().to_string(),
+        Datatype::Int16 => value.as_val::<i16, i16>().to_string(),
+        Datatype::UInt32 => value.as_val::<u32, u32>().to_string(),
+        Datatype::Int32 => value.as_val::<i32, i32>().to_string(),
+        Datatype::UInt64 => value.as_val::<u64, u64>().to_string(),
+        Datatype::Int64 => value.as_val::<i64, i64>().to_string(),
+        Datatype::Float32 => xsd_float(value.as_val::<f32, f32>() as f64),
+        Datatype::Float64 => xsd_float(value.as_val::<f64, f64>()),
+        Datatype::Boolean => value.as_val::<bool, bool>().to_string(),
+        Datatype::BigInt => value.as_val::<Integer, String>(),
+        Datatype::PositiveInteger => value.as_val::<PositiveInteger, String>(),
+        Datatype::NonNegativeInteger => value.as_val::<NonNegativeInteger, String>(),
+        Datatype::NonPositiveInteger => value.as_val::<NonPositiveInteger, String>(),
+        Datatype::NegativeInteger => value.as_val::<NegativeInteger, String>(),
+        Datatype::Decimal => value.as_val::<Decimal, String>(),
+        // date times with and without a time stamp share their encoding
+        Datatype::DateTime | Datatype::DateTimeStamp => value.as_val::<DateTimeStamp, String>(),
+        Datatype::Date => value.as_val::<Date, String>(),
+        Datatype::GYear => value.as_val::<GYear, String>(),
+        Datatype::GMonth => value.as_val::<GMonth, String>(),
+        Datatype::GDay => value.as_val::<GDay, String>(),
+        Datatype::GYearMonth => value.as_val::<GYearMonth, String>(),
+        Datatype::GMonthDay => value.as_val::<GMonthDay, String>(),
+        Datatype::Duration => value.as_val::<Duration, String>(),
+        Datatype::YearMonthDuration => value.as_val::<YearMonthDuration, String>(),
+        Datatype::DayTimeDuration => value.as_val::<DayTimeDuration, String>(),
+        Datatype::Base64Binary => value.as_val::<Base64Binary, String>(),
+        Datatype::HexBinary => value.as_val::<HexBinary, String>(),
+        // all other types are stored as their lexical form
+        _ => String::from_utf8_lossy(&value.to_bytes()).into_owned(),
+    }
+}
+
+fn datatype_iri(datatype: Datatype) -> String {
+    let name = match datatype {
+        Datatype::LangString => {
+            return "http://www.w3.org/1999/02/22-rdf-syntax-ns#langString".to_string()
+        }
+        Datatype::String => "string",
+        Datatype::UInt8 => "unsignedByte",
+        Datatype::Int8 => "byte",
+        Datatype::UInt16 => "unsignedShort",
+        Datatype::Int16 => "short",
+        Datatype::UInt32 => "unsignedInt",
+        Datatype::Int32 => "int",
+        Datatype::UInt64 => "unsignedLong",
+        Datatype::Int64 => "long",
+        Datatype::Float32 => "float",
+        Datatype::Float64 => "double",
+        Datatype::Decimal => "decimal",
+        Datatype::BigInt => "integer",
+        Datatype::Boolean => "boolean",
+        Datatype::AnyURI => "anyURI",
+        Datatype::Language => "language",
+        Datatype::NormalizedString => "normalizedString",
+        Datatype::Token => "token",
+        Datatype::NMToken => "NMTOKEN",
+        Datatype::Name => "Name",
+        Datatype::NCName => "NCName",
+        Datatype::Notation => "NOTATION",
+        Datatype::QName => "QName",
+        Datatype::ID => "ID",
+        Datatype::IDRef => "IDREF",
+        Datatype::Entity => "ENTITY",
+        Datatype::PositiveInteger => "positiveInteger",
+        Datatype::NonNegativeInteger => "nonNegativeInteger",
+        Datatype::NonPositiveInteger => "nonPositiveInteger",
+        Datatype::NegativeInteger => "negativeInteger",
+        Datatype::Date => "date",
+        Datatype::DateTime => "dateTime",
+        Datatype::DateTimeStamp => "dateTimeStamp",
+        Datatype::Time => "time",
+        Datatype::GYear => "gYear",
+        Datatype::GMonth => "gMonth",
+        Datatype::GDay => "gDay",
+        Datatype::GYearMonth => "gYearMonth",
+        Datatype::GMonthDay => "gMonthDay",
+        Datatype::Duration => "duration",
+        Datatype::YearMonthDuration => "yearMonthDuration",
+        Datatype::DayTimeDuration => "dayTimeDuration",
+        Datatype::Base64Binary => "base64Binary",
+        Datatype::HexBinary => "hexBinary",
+        Datatype::AnySimpleType => "anySimpleType",
+    };
+
+    format!("http://www.w3.org/2001/XMLSchema#{}", name)
+}
+
+fn ntriple_value(value: &TypedDictEntry) -> String {
+    let literal = format!("\"{}\"", escape_literal(&lexical_form(value)));
+    if value.datatype() == Datatype::String {
+        literal
+    } else {
+        format!("{}^^<{}>", literal, datatype_iri(value.datatype()))
+    }
+}
+
+/// Render a triple as a line of N-Triples, without the trailing newline.
+fn ntriple(triple: &ValueTriple) -> String {
+    let object = match &triple.object {
+        ObjectType::Node(node) => ntriple_node(node),
+        ObjectType::Value(value) => ntriple_value(value),
+    };
+
+    format!(
+        "{} {} {} .",
+        ntriple_node(&triple.subject),
+        ntriple_node(&triple.predicate),
+        object
+    )
+}
+
+async fn upgrade(path: &Path, to: StoreVersion) -> io::Result<bool> {
+    match read_store_version(path).await? {
+        Some(from) if from != to => {
+            upgrade_store(path, from, to).await?;
+            out!("upgraded store from '{}' to '{}'", from, to);
+        }
+        _ => out!("store is already '{}'", to),
+    }
+
+    Ok(true)
+}
+
+/// Run the command line, returning whether the command succeeded.
+async fn run(args: &[String]) -> io::Result<bool> {
+    let (mut kind, mut args) = (StoreKind::Archive, args);
+    if args.first().map(|a| a.as_str()) == Some("--kind") {
+        let value = args
+            .get(1)
+            .ok_or_else(|| usage_error("option --kind requires a value"))?;
+        kind = StoreKind::parse(value)?;
+        args = &args[2..];
+    }
+    let (path, command, rest) = match args {
+        [path, command, rest @ ..] => (PathBuf::from(path), command.as_str(), rest),
+        _ => return Err(usage_error(USAGE)),
+    };
+    if !path.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("store {} is not a directory", path.display()),
+        ));
+    }
+    if command == "upgrade" {
+        // the store can't be opened as the requested kind until it is upgraded
+        if !rest.is_empty() {
+            return Err(usage_error(USAGE));
+        }
+        return upgrade(&path, kind.version()).await;
+    }
+    let cli = Cli {
+        store: kind.open(&path).await?,
+    };
+
+    let options: &[&str] = match command {
+        "squash" => &["temp-dir"],
+        "rollup" => &["upto"],
+        _ => &[],
+    };
+    let (positional, options) = parse_options(rest, options)?;
+    match (command, positional.as_slice()) {
+        ("labels", []) => cli.labels().await?,
+        ("layer", ["info", layer]) => cli.layer_info(layer).await?,
+        ("stack", [label]) => cli.stack(label).await?,
+        ("dump", [label]) => cli.dump(label).await?,
+        ("squash", [label]) => cli.squash(label, options.get("temp-dir").copied()).await?,
+        ("rollup", [label]) => cli.rollup(label, options.get("upto").copied()).await?,
+        ("export", [label, pack]) => cli.export(label, pack).await?,
+        ("import", [pack]) => cli.import(pack, None).await?,
+        ("import", [pack, label]) => cli.import(pack, Some(label)).await?,
+        ("check", labels) => return cli.check(labels).await,
+        _ => return Err(usage_error(USAGE)),
+    }
+
+    Ok(true)
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|a| a == "--help" || a == "-h") {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+
+    match run(&args).await {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        // the reader of our output went away, so there is nobody left to complain to
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tdb_succinct::TdbDataType;
+    use terminus_store::storage::consts::LayerFileEnum;
+    use terminus_store::store::open_archive_store;
+
+    #[test]
+    fn ntriples_are_escaped() {
+        let triple = ValueTriple::new_string_value("http://a/b c", "_:p", "say \"hi\"\n");
+        assert_eq!(
+            "<http://a/b\\u0020c> _:p \"say \\\"hi\\\"\\n\" .",
+            ntriple(&triple)
+        );
+        let triple = ValueTriple::new_value("a", "b", u32::make_entry(&42));
+        assert_eq!(
+            "<a> <b> \"42\"^^<http://www.w3.org/2001/XMLSchema#unsignedInt> .",
+            ntriple(&triple)
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn export_and_import_between_stores() {
+        let from = tempfile::tempdir().unwrap();
+        let to = tempfile::tempdir().unwrap();
+        let pack = from.path().join("pack.tar.gz");
+        let pack = pack.to_str().unwrap();
+
+        let store = open_archive_store(from.path(), 10);
+        let graph = store.create("foo").await.unwrap();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_node("cow", "likes", "duck"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+        let builder = base.open_write().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+        let child = builder.commit().await.unwrap();
+        graph.set_head(&child).await.unwrap();
+
+        let source = Cli { store };
+        source.export("foo", pack).await.unwrap();
+        assert!(source.check(&[]).await.unwrap());
+
+        let target = Cli {
+            store: StoreKind::RawArchive.open(to.path()).await.unwrap(),
+        };
+        target.import(pack, Some("bar")).await.unwrap();
+        let (head, _) = target.head("bar").await.unwrap();
+        assert_eq!(child.name(), head.name());
+        assert!(target.check(&["bar"]).await.unwrap());
+
+        let info = target
+            .store
+            .layer_info(child.name())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(1, info.depth);
+        assert!(
+            info.section_size(LayerFileEnum::NodeDictionaryBlocks)
+                .unwrap()
+                > 0
+        );
+    }
+}
@@ -3,9 +3,10 @@ use std::io;
 use bytes::{Bytes, BytesMut};
 use futures::TryStreamExt;
 use rayon::prelude::*;
+use tracing::Instrument;
 
 use super::layer::*;
-use crate::{chrono_log, storage::*};
+use crate::storage::*;
 use tdb_succinct::util::{heap_sorted_iter, stream_iter_ok};
 use tdb_succinct::*;
 
@@ -337,6 +338,23 @@ impl<F: 'static + FileLoad + FileStore> TripleFileBuilder<F> {
         Ok(())
     }
 
+    /// Add the given triples, reporting every written triple to `progress`.
+    ///
+    /// This will panic if a greater triple has already been added.
+    pub async fn add_id_triples_with_progress<I: 'static + IntoIterator<Item = IdTriple>>(
+        &mut self,
+        triples: I,
+        progress: &Progress,
+    ) -> io::Result<()> {
+        for triple in triples {
+            self.add_triple(triple.subject, triple.predicate, triple.object)
+                .await?;
+            progress.triple_written()?;
+        }
+
+        Ok(())
+    }
+
     pub async fn finalize(self) -> io::Result<()> {
         self.s_p_adjacency_list_builder.finalize().await?;
         self.sp_o_adjacency_list_builder.finalize().await?;
@@ -373,13 +391,13 @@ pub async fn build_object_index_from_direct_files<
     o_ps_files: AdjacencyListFiles<F>,
     objects_file: Option<F>,
 ) -> io::Result<()> {
-    chrono_log!("starting object index build");
+    tracing::debug!("starting object index build");
     let build_sparse_index = objects_file.is_some();
     let (count, spo_width) = logarray_file_get_length_and_width(sp_o_nums_file.clone()).await?;
     let mut aj_stream = adjacency_list_stream_pairs(sp_o_bits_file, sp_o_nums_file).await?;
     let mut pairs = Vec::with_capacity(std::cmp::min(count, SINGLE_SORT_LIMIT) as usize);
     let mut greatest_sp = 0;
-    chrono_log!("opened sp_o stream");
+    tracing::trace!(pairs = count, "opened sp_o stream");
     let mut tally: u64 = 0;
     let mut temp_arrays: Vec<(LogArray, LogArray)> = Vec::new();
     // gather up pars
@@ -388,14 +406,15 @@ pub async fn build_object_index_from_direct_files<
         pairs.push((object, sp));
         tally += 1;
         if tally % 10000000 == 0 {
-            chrono_log!(
-                "collected {tally} pairs for o_ps index ({}%)",
-                (tally * 100 / count)
+            tracing::debug!(
+                pairs = tally,
+                percentage = tally * 100 / count,
+                "collected pairs for o_ps index"
             );
         }
 
         if tally % SINGLE_SORT_LIMIT == 0 {
-            chrono_log!("collect currently gathered elements into a logarray");
+            tracing::debug!("collect currently gathered elements into a logarray");
             pairs.par_sort_unstable();
             let mut sp_file = BytesMut::with_capacity(0);
             let mut o_file = BytesMut::with_capacity(0);
@@ -418,12 +437,12 @@ pub async fn build_object_index_from_direct_files<
             pairs.clear();
         }
     }
-    chrono_log!("collected object pairs");
+    tracing::debug!(pairs = tally, "collected object pairs");
 
     // par_sort_unstable unfortunately can run out of stack for very
     // large sorts. If so, we have to do something else.
     if pairs.len() as u64 > SINGLE_SORT_LIMIT {
-        chrono_log!("perform multi sort");
+        tracing::trace!("perform multi sort");
         let mut tally: u64 = 0;
         while tally < pairs.len() as u64 {
             let end = std::cmp::min(count as usize, (tally + SINGLE_SORT_LIMIT) as usize);
@@ -431,15 +450,15 @@ pub async fn build_object_index_from_direct_files<
             slice.par_sort_unstable();
             tally += SINGLE_SORT_LIMIT;
         }
-        chrono_log!("perform final sort");
+        tracing::trace!("perform final sort");
         // we use the normal sort as it is fast for cases where you
         // have a bunch of appended sorted slices.
         pairs.sort();
     } else {
-        chrono_log!("perform single sort");
+        tracing::trace!("perform single sort");
         pairs.par_sort_unstable();
     }
-    chrono_log!("sorted object pairs");
+    tracing::debug!("sorted object pairs");
 
     let aj_width = util::calculate_width(greatest_sp);
     let mut o_ps_adjacency_list_builder = AdjacencyListBuilder::new(
@@ -493,10 +512,10 @@ pub async fn build_object_index_from_direct_files<
             .push_all(stream_iter_ok::<_, io::Error, _>(merged_iters))
             .await?;
     }
-    chrono_log!("added object pairs to adjacency list builder");
+    tracing::trace!("added object pairs to adjacency list builder");
 
     o_ps_adjacency_list_builder.finalize().await?;
-    chrono_log!("finalized object index");
+    tracing::debug!("finalized object index");
 
     Ok(())
 }
@@ -537,18 +556,48 @@ pub async fn build_indexes<FLoad: 'static + FileLoad, F: 'static + FileLoad + Fi
     objects_file: Option<F>,
     wavelet_files: BitIndexFiles<F>,
 ) -> io::Result<()> {
-    let object_index_task = tokio::spawn(build_object_index(sp_o_files, o_ps_files, objects_file));
-    let predicate_index_task = tokio::spawn(build_predicate_index(
-        s_p_files.nums_file,
-        wavelet_files.bits_file,
-        wavelet_files.blocks_file,
-        wavelet_files.sblocks_file,
-    ));
+    build_indexes_with_progress(
+        s_p_files,
+        sp_o_files,
+        o_ps_files,
+        objects_file,
+        wavelet_files,
+        &Progress::default(),
+    )
+    .await
+}
+
+/// Build the object and predicate indexes, reporting each built index to `progress`.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn build_indexes_with_progress<
+    FLoad: 'static + FileLoad,
+    F: 'static + FileLoad + FileStore,
+>(
+    s_p_files: AdjacencyListFiles<FLoad>,
+    sp_o_files: AdjacencyListFiles<FLoad>,
+    o_ps_files: AdjacencyListFiles<F>,
+    objects_file: Option<F>,
+    wavelet_files: BitIndexFiles<F>,
+    progress: &Progress,
+) -> io::Result<()> {
+    let object_index_task =
+        tokio::spawn(build_object_index(sp_o_files, o_ps_files, objects_file).in_current_span());
+    let predicate_index_task = tokio::spawn(
+        build_predicate_index(
+            s_p_files.nums_file,
+            wavelet_files.bits_file,
+            wavelet_files.blocks_file,
+            wavelet_files.sblocks_file,
+        )
+        .in_current_span(),
+    );
 
     object_index_task.await??;
-    chrono_log!("built object index");
+    tracing::debug!("built object index");
+    progress.index_built()?;
     predicate_index_task.await??;
-    chrono_log!("built predicate index");
+    tracing::debug!("built predicate index");
+    progress.index_built()?;
 
     Ok(())
 }
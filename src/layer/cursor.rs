@@ -0,0 +1,344 @@
+//! Cursors for resuming iteration over triples.
+//!
+//! A `TripleCursor` records which triples are being iterated over,
+//! and the last triple that was seen. It can be turned into bytes and
+//! back, so that iteration can be resumed later on, for example when
+//! paging through results in a web service. Resuming seeks straight
+//! to the position after the last triple seen, without iterating over
+//! the triples before it.
+//!
+//! Since a cursor records a triple rather than an offset, resuming on
+//! a layer whose triples differ from the original layer continues with
+//! the first triple that comes after the last one seen.
+use std::io;
+
+use bytes::{Buf, BufMut};
+
+use super::layer::IdTriple;
+
+const CURSOR_FORMAT_VERSION: u8 = 1;
+
+/// The triples a cursor iterates over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TriplePattern {
+    /// All triples, in subject order, like `Layer::triples`.
+    All,
+    /// The triples with the given subject, like `Layer::triples_s`.
+    Subject(u64),
+    /// The triples with the given subject and predicate, like `Layer::triples_sp`.
+    SubjectPredicate(u64, u64),
+    /// The triples with the given predicate, like `Layer::triples_p`.
+    Predicate(u64),
+    /// The triples with the given object, like `Layer::triples_o`.
+    Object(u64),
+}
+
+impl TriplePattern {
+    /// Returns true if the given triple matches this pattern.
+    pub fn matches(&self, triple: &IdTriple) -> bool {
+        match *self {
+            Self::All => true,
+            Self::Subject(subject) => triple.subject == subject,
+            Self::SubjectPredicate(subject, predicate) => {
+                triple.subject == subject && triple.predicate == predicate
+            }
+            Self::Predicate(predicate) => triple.predicate == predicate,
+            Self::Object(object) => triple.object == object,
+        }
+    }
+
+    fn start(&self) -> IdTriple {
+        match *self {
+            Self::All => IdTriple::new(0, 0, 0),
+            Self::Subject(subject) => IdTriple::new(subject, 0, 0),
+            Self::SubjectPredicate(subject, predicate) => IdTriple::new(subject, predicate, 0),
+            Self::Predicate(predicate) => IdTriple::new(0, predicate, 0),
+            Self::Object(object) => IdTriple::new(0, 0, object),
+        }
+    }
+
+    fn tag(&self) -> u8 {
+        match self {
+            Self::All => 0,
+            Self::Subject(_) => 1,
+            Self::SubjectPredicate(_, _) => 2,
+            Self::Predicate(_) => 3,
+            Self::Object(_) => 4,
+        }
+    }
+}
+
+/// A position in the iteration over the triples matching a pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TripleCursor {
+    pattern: TriplePattern,
+    last: Option<IdTriple>,
+}
+
+impl TripleCursor {
+    /// Construct a cursor at the start of the triples matching the given pattern.
+    pub fn new(pattern: TriplePattern) -> Self {
+        Self {
+            pattern,
+            last: None,
+        }
+    }
+
+    /// Construct a cursor positioned right after the given triple.
+    ///
+    /// Returns an error if the triple does not match the pattern.
+    pub fn after(pattern: TriplePattern, last: IdTriple) -> io::Result<Self> {
+        if !pattern.matches(&last) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "last triple of a cursor does not match its pattern",
+            ));
+        }
+
+        Ok(Self {
+            pattern,
+            last: Some(last),
+        })
+    }
+
+    /// Returns the pattern of this cursor.
+    pub fn pattern(&self) -> TriplePattern {
+        self.pattern
+    }
+
+    /// Returns the last triple seen by this cursor, or None if it is at the start.
+    pub fn last(&self) -> Option<IdTriple> {
+        self.last
+    }
+
+    /// Move this cursor past the given triple, which should match its pattern.
+    pub fn advance(&mut self, triple: IdTriple) {
+        debug_assert!(self.pattern.matches(&triple));
+        self.last = Some(triple);
+    }
+
+    /// The first triple iteration resumes from, in the iteration order of the pattern.
+    ///
+    /// Triples with a given object are ordered by subject and
+    /// predicate, all other patterns are ordered by subject, predicate
+    /// and object. If the cursor is already at the last possible
+    /// triple, this returns that triple and `resume` yields nothing.
+    pub(crate) fn resume_position(&self) -> IdTriple {
+        match self.last {
+            None => self.pattern.start(),
+            Some(last) => self.next_position(last).unwrap_or(last),
+        }
+    }
+
+    /// The triple following `last` in the iteration order of the
+    /// pattern, or None if there is no such triple.
+    fn next_position(&self, last: IdTriple) -> Option<IdTriple> {
+        match self.pattern {
+            TriplePattern::Object(_) => match last.predicate.checked_add(1) {
+                Some(predicate) => Some(IdTriple::new(last.subject, predicate, last.object)),
+                None => Some(IdTriple::new(last.subject.checked_add(1)?, 0, last.object)),
+            },
+            _ => match last.object.checked_add(1) {
+                Some(object) => Some(IdTriple::new(last.subject, last.predicate, object)),
+                None => match last.predicate.checked_add(1) {
+                    Some(predicate) => Some(IdTriple::new(last.subject, predicate, 0)),
+                    None => Some(IdTriple::new(last.subject.checked_add(1)?, 0, 0)),
+                },
+            },
+        }
+    }
+
+    /// Restrict an iterator positioned at `resume_position` to the triples matching the pattern.
+    pub(crate) fn resume<I: 'static + Iterator<Item = IdTriple> + Send>(
+        &self,
+        iter: I,
+    ) -> TripleCursorIterator {
+        if let Some(last) = self.last {
+            if self.next_position(last).is_none() {
+                return TripleCursorIterator::new(*self, Box::new(std::iter::empty()));
+            }
+        }
+
+        let pattern = self.pattern;
+        TripleCursorIterator::new(
+            *self,
+            Box::new(iter.take_while(move |t| pattern.matches(t))),
+        )
+    }
+
+    /// Encode this cursor as bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(2 + 2 * 8 + 1 + 3 * 8);
+        result.put_u8(CURSOR_FORMAT_VERSION);
+        result.put_u8(self.pattern.tag());
+        match self.pattern {
+            TriplePattern::All => {}
+            TriplePattern::Subject(id)
+            | TriplePattern::Predicate(id)
+            | TriplePattern::Object(id) => result.put_u64(id),
+            TriplePattern::SubjectPredicate(subject, predicate) => {
+                result.put_u64(subject);
+                result.put_u64(predicate);
+            }
+        }
+        match self.last {
+            None => result.put_u8(0),
+            Some(last) => {
+                result.put_u8(1);
+                result.put_u64(last.subject);
+                result.put_u64(last.predicate);
+                result.put_u64(last.object);
+            }
+        }
+
+        result
+    }
+
+    /// Decode a cursor from bytes produced by `to_bytes`.
+    pub fn from_bytes(mut bytes: &[u8]) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid triple cursor");
+        let take_u64 = |bytes: &mut &[u8]| {
+            if bytes.remaining() < 8 {
+                Err(invalid())
+            } else {
+                Ok(bytes.get_u64())
+            }
+        };
+
+        if bytes.remaining() < 2 || bytes.get_u8() != CURSOR_FORMAT_VERSION {
+            return Err(invalid());
+        }
+        let pattern = match bytes.get_u8() {
+            0 => TriplePattern::All,
+            1 => TriplePattern::Subject(take_u64(&mut bytes)?),
+            2 => TriplePattern::SubjectPredicate(take_u64(&mut bytes)?, take_u64(&mut bytes)?),
+            3 => TriplePattern::Predicate(take_u64(&mut bytes)?),
+            4 => TriplePattern::Object(take_u64(&mut bytes)?),
+            _ => return Err(invalid()),
+        };
+        if !bytes.has_remaining() {
+            return Err(invalid());
+        }
+        let cursor = match bytes.get_u8() {
+            0 => Self::new(pattern),
+            1 => {
+                let last = IdTriple::new(
+                    take_u64(&mut bytes)?,
+                    take_u64(&mut bytes)?,
+                    take_u64(&mut bytes)?,
+                );
+                Self::after(pattern, last).map_err(|_| invalid())?
+            }
+            _ => return Err(invalid()),
+        };
+        if bytes.has_remaining() {
+            return Err(invalid());
+        }
+
+        Ok(cursor)
+    }
+}
+
+/// An iterator over triples that keeps track of its position as a cursor.
+pub struct TripleCursorIterator {
+    cursor: TripleCursor,
+    iter: Box<dyn Iterator<Item = IdTriple> + Send>,
+}
+
+impl TripleCursorIterator {
+    /// Construct an iterator from a cursor and an iterator over the triples after it.
+    pub fn new(cursor: TripleCursor, iter: Box<dyn Iterator<Item = IdTriple> + Send>) -> Self {
+        Self { cursor, iter }
+    }
+
+    /// Returns a cursor positioned after the last triple this iterator returned.
+    pub fn cursor(&self) -> TripleCursor {
+        self.cursor
+    }
+}
+
+impl Iterator for TripleCursorIterator {
+    type Item = IdTriple;
+
+    fn next(&mut self) -> Option<IdTriple> {
+        let result = self.iter.next();
+        if let Some(triple) = result {
+            self.cursor.advance(triple);
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_bytes_roundtrip() {
+        let cursors = vec![
+            TripleCursor::new(TriplePattern::All),
+            TripleCursor::after(TriplePattern::All, IdTriple::new(1, 2, 3)).unwrap(),
+            TripleCursor::new(TriplePattern::Subject(4)),
+            TripleCursor::after(
+                TriplePattern::SubjectPredicate(4, 5),
+                IdTriple::new(4, 5, 6),
+            )
+            .unwrap(),
+            TripleCursor::after(TriplePattern::Predicate(7), IdTriple::new(1, 7, 3)).unwrap(),
+            TripleCursor::after(
+                TriplePattern::Object(u64::MAX),
+                IdTriple::new(1, 2, u64::MAX),
+            )
+            .unwrap(),
+        ];
+        for cursor in cursors {
+            assert_eq!(
+                cursor,
+                TripleCursor::from_bytes(&cursor.to_bytes()).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_cursor_bytes() {
+        let bytes = TripleCursor::after(TriplePattern::Subject(4), IdTriple::new(4, 5, 6))
+            .unwrap()
+            .to_bytes();
+        assert!(TripleCursor::from_bytes(&[]).is_err());
+        assert!(TripleCursor::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(TripleCursor::from_bytes(&[&bytes[..], &[0]].concat()).is_err());
+
+        let mut mismatched = bytes.clone();
+        mismatched[9] = 5;
+        assert!(TripleCursor::from_bytes(&mismatched).is_err());
+        assert!(TripleCursor::after(TriplePattern::Subject(4), IdTriple::new(5, 5, 6)).is_err());
+    }
+
+    #[test]
+    fn resume_position_carries_on_overflow() {
+        let cursor =
+            TripleCursor::after(TriplePattern::Subject(4), IdTriple::new(4, 5, u64::MAX)).unwrap();
+        assert_eq!(IdTriple::new(4, 6, 0), cursor.resume_position());
+
+        let cursor =
+            TripleCursor::after(TriplePattern::All, IdTriple::new(4, u64::MAX, u64::MAX)).unwrap();
+        assert_eq!(IdTriple::new(5, 0, 0), cursor.resume_position());
+
+        let cursor =
+            TripleCursor::after(TriplePattern::Object(3), IdTriple::new(4, u64::MAX, 3)).unwrap();
+        assert_eq!(IdTriple::new(5, 0, 3), cursor.resume_position());
+    }
+
+    #[test]
+    fn resume_after_last_possible_triple_is_empty() {
+        let last = IdTriple::new(u64::MAX, u64::MAX, u64::MAX);
+        let cursor = TripleCursor::after(TriplePattern::All, last).unwrap();
+        assert_eq!(last, cursor.resume_position());
+        assert_eq!(0, cursor.resume(vec![last].into_iter()).count());
+
+        let last = IdTriple::new(u64::MAX, u64::MAX, 3);
+        let cursor = TripleCursor::after(TriplePattern::Object(3), last).unwrap();
+        assert_eq!(0, cursor.resume(vec![last].into_iter()).count());
+    }
+}
@@ -0,0 +1,609 @@
+//! Application-defined value datatypes.
+//!
+//! The datatypes of values are fixed by the typed dictionary that
+//! stores them. Applications that need other datatypes implement
+//! `CustomDatatype`, which turns their values into a
+//! `TypedDictEntry`. Such an entry can be used like any other value,
+//! in `ValueTriple::new_value`, `Layer::object_value_id`,
+//! `Layer::id_object_value` and `Layer::object_value_ids_in_range`.
+//!
+//! Custom values are stored as `Datatype::Base64Binary` values,
+//! consisting of a marker, the big-endian datatype id and the encoded
+//! value. All values of a custom datatype therefore end up next to
+//! each other in the dictionary, ordered by their encoding. Values of
+//! datatypes that an application does not know about are still read
+//! back as a `CustomValue`, which keeps their bytes as they were.
+//!
+//! Binary values that are not custom values should be made with
+//! `binary_entry` and read with `binary_from_entry`. These escape
+//! binaries that happen to start with the custom value marker, so
+//! that they are not mistaken for custom values.
+//!
+//! Layers and stores don't know about custom datatypes. They order
+//! custom values by their encoding, so range queries over datatypes
+//! with their own ordering have to go through a `DatatypeRegistry`.
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use tdb_succinct::{Datatype, TypedDictEntry};
+
+use super::layer::*;
+
+/// The bytes that all custom values start with.
+const CUSTOM_VALUE_MARKER: &[u8] = b"\xfftdb";
+
+/// The datatype id that marks an escaped binary rather than a custom value.
+///
+/// This id can't be used by custom datatypes.
+pub const ESCAPED_BINARY_DATATYPE_ID: u32 = u32::MAX;
+
+/// The builtin datatype that custom values are stored as.
+pub const CUSTOM_VALUE_DATATYPE: Datatype = Datatype::Base64Binary;
+
+/// A comparison between two encoded values of a custom datatype.
+pub type CustomOrdering = fn(&[u8], &[u8]) -> Ordering;
+
+/// A value datatype defined by an application.
+pub trait CustomDatatype {
+    /// The type of the values of this datatype.
+    type Value;
+
+    /// The id of this datatype, which is stored along with every value.
+    ///
+    /// This must not be `ESCAPED_BINARY_DATATYPE_ID`.
+    const ID: u32;
+
+    /// Encode a value into bytes.
+    fn encode(value: &Self::Value) -> Bytes;
+
+    /// Decode a value from the bytes it was encoded into.
+    fn decode(bytes: Bytes) -> io::Result<Self::Value>;
+
+    /// The order of encoded values.
+    ///
+    /// The default of None means that values are ordered by their
+    /// encoding, which allows range queries to be answered from the
+    /// dictionary directly.
+    fn ordering() -> Option<CustomOrdering> {
+        None
+    }
+
+    /// Create a dictionary entry for the given value.
+    fn make_entry(value: &Self::Value) -> TypedDictEntry {
+        CustomValue::encode::<Self>(value).to_entry()
+    }
+
+    /// Decode the given dictionary entry, returning None if it is not a value of this datatype.
+    fn from_entry(entry: &TypedDictEntry) -> Option<io::Result<Self::Value>> {
+        CustomValue::from_entry(entry).and_then(|value| value.decode::<Self>())
+    }
+}
+
+/// An encoded value of a custom datatype.
+///
+/// This does not need the datatype to be known, so values of unknown
+/// datatypes can be read and written back unchanged.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CustomValue {
+    datatype: u32,
+    bytes: Bytes,
+}
+
+impl CustomValue {
+    /// Construct a custom value from a datatype id and an encoded value.
+    ///
+    /// This panics if the datatype id is `ESCAPED_BINARY_DATATYPE_ID`.
+    pub fn new(datatype: u32, bytes: Bytes) -> Self {
+        assert!(
+            datatype != ESCAPED_BINARY_DATATYPE_ID,
+            "datatype id {} is reserved for escaped binaries",
+            datatype
+        );
+        Self { datatype, bytes }
+    }
+
+    /// Encode the given value of a custom datatype.
+    pub fn encode<T: CustomDatatype + ?Sized>(value: &T::Value) -> Self {
+        Self::new(T::ID, T::encode(value))
+    }
+
+    /// The id of the datatype of this value.
+    pub fn datatype(&self) -> u32 {
+        self.datatype
+    }
+
+    /// The encoded value.
+    pub fn bytes(&self) -> &Bytes {
+        &self.bytes
+    }
+
+    /// Decode this value, returning None if it is not of the given datatype.
+    pub fn decode<T: CustomDatatype + ?Sized>(&self) -> Option<io::Result<T::Value>> {
+        if self.datatype == T::ID {
+            Some(T::decode(self.bytes.clone()))
+        } else {
+            None
+        }
+    }
+
+    /// Read a custom value from a dictionary entry, returning None if the entry is not a custom value.
+    pub fn from_entry(entry: &TypedDictEntry) -> Option<Self> {
+        if entry.datatype() != CUSTOM_VALUE_DATATYPE {
+            return None;
+        }
+
+        match split_custom_value(entry.to_bytes()) {
+            Some((datatype, bytes)) if datatype != ESCAPED_BINARY_DATATYPE_ID => {
+                Some(Self::new(datatype, bytes))
+            }
+            _ => None,
+        }
+    }
+
+    /// Convert this value into a dictionary entry.
+    pub fn to_entry(&self) -> TypedDictEntry {
+        let mut bytes = datatype_prefix(self.datatype);
+        bytes.extend_from_slice(&self.bytes);
+
+        TypedDictEntry::new(CUSTOM_VALUE_DATATYPE, bytes.freeze().into())
+    }
+}
+
+/// Split stored bytes into a datatype id and the bytes after it, if they start with the marker.
+fn split_custom_value(bytes: Bytes) -> Option<(u32, Bytes)> {
+    let header_len = CUSTOM_VALUE_MARKER.len() + 4;
+    if bytes.len() < header_len || !bytes.starts_with(CUSTOM_VALUE_MARKER) {
+        return None;
+    }
+
+    let mut id = [0; 4];
+    id.copy_from_slice(&bytes[CUSTOM_VALUE_MARKER.len()..header_len]);
+
+    Some((u32::from_be_bytes(id), bytes.slice(header_len..)))
+}
+
+/// Create a dictionary entry for a binary value that is not a custom value.
+///
+/// Binaries that start with the custom value marker are escaped, so
+/// they won't be read back as custom values.
+pub fn binary_entry(bytes: &[u8]) -> TypedDictEntry {
+    let bytes = if bytes.starts_with(CUSTOM_VALUE_MARKER) {
+        let mut escaped = datatype_prefix(ESCAPED_BINARY_DATATYPE_ID);
+        escaped.extend_from_slice(bytes);
+        escaped.freeze()
+    } else {
+        Bytes::copy_from_slice(bytes)
+    };
+
+    TypedDictEntry::new(CUSTOM_VALUE_DATATYPE, bytes.into())
+}
+
+/// Read a binary value that is not a custom value from a dictionary entry.
+///
+/// This undoes the escaping done by `binary_entry`. None is returned
+/// if the entry is not a binary, or if it is a custom value.
+pub fn binary_from_entry(entry: &TypedDictEntry) -> Option<Bytes> {
+    if entry.datatype() != CUSTOM_VALUE_DATATYPE {
+        return None;
+    }
+
+    let bytes = entry.to_bytes();
+    match split_custom_value(bytes.clone()) {
+        None => Some(bytes),
+        Some((ESCAPED_BINARY_DATATYPE_ID, escaped)) => Some(escaped),
+        Some(_) => None,
+    }
+}
+
+fn datatype_prefix(datatype: u32) -> BytesMut {
+    let mut bytes = BytesMut::with_capacity(CUSTOM_VALUE_MARKER.len() + 4);
+    bytes.extend_from_slice(CUSTOM_VALUE_MARKER);
+    bytes.put_u32(datatype);
+
+    bytes
+}
+
+/// The entries that all values of the given datatype are in between.
+///
+/// The first entry is inclusive, the second exclusive.
+fn datatype_bounds(datatype: u32) -> (TypedDictEntry, TypedDictEntry) {
+    let start = datatype_prefix(datatype).freeze();
+    let end = match datatype.checked_add(1) {
+        Some(next) => datatype_prefix(next).freeze(),
+        None => {
+            let mut end = BytesMut::from(CUSTOM_VALUE_MARKER);
+            *end.last_mut().unwrap() += 1;
+            end.freeze()
+        }
+    };
+
+    (
+        TypedDictEntry::new(CUSTOM_VALUE_DATATYPE, start.into()),
+        TypedDictEntry::new(CUSTOM_VALUE_DATATYPE, end.into()),
+    )
+}
+
+/// The custom datatypes known to an application.
+///
+/// The registry knows how values of each datatype are ordered, which
+/// is needed for range queries over datatypes with their own
+/// ordering. Datatypes that were not registered are ordered by their
+/// encoding.
+#[derive(Debug, Clone, Default)]
+pub struct DatatypeRegistry {
+    orderings: HashMap<u32, Option<CustomOrdering>>,
+}
+
+impl DatatypeRegistry {
+    /// Construct an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a custom datatype.
+    ///
+    /// This fails if another datatype was already registered with the
+    /// same id, or if the id is `ESCAPED_BINARY_DATATYPE_ID`.
+    pub fn register<T: CustomDatatype + ?Sized>(&mut self) -> io::Result<()> {
+        if T::ID == ESCAPED_BINARY_DATATYPE_ID {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("datatype id {} is reserved for escaped binaries", T::ID),
+            ));
+        }
+        if self.orderings.contains_key(&T::ID) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("a datatype with id {} was already registered", T::ID),
+            ));
+        }
+
+        self.orderings.insert(T::ID, T::ordering());
+
+        Ok(())
+    }
+
+    /// Returns true if a datatype with the given id was registered.
+    pub fn is_registered(&self, datatype: u32) -> bool {
+        self.orderings.contains_key(&datatype)
+    }
+
+    fn ordering(&self, datatype: u32) -> Option<CustomOrdering> {
+        self.orderings.get(&datatype).copied().flatten()
+    }
+
+    /// Compare two custom values, first by datatype and then by the order of that datatype.
+    pub fn compare(&self, v1: &CustomValue, v2: &CustomValue) -> Ordering {
+        v1.datatype
+            .cmp(&v2.datatype)
+            .then_with(|| match self.ordering(v1.datatype) {
+                Some(ordering) => ordering(&v1.bytes, &v2.bytes),
+                None => v1.bytes.cmp(&v2.bytes),
+            })
+    }
+
+    /// The ids of all values of a custom datatype from `from` up to but not including `to`.
+    ///
+    /// Both bounds have to be of the same datatype. The ids are
+    /// returned in the order of their values. Datatypes that are
+    /// ordered by their encoding are looked up in the dictionary
+    /// directly, while for other datatypes all values of the datatype
+    /// are compared.
+    pub fn value_ids_in_range<L: Layer + ?Sized>(
+        &self,
+        layer: &L,
+        from: &CustomValue,
+        to: &CustomValue,
+    ) -> io::Result<Vec<u64>> {
+        if from.datatype != to.datatype {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "range bounds are of different datatypes",
+            ));
+        }
+
+        let ordering = match self.ordering(from.datatype) {
+            None => {
+                return Ok(layer
+                    .object_value_ids_in_range(&from.to_entry(), &to.to_entry())
+                    .collect())
+            }
+            Some(ordering) => ordering,
+        };
+
+        let (start, end) = datatype_bounds(from.datatype);
+        let mut values: Vec<_> = layer
+            .object_value_ids_in_range(&start, &end)
+            .filter_map(|id| {
+                let value = layer
+                    .id_object_value(id)
+                    .and_then(|entry| CustomValue::from_entry(&entry))?;
+                if ordering(&value.bytes, &from.bytes) != Ordering::Less
+                    && ordering(&value.bytes, &to.bytes) == Ordering::Less
+                {
+                    Some((value, id))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        values.sort_by(|(v1, _), (v2, _)| ordering(&v1.bytes, &v2.bytes));
+
+        Ok(values.into_iter().map(|(_, id)| id).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::internal::base::base_tests::base_layer_files;
+    use crate::layer::internal::base::BaseLayer;
+    use crate::layer::internal::child::child_tests::child_layer_files;
+    use crate::layer::internal::child::ChildLayer;
+    use crate::layer::internal::InternalLayer;
+    use crate::layer::simple_builder::{LayerBuilder, SimpleLayerBuilder};
+    use std::sync::Arc;
+    use tdb_succinct::TdbDataType;
+
+    /// A point on a grid, ordered by x and then by y.
+    struct GridPoint;
+
+    impl CustomDatatype for GridPoint {
+        type Value = (u32, u32);
+        const ID: u32 = 7;
+
+        fn encode(value: &(u32, u32)) -> Bytes {
+            let mut bytes = BytesMut::new();
+            bytes.put_u32(value.0);
+            bytes.put_u32(value.1);
+            bytes.freeze()
+        }
+
+        fn decode(bytes: Bytes) -> io::Result<(u32, u32)> {
+            if bytes.len() != 8 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "grid point should be 8 bytes",
+                ));
+            }
+            let mut x = [0; 4];
+            let mut y = [0; 4];
+            x.copy_from_slice(&bytes[..4]);
+            y.copy_from_slice(&bytes[4..]);
+            Ok((u32::from_be_bytes(x), u32::from_be_bytes(y)))
+        }
+    }
+
+    /// A length in whole units, ordered by its decimal digits as a number.
+    struct Length;
+
+    impl CustomDatatype for Length {
+        type Value = u64;
+        const ID: u32 = 8;
+
+        fn encode(value: &u64) -> Bytes {
+            Bytes::from(value.to_string())
+        }
+
+        fn decode(bytes: Bytes) -> io::Result<u64> {
+            std::str::from_utf8(&bytes)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid length"))
+        }
+
+        fn ordering() -> Option<CustomOrdering> {
+            Some(|v1, v2| v1.len().cmp(&v2.len()).then_with(|| v1.cmp(v2)))
+        }
+    }
+
+    async fn custom_value_stack() -> Arc<InternalLayer> {
+        let files = base_layer_files();
+        let mut builder = SimpleLayerBuilder::new([1, 2, 3, 4, 5], files.clone());
+        for (i, point) in [(1, 2), (3, 1), (2, 9)].iter().enumerate() {
+            builder.add_value_triple(ValueTriple::new_value(
+                &format!("point{}", i),
+                "at",
+                GridPoint::make_entry(point),
+            ));
+        }
+        for length in [9, 100] {
+            builder.add_value_triple(ValueTriple::new_value(
+                "rope",
+                "length",
+                Length::make_entry(&length),
+            ));
+        }
+        builder.add_value_triple(ValueTriple::new_value(
+            "mystery",
+            "is",
+            CustomValue::new(42, Bytes::from_static(b"opaque")).to_entry(),
+        ));
+        builder.add_value_triple(ValueTriple::new_string_value("point0", "name", "origin"));
+        builder.commit().await.unwrap();
+        let base: Arc<InternalLayer> = Arc::new(
+            BaseLayer::load_from_files([1, 2, 3, 4, 5], &files)
+                .await
+                .unwrap(),
+        );
+
+        let files = child_layer_files();
+        let mut builder =
+            SimpleLayerBuilder::from_parent([5, 4, 3, 2, 1], base.clone(), files.clone());
+        builder.add_value_triple(ValueTriple::new_value(
+            "point3",
+            "at",
+            GridPoint::make_entry(&(2, 3)),
+        ));
+        builder.add_value_triple(ValueTriple::new_value(
+            "rope",
+            "length",
+            Length::make_entry(&20),
+        ));
+        builder.commit().await.unwrap();
+
+        Arc::new(
+            ChildLayer::load_from_files([5, 4, 3, 2, 1], base, &files)
+                .await
+                .unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn custom_values_roundtrip() {
+        let layer = custom_value_stack().await;
+
+        let id = layer
+            .object_value_id(&GridPoint::make_entry(&(2, 9)))
+            .unwrap();
+        let entry = layer.id_object_value(id).unwrap();
+        assert_eq!((2, 9), GridPoint::from_entry(&entry).unwrap().unwrap());
+        assert!(Length::from_entry(&entry).is_none());
+
+        let id = layer.object_value_id(&Length::make_entry(&20)).unwrap();
+        let entry = layer.id_object_value(id).unwrap();
+        assert_eq!(20, Length::from_entry(&entry).unwrap().unwrap());
+
+        let name = layer
+            .object_value_id(&String::make_entry(&"origin"))
+            .unwrap();
+        assert!(CustomValue::from_entry(&layer.id_object_value(name).unwrap()).is_none());
+    }
+
+    #[tokio::test]
+    async fn unknown_datatype_is_kept_as_bytes() {
+        let layer = custom_value_stack().await;
+        let mystery = layer.subject_id("mystery").unwrap();
+        let triple = layer.triples_s(mystery).next().unwrap();
+        let entry = layer.id_object_value(triple.object).unwrap();
+
+        let value = CustomValue::from_entry(&entry).unwrap();
+        assert_eq!(42, value.datatype());
+        assert_eq!(&b"opaque"[..], &value.bytes()[..]);
+        assert!(!DatatypeRegistry::new().is_registered(42));
+        assert_eq!(entry, value.to_entry());
+        assert_eq!(
+            Some(triple.object),
+            layer.object_value_id(&value.to_entry())
+        );
+    }
+
+    #[tokio::test]
+    async fn binary_starting_with_marker_is_not_a_custom_value() {
+        let mut binary = BytesMut::from(CUSTOM_VALUE_MARKER);
+        binary.put_u32(GridPoint::ID);
+        binary.put_u32(2);
+        binary.put_u32(5);
+        let entry = binary_entry(&binary);
+        assert!(CustomValue::from_entry(&entry).is_none());
+        assert!(GridPoint::from_entry(&entry).is_none());
+        assert_eq!(&binary[..], &binary_from_entry(&entry).unwrap()[..]);
+
+        let files = base_layer_files();
+        let mut builder = SimpleLayerBuilder::new([1, 2, 3, 4, 5], files.clone());
+        builder.add_value_triple(ValueTriple::new_value(
+            "point0",
+            "at",
+            GridPoint::make_entry(&(2, 3)),
+        ));
+        builder.add_value_triple(ValueTriple::new_value("blob", "is", entry.clone()));
+        builder.add_value_triple(ValueTriple::new_value(
+            "other_blob",
+            "is",
+            binary_entry(b"plain"),
+        ));
+        builder.commit().await.unwrap();
+        let layer = BaseLayer::load_from_files([1, 2, 3, 4, 5], &files)
+            .await
+            .unwrap();
+
+        let id = layer.object_value_id(&entry).unwrap();
+        let stored = layer.id_object_value(id).unwrap();
+        assert!(CustomValue::from_entry(&stored).is_none());
+        assert_eq!(&binary[..], &binary_from_entry(&stored).unwrap()[..]);
+
+        let id = layer.object_value_id(&binary_entry(b"plain")).unwrap();
+        let stored = layer.id_object_value(id).unwrap();
+        assert_eq!(&b"plain"[..], &binary_from_entry(&stored).unwrap()[..]);
+        let point = layer
+            .object_value_id(&GridPoint::make_entry(&(2, 3)))
+            .unwrap();
+        assert!(binary_from_entry(&layer.id_object_value(point).unwrap()).is_none());
+
+        let mut registry = DatatypeRegistry::new();
+        registry.register::<GridPoint>().unwrap();
+        let points = registry
+            .value_ids_in_range(
+                &layer,
+                &CustomValue::encode::<GridPoint>(&(0, 0)),
+                &CustomValue::encode::<GridPoint>(&(9, 9)),
+            )
+            .unwrap();
+        assert_eq!(vec![point], points);
+    }
+
+    #[test]
+    fn escaped_binary_datatype_id_is_reserved() {
+        struct Reserved;
+
+        impl CustomDatatype for Reserved {
+            type Value = ();
+            const ID: u32 = ESCAPED_BINARY_DATATYPE_ID;
+
+            fn encode(_value: &()) -> Bytes {
+                Bytes::new()
+            }
+
+            fn decode(_bytes: Bytes) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        assert!(DatatypeRegistry::new().register::<Reserved>().is_err());
+    }
+
+    #[tokio::test]
+    async fn custom_value_ranges() {
+        let layer = custom_value_stack().await;
+        let mut registry = DatatypeRegistry::new();
+        registry.register::<GridPoint>().unwrap();
+        registry.register::<Length>().unwrap();
+        assert!(registry.register::<Length>().is_err());
+
+        let points: Vec<_> = registry
+            .value_ids_in_range(
+                &*layer,
+                &CustomValue::encode::<GridPoint>(&(1, 5)),
+                &CustomValue::encode::<GridPoint>(&(3, 0)),
+            )
+            .unwrap()
+            .into_iter()
+            .map(|id| GridPoint::from_entry(&layer.id_object_value(id).unwrap()))
+            .map(|p| p.unwrap().unwrap())
+            .collect();
+        assert_eq!(vec![(2, 3), (2, 9)], points);
+
+        let lengths: Vec<_> = registry
+            .value_ids_in_range(
+                &*layer,
+                &CustomValue::encode::<Length>(&5),
+                &CustomValue::encode::<Length>(&100),
+            )
+            .unwrap()
+            .into_iter()
+            .map(|id| Length::from_entry(&layer.id_object_value(id).unwrap()))
+            .map(|l| l.unwrap().unwrap())
+            .collect();
+        assert_eq!(vec![9, 20], lengths);
+
+        assert!(registry
+            .value_ids_in_range(
+                &*layer,
+                &CustomValue::encode::<Length>(&5),
+                &CustomValue::encode::<GridPoint>(&(1, 1)),
+            )
+            .is_err());
+    }
+}
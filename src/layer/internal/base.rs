@@ -8,8 +8,8 @@ use futures::task::{Context, Poll};
 use super::super::builder::*;
 use super::super::id_map::*;
 use super::super::layer::*;
-use crate::layer::InternalLayer;
-use crate::{chrono_log, storage::*};
+use crate::layer::{GraphIndex, InternalLayer, TextIndex};
+use crate::storage::*;
 use tdb_succinct::*;
 
 use std::io;
@@ -38,6 +38,12 @@ pub struct BaseLayer {
     pub(super) o_ps_adjacency_list: AdjacencyList,
 
     pub(super) predicate_wavelet_tree: WaveletTree,
+
+    pub(super) quads: Option<GraphIndex>,
+
+    pub(super) text_index: Option<TextIndex>,
+
+    pub(super) mapped_size: usize,
 }
 
 impl BaseLayer {
@@ -50,6 +56,7 @@ impl BaseLayer {
     }
 
     pub fn load(name: [u32; 5], maps: BaseLayerMaps) -> InternalLayer {
+        let mapped_size = maps.byte_size();
         let node_dictionary = StringDict::parse(
             maps.node_dictionary_maps.offsets_map,
             maps.node_dictionary_maps.blocks_map,
@@ -119,6 +126,13 @@ impl BaseLayer {
             predicate_wavelet_tree_width,
         );
 
+        let quads = maps
+            .quads_map
+            .map(|quads_map| GraphIndex::parse(quads_map).unwrap());
+        let text_index = maps
+            .text_index_map
+            .map(|text_index_map| TextIndex::parse(text_index_map).unwrap());
+
         InternalLayer::Base(BaseLayer {
             name,
             node_dictionary,
@@ -137,6 +151,12 @@ impl BaseLayer {
             o_ps_adjacency_list,
 
             predicate_wavelet_tree,
+
+            quads,
+
+            text_index,
+
+            mapped_size,
         })
     }
 }
@@ -373,30 +393,53 @@ impl<F: 'static + FileLoad + FileStore> BaseLayerFileBuilderPhase2<F> {
         self.builder.add_id_triples(triples).await
     }
 
+    /// Add the given triples, reporting every written triple to `progress`.
+    ///
+    /// This will panic if a greater triple has already been added.
+    pub async fn add_id_triples_with_progress<I: 'static + IntoIterator<Item = IdTriple>>(
+        &mut self,
+        triples: I,
+        progress: &Progress,
+    ) -> io::Result<()>
+    where
+        <I as std::iter::IntoIterator>::IntoIter: Unpin + Send,
+    {
+        self.builder
+            .add_id_triples_with_progress(triples, progress)
+            .await
+    }
+
     pub(crate) async fn partial_finalize(self) -> io::Result<BaseLayerFiles<F>> {
         self.builder.finalize().await?;
-        chrono_log!("finalized base triples builder");
+        tracing::trace!("finalized base triples builder");
 
         Ok(self.files)
     }
 
     pub async fn finalize(self) -> io::Result<()> {
+        self.finalize_with_progress(&Progress::default()).await
+    }
+
+    /// Write the layer data to storage, reporting the built indexes to `progress`.
+    pub async fn finalize_with_progress(self, progress: &Progress) -> io::Result<()> {
         self.builder.finalize().await?;
-        chrono_log!("finalized base triples builder");
+        tracing::trace!("finalized base triples builder");
+        progress.triples_done()?;
         let s_p_adjacency_list_files = self.files.s_p_adjacency_list_files.clone();
         let sp_o_adjacency_list_files = self.files.sp_o_adjacency_list_files.clone();
         let o_ps_adjacency_list_files = self.files.o_ps_adjacency_list_files.clone();
         let predicate_wavelet_tree_files = self.files.predicate_wavelet_tree_files.clone();
-        build_indexes(
+        build_indexes_with_progress(
             s_p_adjacency_list_files,
             sp_o_adjacency_list_files,
             o_ps_adjacency_list_files,
             None,
             predicate_wavelet_tree_files,
+            progress,
         )
         .await?;
 
-        chrono_log!("finalized base builder");
+        tracing::debug!("finalized base builder");
 
         Ok(())
     }
@@ -0,0 +1,322 @@
+//! Bulk loading of base layers that don't fit in memory.
+//!
+//! Triples are read in runs of a bounded size. Each run is sorted and
+//! written to a temporary directory as a base layer without indexes.
+//! The runs are then merged into the output layer using the same
+//! external merge as `merge_base_layers`, which also builds the
+//! indexes. Quads are loaded the same way, with each run also
+//! getting a graph index. Bulk loaded layers get no text index.
+use std::{io, path::Path};
+
+use futures::{Stream, StreamExt};
+use tdb_succinct::TypedDictEntry;
+use tempfile::TempDir;
+
+use crate::{
+    layer::{
+        base_merge::merge_base_layers_with_progress, write_graph_index, BaseLayerFileBuilder,
+        IdQuadTriple, IdTriple, ValueQuadTriple,
+    },
+    storage::{
+        directory::DirectoryLayerStore, BaseLayerFiles, FileLoad, FileStore, PersistentLayerStore,
+        Progress,
+    },
+};
+
+use super::{ObjectType, ValueTriple};
+
+/// The default amount of triples that is sorted in memory before being written to disk as a run.
+pub const BULK_LOAD_RUN_SIZE: usize = 1_000_000;
+
+fn sorted_dedup<T: Ord>(mut v: Vec<T>) -> Vec<T> {
+    v.sort();
+    v.dedup();
+
+    v
+}
+
+fn position<T: Ord + ?Sized, U: std::borrow::Borrow<T>>(sorted: &[U], item: &T) -> u64 {
+    sorted
+        .binary_search_by(|e| e.borrow().cmp(item))
+        .expect("item should have been collected in the run dictionary") as u64
+        + 1
+}
+
+/// Write a single run of triples, each optionally in a graph, as a base layer without indexes.
+async fn write_run<F: 'static + FileLoad + FileStore + Clone>(
+    run: Vec<(ValueTriple, Option<String>)>,
+    files: BaseLayerFiles<F>,
+) -> io::Result<()> {
+    let mut nodes = Vec::with_capacity(run.len());
+    let mut predicates = Vec::with_capacity(run.len());
+    let mut values = Vec::new();
+    for (triple, graph) in run.iter() {
+        nodes.push(triple.subject.clone());
+        predicates.push(triple.predicate.clone());
+        match &triple.object {
+            ObjectType::Node(node) => nodes.push(node.clone()),
+            ObjectType::Value(value) => values.push(value.clone()),
+        }
+        if let Some(graph) = graph {
+            nodes.push(graph.clone());
+        }
+    }
+    let nodes = sorted_dedup(nodes);
+    let predicates = sorted_dedup(predicates);
+    let values: Vec<TypedDictEntry> = sorted_dedup(values);
+
+    let mut quads = Vec::new();
+    let mut triples: Vec<IdTriple> = run
+        .into_iter()
+        .map(|(triple, graph)| {
+            let subject = position(&nodes, triple.subject.as_str());
+            let predicate = position(&predicates, triple.predicate.as_str());
+            let object = match &triple.object {
+                ObjectType::Node(node) => position(&nodes, node.as_str()),
+                ObjectType::Value(value) => nodes.len() as u64 + position(&values, value),
+            };
+            let triple = IdTriple::new(subject, predicate, object);
+            if let Some(graph) = graph {
+                quads.push(IdQuadTriple::new(position(&nodes, graph.as_str()), triple));
+            }
+
+            triple
+        })
+        .collect();
+    triples.sort();
+    triples.dedup();
+
+    let mut builder = BaseLayerFileBuilder::from_files(&files).await?;
+    builder.add_nodes(nodes);
+    builder.add_predicates(predicates);
+    builder.add_values(values);
+    let mut builder = builder.into_phase2().await?;
+    builder.add_id_triples(triples).await?;
+    builder.partial_finalize().await?;
+    if !quads.is_empty() {
+        write_graph_index(&files.quads_file, quads).await?;
+    }
+
+    Ok(())
+}
+
+/// Load the given triples into the output files as a base layer, reporting to `progress`.
+///
+/// At most `run_size` triples are kept in memory at any time. All
+/// intermediate files are kept in temporary directories inside
+/// `temp_path`, which are removed when the load completes, fails or
+/// is cancelled.
+pub async fn bulk_load_base_layer_with_progress<
+    F: 'static + FileLoad + FileStore + Clone,
+    S: Stream<Item = ValueTriple> + Send,
+    P: AsRef<Path>,
+>(
+    triples: S,
+    output: BaseLayerFiles<F>,
+    temp_path: P,
+    run_size: usize,
+    progress: &Progress,
+) -> io::Result<()> {
+    bulk_load(
+        triples.map(|triple| (triple, None)),
+        output,
+        temp_path,
+        run_size,
+        progress,
+    )
+    .await
+}
+
+/// Load the given quads into the output files as a base layer, reporting to `progress`.
+///
+/// Like `bulk_load_base_layer_with_progress`, but also puts every
+/// triple in the graph of its quad.
+pub async fn bulk_load_base_layer_quads_with_progress<
+    F: 'static + FileLoad + FileStore + Clone,
+    S: Stream<Item = ValueQuadTriple> + Send,
+    P: AsRef<Path>,
+>(
+    quads: S,
+    output: BaseLayerFiles<F>,
+    temp_path: P,
+    run_size: usize,
+    progress: &Progress,
+) -> io::Result<()> {
+    bulk_load(
+        quads.map(|quad| (quad.triple, Some(quad.graph))),
+        output,
+        temp_path,
+        run_size,
+        progress,
+    )
+    .await
+}
+
+#[tracing::instrument(
+    level = "debug",
+    skip_all,
+    fields(runs = tracing::field::Empty, triples = tracing::field::Empty)
+)]
+async fn bulk_load<
+    F: 'static + FileLoad + FileStore + Clone,
+    S: Stream<Item = (ValueTriple, Option<String>)> + Send,
+    P: AsRef<Path>,
+>(
+    triples: S,
+    output: BaseLayerFiles<F>,
+    temp_path: P,
+    run_size: usize,
+    progress: &Progress,
+) -> io::Result<()> {
+    assert!(run_size > 0, "run size should be larger than 0");
+    let temp_dir = TempDir::new_in(&temp_path)?;
+    let run_store = DirectoryLayerStore::new(temp_dir.path());
+    let mut run_files = Vec::new();
+    let mut tally: u64 = 0;
+
+    let mut chunks = Box::pin(triples.chunks(run_size));
+    while let Some(run) = chunks.next().await {
+        progress.check()?;
+        tally += run.len() as u64;
+        let run_name = run_store.create_directory().await?;
+        let files = run_store.base_layer_files(run_name).await?;
+        write_run(run, files.clone()).await?;
+        run_files.push(files);
+        tracing::debug!(runs = run_files.len(), triples = tally, "wrote run");
+    }
+    tracing::Span::current().record("runs", run_files.len());
+    tracing::Span::current().record("triples", tally);
+
+    if run_files.is_empty() {
+        // nothing to merge, so just write an empty layer
+        let builder = BaseLayerFileBuilder::from_files(&output).await?;
+        progress.dictionaries_merged(3)?;
+        builder
+            .into_phase2()
+            .await?
+            .finalize_with_progress(progress)
+            .await?;
+        progress.done();
+
+        return Ok(());
+    }
+
+    merge_base_layers_with_progress(&run_files, output, temp_path, progress).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::{BaseLayer, Layer};
+    use crate::storage::memory::base_layer_memory_files;
+
+    fn example_triples() -> Vec<ValueTriple> {
+        vec![
+            ValueTriple::new_node("cow", "likes", "duck"),
+            ValueTriple::new_node("duck", "likes", "cow"),
+            ValueTriple::new_string_value("cow", "says", "moo"),
+            ValueTriple::new_string_value("duck", "says", "quack"),
+            ValueTriple::new_node("pig", "likes", "cow"),
+            ValueTriple::new_string_value("pig", "says", "oink"),
+            ValueTriple::new_node("cow", "likes", "duck"),
+        ]
+    }
+
+    #[tokio::test]
+    async fn bulk_load_in_multiple_runs() {
+        let temp = tempfile::tempdir().unwrap();
+        let files = base_layer_memory_files();
+        bulk_load_base_layer_with_progress(
+            futures::stream::iter(example_triples()),
+            files.clone(),
+            temp.path(),
+            2,
+            &Progress::default(),
+        )
+        .await
+        .unwrap();
+
+        let layer = BaseLayer::load_from_files([1, 2, 3, 4, 5], &files)
+            .await
+            .unwrap();
+
+        let mut expected: Vec<_> = example_triples();
+        expected.sort();
+        expected.dedup();
+        let mut result: Vec<_> = layer
+            .triples()
+            .map(|t| layer.id_triple_to_string(&t).unwrap())
+            .collect();
+        result.sort();
+
+        assert_eq!(expected, result);
+        assert_eq!(3, layer.subject_id("pig").unwrap());
+        assert_eq!(0, std::fs::read_dir(temp.path()).unwrap().count());
+    }
+
+    #[tokio::test]
+    async fn bulk_load_quads_in_multiple_runs() {
+        let temp = tempfile::tempdir().unwrap();
+        let files = base_layer_memory_files();
+        let quads: Vec<_> = example_triples()
+            .into_iter()
+            .enumerate()
+            .map(|(i, triple)| {
+                let graph = if i % 2 == 0 { "even" } else { "odd" };
+                ValueQuadTriple::new(graph, triple)
+            })
+            .collect();
+        bulk_load_base_layer_quads_with_progress(
+            futures::stream::iter(quads.clone()),
+            files.clone(),
+            temp.path(),
+            2,
+            &Progress::default(),
+        )
+        .await
+        .unwrap();
+
+        let layer = BaseLayer::load_from_files([1, 2, 3, 4, 5], &files)
+            .await
+            .unwrap();
+
+        for graph in ["even", "odd"] {
+            let mut expected: Vec<_> = quads
+                .iter()
+                .filter(|quad| quad.graph == graph)
+                .map(|quad| quad.triple.clone())
+                .collect();
+            expected.sort();
+            expected.dedup();
+            let mut result: Vec<_> = layer
+                .quads_g(layer.graph_id(graph).unwrap())
+                .map(|quad| layer.id_triple_to_string(&quad.triple).unwrap())
+                .collect();
+            result.sort();
+
+            assert_eq!(expected, result);
+        }
+        assert_eq!(0, std::fs::read_dir(temp.path()).unwrap().count());
+    }
+
+    #[tokio::test]
+    async fn bulk_load_empty_stream() {
+        let temp = tempfile::tempdir().unwrap();
+        let files = base_layer_memory_files();
+        bulk_load_base_layer_with_progress(
+            futures::stream::empty(),
+            files.clone(),
+            temp.path(),
+            2,
+            &Progress::default(),
+        )
+        .await
+        .unwrap();
+
+        let layer = BaseLayer::load_from_files([1, 2, 3, 4, 5], &files)
+            .await
+            .unwrap();
+
+        assert_eq!(0, layer.triples().count());
+    }
+}
@@ -6,13 +6,17 @@ use std::{
 use futures::{Stream, StreamExt, TryStreamExt};
 use tempfile::TempDir;
 use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+use tracing::Instrument;
 
 use crate::{
-    chrono_log,
-    layer::{builder::build_indexes, open_base_triple_stream, BaseLayerFileBuilderPhase2},
+    layer::{
+        builder::build_indexes_with_progress, open_base_triple_stream, write_sorted_graph_index,
+        write_text_index_external, BaseLayerFileBuilderPhase2, GraphIndex, IdQuadTriple, IdTriple,
+        TEXT_INDEX_RUN_SIZE,
+    },
     storage::{
         directory::DirectoryLayerStore, AdjacencyListFiles, BaseLayerFiles, DictionaryFiles,
-        FileLoad, FileStore, PersistentLayerStore, TypedDictionaryFiles,
+        FileLoad, FileStore, PersistentLayerStore, Progress, TypedDictionaryFiles,
     },
 };
 use tdb_succinct::{
@@ -28,7 +32,7 @@ fn try_enumerate<T, E, S: Stream<Item = Result<T, E>> + Send>(
     s.enumerate().map(|(ix, r)| r.map(|v| (ix, v)))
 }
 
-async fn dicts_to_map<
+pub(crate) async fn dicts_to_map<
     F1: FileLoad + FileStore + 'static,
     F2: FileLoad + FileStore + 'static,
     I: ExactSizeIterator<Item = DictionaryFiles<F1>>,
@@ -50,7 +54,7 @@ async fn dicts_to_map<
     Ok(map_result)
 }
 
-async fn typed_dicts_to_map<
+pub(crate) async fn typed_dicts_to_map<
     F1: FileLoad + FileStore + 'static,
     F2: FileLoad + FileStore + 'static,
     I: ExactSizeIterator<Item = TypedDictionaryFiles<F1>>,
@@ -76,6 +80,14 @@ async fn typed_dicts_to_map<
     Ok(map_result)
 }
 
+fn map_node_value(id: u64, node_map: &[usize], value_map: &[usize], num_nodes: usize) -> u64 {
+    if (id as usize - 1) < node_map.len() {
+        node_map[id as usize - 1] as u64 + 1
+    } else {
+        value_map[id as usize - 1 - node_map.len()] as u64 + num_nodes as u64 + 1
+    }
+}
+
 fn map_triple(
     triple: (u64, u64, u64),
     node_map: &[usize],
@@ -85,12 +97,7 @@ fn map_triple(
 ) -> (u64, u64, u64) {
     let s = node_map[triple.0 as usize - 1] as u64 + 1;
     let p = predicate_map[triple.1 as usize - 1] as u64 + 1;
-
-    let o = if (triple.2 as usize - 1) < node_map.len() {
-        node_map[triple.2 as usize - 1] as u64 + 1
-    } else {
-        value_map[triple.2 as usize - 1 - node_map.len()] as u64 + num_nodes as u64 + 1
-    };
+    let o = map_node_value(triple.2, node_map, value_map, num_nodes);
 
     (s, p, o)
 }
@@ -109,7 +116,7 @@ async fn test_write_file<F: FileLoad + FileStore + 'static, P: Into<PathBuf>>(
 
     tokio::io::copy(&mut input_file, &mut output_file).await?;
     output_file.flush().await?;
-    chrono_log!("wrote {to:?}");
+    tracing::debug!(path = ?to, "wrote file");
     Ok(())
 }
 
@@ -118,7 +125,7 @@ async fn test_write_dict<F: FileLoad + FileStore + 'static>(
     prefix: &str,
     files: &DictionaryFiles<F>,
 ) -> io::Result<()> {
-    chrono_log!("writing {prefix} files");
+    tracing::debug!(prefix, "writing files");
     let blocks_path = format!("{prefix}.blocks");
     let offsets_path = format!("{prefix}.offsets");
 
@@ -133,7 +140,7 @@ async fn test_write_typed_dict<F: FileLoad + FileStore + 'static>(
     prefix: &str,
     files: &TypedDictionaryFiles<F>,
 ) -> io::Result<()> {
-    chrono_log!("writing {prefix} files");
+    tracing::debug!(prefix, "writing files");
     let types_present_path = format!("{prefix}.types_present");
     let type_offsets_path = format!("{prefix}.type_offsets");
     let blocks_path = format!("{prefix}.blocks");
@@ -170,7 +177,32 @@ pub async fn merge_base_layers<F: FileLoad + FileStore + 'static, P: AsRef<Path>
     output: BaseLayerFiles<F>,
     temp_path: P,
 ) -> io::Result<()> {
-    chrono_log!("started merge of base layers");
+    merge_base_layers_with_progress(inputs, output, temp_path, &Progress::default()).await
+}
+
+/// Merge the given base layers into the output files, reporting to `progress`.
+///
+/// All intermediate files are kept in a temporary directory inside
+/// `temp_path`, which is removed when the merge completes or is
+/// cancelled. The output files are only written once the merge has
+/// fully completed. The output gets a text index if any of the
+/// inputs has one.
+#[tracing::instrument(
+    level = "debug",
+    skip_all,
+    fields(inputs = inputs.len(), triples = tracing::field::Empty)
+)]
+pub async fn merge_base_layers_with_progress<
+    F: FileLoad + FileStore + 'static,
+    F2: FileLoad + FileStore + 'static,
+    P: AsRef<Path>,
+>(
+    inputs: &[BaseLayerFiles<F>],
+    output: BaseLayerFiles<F2>,
+    temp_path: P,
+    progress: &Progress,
+) -> io::Result<()> {
+    tracing::debug!("started merge of base layers");
 
     // we are going to assume that this is a big expensive merge. all
     // files will be constructed on disk first and only after being
@@ -192,25 +224,37 @@ pub async fn merge_base_layers<F: FileLoad + FileStore + 'static, P: AsRef<Path>
         .iter()
         .map(|i| i.value_dictionary_files.clone())
         .collect();
-    let node_map_task = tokio::spawn(dicts_to_map(
-        node_dicts.into_iter(),
-        temp_output_files.node_dictionary_files.clone(),
-    ));
-    let predicate_map_task = tokio::spawn(dicts_to_map(
-        predicate_dicts.into_iter(),
-        temp_output_files.predicate_dictionary_files.clone(),
-    ));
-    let value_map_task = tokio::spawn(typed_dicts_to_map(
-        value_dicts.into_iter(),
-        temp_output_files.value_dictionary_files.clone(),
-    ));
+    let node_map_task = tokio::spawn(
+        dicts_to_map(
+            node_dicts.into_iter(),
+            temp_output_files.node_dictionary_files.clone(),
+        )
+        .in_current_span(),
+    );
+    let predicate_map_task = tokio::spawn(
+        dicts_to_map(
+            predicate_dicts.into_iter(),
+            temp_output_files.predicate_dictionary_files.clone(),
+        )
+        .in_current_span(),
+    );
+    let value_map_task = tokio::spawn(
+        typed_dicts_to_map(
+            value_dicts.into_iter(),
+            temp_output_files.value_dictionary_files.clone(),
+        )
+        .in_current_span(),
+    );
 
     let (node_map, node_count) = node_map_task.await??;
-    chrono_log!(" merged node dicts");
+    tracing::debug!(nodes = node_count, "merged node dicts");
+    progress.dictionaries_merged(1)?;
     let (predicate_map, predicate_count) = predicate_map_task.await??;
-    chrono_log!(" merged predicate dicts");
+    tracing::debug!(predicates = predicate_count, "merged predicate dicts");
+    progress.dictionaries_merged(1)?;
     let (value_map, value_count) = value_map_task.await??;
-    chrono_log!("merged value dicts");
+    tracing::debug!(values = value_count, "merged value dicts");
+    progress.dictionaries_merged(1)?;
     //test_write_dict("/tmp/node_dict", &temp_output_files.node_dictionary_files).await?;
     //test_write_dict(
     //    "/tmp/predicate_dict",
@@ -250,7 +294,7 @@ pub async fn merge_base_layers<F: FileLoad + FileStore + 'static, P: AsRef<Path>
         value_count,
     )
     .await?;
-    chrono_log!("constructed phase 2 builder");
+    tracing::trace!("constructed phase 2 builder");
 
     let mut last_triple = None;
     let mut tally: u64 = 0;
@@ -261,15 +305,18 @@ pub async fn merge_base_layers<F: FileLoad + FileStore + 'static, P: AsRef<Path>
 
         last_triple = Some(triple);
         builder.add_triple(triple.0, triple.1, triple.2).await?;
+        progress.triple_written()?;
         tally += 1;
         if tally % 1000000 == 0 {
-            chrono_log!("wrote {tally} triples");
+            tracing::debug!(triples = tally, "wrote triples");
         }
     }
-    chrono_log!("added all merged triples");
+    tracing::debug!(triples = tally, "added all merged triples");
+    tracing::Span::current().record("triples", tally);
 
     let files = builder.partial_finalize().await?;
-    chrono_log!("finalized triple map");
+    tracing::trace!("finalized triple map");
+    progress.triples_done()?;
     //test_write_adjacency_list_files("/tmp/triples_s_p", &files.s_p_adjacency_list_files).await?;
     //test_write_adjacency_list_files("/tmp/triples_sp_o", &files.sp_o_adjacency_list_files).await?;
 
@@ -277,18 +324,73 @@ pub async fn merge_base_layers<F: FileLoad + FileStore + 'static, P: AsRef<Path>
     let sp_o_adjacency_list_files = files.sp_o_adjacency_list_files.clone();
     let o_ps_adjacency_list_files = files.o_ps_adjacency_list_files.clone();
     let predicate_wavelet_tree_files = files.predicate_wavelet_tree_files.clone();
-    build_indexes(
+    build_indexes_with_progress(
         s_p_adjacency_list_files,
         sp_o_adjacency_list_files,
         o_ps_adjacency_list_files,
         None,
         predicate_wavelet_tree_files,
+        progress,
     )
     .await?;
-    chrono_log!(" built indexes");
+    tracing::debug!("built indexes");
+
+    // the id maps keep the order of the ids, so the remapped quads of
+    // every input are still sorted and can be merged as streams
+    let mut quads = Vec::new();
+    let mut max_quads = 0;
+    for (ix, input) in inputs.iter().enumerate() {
+        if let Some(map) = input.quads_file.map_if_exists().await? {
+            let index = GraphIndex::parse(map)?;
+            max_quads += index.len() as u64;
+            let (node_map, predicate_map, value_map) =
+                (&node_map[ix], &predicate_map[ix], &value_map[ix]);
+            quads.push(futures::stream::iter(index.quads().map(move |quad| {
+                let graph = map_node_value(quad.graph, node_map, value_map, node_count);
+                let (s, p, o) = map_triple(
+                    (
+                        quad.triple.subject,
+                        quad.triple.predicate,
+                        quad.triple.object,
+                    ),
+                    node_map,
+                    predicate_map,
+                    value_map,
+                    node_count,
+                );
+                Ok(IdQuadTriple::new(graph, IdTriple::new(s, p, o)))
+            })));
+        }
+    }
+    if !quads.is_empty() {
+        write_sorted_graph_index(
+            &temp_output_files.quads_file,
+            quads,
+            (node_count + value_count) as u64,
+            predicate_count as u64,
+            max_quads,
+            temp_dir.path(),
+        )
+        .await?;
+    }
+
+    let mut text_index = false;
+    for input in inputs.iter() {
+        text_index |= input.text_index_file.exists().await?;
+    }
+    if text_index {
+        write_text_index_external(
+            &temp_output_files.value_dictionary_files,
+            &temp_output_files.text_index_file,
+            temp_dir.path(),
+            TEXT_INDEX_RUN_SIZE,
+        )
+        .await?;
+    }
 
     // now that everything has been constructed on disk, copy over to the actual layer store
     output.copy_from(&temp_output_files).await?;
+    progress.done();
 
     Ok(())
 }
@@ -49,6 +49,13 @@ pub struct ChildLayer {
 
     pub(super) pos_predicate_wavelet_tree: WaveletTree,
     pub(super) neg_predicate_wavelet_tree: WaveletTree,
+
+    pub(super) pos_quads: Option<GraphIndex>,
+    pub(super) neg_quads: Option<GraphIndex>,
+
+    pub(super) text_index: Option<TextIndex>,
+
+    pub(super) mapped_size: usize,
 }
 
 impl ChildLayer {
@@ -62,6 +69,7 @@ impl ChildLayer {
     }
 
     pub fn load(name: [u32; 5], parent: Arc<InternalLayer>, maps: ChildLayerMaps) -> InternalLayer {
+        let mapped_size = maps.byte_size();
         let node_dictionary = StringDict::parse(
             maps.node_dictionary_maps.offsets_map,
             maps.node_dictionary_maps.blocks_map,
@@ -164,6 +172,16 @@ impl ChildLayer {
             neg_predicate_wavelet_tree_width,
         );
 
+        let pos_quads = maps
+            .pos_quads_map
+            .map(|quads_map| GraphIndex::parse(quads_map).unwrap());
+        let neg_quads = maps
+            .neg_quads_map
+            .map(|quads_map| GraphIndex::parse(quads_map).unwrap());
+        let text_index = maps
+            .text_index_map
+            .map(|text_index_map| TextIndex::parse(text_index_map).unwrap());
+
         InternalLayer::Child(ChildLayer {
             name,
             parent,
@@ -193,6 +211,13 @@ impl ChildLayer {
 
             pos_predicate_wavelet_tree,
             neg_predicate_wavelet_tree,
+
+            pos_quads,
+            neg_quads,
+
+            text_index,
+
+            mapped_size,
         })
     }
 }
@@ -491,6 +516,19 @@ impl<F: 'static + FileLoad + FileStore + Clone + Send + Sync> ChildLayerFileBuil
     /// This will panic if a greater triple has already been added,
     /// and do nothing if the parent already contains this triple.
     pub async fn add_id_triples(&mut self, triples: Vec<IdTriple>) -> io::Result<()> {
+        self.add_id_triples_with_progress(triples, &Progress::default())
+            .await
+    }
+
+    /// Add the given triples, reporting every written triple to `progress`.
+    ///
+    /// This will panic if a greater triple has already been added,
+    /// and do nothing if the parent already contains this triple.
+    pub async fn add_id_triples_with_progress(
+        &mut self,
+        triples: Vec<IdTriple>,
+        progress: &Progress,
+    ) -> io::Result<()> {
         let parent = self.parent.clone();
         let filtered: Vec<_> = triples
             .into_par_iter()
@@ -502,6 +540,7 @@ impl<F: 'static + FileLoad + FileStore + Clone + Send + Sync> ChildLayerFileBuil
         for triple in filtered {
             self.add_triple_unchecked(triple.subject, triple.predicate, triple.object)
                 .await?;
+            progress.triple_written()?;
         }
 
         Ok(())
@@ -512,6 +551,19 @@ impl<F: 'static + FileLoad + FileStore + Clone + Send + Sync> ChildLayerFileBuil
     /// This will panic if a greater triple has already been removed,
     /// and do nothing if the parent doesn't know aobut this triple.
     pub async fn remove_id_triples(&mut self, triples: Vec<IdTriple>) -> io::Result<()> {
+        self.remove_id_triples_with_progress(triples, &Progress::default())
+            .await
+    }
+
+    /// Remove the given triples, reporting every written removal to `progress`.
+    ///
+    /// This will panic if a greater triple has already been removed,
+    /// and do nothing if the parent doesn't know aobut this triple.
+    pub async fn remove_id_triples_with_progress(
+        &mut self,
+        triples: Vec<IdTriple>,
+        progress: &Progress,
+    ) -> io::Result<()> {
         let parent = self.parent.clone();
         let filtered: Vec<_> = triples
             .into_par_iter()
@@ -523,6 +575,7 @@ impl<F: 'static + FileLoad + FileStore + Clone + Send + Sync> ChildLayerFileBuil
         for triple in filtered {
             self.remove_triple_unchecked(triple.subject, triple.predicate, triple.object)
                 .await?;
+            progress.triple_written()?;
         }
 
         Ok(())
@@ -530,26 +583,58 @@ impl<F: 'static + FileLoad + FileStore + Clone + Send + Sync> ChildLayerFileBuil
 
     /// Write the layer data to storage.
     pub async fn finalize(self) -> io::Result<()> {
+        self.finalize_with_progress(&Progress::default()).await
+    }
+
+    /// Write the layer data to storage, reporting the built indexes to `progress`.
+    pub async fn finalize_with_progress(self, progress: &Progress) -> io::Result<()> {
         let pos_task = tokio::spawn(self.pos_builder.finalize());
         let neg_task = tokio::spawn(self.neg_builder.finalize());
 
         pos_task.await??;
         neg_task.await??;
+        progress.triples_done()?;
 
-        let pos_indexes_task = tokio::spawn(build_indexes(
+        let pos_progress = progress.clone();
+        let pos_files = (
             self.files.pos_s_p_adjacency_list_files,
             self.files.pos_sp_o_adjacency_list_files,
             self.files.pos_o_ps_adjacency_list_files,
-            Some(self.files.pos_objects_file),
+            self.files.pos_objects_file,
             self.files.pos_predicate_wavelet_tree_files,
-        ));
-        let neg_indexes_task = tokio::spawn(build_indexes(
+        );
+        let pos_indexes_task = tokio::spawn(async move {
+            let (s_p_files, sp_o_files, o_ps_files, objects_file, wavelet_files) = pos_files;
+            build_indexes_with_progress(
+                s_p_files,
+                sp_o_files,
+                o_ps_files,
+                Some(objects_file),
+                wavelet_files,
+                &pos_progress,
+            )
+            .await
+        });
+        let neg_progress = progress.clone();
+        let neg_files = (
             self.files.neg_s_p_adjacency_list_files,
             self.files.neg_sp_o_adjacency_list_files,
             self.files.neg_o_ps_adjacency_list_files,
-            Some(self.files.neg_objects_file),
+            self.files.neg_objects_file,
             self.files.neg_predicate_wavelet_tree_files,
-        ));
+        );
+        let neg_indexes_task = tokio::spawn(async move {
+            let (s_p_files, sp_o_files, o_ps_files, objects_file, wavelet_files) = neg_files;
+            build_indexes_with_progress(
+                s_p_files,
+                sp_o_files,
+                o_ps_files,
+                Some(objects_file),
+                wavelet_files,
+                &neg_progress,
+            )
+            .await
+        });
 
         pos_indexes_task.await??;
         neg_indexes_task.await??;
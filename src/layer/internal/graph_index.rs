@@ -0,0 +1,765 @@
+//! Graph indexes, recording which graph a layer put its triples in.
+//!
+//! A graph index is a graph-indexed adjacency list. It consists of
+//! the sorted list of graph ids, for each graph the offset just past
+//! its last triple, and the subject, predicate and object columns of
+//! the triples, sorted by graph and then by triple. Retrieving the
+//! triples of a single graph is therefore a binary search followed by
+//! a slice.
+//!
+//! The index is stored in a single file as five header-first
+//! logarrays. Layers without quads do not have this file at all.
+//!
+//! Removing a triple also removes it from every graph it was in, so
+//! the graphs of a stack can be worked out from the graph indexes
+//! alone. Rollups, squashes, merges of base layers and bulk loads all
+//! keep the graphs of their input. Merges, bulk loads and external
+//! squashes write the index out of sorted runs of quads, so they never
+//! hold all quads in memory.
+use std::collections::BTreeSet;
+use std::convert::TryInto;
+use std::io;
+use std::path::Path;
+
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, TryStreamExt};
+use tdb_succinct::util::heap_sorted_stream;
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+
+use super::super::layer::*;
+use crate::storage::directory::DirectoryLayerStore;
+use crate::storage::*;
+use tdb_succinct::*;
+
+/// A graph-indexed list of triples.
+#[derive(Clone)]
+pub struct GraphIndex {
+    graphs: MonotonicLogArray,
+    offsets: MonotonicLogArray,
+    subjects: LogArray,
+    predicates: LogArray,
+    objects: LogArray,
+}
+
+impl GraphIndex {
+    /// Parse a graph index from the contents of its file.
+    pub fn parse(bytes: Bytes) -> io::Result<Self> {
+        let (graphs, rest) = MonotonicLogArray::parse_header_first(bytes)?;
+        let (offsets, rest) = MonotonicLogArray::parse_header_first(rest)?;
+        let (subjects, rest) = LogArray::parse_header_first(rest)?;
+        let (predicates, rest) = LogArray::parse_header_first(rest)?;
+        let (objects, _) = LogArray::parse_header_first(rest)?;
+
+        let len = subjects.len();
+        if offsets.len() != graphs.len()
+            || predicates.len() != len
+            || objects.len() != len
+            || (!offsets.is_empty() && offsets.entry(offsets.len() - 1) != len as u64)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "graph index structures do not match up",
+            ));
+        }
+
+        Ok(Self {
+            graphs,
+            offsets,
+            subjects,
+            predicates,
+            objects,
+        })
+    }
+
+    /// Serialize the given quads into the contents of a graph index file.
+    pub fn build<I: IntoIterator<Item = IdQuadTriple>>(quads: I) -> Bytes {
+        let quads: BTreeSet<IdQuadTriple> = quads.into_iter().collect();
+
+        let mut graphs = LateLogArrayBufBuilder::new(BytesMut::new());
+        let mut offsets = LateLogArrayBufBuilder::new(BytesMut::new());
+        let mut subjects = LateLogArrayBufBuilder::new(BytesMut::new());
+        let mut predicates = LateLogArrayBufBuilder::new(BytesMut::new());
+        let mut objects = LateLogArrayBufBuilder::new(BytesMut::new());
+
+        for (index, quad) in quads.iter().enumerate() {
+            if graphs.last() != Some(quad.graph) {
+                if index != 0 {
+                    offsets.push(index as u64);
+                }
+                graphs.push(quad.graph);
+            }
+            subjects.push(quad.triple.subject);
+            predicates.push(quad.triple.predicate);
+            objects.push(quad.triple.object);
+        }
+        if !quads.is_empty() {
+            offsets.push(quads.len() as u64);
+        }
+
+        let mut result = BytesMut::new();
+        for builder in [graphs, offsets, subjects, predicates, objects] {
+            result.extend(builder.finalize_header_first());
+        }
+
+        result.freeze()
+    }
+
+    /// Returns the amount of quads in this index.
+    pub fn len(&self) -> usize {
+        self.subjects.len()
+    }
+
+    /// Returns true if this index contains no quads.
+    pub fn is_empty(&self) -> bool {
+        self.subjects.is_empty()
+    }
+
+    /// Iterator over the ids of all graphs in this index.
+    pub fn graphs(&self) -> impl Iterator<Item = u64> + Send {
+        self.graphs.iter()
+    }
+
+    fn graph_range(&self, graph: u64) -> (usize, usize) {
+        match self.graphs.index_of(graph) {
+            None => (0, 0),
+            Some(index) => {
+                let start = if index == 0 {
+                    0
+                } else {
+                    self.offsets.entry(index - 1) as usize
+                };
+                (start, self.offsets.entry(index) as usize)
+            }
+        }
+    }
+
+    fn triple_at(&self, index: usize) -> IdTriple {
+        IdTriple::new(
+            self.subjects.entry(index),
+            self.predicates.entry(index),
+            self.objects.entry(index),
+        )
+    }
+
+    /// Iterator over all triples in the given graph, in sorted order.
+    pub fn triples_g(&self, graph: u64) -> impl Iterator<Item = IdTriple> + Send {
+        let (start, end) = self.graph_range(graph);
+        let index = self.clone();
+        (start..end).map(move |i| index.triple_at(i))
+    }
+
+    /// Iterator over all quads in this index, ordered by graph and then by triple.
+    pub fn quads(&self) -> impl Iterator<Item = IdQuadTriple> + Send {
+        let index = self.clone();
+        (0..self.graphs.len()).flat_map(move |i| {
+            let graph = index.graphs.entry(i);
+            index
+                .triples_g(graph)
+                .map(move |triple| IdQuadTriple::new(graph, triple))
+        })
+    }
+
+    /// Returns true if the given triple is in the given graph.
+    pub fn contains(&self, graph: u64, triple: IdTriple) -> bool {
+        let (mut start, mut end) = self.graph_range(graph);
+        while start < end {
+            let mid = (start + end) / 2;
+            match self.triple_at(mid).cmp(&triple) {
+                std::cmp::Ordering::Equal => return true,
+                std::cmp::Ordering::Less => start = mid + 1,
+                std::cmp::Ordering::Greater => end = mid,
+            }
+        }
+
+        false
+    }
+}
+
+type Changes<T> = std::iter::Peekable<Box<dyn Iterator<Item = T> + Send>>;
+
+/// Lazily merges the sorted additions and removals of every layer of a stack.
+///
+/// For every item, the change in the topmost layer decides whether
+/// it is there.
+struct StackChanges<T> {
+    positives: Vec<Changes<T>>,
+    negatives: Vec<Changes<T>>,
+}
+
+impl<T: 'static + Copy + Ord + Send> StackChanges<T> {
+    fn new<'a, I: IntoIterator<Item = (Option<&'a GraphIndex>, Option<&'a GraphIndex>)>>(
+        indexes: I,
+        changes: impl Fn(&GraphIndex) -> Box<dyn Iterator<Item = T> + Send>,
+    ) -> Self {
+        let changes = |index: Option<&GraphIndex>| -> Changes<T> {
+            match index {
+                Some(index) => changes(index),
+                None => Box::new(std::iter::empty()),
+            }
+            .peekable()
+        };
+        let mut positives = Vec::new();
+        let mut negatives = Vec::new();
+        for (pos, neg) in indexes {
+            if pos.is_none() && neg.is_none() {
+                continue;
+            }
+            positives.push(changes(pos));
+            negatives.push(changes(neg));
+        }
+
+        Self {
+            positives,
+            negatives,
+        }
+    }
+
+    fn next(&mut self) -> Option<T> {
+        loop {
+            let lowest = self
+                .positives
+                .iter_mut()
+                .chain(self.negatives.iter_mut())
+                .filter_map(|t| t.peek().copied())
+                .min()?;
+
+            // the topmost layer that changed this item decides, and
+            // the changes of all layers are skipped past
+            let mut result = None;
+            for (pos, neg) in self.positives.iter_mut().zip(self.negatives.iter_mut()) {
+                let added = pos.next_if_eq(&lowest).is_some();
+                let removed = neg.next_if_eq(&lowest).is_some();
+                if result.is_none() && (added || removed) {
+                    result = Some(added);
+                }
+            }
+
+            if result == Some(true) {
+                return Some(lowest);
+            }
+        }
+    }
+}
+
+/// Iterator over the triples in a graph across a layer stack.
+///
+/// The sorted graph additions and removals of every layer are
+/// merged lazily. For every triple, the change in the topmost layer
+/// decides whether it is in the graph.
+pub struct GraphStackIterator(StackChanges<IdTriple>);
+
+impl GraphStackIterator {
+    /// Construct an iterator over the given graph from the graph indexes of a stack, topmost layer first.
+    pub fn new<'a, I: IntoIterator<Item = (Option<&'a GraphIndex>, Option<&'a GraphIndex>)>>(
+        indexes: I,
+        graph: u64,
+    ) -> Self {
+        Self(StackChanges::new(indexes, |index| {
+            Box::new(index.triples_g(graph))
+        }))
+    }
+}
+
+impl Iterator for GraphStackIterator {
+    type Item = IdTriple;
+
+    fn next(&mut self) -> Option<IdTriple> {
+        self.0.next()
+    }
+}
+
+/// Iterator over all quads across a layer stack, ordered by graph and then by triple.
+///
+/// Like `GraphStackIterator`, but for all graphs at once.
+pub struct QuadStackIterator(StackChanges<IdQuadTriple>);
+
+impl QuadStackIterator {
+    /// Construct an iterator from the graph indexes of a stack, topmost layer first.
+    pub fn new<'a, I: IntoIterator<Item = (Option<&'a GraphIndex>, Option<&'a GraphIndex>)>>(
+        indexes: I,
+    ) -> Self {
+        Self(StackChanges::new(indexes, |index| Box::new(index.quads())))
+    }
+}
+
+impl Iterator for QuadStackIterator {
+    type Item = IdQuadTriple;
+
+    fn next(&mut self) -> Option<IdQuadTriple> {
+        self.0.next()
+    }
+}
+
+/// Write a graph index with the given quads to the given file.
+pub async fn write_graph_index<F: FileStore, I: IntoIterator<Item = IdQuadTriple>>(
+    file: &F,
+    quads: I,
+) -> io::Result<()> {
+    let bytes = GraphIndex::build(quads);
+    let mut writer = file.open_write().await?;
+    writer.write_all(&bytes).await?;
+    writer.flush().await?;
+    writer.sync_all().await?;
+
+    Ok(())
+}
+
+/// Writes a graph index out of quads that are added in sorted order.
+///
+/// The five logarrays are written to separate section files first,
+/// so the quads are never kept in memory.
+pub struct GraphIndexFileBuilder<F: 'static + FileLoad + FileStore> {
+    sections: [F; 5],
+    graphs: LogArrayFileBuilder<F::Write>,
+    offsets: LogArrayFileBuilder<F::Write>,
+    subjects: LogArrayFileBuilder<F::Write>,
+    predicates: LogArrayFileBuilder<F::Write>,
+    objects: LogArrayFileBuilder<F::Write>,
+    count: u64,
+    last: Option<IdQuadTriple>,
+}
+
+impl<F: 'static + FileLoad + FileStore> GraphIndexFileBuilder<F> {
+    /// Construct a builder that writes its sections to the given files.
+    ///
+    /// The bounds only determine the width of the logarrays, so they
+    /// may be larger than what ends up being written.
+    pub async fn new(
+        sections: [F; 5],
+        max_node_value: u64,
+        max_predicate: u64,
+        max_quads: u64,
+    ) -> io::Result<Self> {
+        let node_value_width = util::calculate_width(max_node_value);
+        Ok(Self {
+            graphs: LogArrayFileBuilder::new(sections[0].open_write().await?, node_value_width),
+            offsets: LogArrayFileBuilder::new(
+                sections[1].open_write().await?,
+                util::calculate_width(max_quads),
+            ),
+            subjects: LogArrayFileBuilder::new(sections[2].open_write().await?, node_value_width),
+            predicates: LogArrayFileBuilder::new(
+                sections[3].open_write().await?,
+                util::calculate_width(max_predicate),
+            ),
+            objects: LogArrayFileBuilder::new(sections[4].open_write().await?, node_value_width),
+            sections,
+            count: 0,
+            last: None,
+        })
+    }
+
+    /// Add a quad, which should not sort before any quad added so far. Duplicates are skipped.
+    pub async fn push(&mut self, quad: IdQuadTriple) -> io::Result<()> {
+        if let Some(last) = self.last {
+            match quad.cmp(&last) {
+                std::cmp::Ordering::Equal => return Ok(()),
+                std::cmp::Ordering::Less => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "quads should be added to a graph index in sorted order",
+                    ))
+                }
+                std::cmp::Ordering::Greater => {}
+            }
+        }
+        if self.last.map(|last| last.graph) != Some(quad.graph) {
+            if self.count != 0 {
+                self.offsets.push(self.count).await?;
+            }
+            self.graphs.push(quad.graph).await?;
+        }
+        self.subjects.push(quad.triple.subject).await?;
+        self.predicates.push(quad.triple.predicate).await?;
+        self.objects.push(quad.triple.object).await?;
+        self.count += 1;
+        self.last = Some(quad);
+
+        Ok(())
+    }
+
+    /// Add all quads of the given sorted stream.
+    pub async fn push_all<S: Stream<Item = io::Result<IdQuadTriple>> + Unpin>(
+        &mut self,
+        mut quads: S,
+    ) -> io::Result<()> {
+        while let Some(quad) = quads.try_next().await? {
+            self.push(quad).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Finish the index and write it to the given file.
+    ///
+    /// Nothing is written if no quads were added, as layers without
+    /// quads don't have a graph index.
+    pub async fn finalize<G: FileStore>(mut self, file: &G) -> io::Result<()> {
+        if self.count != 0 {
+            self.offsets.push(self.count).await?;
+        }
+        self.graphs.finalize().await?;
+        self.offsets.finalize().await?;
+        self.subjects.finalize().await?;
+        self.predicates.finalize().await?;
+        self.objects.finalize().await?;
+        if self.count == 0 {
+            return Ok(());
+        }
+
+        // the sections end in their control word, which goes first in
+        // the graph index
+        let mut writer = file.open_write().await?;
+        for section in self.sections.iter() {
+            let size = section.size().await?;
+            let mut control_word = [0; 8];
+            section
+                .open_read_from(size - 8)
+                .await?
+                .read_exact(&mut control_word)
+                .await?;
+            writer.write_all(&control_word).await?;
+            let mut data = section.open_read().await?.take(size as u64 - 8);
+            tokio::io::copy(&mut data, &mut writer).await?;
+        }
+        writer.flush().await?;
+        writer.sync_all().await
+    }
+}
+
+/// Write a graph index of the quads of the given sorted streams to the given file, without keeping them in memory.
+///
+/// Quads that are in several streams are only written once. The
+/// sections of the index are written to a temporary directory inside
+/// `temp_dir` first. Nothing is written if there are no quads.
+pub async fn write_sorted_graph_index<
+    'a,
+    F: FileStore,
+    S: 'a + Stream<Item = io::Result<IdQuadTriple>> + Unpin + Send,
+>(
+    file: &F,
+    streams: Vec<S>,
+    max_node_value: u64,
+    max_predicate: u64,
+    max_quads: u64,
+    temp_dir: &Path,
+) -> io::Result<()> {
+    let temp_dir = TempDir::new_in(temp_dir)?;
+    let store = DirectoryLayerStore::new(temp_dir.path());
+    let dir = store.create_directory().await?;
+    let mut sections = Vec::with_capacity(5);
+    for name in ["graphs", "offsets", "subjects", "predicates", "objects"] {
+        sections.push(store.get_file(dir, name).await?);
+    }
+    let sections: [_; 5] = sections
+        .try_into()
+        .unwrap_or_else(|_| unreachable!("there are five sections"));
+
+    let mut builder =
+        GraphIndexFileBuilder::new(sections, max_node_value, max_predicate, max_quads).await?;
+    builder.push_all(heap_sorted_stream(streams).await?).await?;
+    builder.finalize(file).await
+}
+
+/// Write a graph index of the given quads to the given file, using a bounded amount of memory.
+///
+/// The quads don't need to be sorted. They are sorted in runs of at
+/// most `run_size` quads, which are written to a temporary directory
+/// inside `temp_dir` and then merged into the index. Nothing is
+/// written if there are no quads.
+pub async fn write_graph_index_external<F: FileStore, I: Iterator<Item = IdQuadTriple>>(
+    file: &F,
+    mut quads: I,
+    max_node_value: u64,
+    max_predicate: u64,
+    temp_dir: &Path,
+    run_size: usize,
+) -> io::Result<()> {
+    assert!(run_size > 0, "run size should be larger than 0");
+    let temp_dir = TempDir::new_in(temp_dir)?;
+    let store = DirectoryLayerStore::new(temp_dir.path());
+    let dir = store.create_directory().await?;
+    let mut runs = Vec::new();
+    let mut count = 0;
+    loop {
+        let mut run: Vec<IdQuadTriple> = quads.by_ref().take(run_size).collect();
+        if run.is_empty() {
+            break;
+        }
+        run.sort();
+        run.dedup();
+
+        let run_file = store.get_file(dir, &format!("run{}", runs.len())).await?;
+        let mut writer = run_file.open_write().await?;
+        for quad in run.iter() {
+            writer.write_u64(quad.graph).await?;
+            writer.write_u64(quad.triple.subject).await?;
+            writer.write_u64(quad.triple.predicate).await?;
+            writer.write_u64(quad.triple.object).await?;
+        }
+        writer.flush().await?;
+        writer.sync_all().await?;
+        count += run.len() as u64;
+        runs.push((run_file, run.len()));
+        tracing::debug!(runs = runs.len(), "wrote quad run");
+    }
+
+    let mut streams = Vec::with_capacity(runs.len());
+    for (run_file, run_len) in runs.iter() {
+        streams.push(read_quads(run_file, *run_len).await?);
+    }
+
+    write_sorted_graph_index(
+        file,
+        streams,
+        max_node_value,
+        max_predicate,
+        count,
+        temp_dir.path(),
+    )
+    .await
+}
+
+async fn read_quads<F: FileLoad>(
+    file: &F,
+    count: usize,
+) -> io::Result<impl Stream<Item = io::Result<IdQuadTriple>> + Unpin + Send> {
+    let reader = BufReader::new(file.open_read().await?);
+
+    Ok(Box::pin(futures::stream::try_unfold(
+        (reader, count),
+        |(mut reader, remaining)| async move {
+            if remaining == 0 {
+                return Ok(None);
+            }
+
+            let graph = reader.read_u64().await?;
+            let subject = reader.read_u64().await?;
+            let predicate = reader.read_u64().await?;
+            let object = reader.read_u64().await?;
+
+            Ok(Some((
+                IdQuadTriple::new(graph, IdTriple::new(subject, predicate, object)),
+                (reader, remaining - 1),
+            )))
+        },
+    )))
+}
+
+/// The combined quad additions and removals of a sequence of layers.
+///
+/// Changes are to be applied from the bottom layer to the top
+/// layer. For every quad, only the last change is kept, which is
+/// what decides whether that quad is in its graph on top of the
+/// sequence.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QuadChanges {
+    additions: BTreeSet<IdQuadTriple>,
+    removals: BTreeSet<IdQuadTriple>,
+}
+
+impl QuadChanges {
+    /// Construct an empty set of changes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the addition of a quad.
+    pub fn add(&mut self, quad: IdQuadTriple) {
+        self.removals.remove(&quad);
+        self.additions.insert(quad);
+    }
+
+    /// Record the removal of a quad.
+    pub fn remove(&mut self, quad: IdQuadTriple) {
+        self.additions.remove(&quad);
+        self.removals.insert(quad);
+    }
+
+    /// Record all changes of a later sequence of layers.
+    pub fn apply(&mut self, changes: QuadChanges) {
+        for quad in changes.removals {
+            self.remove(quad);
+        }
+        for quad in changes.additions {
+            self.add(quad);
+        }
+    }
+
+    /// The quads that were last added, in sorted order.
+    pub fn additions(&self) -> impl Iterator<Item = IdQuadTriple> + '_ {
+        self.additions.iter().copied()
+    }
+
+    /// The quads that were last removed, in sorted order.
+    pub fn removals(&self) -> impl Iterator<Item = IdQuadTriple> + '_ {
+        self.removals.iter().copied()
+    }
+
+    /// Returns true if there are no changes.
+    pub fn is_empty(&self) -> bool {
+        self.additions.is_empty() && self.removals.is_empty()
+    }
+
+    /// Split into the additions and removals.
+    pub fn into_parts(self) -> (BTreeSet<IdQuadTriple>, BTreeSet<IdQuadTriple>) {
+        (self.additions, self.removals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackedStore;
+
+    fn quad(g: u64, s: u64, p: u64, o: u64) -> IdQuadTriple {
+        IdQuadTriple::new(g, IdTriple::new(s, p, o))
+    }
+
+    #[tokio::test]
+    async fn graph_index_roundtrip() {
+        let file = MemoryBackedStore::new();
+        let quads = vec![
+            quad(5, 1, 1, 2),
+            quad(3, 2, 1, 1),
+            quad(3, 1, 2, 3),
+            quad(7, 1, 1, 1),
+            quad(3, 1, 2, 3),
+        ];
+        write_graph_index(&file, quads).await.unwrap();
+
+        let index = GraphIndex::parse(file.map().await.unwrap()).unwrap();
+        assert_eq!(4, index.len());
+        assert_eq!(vec![3, 5, 7], index.graphs().collect::<Vec<_>>());
+        assert_eq!(
+            vec![IdTriple::new(1, 2, 3), IdTriple::new(2, 1, 1)],
+            index.triples_g(3).collect::<Vec<_>>()
+        );
+        assert_eq!(0, index.triples_g(4).count());
+        assert_eq!(
+            vec![
+                quad(3, 1, 2, 3),
+                quad(3, 2, 1, 1),
+                quad(5, 1, 1, 2),
+                quad(7, 1, 1, 1)
+            ],
+            index.quads().collect::<Vec<_>>()
+        );
+        assert!(index.contains(5, IdTriple::new(1, 1, 2)));
+        assert!(!index.contains(5, IdTriple::new(1, 1, 1)));
+        assert!(!index.contains(6, IdTriple::new(1, 1, 2)));
+    }
+
+    #[test]
+    fn graph_stack_iterator_lets_topmost_change_win() {
+        let bottom = GraphIndex::parse(GraphIndex::build(vec![
+            quad(1, 1, 1, 1),
+            quad(1, 2, 2, 2),
+            quad(1, 3, 3, 3),
+            quad(2, 4, 4, 4),
+        ]))
+        .unwrap();
+        let middle_pos = GraphIndex::parse(GraphIndex::build(vec![quad(1, 5, 5, 5)])).unwrap();
+        let middle_neg =
+            GraphIndex::parse(GraphIndex::build(vec![quad(1, 1, 1, 1), quad(1, 2, 2, 2)])).unwrap();
+        let top_pos = GraphIndex::parse(GraphIndex::build(vec![quad(1, 2, 2, 2)])).unwrap();
+
+        let stack = vec![
+            (Some(&top_pos), None),
+            (None, None),
+            (Some(&middle_pos), Some(&middle_neg)),
+            (Some(&bottom), None),
+        ];
+        assert_eq!(
+            vec![
+                IdTriple::new(2, 2, 2),
+                IdTriple::new(3, 3, 3),
+                IdTriple::new(5, 5, 5)
+            ],
+            GraphStackIterator::new(stack.clone(), 1).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![IdTriple::new(4, 4, 4)],
+            GraphStackIterator::new(stack.clone(), 2).collect::<Vec<_>>()
+        );
+        assert_eq!(0, GraphStackIterator::new(stack, 3).count());
+    }
+
+    #[test]
+    fn quad_stack_iterator_lets_topmost_change_win() {
+        let bottom = GraphIndex::parse(GraphIndex::build(vec![
+            quad(1, 1, 1, 1),
+            quad(1, 2, 2, 2),
+            quad(2, 4, 4, 4),
+        ]))
+        .unwrap();
+        let top_pos = GraphIndex::parse(GraphIndex::build(vec![quad(3, 1, 1, 1)])).unwrap();
+        let top_neg =
+            GraphIndex::parse(GraphIndex::build(vec![quad(1, 1, 1, 1), quad(2, 4, 4, 4)])).unwrap();
+
+        assert_eq!(
+            vec![quad(1, 2, 2, 2), quad(3, 1, 1, 1)],
+            QuadStackIterator::new(vec![
+                (Some(&top_pos), Some(&top_neg)),
+                (Some(&bottom), None)
+            ])
+            .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn external_graph_index_matches_in_memory_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let quads: Vec<_> = (0..100)
+            .map(|i| quad(i % 7 + 1, i % 5 + 1, i % 3 + 1, i % 11 + 1))
+            .collect();
+        let expected = MemoryBackedStore::new();
+        write_graph_index(&expected, quads.clone()).await.unwrap();
+        let external = MemoryBackedStore::new();
+        write_graph_index_external(&external, quads.into_iter(), 11, 3, dir.path(), 8)
+            .await
+            .unwrap();
+
+        let expected = GraphIndex::parse(expected.map().await.unwrap()).unwrap();
+        let external = GraphIndex::parse(external.map().await.unwrap()).unwrap();
+        assert_eq!(
+            expected.quads().collect::<Vec<_>>(),
+            external.quads().collect::<Vec<_>>()
+        );
+        assert!(std::fs::read_dir(dir.path()).unwrap().next().is_none());
+    }
+
+    #[tokio::test]
+    async fn external_graph_index_without_quads_is_not_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = MemoryBackedStore::new();
+        write_graph_index_external(&file, std::iter::empty(), 1, 1, dir.path(), 8)
+            .await
+            .unwrap();
+
+        assert!(!file.exists().await.unwrap());
+    }
+
+    #[test]
+    fn last_quad_change_wins() {
+        let mut changes = QuadChanges::new();
+        changes.add(quad(1, 1, 1, 1));
+        changes.remove(quad(1, 2, 2, 2));
+
+        let mut later = QuadChanges::new();
+        later.remove(quad(1, 1, 1, 1));
+        later.add(quad(1, 2, 2, 2));
+        later.add(quad(2, 3, 3, 3));
+        changes.apply(later);
+
+        assert_eq!(
+            vec![quad(1, 2, 2, 2), quad(2, 3, 3, 3)],
+            changes.additions().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![quad(1, 1, 1, 1)],
+            changes.removals().collect::<Vec<_>>()
+        );
+    }
+}
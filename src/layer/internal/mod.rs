@@ -1,24 +1,33 @@
 pub mod base;
+pub mod base_bulk;
 pub mod base_merge;
 pub mod child;
+mod graph_index;
 mod object_iterator;
 mod predicate_iterator;
 pub mod rollup;
+pub mod squash_external;
 mod subject_iterator;
+mod text_index;
 
+use super::cursor::*;
 use super::id_map::*;
 use super::layer::*;
 use tdb_succinct::*;
 
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
 use std::convert::TryInto;
+use std::ops::Range;
+use std::sync::Arc;
 
 pub use base::*;
 pub use child::*;
+pub use graph_index::*;
 pub use object_iterator::*;
 pub use predicate_iterator::*;
 pub use rollup::*;
 pub use subject_iterator::*;
+pub use text_index::*;
 
 #[derive(Clone)]
 pub enum InternalLayer {
@@ -55,6 +64,15 @@ impl InternalLayer {
         }
     }
 
+    /// Like `immediate_parent`, but returns the shared pointer to the parent.
+    pub fn immediate_parent_arc(&self) -> Option<&Arc<InternalLayer>> {
+        match self {
+            Base(_) => None,
+            Child(child) => Some(&child.parent),
+            Rollup(rollup) => rollup.internal.immediate_parent_arc(),
+        }
+    }
+
     pub fn layer_stack_size(&self) -> usize {
         let mut count = 1;
         let mut l = self;
@@ -66,6 +84,15 @@ impl InternalLayer {
         count
     }
 
+    /// The amount of bytes mapped by this layer's own structures, not counting its parents.
+    pub fn mapped_size(&self) -> usize {
+        match self {
+            Base(base) => base.mapped_size,
+            Child(child) => child.mapped_size,
+            Rollup(rollup) => rollup.internal.mapped_size(),
+        }
+    }
+
     pub fn node_dictionary(&self) -> &StringDict {
         match self {
             Base(base) => &base.node_dictionary,
@@ -218,6 +245,33 @@ impl InternalLayer {
         }
     }
 
+    /// The graph index of the quads added in this layer, if there are any.
+    pub fn pos_graph_index(&self) -> Option<&GraphIndex> {
+        match self {
+            Base(base) => base.quads.as_ref(),
+            Child(child) => child.pos_quads.as_ref(),
+            Rollup(rollup) => rollup.internal.pos_graph_index(),
+        }
+    }
+
+    /// The graph index of the quads removed in this layer, if there are any.
+    pub fn neg_graph_index(&self) -> Option<&GraphIndex> {
+        match self {
+            Base(_) => None,
+            Child(child) => child.neg_quads.as_ref(),
+            Rollup(rollup) => rollup.internal.neg_graph_index(),
+        }
+    }
+
+    /// The text index of the values added in this layer, if it has one.
+    pub fn text_index(&self) -> Option<&TextIndex> {
+        match self {
+            Base(base) => base.text_index.as_ref(),
+            Child(child) => child.text_index.as_ref(),
+            Rollup(rollup) => rollup.internal.text_index(),
+        }
+    }
+
     pub fn predicate_dict_get(&self, id: usize) -> Option<String> {
         self.predicate_dictionary().get(id)
     }
@@ -254,6 +308,55 @@ impl InternalLayer {
         self.value_dictionary().entry(id)
     }
 
+    /// The ids in this layer's own value dictionary from `from` up to but not including `to`.
+    pub fn value_dict_range(&self, from: &TypedDictEntry, to: &TypedDictEntry) -> Range<u64> {
+        let datatype = from.datatype();
+        let dict = self.value_dictionary();
+        if dict.type_segment(datatype).is_none() {
+            return 0..0;
+        }
+
+        let position = |entry: &TypedDictEntry| match dict.id_slice(datatype, &entry.to_bytes()) {
+            IdLookupResult::Found(id) => id,
+            IdLookupResult::Closest(id) => id + 1,
+            IdLookupResult::NotFound => 1,
+        };
+        let start = position(from);
+        let end = position(to).max(start);
+
+        start..end
+    }
+
+    /// The ids in this layer's own value dictionary of the textual values containing all given tokens, in sorted order.
+    ///
+    /// Without a text index, the value dictionary is scanned.
+    pub fn value_dict_text_matches(
+        &self,
+        tokens: &[String],
+    ) -> Box<dyn Iterator<Item = u64> + Send> {
+        match self.text_index() {
+            Some(index) => Box::new(index.search(tokens)),
+            None => {
+                let matches: Vec<u64> = self
+                    .value_dictionary()
+                    .iter()
+                    .zip(1..)
+                    .filter_map(|(value, id)| {
+                        let value_tokens: HashSet<String> =
+                            tokenize(&value_text(&value)?).collect();
+                        if tokens.iter().all(|token| value_tokens.contains(token)) {
+                            Some(id)
+                        } else {
+                            None
+                        }
+                    })
+                    .collect();
+
+                Box::new(matches.into_iter())
+            }
+        }
+    }
+
     pub fn internal_triple_addition_exists(
         &self,
         subject: u64,
@@ -466,6 +569,146 @@ impl InternalLayer {
         )
     }
 
+    /// Iterator over the additions of this layer matching the cursor's pattern that come after its last triple.
+    pub fn internal_triple_additions_from_cursor(
+        &self,
+        cursor: &TripleCursor,
+    ) -> TripleCursorIterator {
+        let position = cursor.resume_position();
+        match cursor.pattern() {
+            TriplePattern::Predicate(predicate) => cursor.resume(
+                self.internal_triple_additions_p(predicate)
+                    .seek_triple(position),
+            ),
+            TriplePattern::Object(_) => cursor.resume(
+                self.internal_triple_additions_by_object()
+                    .seek_triple(position),
+            ),
+            _ => cursor.resume(self.internal_triple_additions().seek_triple(position)),
+        }
+    }
+
+    /// Iterator over the removals of this layer matching the cursor's pattern that come after its last triple.
+    pub fn internal_triple_removals_from_cursor(
+        &self,
+        cursor: &TripleCursor,
+    ) -> TripleCursorIterator {
+        let position = cursor.resume_position();
+        match cursor.pattern() {
+            TriplePattern::Predicate(predicate) => cursor.resume(
+                self.internal_triple_removals_p(predicate)
+                    .seek_triple(position),
+            ),
+            TriplePattern::Object(_) => cursor.resume(
+                self.internal_triple_removals_by_object()
+                    .seek_triple(position),
+            ),
+            _ => cursor.resume(self.internal_triple_removals().seek_triple(position)),
+        }
+    }
+
+    /// Returns the amount of additions in this layer that match the given pattern.
+    pub fn internal_triple_layer_addition_count_matching(&self, pattern: TriplePattern) -> usize {
+        match pattern {
+            TriplePattern::All => self.internal_triple_layer_addition_count(),
+            TriplePattern::Subject(subject) => layer_triple_count_s(
+                self.pos_subjects(),
+                self.pos_s_p_adjacency_list(),
+                self.pos_sp_o_adjacency_list(),
+                subject,
+            ),
+            TriplePattern::SubjectPredicate(subject, predicate) => layer_triple_count_sp(
+                self.pos_subjects(),
+                self.pos_s_p_adjacency_list(),
+                self.pos_sp_o_adjacency_list(),
+                subject,
+                predicate,
+            ),
+            TriplePattern::Predicate(predicate) => layer_triple_count_p(
+                self.pos_predicate_wavelet_tree(),
+                self.pos_sp_o_adjacency_list(),
+                predicate,
+            ),
+            TriplePattern::Object(object) => {
+                layer_triple_count_o(self.pos_objects(), self.pos_o_ps_adjacency_list(), object)
+            }
+        }
+    }
+
+    /// Returns the amount of removals in this layer that match the given pattern.
+    pub fn internal_triple_layer_removal_count_matching(&self, pattern: TriplePattern) -> usize {
+        match pattern {
+            TriplePattern::All => self.internal_triple_layer_removal_count(),
+            TriplePattern::Subject(subject) => {
+                match (
+                    self.neg_s_p_adjacency_list(),
+                    self.neg_sp_o_adjacency_list(),
+                ) {
+                    (Some(s_p_adjacency_list), Some(sp_o_adjacency_list)) => layer_triple_count_s(
+                        self.neg_subjects(),
+                        s_p_adjacency_list,
+                        sp_o_adjacency_list,
+                        subject,
+                    ),
+                    _ => 0,
+                }
+            }
+            TriplePattern::SubjectPredicate(subject, predicate) => {
+                match (
+                    self.neg_s_p_adjacency_list(),
+                    self.neg_sp_o_adjacency_list(),
+                ) {
+                    (Some(s_p_adjacency_list), Some(sp_o_adjacency_list)) => layer_triple_count_sp(
+                        self.neg_subjects(),
+                        s_p_adjacency_list,
+                        sp_o_adjacency_list,
+                        subject,
+                        predicate,
+                    ),
+                    _ => 0,
+                }
+            }
+            TriplePattern::Predicate(predicate) => {
+                match (
+                    self.neg_predicate_wavelet_tree(),
+                    self.neg_sp_o_adjacency_list(),
+                ) {
+                    (Some(predicate_wavelet_tree), Some(sp_o_adjacency_list)) => {
+                        layer_triple_count_p(predicate_wavelet_tree, sp_o_adjacency_list, predicate)
+                    }
+                    _ => 0,
+                }
+            }
+            TriplePattern::Object(object) => match self.neg_o_ps_adjacency_list() {
+                Some(o_ps_adjacency_list) => {
+                    layer_triple_count_o(self.neg_objects(), o_ps_adjacency_list, object)
+                }
+                None => 0,
+            },
+        }
+    }
+
+    /// Returns the amount of triples in this layer and all its parents that match the given pattern.
+    ///
+    /// Every layer only adds triples that don't exist yet, and only
+    /// removes triples that do, so this is the difference between the
+    /// matching additions and removals of all layers in the stack.
+    fn triple_count_matching(&self, pattern: TriplePattern) -> usize {
+        let layers = self.immediate_layers();
+        let additions: usize = layers
+            .iter()
+            .map(|layer| layer.internal_triple_layer_addition_count_matching(pattern))
+            .sum();
+        let removals: usize = layers
+            .iter()
+            .map(|layer| layer.internal_triple_layer_removal_count_matching(pattern))
+            .sum();
+
+        additions
+            .checked_sub(removals)
+            .expect("a layer can only remove triples that exist below it, so removals never exceed additions")
+    }
+
     pub fn internal_triple_layer_addition_count(&self) -> usize {
         self.pos_sp_o_adjacency_list().right_count()
             - self
@@ -484,6 +727,70 @@ impl InternalLayer {
         }
     }
 
+    /// The quads added and removed in just this layer.
+    pub fn internal_quad_changes(&self) -> QuadChanges {
+        let mut changes = QuadChanges::new();
+        if let Some(index) = self.neg_graph_index() {
+            index.quads().for_each(|quad| changes.remove(quad));
+        }
+        if let Some(index) = self.pos_graph_index() {
+            index.quads().for_each(|quad| changes.add(quad));
+        }
+
+        changes
+    }
+
+    /// The quad changes of the layers above the given ancestor, or of the whole stack if no ancestor is given.
+    pub fn quad_changes_upto(&self, upto: Option<[u32; 5]>) -> QuadChanges {
+        let layers = match upto {
+            None => self.immediate_layers(),
+            Some(upto) if upto == self.name() => return QuadChanges::new(),
+            Some(upto) => self.immediate_layers_upto(upto),
+        };
+
+        let mut changes = QuadChanges::new();
+        for layer in layers {
+            changes.apply(layer.internal_quad_changes());
+        }
+
+        changes
+    }
+
+    /// Iterator over the quads of the whole stack, ordered by graph and then by triple.
+    ///
+    /// Like `quad_changes_upto(None).additions()`, but merged lazily
+    /// from the graph indexes instead of collected in memory. Quads
+    /// whose triple was removed from the stack are included.
+    pub fn stack_quads(&self) -> QuadStackIterator {
+        QuadStackIterator::new(
+            self.immediate_layers()
+                .into_iter()
+                .rev()
+                .map(|layer| (layer.pos_graph_index(), layer.neg_graph_index())),
+        )
+    }
+
+    /// Returns true if the given triple is in the given graph.
+    pub fn graph_contains(&self, graph: u64, triple: IdTriple) -> bool {
+        let mut layer = Some(self);
+        while let Some(l) = layer {
+            if let Some(index) = l.pos_graph_index() {
+                if index.contains(graph, triple) {
+                    return true;
+                }
+            }
+            if let Some(index) = l.neg_graph_index() {
+                if index.contains(graph, triple) {
+                    return false;
+                }
+            }
+
+            layer = l.immediate_parent();
+        }
+
+        false
+    }
+
     pub fn immediate_layers(&self) -> Vec<&InternalLayer> {
         let mut layer = Some(self);
         let mut result = Vec::new();
@@ -884,6 +1191,139 @@ impl Layer for InternalLayer {
         )
     }
 
+    fn count_s(&self, subject: u64) -> usize {
+        self.triple_count_matching(TriplePattern::Subject(subject))
+    }
+
+    fn count_sp(&self, subject: u64, predicate: u64) -> usize {
+        self.triple_count_matching(TriplePattern::SubjectPredicate(subject, predicate))
+    }
+
+    fn count_p(&self, predicate: u64) -> usize {
+        self.triple_count_matching(TriplePattern::Predicate(predicate))
+    }
+
+    fn count_o(&self, object: u64) -> usize {
+        self.triple_count_matching(TriplePattern::Object(object))
+    }
+
+    fn triples_from_cursor(&self, cursor: &TripleCursor) -> TripleCursorIterator {
+        let position = cursor.resume_position();
+        match cursor.pattern() {
+            TriplePattern::Predicate(predicate) => cursor.resume(
+                InternalTriplePredicateIterator::from_layer(self, predicate).seek_triple(position),
+            ),
+            TriplePattern::Object(_) => {
+                cursor.resume(InternalTripleObjectIterator::from_layer(self).seek_triple(position))
+            }
+            _ => {
+                cursor.resume(InternalTripleSubjectIterator::from_layer(self).seek_triple(position))
+            }
+        }
+    }
+
+    fn object_value_ids_in_range(
+        &self,
+        from: &TypedDictEntry,
+        to: &TypedDictEntry,
+    ) -> Box<dyn Iterator<Item = u64> + Send> {
+        let mut ids_per_layer = Vec::new();
+        for layer in self.immediate_layers() {
+            let range = layer.value_dict_range(from, to);
+            if range.is_empty() {
+                continue;
+            }
+
+            let parent_count = layer
+                .immediate_parent()
+                .map_or(0, |p| p.node_and_value_count() as u64);
+            let node_count = layer.node_dict_len() as u64;
+            let ids: Vec<_> = range
+                .map(|id| layer.node_value_id_map().inner_to_outer(id + node_count) + parent_count)
+                .collect();
+            ids_per_layer.push(ids);
+        }
+
+        if ids_per_layer.len() <= 1 {
+            return Box::new(ids_per_layer.into_iter().flatten());
+        }
+
+        // values of different layers interleave, so they have to be put in order
+        let mut values: Vec<_> = ids_per_layer
+            .into_iter()
+            .flatten()
+            .map(|id| {
+                let value = self
+                    .id_object_value(id)
+                    .expect("ids from a value dictionary range should always refer to a value");
+                (value, id)
+            })
+            .collect();
+        values.sort();
+
+        Box::new(values.into_iter().map(|(_, id)| id))
+    }
+
+    fn search_text(&self, query: &str) -> Box<dyn Iterator<Item = u64> + Send> {
+        let mut tokens: Vec<String> = tokenize(query).collect();
+        tokens.sort();
+        tokens.dedup();
+        if tokens.is_empty() {
+            return Box::new(std::iter::empty());
+        }
+
+        // every layer has its own range of ids above those of its
+        // parent, so chaining the layers from the bottom up keeps
+        // the ids sorted.
+        let mut ids: Box<dyn Iterator<Item = u64> + Send> = Box::new(std::iter::empty());
+        for layer in self.immediate_layers() {
+            let parent_count = layer
+                .immediate_parent()
+                .map_or(0, |p| p.node_and_value_count() as u64);
+            let node_count = layer.node_dict_len() as u64;
+            let id_map = layer.node_value_id_map().clone();
+            let matches = layer
+                .value_dict_text_matches(&tokens)
+                .map(move |id| id_map.inner_to_outer(id + node_count) + parent_count);
+            if layer.node_value_id_map().id_wtree.is_none() {
+                ids = Box::new(ids.chain(matches));
+            } else {
+                // an id map doesn't keep the order of the ids
+                let mut matches: Vec<u64> = matches.collect();
+                matches.sort();
+                ids = Box::new(ids.chain(matches));
+            }
+        }
+
+        // values whose triples were all removed further up the stack don't count
+        let layer = self.clone();
+        Box::new(ids.filter(move |id| layer.triples_o(*id).next().is_some()))
+    }
+
+    fn quads_g(&self, graph: u64) -> Box<dyn Iterator<Item = IdQuadTriple> + Send> {
+        let indexes = self
+            .immediate_layers()
+            .into_iter()
+            .rev()
+            .map(|layer| (layer.pos_graph_index(), layer.neg_graph_index()));
+
+        Box::new(GraphStackIterator::new(indexes, graph).map(move |t| IdQuadTriple::new(graph, t)))
+    }
+
+    fn triple_graphs(&self, triple: &IdTriple) -> Vec<u64> {
+        let graphs: BTreeSet<u64> = self
+            .immediate_layers()
+            .into_iter()
+            .filter_map(|layer| layer.pos_graph_index())
+            .flat_map(|index| index.graphs())
+            .collect();
+
+        graphs
+            .into_iter()
+            .filter(|graph| self.graph_contains(*graph, *triple))
+            .collect()
+    }
+
     fn single_triple_sp(&self, subject: u64, predicate: u64) -> Option<IdTriple> {
         // is subject/predicate in the positives? we're in luck
         if let Some(pos) = sp_o_position(
@@ -975,31 +1415,136 @@ impl From<RollupLayer> for InternalLayer {
     }
 }
 
-fn sp_o_position(
-    subjects: Option<&MonotonicLogArray>,
-    s_p_adjacency_list: &AdjacencyList,
-    sp_o_adjacency_list: &AdjacencyList,
-    subject: u64,
-    predicate: u64,
+/// Returns the position of the given subject or object in a layer's adjacency list.
+fn id_position(
+    ids: Option<&MonotonicLogArray>,
+    adjacency_list: &AdjacencyList,
+    id: u64,
 ) -> Option<u64> {
-    if subject == 0 || predicate == 0 {
+    if id == 0 {
         return None;
     }
 
-    let s_position = match subjects {
+    match ids {
         None => {
-            if subject > s_p_adjacency_list.left_count() as u64 {
-                return None;
+            if id > adjacency_list.left_count() as u64 {
+                None
             } else {
-                subject - 1
+                Some(id - 1)
             }
         }
-        Some(subjects) => match subjects.index_of(subject) {
-            Some(pos) => pos as u64,
-            None => return None,
-        },
+        Some(ids) => ids.index_of(id).map(|pos| pos as u64),
+    }
+}
+
+/// Returns the range of positions in the adjacency list for the given left position.
+fn adjacency_range(adjacency_list: &AdjacencyList, position: u64) -> Range<u64> {
+    adjacency_list.offset_for(position + 1)..adjacency_list.offset_for(position + 2)
+}
+
+/// Returns the amount of objects for the subject-predicate pairs in the given range of positions.
+fn pair_object_count(sp_o_adjacency_list: &AdjacencyList, s_p_positions: Range<u64>) -> usize {
+    (sp_o_adjacency_list.offset_for(s_p_positions.end + 1)
+        - sp_o_adjacency_list.offset_for(s_p_positions.start + 1)) as usize
+}
+
+/// Count the triples with the given subject in a layer's additions or removals.
+pub(crate) fn layer_triple_count_s(
+    subjects: Option<&MonotonicLogArray>,
+    s_p_adjacency_list: &AdjacencyList,
+    sp_o_adjacency_list: &AdjacencyList,
+    subject: u64,
+) -> usize {
+    let s_position = match id_position(subjects, s_p_adjacency_list, subject) {
+        Some(position) => position,
+        None => return 0,
+    };
+    let s_p_positions = adjacency_range(s_p_adjacency_list, s_position);
+    if s_p_adjacency_list.num_at_pos(s_p_positions.start) == 0 {
+        // a subject without predicates
+        return 0;
+    }
+
+    pair_object_count(sp_o_adjacency_list, s_p_positions)
+}
+
+/// Count the triples with the given subject and predicate in a layer's additions or removals.
+pub(crate) fn layer_triple_count_sp(
+    subjects: Option<&MonotonicLogArray>,
+    s_p_adjacency_list: &AdjacencyList,
+    sp_o_adjacency_list: &AdjacencyList,
+    subject: u64,
+    predicate: u64,
+) -> usize {
+    let s_position = match id_position(subjects, s_p_adjacency_list, subject) {
+        Some(position) if predicate != 0 => position,
+        _ => return 0,
+    };
+    let s_p_positions = adjacency_range(s_p_adjacency_list, s_position);
+    let s_p_position = lower_bound(s_p_positions.start, s_p_positions.end, |pos| {
+        s_p_adjacency_list.num_at_pos(pos) >= predicate
+    });
+    if s_p_position == s_p_positions.end || s_p_adjacency_list.num_at_pos(s_p_position) != predicate
+    {
+        return 0;
+    }
+
+    pair_object_count(sp_o_adjacency_list, s_p_position..s_p_position + 1)
+}
+
+/// Count the triples with the given predicate in a layer's additions or removals.
+///
+/// This looks up the subject-predicate pairs for the predicate in the
+/// wavelet tree, without going over their objects.
+pub(crate) fn layer_triple_count_p(
+    predicate_wavelet_tree: &WaveletTree,
+    sp_o_adjacency_list: &AdjacencyList,
+    predicate: u64,
+) -> usize {
+    if predicate == 0 {
+        return 0;
+    }
+
+    match predicate_wavelet_tree.lookup(predicate) {
+        Some(lookup) => lookup
+            .iter()
+            .map(|pos| pair_object_count(sp_o_adjacency_list, pos..pos + 1))
+            .sum(),
+        None => 0,
+    }
+}
+
+/// Count the triples with the given object in a layer's additions or removals.
+pub(crate) fn layer_triple_count_o(
+    objects: Option<&MonotonicLogArray>,
+    o_ps_adjacency_list: &AdjacencyList,
+    object: u64,
+) -> usize {
+    let o_position = match id_position(objects, o_ps_adjacency_list, object) {
+        Some(position) => position,
+        None => return 0,
     };
+    let o_ps_positions = adjacency_range(o_ps_adjacency_list, o_position);
+    if o_ps_adjacency_list.num_at_pos(o_ps_positions.start) == 0 {
+        // an object that is not used in any triple
+        return 0;
+    }
+
+    (o_ps_positions.end - o_ps_positions.start) as usize
+}
+
+fn sp_o_position(
+    subjects: Option<&MonotonicLogArray>,
+    s_p_adjacency_list: &AdjacencyList,
+    sp_o_adjacency_list: &AdjacencyList,
+    subject: u64,
+    predicate: u64,
+) -> Option<u64> {
+    if predicate == 0 {
+        return None;
+    }
 
+    let s_position = id_position(subjects, s_p_adjacency_list, subject)?;
     let mut s_p_position = s_p_adjacency_list.offset_for(s_position + 1);
     loop {
         let bit = s_p_adjacency_list.bit_at_pos(s_p_position);
@@ -1135,6 +1680,33 @@ mod tests {
         assert_eq!(1, layer.internal_triple_layer_addition_count());
     }
 
+    #[tokio::test]
+    async fn search_text_without_text_index() {
+        let files = base_layer_files();
+        let mut builder = BaseLayerFileBuilder::from_files(&files).await.unwrap();
+        builder.add_nodes(vec!["cow".to_string(), "pig".to_string()]);
+        builder.add_predicates(vec!["says".to_string()]);
+        builder.add_values(
+            vec!["Moo moo", "oink", "the pig"]
+                .into_iter()
+                .map(|s| String::make_entry(&s)),
+        );
+        let mut builder = builder.into_phase2().await.unwrap();
+        builder.add_triple(1, 1, 3).await.unwrap();
+        builder.add_triple(2, 1, 4).await.unwrap();
+        builder.finalize().await.unwrap();
+
+        let layer = BaseLayer::load_from_files([1, 2, 3, 4, 5], &files)
+            .await
+            .unwrap();
+
+        assert!(layer.text_index().is_none());
+        assert_eq!(vec![3], layer.search_text("MOO").collect::<Vec<_>>());
+        assert_eq!(vec![4], layer.search_text("oink").collect::<Vec<_>>());
+        // "the pig" is in the dictionary, but no triple refers to it
+        assert!(layer.search_text("pig").next().is_none());
+    }
+
     #[tokio::test]
     async fn object_is_node_in_base_layer() {
         let dir = tempdir().unwrap();
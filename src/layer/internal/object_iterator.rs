@@ -1,3 +1,4 @@
+use super::subject_iterator::lower_bound;
 use crate::layer::*;
 use std::convert::TryInto;
 use tdb_succinct::*;
@@ -66,6 +67,60 @@ impl InternalLayerTripleObjectIterator {
         }
     }
 
+    /// Position this iterator at the first triple that is not smaller than the given triple.
+    ///
+    /// Triples are ordered by object first, then by subject and predicate.
+    pub fn seek_triple(mut self, triple: IdTriple) -> Self {
+        self.seek_triple_ref(triple);
+
+        self
+    }
+
+    pub fn seek_triple_ref(&mut self, triple: IdTriple) {
+        self.seek_object_ref(triple.object);
+        if triple.object == 0
+            || self.o_position >= self.o_ps_adjacency_list.left_count() as u64
+            || self.object_at(self.o_position) != triple.object
+        {
+            return;
+        }
+
+        // the subject-predicate pairs of an object are sorted
+        let o_ps_end = self.o_ps_adjacency_list.offset_for(self.o_position + 2);
+        let o_ps_position = lower_bound(self.o_ps_position, o_ps_end, |pos| {
+            self.subject_predicate_at(pos) >= (triple.subject, triple.predicate)
+        });
+        if o_ps_position == o_ps_end {
+            self.o_position += 1;
+            if self.stop_at_boundary {
+                self.hit_boundary = true;
+            }
+        }
+        self.o_ps_position = o_ps_position;
+    }
+
+    fn object_at(&self, o_position: u64) -> u64 {
+        match self.objects.as_ref() {
+            Some(objects) => objects.entry(o_position.try_into().unwrap()),
+            None => o_position + 1,
+        }
+    }
+
+    fn subject_predicate_at(&self, o_ps_position: u64) -> (u64, u64) {
+        let sp_pair_num = self.o_ps_adjacency_list.num_at_pos(o_ps_position);
+        if sp_pair_num == 0 {
+            return (0, 0);
+        }
+
+        let (mapped_subject, predicate) = self.s_p_adjacency_list.pair_at_pos(sp_pair_num - 1);
+        let subject = match self.subjects.as_ref() {
+            Some(subjects) => subjects.entry(mapped_subject as usize - 1),
+            None => mapped_subject,
+        };
+
+        (subject, predicate)
+    }
+
     pub fn peek(&mut self) -> Option<&IdTriple> {
         self.peeked = self.next();
 
@@ -122,18 +177,8 @@ impl Iterator for InternalLayerTripleObjectIterator {
                     continue;
                 }
 
-                let (mapped_subject, predicate) =
-                    self.s_p_adjacency_list.pair_at_pos(sp_pair_num - 1);
-
-                let subject = match self.subjects.as_ref() {
-                    Some(subjects) => subjects.entry(mapped_subject as usize - 1),
-                    None => mapped_subject,
-                };
-
-                let object = match self.objects.as_ref() {
-                    Some(objects) => objects.entry(o_pos.try_into().unwrap()),
-                    None => o_pos + 1,
-                };
+                let (subject, predicate) = self.subject_predicate_at(self.o_ps_position - 1);
+                let object = self.object_at(o_pos);
 
                 return Some(IdTriple::new(subject, predicate, object));
             }
@@ -154,6 +199,16 @@ impl OptInternalLayerTripleObjectIterator {
         }
     }
 
+    pub fn seek_triple(self, triple: IdTriple) -> Self {
+        OptInternalLayerTripleObjectIterator(self.0.map(|i| i.seek_triple(triple)))
+    }
+
+    pub fn seek_triple_ref(&mut self, triple: IdTriple) {
+        if let Some(i) = self.0.as_mut() {
+            i.seek_triple_ref(triple)
+        }
+    }
+
     pub fn stop_at_boundary(self, stop: bool) -> Self {
         OptInternalLayerTripleObjectIterator(self.0.map(|i| i.stop_at_boundary(stop)))
     }
@@ -219,6 +274,19 @@ impl InternalTripleObjectIterator {
 
         self
     }
+
+    /// Position this iterator at the first triple that is not smaller than the given triple, in object order.
+    pub fn seek_triple(mut self, triple: IdTriple) -> Self {
+        for p in self.positives.iter_mut() {
+            p.seek_triple_ref(triple);
+        }
+
+        for n in self.negatives.iter_mut() {
+            n.seek_triple_ref(triple);
+        }
+
+        self
+    }
 }
 
 impl Iterator for InternalTripleObjectIterator {
@@ -1,3 +1,4 @@
+use super::subject_iterator::lower_bound;
 use crate::layer::*;
 use std::convert::TryInto;
 use tdb_succinct::*;
@@ -51,6 +52,43 @@ impl InternalLayerTriplePredicateIterator {
         true
     }
 
+    /// Position this iterator at the first triple that is not smaller than the given triple.
+    ///
+    /// The given triple is expected to have the predicate this iterator is for.
+    pub fn seek_triple(mut self, triple: IdTriple) -> Self {
+        self.seek_triple_ref(triple);
+
+        self
+    }
+
+    pub fn seek_triple_ref(&mut self, triple: IdTriple) {
+        self.peeked = None;
+
+        // the positions of a predicate are in subject order
+        let lookup = &self.predicate_wavelet_lookup;
+        let subject_iterator = &self.subject_iterator;
+        let predicate_pos = lower_bound(0, self.len as u64, |pos| {
+            subject_iterator.subject_at_s_p_pos(lookup.entry(pos.try_into().unwrap()))
+                >= triple.subject
+        });
+        self.predicate_pos = predicate_pos;
+        self.sp_boundary = true;
+
+        if predicate_pos < self.len as u64
+            && subject_iterator.subject_at_s_p_pos(lookup.entry(predicate_pos.try_into().unwrap()))
+                == triple.subject
+        {
+            // continue halfway the objects for this subject, unless all of them come before the triple
+            self.subject_iterator.seek_triple_ref(triple);
+            self.predicate_pos += 1;
+            self.sp_boundary = self
+                .subject_iterator
+                .peek()
+                .map(|t| (t.subject, t.predicate))
+                != Some((triple.subject, triple.predicate));
+        }
+    }
+
     pub fn peek(&mut self) -> Option<&IdTriple> {
         self.peeked = self.next();
 
@@ -101,6 +139,16 @@ pub struct OptInternalLayerTriplePredicateIterator(
 );
 
 impl OptInternalLayerTriplePredicateIterator {
+    pub fn seek_triple_ref(&mut self, triple: IdTriple) {
+        if let Some(i) = self.0.as_mut() {
+            i.seek_triple_ref(triple)
+        };
+    }
+
+    pub fn seek_triple(self, triple: IdTriple) -> Self {
+        OptInternalLayerTriplePredicateIterator(self.0.map(|i| i.seek_triple(triple)))
+    }
+
     pub fn peek(&mut self) -> Option<&IdTriple> {
         self.0.as_mut().and_then(|i| i.peek())
     }
@@ -141,6 +189,19 @@ impl InternalTriplePredicateIterator {
             negatives,
         }
     }
+
+    /// Position this iterator at the first triple that is not smaller than the given triple.
+    pub fn seek_triple(mut self, triple: IdTriple) -> Self {
+        for p in self.positives.iter_mut() {
+            p.seek_triple_ref(triple);
+        }
+
+        for n in self.negatives.iter_mut() {
+            n.seek_triple_ref(triple);
+        }
+
+        self
+    }
 }
 
 impl Iterator for InternalTriplePredicateIterator {
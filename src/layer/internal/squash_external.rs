@@ -0,0 +1,711 @@
+//! Squashing of layer stacks that don't fit in memory.
+//!
+//! The changes of the stack are read in runs of a bounded size. For
+//! each run, the dictionary entries it uses that are not part of the
+//! layer it is squashed onto are collected, sorted and written to a
+//! temporary directory, together with the sorted triples of the run
+//! expressed in these run dictionaries. The run dictionaries are then
+//! merged into the output dictionaries as streams, in the same way as
+//! `merge_base_layers` does, after which the triples of all runs are
+//! remapped and merged into the output layer.
+//!
+//! Apart from a single run, the only thing kept in memory is the
+//! mapping from run dictionary entries to output dictionary entries,
+//! which is a word per entry.
+use std::{io, path::Path, sync::Arc};
+
+use futures::{Stream, TryStreamExt};
+use tdb_succinct::{util::heap_sorted_stream, TypedDictEntry};
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+
+use super::{
+    base_merge::{dicts_to_map, typed_dicts_to_map},
+    BaseLayer, BaseLayerFileBuilderPhase2, ChildLayerFileBuilderPhase2, InternalLayer,
+    InternalTripleStackIterator, TripleChange,
+};
+use crate::layer::{
+    builder::DictionarySetFileBuilder, write_graph_index_external, write_text_index_external,
+    IdTriple, Layer, ObjectType,
+};
+use crate::storage::{
+    directory::DirectoryLayerStore, name_to_string, BaseLayerFiles, ChildLayerFiles,
+    DictionaryFiles, FileLoad, FileStore, PersistentLayerStore, Progress, SyncableFile,
+    TypedDictionaryFiles,
+};
+
+/// The default amount of triple changes that is sorted in memory before being written to disk as a run.
+pub const SQUASH_RUN_SIZE: usize = 1_000_000;
+
+/// The sorted changes of a run, expressed in the run dictionaries.
+struct Run<F: 'static + FileLoad + FileStore> {
+    files: BaseLayerFiles<F>,
+    additions: (F, usize),
+    removals: (F, usize),
+}
+
+/// Collects runs of changes, writing each run to disk once it is full.
+struct RunWriter<'a> {
+    layer: &'a InternalLayer,
+    base_node_value_count: u64,
+    base_predicate_count: u64,
+    store: DirectoryLayerStore,
+    runs: Vec<Run<<DirectoryLayerStore as PersistentLayerStore>::File>>,
+}
+
+fn sorted_dedup<T: Ord>(mut v: Vec<T>) -> Vec<T> {
+    v.sort();
+    v.dedup();
+
+    v
+}
+
+fn position<T: Ord>(sorted: &[T], item: &T) -> u64 {
+    sorted
+        .binary_search(item)
+        .expect("item should have been collected in the run dictionary") as u64
+        + 1
+}
+
+async fn write_triples<F: FileStore>(file: &F, triples: &[IdTriple]) -> io::Result<()> {
+    let mut writer = file.open_write().await?;
+    for triple in triples {
+        writer.write_u64(triple.subject).await?;
+        writer.write_u64(triple.predicate).await?;
+        writer.write_u64(triple.object).await?;
+    }
+    writer.flush().await?;
+    writer.sync_all().await
+}
+
+async fn read_triples<F: FileLoad>(
+    file: &F,
+    count: usize,
+) -> io::Result<impl Stream<Item = io::Result<(u64, u64, u64)>> + Unpin + Send> {
+    let reader = BufReader::new(file.open_read().await?);
+
+    Ok(Box::pin(futures::stream::try_unfold(
+        (reader, count),
+        |(mut reader, remaining)| async move {
+            if remaining == 0 {
+                return Ok(None);
+            }
+
+            let subject = reader.read_u64().await?;
+            let predicate = reader.read_u64().await?;
+            let object = reader.read_u64().await?;
+
+            Ok(Some((
+                (subject, predicate, object),
+                (reader, remaining - 1),
+            )))
+        },
+    )))
+}
+
+impl<'a> RunWriter<'a> {
+    fn new(
+        layer: &'a InternalLayer,
+        base_node_value_count: u64,
+        base_predicate_count: u64,
+        temp_dir: &Path,
+    ) -> Self {
+        Self {
+            layer,
+            base_node_value_count,
+            base_predicate_count,
+            store: DirectoryLayerStore::new(temp_dir),
+            runs: Vec::new(),
+        }
+    }
+
+    fn is_new_node_value(&self, id: u64) -> bool {
+        id > self.base_node_value_count
+    }
+
+    fn is_new_predicate(&self, id: u64) -> bool {
+        id > self.base_predicate_count
+    }
+
+    fn node(&self, id: u64) -> io::Result<String> {
+        self.object(id)?
+            .node()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "expected a node id"))
+    }
+
+    fn predicate(&self, id: u64) -> io::Result<String> {
+        self.layer.id_predicate(id).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "unknown predicate id in stack")
+        })
+    }
+
+    fn object(&self, id: u64) -> io::Result<ObjectType> {
+        self.layer
+            .id_object(id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unknown object id in stack"))
+    }
+
+    /// Write a run of changes, together with the given extra nodes.
+    async fn write_run(
+        &mut self,
+        changes: Vec<(TripleChange, IdTriple)>,
+        extra_nodes: Vec<String>,
+    ) -> io::Result<()> {
+        // entries that are new to the stack are resolved once, and used
+        // both for the run dictionaries and for remapping the triples
+        let mut nodes = extra_nodes;
+        let mut predicates = Vec::new();
+        let mut values = Vec::new();
+        let mut resolved = Vec::with_capacity(changes.len());
+        for (change, triple) in changes {
+            let subject = if self.is_new_node_value(triple.subject) {
+                let subject = self.node(triple.subject)?;
+                nodes.push(subject.clone());
+                Some(subject)
+            } else {
+                None
+            };
+            let predicate = if self.is_new_predicate(triple.predicate) {
+                let predicate = self.predicate(triple.predicate)?;
+                predicates.push(predicate.clone());
+                Some(predicate)
+            } else {
+                None
+            };
+            let object = if self.is_new_node_value(triple.object) {
+                let object = self.object(triple.object)?;
+                match &object {
+                    ObjectType::Node(node) => nodes.push(node.clone()),
+                    ObjectType::Value(value) => values.push(value.clone()),
+                }
+                Some(object)
+            } else {
+                None
+            };
+
+            resolved.push((change, triple, subject, predicate, object));
+        }
+        let nodes = sorted_dedup(nodes);
+        let predicates = sorted_dedup(predicates);
+        let values: Vec<TypedDictEntry> = sorted_dedup(values);
+
+        let mut additions = Vec::new();
+        let mut removals = Vec::new();
+        for (change, triple, subject, predicate, object) in resolved {
+            let subject = subject
+                .map(|s| self.base_node_value_count + position(&nodes, &s))
+                .unwrap_or(triple.subject);
+            let predicate = predicate
+                .map(|p| self.base_predicate_count + position(&predicates, &p))
+                .unwrap_or(triple.predicate);
+            let object = object
+                .map(|o| {
+                    self.base_node_value_count
+                        + match o {
+                            ObjectType::Node(node) => position(&nodes, &node),
+                            ObjectType::Value(value) => {
+                                nodes.len() as u64 + position(&values, &value)
+                            }
+                        }
+                })
+                .unwrap_or(triple.object);
+
+            let mapped = IdTriple::new(subject, predicate, object);
+            match change {
+                TripleChange::Addition => additions.push(mapped),
+                TripleChange::Removal => removals.push(mapped),
+            }
+        }
+        let additions = sorted_dedup(additions);
+        let removals = sorted_dedup(removals);
+
+        let run_name = self.store.create_directory().await?;
+        let files = self.store.base_layer_files(run_name).await?;
+        let mut builder = DictionarySetFileBuilder::from_files(
+            files.node_dictionary_files.clone(),
+            files.predicate_dictionary_files.clone(),
+            files.value_dictionary_files.clone(),
+        )
+        .await?;
+        builder.add_nodes(nodes);
+        builder.add_predicates(predicates);
+        builder.add_values(values);
+        builder.finalize().await?;
+
+        let additions_file = self.store.get_file(run_name, "additions").await?;
+        write_triples(&additions_file, &additions).await?;
+        let removals_file = self.store.get_file(run_name, "removals").await?;
+        write_triples(&removals_file, &removals).await?;
+
+        self.runs.push(Run {
+            files,
+            additions: (additions_file, additions.len()),
+            removals: (removals_file, removals.len()),
+        });
+        tracing::debug!(runs = self.runs.len(), "wrote run");
+
+        Ok(())
+    }
+
+    /// Split the given changes into runs and write them.
+    async fn write_runs<I: Iterator<Item = (TripleChange, IdTriple)> + Send>(
+        &mut self,
+        mut changes: I,
+        run_size: usize,
+        progress: &Progress,
+    ) -> io::Result<()> {
+        assert!(run_size > 0, "run size should be larger than 0");
+        loop {
+            progress.check()?;
+            let run: Vec<_> = changes.by_ref().take(run_size).collect();
+            if run.is_empty() {
+                return Ok(());
+            }
+
+            self.write_run(run, Vec::new()).await?;
+        }
+    }
+}
+
+/// The dictionary sizes of the output and the changes of all runs, remapped to the output dictionaries.
+struct MergedRuns<S> {
+    node_count: usize,
+    predicate_count: usize,
+    value_count: usize,
+    additions: S,
+    removals: S,
+}
+
+/// Merge the dictionaries of all runs into the given output files,
+/// and return the remapped changes as sorted streams.
+async fn merge_runs<
+    F: 'static + FileLoad + FileStore + Clone,
+    F2: 'static + FileLoad + FileStore,
+>(
+    runs: &[Run<F>],
+    base_node_value_count: u64,
+    base_predicate_count: u64,
+    output: (
+        DictionaryFiles<F2>,
+        DictionaryFiles<F2>,
+        TypedDictionaryFiles<F2>,
+    ),
+    progress: &Progress,
+) -> io::Result<MergedRuns<impl Stream<Item = io::Result<(u64, u64, u64)>> + Unpin + Send>> {
+    let (node_files, predicate_files, value_files) = output;
+    let (node_maps, node_count) = dicts_to_map(
+        runs.iter().map(|r| r.files.node_dictionary_files.clone()),
+        node_files,
+    )
+    .await?;
+    progress.dictionaries_merged(1)?;
+    let (predicate_maps, predicate_count) = dicts_to_map(
+        runs.iter()
+            .map(|r| r.files.predicate_dictionary_files.clone()),
+        predicate_files,
+    )
+    .await?;
+    progress.dictionaries_merged(1)?;
+    let (value_maps, value_count) = typed_dicts_to_map(
+        runs.iter().map(|r| r.files.value_dictionary_files.clone()),
+        value_files,
+    )
+    .await?;
+    progress.dictionaries_merged(1)?;
+    tracing::debug!(
+        nodes = node_count,
+        predicates = predicate_count,
+        values = value_count,
+        "merged run dictionaries"
+    );
+
+    let maps: Arc<Vec<_>> = Arc::new(
+        node_maps
+            .into_iter()
+            .zip(predicate_maps)
+            .zip(value_maps)
+            .collect(),
+    );
+    let map_node_value = move |id: u64, node_map: &[usize], value_map: &[usize]| {
+        if id <= base_node_value_count {
+            return id;
+        }
+
+        let local = (id - base_node_value_count - 1) as usize;
+        base_node_value_count
+            + if local < node_map.len() {
+                node_map[local] as u64 + 1
+            } else {
+                value_map[local - node_map.len()] as u64 + node_count as u64 + 1
+            }
+    };
+
+    let mut addition_streams = Vec::with_capacity(runs.len());
+    let mut removal_streams = Vec::with_capacity(runs.len());
+    for (ix, run) in runs.iter().enumerate() {
+        for (streams, (file, count)) in [
+            (&mut addition_streams, &run.additions),
+            (&mut removal_streams, &run.removals),
+        ] {
+            let maps = maps.clone();
+            let stream = read_triples(file, *count).await?.map_ok(move |(s, p, o)| {
+                let ((node_map, predicate_map), value_map) = &maps[ix];
+                let p = if p <= base_predicate_count {
+                    p
+                } else {
+                    base_predicate_count
+                        + predicate_map[(p - base_predicate_count - 1) as usize] as u64
+                        + 1
+                };
+
+                (
+                    map_node_value(s, node_map, value_map),
+                    p,
+                    map_node_value(o, node_map, value_map),
+                )
+            });
+            streams.push(stream);
+        }
+    }
+
+    Ok(MergedRuns {
+        node_count,
+        predicate_count,
+        value_count,
+        additions: heap_sorted_stream(addition_streams).await?,
+        removals: heap_sorted_stream(removal_streams).await?,
+    })
+}
+
+/// Squash the given layer and all its ancestors into the output files as a base layer, reporting to `progress`.
+///
+/// At most `run_size` triples are kept in memory at any time. All
+/// intermediate files are kept in a temporary directory inside
+/// `temp_path`, which is removed when the squash completes, fails or
+/// is cancelled. Like `squash`, the graphs of quads whose triple
+/// still exists are kept. Their quads are sorted in runs of at most
+/// `run_size` quads as well.
+#[tracing::instrument(
+    level = "debug",
+    skip_all,
+    fields(layer = %name_to_string(layer.name()), triples = tracing::field::Empty)
+)]
+pub async fn squash_external_with_progress<
+    F: 'static + FileLoad + FileStore + Clone,
+    P: AsRef<Path>,
+>(
+    layer: &InternalLayer,
+    output: BaseLayerFiles<F>,
+    temp_path: P,
+    run_size: usize,
+    progress: &Progress,
+) -> io::Result<()> {
+    let temp_dir = TempDir::new_in(&temp_path)?;
+    let mut writer = RunWriter::new(layer, 0, 0, temp_dir.path());
+    writer
+        .write_runs(
+            layer.triples().map(|t| (TripleChange::Addition, t)),
+            run_size,
+            progress,
+        )
+        .await?;
+
+    let quads = || {
+        layer
+            .stack_quads()
+            .filter(|quad| layer.id_triple_exists(quad.triple))
+    };
+    // graph names need not appear in any triple, so they get runs of their own
+    let mut graphs = Vec::new();
+    let mut last_graph = None;
+    for quad in quads() {
+        if last_graph == Some(quad.graph) {
+            continue;
+        }
+        last_graph = Some(quad.graph);
+        graphs.push(writer.node(quad.graph)?);
+        if graphs.len() >= run_size {
+            progress.check()?;
+            writer
+                .write_run(Vec::new(), std::mem::take(&mut graphs))
+                .await?;
+        }
+    }
+    if !graphs.is_empty() {
+        writer.write_run(Vec::new(), graphs).await?;
+    }
+    let has_quads = last_graph.is_some();
+
+    if writer.runs.is_empty() {
+        // nothing to merge, so just write an empty layer
+        let builder = super::BaseLayerFileBuilder::from_files(&output).await?;
+        progress.dictionaries_merged(3)?;
+        builder
+            .into_phase2()
+            .await?
+            .finalize_with_progress(progress)
+            .await?;
+        progress.done();
+
+        return Ok(());
+    }
+
+    let merged = merge_runs(
+        &writer.runs,
+        0,
+        0,
+        (
+            output.node_dictionary_files.clone(),
+            output.predicate_dictionary_files.clone(),
+            output.value_dictionary_files.clone(),
+        ),
+        progress,
+    )
+    .await?;
+    let mut builder = BaseLayerFileBuilderPhase2::new(
+        output.clone(),
+        merged.node_count,
+        merged.predicate_count,
+        merged.value_count,
+    )
+    .await?;
+    let mut additions = merged.additions;
+    let mut tally: u64 = 0;
+    let mut last_triple = None;
+    while let Some(triple) = additions.try_next().await? {
+        if Some(triple) == last_triple {
+            continue;
+        }
+        last_triple = Some(triple);
+        builder.add_triple(triple.0, triple.1, triple.2).await?;
+        progress.triple_written()?;
+        tally += 1;
+    }
+    tracing::Span::current().record("triples", tally);
+    builder.finalize_with_progress(progress).await?;
+
+    if has_quads {
+        let squashed = BaseLayer::load_from_files(layer.name(), &output).await?;
+        let quads = quads().map(|quad| {
+            layer
+                .id_quad_to_string(&quad)
+                .and_then(|quad| squashed.value_quad_to_id(&quad))
+                .expect("squashed quad should exist in the squashed layer")
+        });
+        write_graph_index_external(
+            &output.quads_file,
+            quads,
+            (merged.node_count + merged.value_count) as u64,
+            merged.predicate_count as u64,
+            temp_dir.path(),
+            run_size,
+        )
+        .await?;
+    }
+    if layer.text_index().is_some() {
+        write_text_index_external(
+            &output.value_dictionary_files,
+            &output.text_index_file,
+            temp_dir.path(),
+            run_size,
+        )
+        .await?;
+    }
+    progress.done();
+
+    Ok(())
+}
+
+/// Squash the given layer and its ancestors up to but not including `upto` into the output files as a child layer of `upto`, reporting to `progress`.
+///
+/// At most `run_size` triple changes are kept in memory at any time.
+/// All intermediate files are kept in a temporary directory inside
+/// `temp_path`, which is removed when the squash completes, fails or
+/// is cancelled. Like `squash_upto`, only triples are kept.
+#[tracing::instrument(
+    level = "debug",
+    skip_all,
+    fields(
+        layer = %name_to_string(layer.name()),
+        upto = %name_to_string(upto.name()),
+        changes = tracing::field::Empty
+    )
+)]
+pub async fn squash_upto_external_with_progress<
+    F: 'static + FileLoad + FileStore + Clone + Send + Sync,
+    P: AsRef<Path>,
+>(
+    layer: &InternalLayer,
+    upto: Arc<InternalLayer>,
+    output: ChildLayerFiles<F>,
+    temp_path: P,
+    run_size: usize,
+    progress: &Progress,
+) -> io::Result<()> {
+    let changes = InternalTripleStackIterator::from_layer_stack(layer, upto.name())
+        .map_err(|e| io::Error::new(io::ErrorKind::NotFound, e))?;
+    let base_node_value_count = upto.node_and_value_count() as u64;
+    let base_predicate_count = upto.predicate_count() as u64;
+
+    let temp_dir = TempDir::new_in(&temp_path)?;
+    let mut writer = RunWriter::new(
+        layer,
+        base_node_value_count,
+        base_predicate_count,
+        temp_dir.path(),
+    );
+    writer.write_runs(changes, run_size, progress).await?;
+
+    let (node_count, predicate_count, value_count, additions, removals) = if writer.runs.is_empty()
+    {
+        let builder = DictionarySetFileBuilder::from_files(
+            output.node_dictionary_files.clone(),
+            output.predicate_dictionary_files.clone(),
+            output.value_dictionary_files.clone(),
+        )
+        .await?;
+        builder.finalize().await?;
+        progress.dictionaries_merged(3)?;
+
+        (0, 0, 0, None, None)
+    } else {
+        let merged = merge_runs(
+            &writer.runs,
+            base_node_value_count,
+            base_predicate_count,
+            (
+                output.node_dictionary_files.clone(),
+                output.predicate_dictionary_files.clone(),
+                output.value_dictionary_files.clone(),
+            ),
+            progress,
+        )
+        .await?;
+
+        (
+            merged.node_count,
+            merged.predicate_count,
+            merged.value_count,
+            Some(merged.additions),
+            Some(merged.removals),
+        )
+    };
+
+    if layer.text_index().is_some() {
+        write_text_index_external(
+            &output.value_dictionary_files,
+            &output.text_index_file,
+            temp_dir.path(),
+            run_size,
+        )
+        .await?;
+    }
+
+    let mut builder =
+        ChildLayerFileBuilderPhase2::new(upto, output, node_count, predicate_count, value_count)
+            .await?;
+    let mut tally: u64 = 0;
+    if let Some(mut additions) = additions {
+        let mut last_triple = None;
+        while let Some(triple) = additions.try_next().await? {
+            if Some(triple) == last_triple {
+                continue;
+            }
+            last_triple = Some(triple);
+            builder
+                .add_triple_unchecked(triple.0, triple.1, triple.2)
+                .await?;
+            progress.triple_written()?;
+            tally += 1;
+        }
+    }
+    if let Some(mut removals) = removals {
+        let mut last_triple = None;
+        while let Some(triple) = removals.try_next().await? {
+            if Some(triple) == last_triple {
+                continue;
+            }
+            last_triple = Some(triple);
+            builder
+                .remove_triple_unchecked(triple.0, triple.1, triple.2)
+                .await?;
+            progress.triple_written()?;
+            tally += 1;
+        }
+    }
+    tracing::Span::current().record("changes", tally);
+    builder.finalize_with_progress(progress).await?;
+    progress.done();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::base::base_tests::base_layer_files;
+    use crate::layer::{Layer, ValueQuadTriple, ValueTriple};
+    use crate::storage::memory::{MemoryLabelStore, MemoryLayerStore};
+    use crate::storage::LayerStore;
+    use crate::store::Store;
+    use tdb_succinct::TdbDataType;
+    use tempfile::tempdir;
+
+    fn string_triples(layer: &dyn Layer) -> Vec<ValueTriple> {
+        let mut triples: Vec<_> = layer
+            .triples()
+            .map(|t| layer.id_triple_to_string(&t).unwrap())
+            .collect();
+        triples.sort();
+        triples
+    }
+
+    #[tokio::test]
+    async fn squash_external_over_many_runs() {
+        let layer_store = MemoryLayerStore::new();
+        let store = Store::new(MemoryLabelStore::new(), layer_store.clone());
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_node("cow", "likes", "duck"))
+            .unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("duck", "says", "quack"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+        let builder = base.open_write().await.unwrap();
+        builder
+            .remove_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_node("duck", "likes", "pond"))
+            .unwrap();
+        builder
+            .add_value_quad(ValueQuadTriple::new(
+                "farm",
+                ValueTriple::new_node("pig", "likes", "mud"),
+            ))
+            .unwrap();
+        let child = builder.commit().await.unwrap();
+        let layer = layer_store.get_layer(child.name()).await.unwrap().unwrap();
+
+        let temp = tempdir().unwrap();
+        let files = base_layer_files();
+        squash_external_with_progress(&layer, files.clone(), temp.path(), 1, &Progress::new())
+            .await
+            .unwrap();
+        let squashed = BaseLayer::load_from_files([0; 5], &files).await.unwrap();
+
+        assert_eq!(string_triples(&*layer), string_triples(&squashed));
+        assert_eq!(None, squashed.object_value_id(&String::make_entry(&"moo")));
+        let farm = squashed.graph_id("farm").unwrap();
+        let quads: Vec<_> = squashed
+            .quads_g(farm)
+            .map(|q| squashed.id_triple_to_string(&q.triple).unwrap())
+            .collect();
+        assert_eq!(vec![ValueTriple::new_node("pig", "likes", "mud")], quads);
+        assert_eq!(0, std::fs::read_dir(temp.path()).unwrap().count());
+    }
+}
@@ -109,6 +109,61 @@ impl InternalLayerTripleSubjectIterator {
         }
     }
 
+    /// Position this iterator at the first triple that is not smaller than the given triple.
+    pub fn seek_triple(mut self, triple: IdTriple) -> Self {
+        self.seek_triple_ref(triple);
+
+        self
+    }
+
+    pub fn seek_triple_ref(&mut self, triple: IdTriple) {
+        self.seek_subject_ref(triple.subject);
+        if triple.subject == 0
+            || self.s_position >= self.s_p_adjacency_list.left_count() as u64
+            || self.subject_at(self.s_position) != triple.subject
+        {
+            return;
+        }
+
+        // predicates are sorted within a subject, and objects within a subject-predicate pair
+        let s_p_end = self.s_p_adjacency_list.offset_for(self.s_position + 2);
+        let s_p_position = lower_bound(self.s_p_position, s_p_end, |pos| {
+            self.s_p_adjacency_list.num_at_pos(pos) >= triple.predicate
+        });
+        self.s_p_position = s_p_position;
+        self.sp_o_position = self.sp_o_adjacency_list.offset_for(s_p_position + 1);
+        if s_p_position == s_p_end {
+            self.s_position += 1;
+            return;
+        }
+        if self.s_p_adjacency_list.num_at_pos(s_p_position) != triple.predicate {
+            return;
+        }
+
+        let sp_o_end = self.sp_o_adjacency_list.offset_for(s_p_position + 2);
+        let sp_o_position = lower_bound(self.sp_o_position, sp_o_end, |pos| {
+            self.sp_o_adjacency_list.num_at_pos(pos) >= triple.object
+        });
+        if sp_o_position == sp_o_end {
+            if self.s_p_adjacency_list.bit_at_pos(s_p_position) {
+                self.s_position += 1;
+            }
+            self.s_p_position += 1;
+        }
+        self.sp_o_position = sp_o_position;
+    }
+
+    fn subject_at(&self, s_position: u64) -> u64 {
+        match self.subjects.as_ref() {
+            Some(subjects) => subjects.entry(s_position.try_into().unwrap()),
+            None => s_position + 1,
+        }
+    }
+
+    pub(super) fn subject_at_s_p_pos(&self, pos: u64) -> u64 {
+        self.subject_at(self.s_p_adjacency_list.left_at_pos(pos) - 1)
+    }
+
     pub fn seek_s_p_pos(&mut self, pos: u64) {
         self.peeked = None;
 
@@ -165,10 +220,7 @@ impl Iterator for InternalLayerTripleSubjectIterator {
                     continue;
                 }
 
-                let subject = match self.subjects.as_ref() {
-                    Some(subjects) => subjects.entry(s_pos.try_into().unwrap()),
-                    None => s_pos + 1,
-                };
+                let subject = self.subject_at(s_pos);
 
                 return Some(IdTriple::new(subject, predicate, object));
             }
@@ -176,6 +228,22 @@ impl Iterator for InternalLayerTripleSubjectIterator {
     }
 }
 
+/// Returns the first position in the given range for which the predicate holds.
+///
+/// The predicate has to be false for some prefix of the range, and true for the rest.
+pub(super) fn lower_bound<F: Fn(u64) -> bool>(mut start: u64, mut end: u64, predicate: F) -> u64 {
+    while start < end {
+        let mid = start + (end - start) / 2;
+        if predicate(mid) {
+            end = mid;
+        } else {
+            start = mid + 1;
+        }
+    }
+
+    start
+}
+
 #[derive(Clone)]
 pub struct OptInternalLayerTripleSubjectIterator(pub Option<InternalLayerTripleSubjectIterator>);
 
@@ -202,6 +270,16 @@ impl OptInternalLayerTripleSubjectIterator {
         )
     }
 
+    pub fn seek_triple_ref(&mut self, triple: IdTriple) {
+        if let Some(i) = self.0.as_mut() {
+            i.seek_triple_ref(triple)
+        };
+    }
+
+    pub fn seek_triple(self, triple: IdTriple) -> Self {
+        OptInternalLayerTripleSubjectIterator(self.0.map(|i| i.seek_triple(triple)))
+    }
+
     pub fn peek(&mut self) -> Option<&IdTriple> {
         self.0.as_mut().and_then(|i| i.peek())
     }
@@ -268,6 +346,19 @@ impl InternalTripleSubjectIterator {
 
         self
     }
+
+    /// Position this iterator at the first triple that is not smaller than the given triple.
+    pub fn seek_triple(mut self, triple: IdTriple) -> Self {
+        for p in self.positives.iter_mut() {
+            p.seek_triple_ref(triple);
+        }
+
+        for n in self.negatives.iter_mut() {
+            n.seek_triple_ref(triple);
+        }
+
+        self
+    }
 }
 
 impl Iterator for InternalTripleSubjectIterator {
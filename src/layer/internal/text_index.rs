@@ -0,0 +1,637 @@
+//! Text indexes, mapping the words in a layer's string values to those values.
+//!
+//! Text is split into tokens on everything that is not alphanumeric,
+//! and tokens are lowercased. Only textual values are indexed, such
+//! as strings and language strings.
+//!
+//! A text index consists of the sorted list of tokens, and for each
+//! token the sorted list of values it occurs in. Values are recorded
+//! by their id in the layer's own value dictionary, so the index does
+//! not depend on the layers below it.
+//!
+//! The index is stored in a single file as three logarrays, followed
+//! by the bytes of all tokens and a footer with the byte length of
+//! each logarray. The first logarray holds for each token the offset
+//! just past its bytes, the second for each token the offset just
+//! past its values, and the third the values themselves.
+//!
+//! Text indexes are optional. Only layers that were built with text
+//! indexing turned on have this file, and layers made out of other
+//! layers, like rollups and squashes, only have it if the layers they
+//! were made out of have it. Layers without an index are searched by
+//! scanning their value dictionary instead.
+use std::cmp::Ordering;
+use std::io;
+use std::path::Path;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{Stream, TryStreamExt};
+use tdb_succinct::util::{calculate_width, heap_sorted_stream};
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+
+use crate::storage::directory::DirectoryLayerStore;
+use crate::storage::memory::MemoryBackedStore;
+use crate::storage::*;
+use tdb_succinct::*;
+
+/// The default amount of token occurrences that is sorted in memory before being written to disk as a run.
+pub const TEXT_INDEX_RUN_SIZE: usize = 1_000_000;
+
+/// The footer holds the byte lengths of the three logarrays.
+const FOOTER_LEN: usize = 24;
+
+/// Split text into lowercased tokens.
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(|token| token.to_lowercase())
+}
+
+/// Returns the text of the given value if it is of a textual datatype.
+pub fn value_text(value: &TypedDictEntry) -> Option<String> {
+    match value.datatype() {
+        Datatype::String | Datatype::LangString | Datatype::NormalizedString | Datatype::Token => {
+            Some(String::from_utf8_lossy(&value.to_bytes()).into_owned())
+        }
+        _ => None,
+    }
+}
+
+/// The sorted and deduplicated token occurrences of the given dictionary entry.
+fn occurrences(id: u64, value: &TypedDictEntry) -> Vec<(Vec<u8>, u64)> {
+    let mut tokens: Vec<_> = match value_text(value) {
+        Some(text) => tokenize(&text).map(String::into_bytes).collect(),
+        None => return Vec::new(),
+    };
+    tokens.sort();
+    tokens.dedup();
+
+    tokens.into_iter().map(|token| (token, id)).collect()
+}
+
+/// An inverted index from tokens to the values of a layer containing them.
+#[derive(Clone)]
+pub struct TextIndex {
+    token_offsets: MonotonicLogArray,
+    value_offsets: MonotonicLogArray,
+    values: LogArray,
+    tokens: Bytes,
+}
+
+impl TextIndex {
+    /// Parse a text index from the contents of its file.
+    pub fn parse(mut bytes: Bytes) -> io::Result<Self> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "text index structures do not match up",
+            )
+        };
+        if bytes.len() < FOOTER_LEN {
+            return Err(invalid());
+        }
+        let mut footer = bytes.split_off(bytes.len() - FOOTER_LEN);
+        let lens = [
+            footer.get_u64() as usize,
+            footer.get_u64() as usize,
+            footer.get_u64() as usize,
+        ];
+        if lens.iter().sum::<usize>() > bytes.len() {
+            return Err(invalid());
+        }
+        let token_offsets = MonotonicLogArray::parse(bytes.split_to(lens[0]))?;
+        let value_offsets = MonotonicLogArray::parse(bytes.split_to(lens[1]))?;
+        let values = LogArray::parse(bytes.split_to(lens[2]))?;
+        let tokens = bytes;
+
+        let len = token_offsets.len();
+        if value_offsets.len() != len
+            || (len != 0
+                && (token_offsets.entry(len - 1) != tokens.len() as u64
+                    || value_offsets.entry(len - 1) != values.len() as u64))
+        {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            token_offsets,
+            value_offsets,
+            values,
+            tokens,
+        })
+    }
+
+    /// Returns the amount of distinct tokens in this index.
+    pub fn len(&self) -> usize {
+        self.token_offsets.len()
+    }
+
+    /// Returns true if this index contains no tokens.
+    pub fn is_empty(&self) -> bool {
+        self.token_offsets.is_empty()
+    }
+
+    fn start(offsets: &MonotonicLogArray, index: usize) -> usize {
+        if index == 0 {
+            0
+        } else {
+            offsets.entry(index - 1) as usize
+        }
+    }
+
+    fn token(&self, index: usize) -> &[u8] {
+        &self.tokens
+            [Self::start(&self.token_offsets, index)..self.token_offsets.entry(index) as usize]
+    }
+
+    fn token_index(&self, token: &str) -> Option<usize> {
+        let (mut start, mut end) = (0, self.len());
+        while start < end {
+            let mid = (start + end) / 2;
+            match self.token(mid).cmp(token.as_bytes()) {
+                Ordering::Equal => return Some(mid),
+                Ordering::Less => start = mid + 1,
+                Ordering::Greater => end = mid,
+            }
+        }
+
+        None
+    }
+
+    /// Iterator over the value dictionary ids of all values containing the given token, in sorted order.
+    pub fn lookup(&self, token: &str) -> impl Iterator<Item = u64> + Send {
+        let (start, end) = match self.token_index(token) {
+            Some(index) => (
+                Self::start(&self.value_offsets, index),
+                self.value_offsets.entry(index) as usize,
+            ),
+            None => (0, 0),
+        };
+        let values = self.values.clone();
+        (start..end).map(move |i| values.entry(i))
+    }
+
+    /// Iterator over the value dictionary ids of all values containing all given tokens, in sorted order.
+    pub fn search(&self, tokens: &[String]) -> SortedIntersection {
+        SortedIntersection::new(
+            tokens
+                .iter()
+                .map(|token| Box::new(self.lookup(token)) as Box<dyn Iterator<Item = u64> + Send>)
+                .collect(),
+        )
+    }
+}
+
+/// Iterator over the items that all given sorted iterators have in common.
+pub struct SortedIntersection {
+    iters: Vec<Box<dyn Iterator<Item = u64> + Send>>,
+}
+
+impl SortedIntersection {
+    /// Construct an intersection of the given sorted iterators.
+    ///
+    /// An intersection of no iterators is empty.
+    pub fn new(iters: Vec<Box<dyn Iterator<Item = u64> + Send>>) -> Self {
+        Self { iters }
+    }
+}
+
+impl Iterator for SortedIntersection {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        let (first, rest) = self.iters.split_first_mut()?;
+        let mut candidate = first.next()?;
+        let mut agreeing = 1;
+        let mut ix = 1 % (rest.len() + 1);
+        while agreeing <= rest.len() {
+            let iter = if ix == 0 {
+                &mut *first
+            } else {
+                &mut rest[ix - 1]
+            };
+            // skip ahead to the candidate
+            let item = iter.find(|item| *item >= candidate)?;
+            if item == candidate {
+                agreeing += 1;
+            } else {
+                candidate = item;
+                agreeing = 1;
+            }
+            ix = (ix + 1) % (rest.len() + 1);
+        }
+
+        Some(candidate)
+    }
+}
+
+/// Writes the sections of a text index from token occurrences given in sorted order.
+struct TextIndexWriter<F: 'static + FileLoad + FileStore> {
+    sections: [F; 4],
+    token_offsets: LogArrayFileBuilder<F::Write>,
+    value_offsets: LogArrayFileBuilder<F::Write>,
+    values: LogArrayFileBuilder<F::Write>,
+    tokens: F::Write,
+    token_len: u64,
+    value_count: u64,
+    last: Option<(Vec<u8>, u64)>,
+}
+
+impl<F: 'static + FileLoad + FileStore> TextIndexWriter<F> {
+    /// Construct a writer for at most the given amount of token bytes and occurrences.
+    ///
+    /// The bounds only determine the width of the logarrays, so they
+    /// may be larger than what ends up being written.
+    async fn new(
+        sections: [F; 4],
+        max_token_bytes: u64,
+        max_occurrences: u64,
+        max_value: u64,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            token_offsets: LogArrayFileBuilder::new(
+                sections[0].open_write().await?,
+                calculate_width(max_token_bytes),
+            ),
+            value_offsets: LogArrayFileBuilder::new(
+                sections[1].open_write().await?,
+                calculate_width(max_occurrences),
+            ),
+            values: LogArrayFileBuilder::new(
+                sections[2].open_write().await?,
+                calculate_width(max_value),
+            ),
+            tokens: sections[3].open_write().await?,
+            sections,
+            token_len: 0,
+            value_count: 0,
+            last: None,
+        })
+    }
+
+    async fn push(&mut self, token: Vec<u8>, value: u64) -> io::Result<()> {
+        match &self.last {
+            Some((last_token, last_value)) if *last_token == token => {
+                if *last_value == value {
+                    return Ok(());
+                }
+            }
+            last => {
+                if last.is_some() {
+                    self.value_offsets.push(self.value_count).await?;
+                }
+                self.tokens.write_all(&token).await?;
+                self.token_len += token.len() as u64;
+                self.token_offsets.push(self.token_len).await?;
+            }
+        }
+        self.values.push(value).await?;
+        self.value_count += 1;
+        self.last = Some((token, value));
+
+        Ok(())
+    }
+
+    async fn push_all<S: Stream<Item = io::Result<(Vec<u8>, u64)>> + Unpin>(
+        &mut self,
+        mut occurrences: S,
+    ) -> io::Result<()> {
+        while let Some((token, value)) = occurrences.try_next().await? {
+            self.push(token, value).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Finish the sections and write them, followed by the footer, to the given file.
+    async fn finalize<G: FileStore>(mut self, file: &G) -> io::Result<()> {
+        if self.last.is_some() {
+            self.value_offsets.push(self.value_count).await?;
+        }
+        self.token_offsets.finalize().await?;
+        self.value_offsets.finalize().await?;
+        self.values.finalize().await?;
+        self.tokens.flush().await?;
+        self.tokens.sync_all().await?;
+
+        let mut writer = file.open_write().await?;
+        let mut footer = BytesMut::with_capacity(FOOTER_LEN);
+        for (ix, section) in self.sections.iter().enumerate() {
+            let mut reader = section.open_read().await?;
+            let len = tokio::io::copy(&mut reader, &mut writer).await?;
+            if ix < 3 {
+                footer.put_u64(len);
+            }
+        }
+        writer.write_all(&footer).await?;
+        writer.flush().await?;
+        writer.sync_all().await
+    }
+}
+
+fn read_dictionary(maps: TypedDictionaryMaps) -> TypedDict {
+    TypedDict::from_parts(
+        maps.types_present_map,
+        maps.type_offsets_map,
+        maps.offsets_map,
+        maps.blocks_map,
+    )
+}
+
+/// Write a text index of the given value dictionary to the given file.
+///
+/// All token occurrences are sorted in memory. Use
+/// `write_text_index_external` where memory is bounded.
+pub async fn write_text_index<F: 'static + FileLoad + FileStore>(
+    value_dictionary_files: &TypedDictionaryFiles<F>,
+    file: &F,
+) -> io::Result<()> {
+    let dictionary = read_dictionary(value_dictionary_files.map_all().await?);
+    let occurrences = dictionary
+        .iter()
+        .zip(1..)
+        .flat_map(|(value, id)| occurrences(id, &value))
+        .collect();
+
+    write_occurrences_in_memory(occurrences, dictionary.num_entries() as u64, file).await
+}
+
+async fn write_occurrences_in_memory<F: FileStore>(
+    mut occurrences: Vec<(Vec<u8>, u64)>,
+    max_value: u64,
+    file: &F,
+) -> io::Result<()> {
+    occurrences.sort();
+    let token_bytes = occurrences
+        .iter()
+        .map(|(token, _)| token.len() as u64)
+        .sum();
+
+    let sections = [(); 4].map(|_| MemoryBackedStore::new());
+    let mut writer =
+        TextIndexWriter::new(sections, token_bytes, occurrences.len() as u64, max_value).await?;
+    writer
+        .push_all(futures::stream::iter(occurrences.into_iter().map(Ok)))
+        .await?;
+    writer.finalize(file).await
+}
+
+/// Sorted runs of token occurrences, written to a temporary directory.
+struct OccurrenceRuns {
+    store: DirectoryLayerStore,
+    dir: [u32; 5],
+    runs: Vec<(<DirectoryLayerStore as PersistentLayerStore>::File, usize)>,
+    token_bytes: u64,
+    count: u64,
+}
+
+impl OccurrenceRuns {
+    async fn new(temp_dir: &Path) -> io::Result<Self> {
+        let store = DirectoryLayerStore::new(temp_dir);
+        let dir = store.create_directory().await?;
+
+        Ok(Self {
+            store,
+            dir,
+            runs: Vec::new(),
+            token_bytes: 0,
+            count: 0,
+        })
+    }
+
+    async fn file(
+        &self,
+        name: &str,
+    ) -> io::Result<<DirectoryLayerStore as PersistentLayerStore>::File> {
+        self.store.get_file(self.dir, name).await
+    }
+
+    async fn write_run(&mut self, run: &mut Vec<(Vec<u8>, u64)>) -> io::Result<()> {
+        run.sort();
+        let file = self.file(&format!("run{}", self.runs.len())).await?;
+        let mut writer = file.open_write().await?;
+        for (token, value) in run.iter() {
+            writer.write_u32(token.len() as u32).await?;
+            writer.write_all(token).await?;
+            writer.write_u64(*value).await?;
+            self.token_bytes += token.len() as u64;
+        }
+        writer.flush().await?;
+        writer.sync_all().await?;
+
+        self.count += run.len() as u64;
+        self.runs.push((file, run.len()));
+        run.clear();
+        tracing::debug!(runs = self.runs.len(), "wrote text index run");
+
+        Ok(())
+    }
+}
+
+async fn read_occurrences<F: FileLoad>(
+    file: &F,
+    count: usize,
+) -> io::Result<impl Stream<Item = io::Result<(Vec<u8>, u64)>> + Unpin + Send> {
+    let reader = BufReader::new(file.open_read().await?);
+
+    Ok(Box::pin(futures::stream::try_unfold(
+        (reader, count),
+        |(mut reader, remaining)| async move {
+            if remaining == 0 {
+                return Ok(None);
+            }
+
+            let len = reader.read_u32().await? as usize;
+            let mut token = vec![0; len];
+            reader.read_exact(&mut token).await?;
+            let value = reader.read_u64().await?;
+
+            Ok(Some(((token, value), (reader, remaining - 1))))
+        },
+    )))
+}
+
+/// Write a text index of the given value dictionary to the given file, using a bounded amount of memory.
+///
+/// Token occurrences are sorted in runs of at most about `run_size`
+/// occurrences, which are written to a temporary directory inside
+/// `temp_dir` and then merged into the index.
+pub async fn write_text_index_external<F: 'static + FileLoad + FileStore>(
+    value_dictionary_files: &TypedDictionaryFiles<F>,
+    file: &F,
+    temp_dir: &Path,
+    run_size: usize,
+) -> io::Result<()> {
+    let dictionary = read_dictionary(value_dictionary_files.map_all().await?);
+    let max_value = dictionary.num_entries() as u64;
+    let temp_dir = TempDir::new_in(temp_dir)?;
+    let mut runs = OccurrenceRuns::new(temp_dir.path()).await?;
+
+    let mut run = Vec::new();
+    for (value, id) in dictionary.iter().zip(1..) {
+        run.extend(occurrences(id, &value));
+        if run.len() >= run_size {
+            runs.write_run(&mut run).await?;
+        }
+    }
+    if runs.runs.is_empty() {
+        // everything fit in a single run
+        return write_occurrences_in_memory(run, max_value, file).await;
+    }
+    if !run.is_empty() {
+        runs.write_run(&mut run).await?;
+    }
+
+    let mut streams = Vec::with_capacity(runs.runs.len());
+    for (run_file, count) in runs.runs.iter() {
+        streams.push(read_occurrences(run_file, *count).await?);
+    }
+    let occurrences = heap_sorted_stream(streams).await?;
+
+    let sections = [
+        runs.file("token_offsets").await?,
+        runs.file("value_offsets").await?,
+        runs.file("values").await?,
+        runs.file("tokens").await?,
+    ];
+    let mut writer =
+        TextIndexWriter::new(sections, runs.token_bytes, runs.count, max_value).await?;
+    writer.push_all(occurrences).await?;
+    writer.finalize(file).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    async fn dictionary_files(
+        mut values: Vec<TypedDictEntry>,
+    ) -> (TypedDict, TypedDictionaryFiles<MemoryBackedStore>) {
+        values.sort();
+        let mut builder = TypedDictBufBuilder::new(
+            BytesMut::new(),
+            BytesMut::new(),
+            BytesMut::new(),
+            BytesMut::new(),
+        );
+        builder.add_all(values.into_iter());
+        let (mut types_present, mut type_offsets, mut offsets, mut blocks) = builder.finalize();
+
+        let files = TypedDictionaryFiles {
+            types_present_file: MemoryBackedStore::new(),
+            type_offsets_file: MemoryBackedStore::new(),
+            blocks_file: MemoryBackedStore::new(),
+            offsets_file: MemoryBackedStore::new(),
+        };
+        files
+            .write_all_from_bufs(
+                &mut types_present,
+                &mut type_offsets,
+                &mut offsets,
+                &mut blocks,
+            )
+            .await
+            .unwrap();
+
+        (read_dictionary(files.map_all().await.unwrap()), files)
+    }
+
+    #[test]
+    fn tokens_are_lowercased_words() {
+        assert_eq!(
+            vec!["the", "cow", "says", "moo", "ünïcode", "42"],
+            tokenize("The cow says: 'Moo!' (ÜNÏCODE, 42)").collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn build_and_look_up_text_index() {
+        let (dictionary, files) = dictionary_files(vec![
+            String::make_entry(&"the cow says moo"),
+            String::make_entry(&"the pig says oink"),
+            String::make_entry(&"Moo moo"),
+            u32::make_entry(&42),
+        ])
+        .await;
+        let file = MemoryBackedStore::new();
+        write_text_index(&files, &file).await.unwrap();
+        let index = TextIndex::parse(file.map().await.unwrap()).unwrap();
+
+        let texts = |tokens: &[&str]| -> Vec<String> {
+            let tokens: Vec<String> = tokens.iter().map(|t| t.to_string()).collect();
+            index
+                .search(&tokens)
+                .map(|id| value_text(&dictionary.entry(id as usize).unwrap()).unwrap())
+                .collect()
+        };
+        assert_eq!(vec!["Moo moo", "the cow says moo"], texts(&["moo"]));
+        assert_eq!(
+            vec!["the cow says moo", "the pig says oink"],
+            texts(&["says"])
+        );
+        assert_eq!(vec!["the cow says moo"], texts(&["says", "moo", "the"]));
+        assert!(texts(&["moo", "oink"]).is_empty());
+        assert!(texts(&["horse"]).is_empty());
+        assert!(texts(&["42"]).is_empty());
+        assert!(texts(&[]).is_empty());
+        assert_eq!(6, index.len());
+    }
+
+    #[tokio::test]
+    async fn empty_index_without_text() {
+        let (_, files) = dictionary_files(vec![u32::make_entry(&42)]).await;
+        let file = MemoryBackedStore::new();
+        write_text_index(&files, &file).await.unwrap();
+        let index = TextIndex::parse(file.map().await.unwrap()).unwrap();
+
+        assert!(index.is_empty());
+        assert!(index.lookup("42").next().is_none());
+    }
+
+    #[test]
+    fn intersection_skips_ahead() {
+        let lists: Vec<Box<dyn Iterator<Item = u64> + Send>> = vec![
+            Box::new(vec![1, 3, 5, 7, 9, 11].into_iter()),
+            Box::new(vec![2, 3, 4, 9, 10, 11].into_iter()),
+            Box::new(vec![3, 8, 9, 11, 12].into_iter()),
+        ];
+
+        assert_eq!(
+            vec![3, 9, 11],
+            SortedIntersection::new(lists).collect::<Vec<_>>()
+        );
+        assert!(SortedIntersection::new(Vec::new()).next().is_none());
+    }
+
+    #[tokio::test]
+    async fn external_index_matches_in_memory_index() {
+        let values: Vec<_> = (0..200)
+            .map(|i| String::make_entry(&format!("word{} word{} shared", i % 7, i % 13)))
+            .chain((0..20).map(|i| u32::make_entry(&i)))
+            .collect();
+        let (_, files) = dictionary_files(values).await;
+
+        let in_memory = MemoryBackedStore::new();
+        write_text_index(&files, &in_memory).await.unwrap();
+        let in_memory = TextIndex::parse(in_memory.map().await.unwrap()).unwrap();
+
+        let temp = tempdir().unwrap();
+        let external = MemoryBackedStore::new();
+        write_text_index_external(&files, &external, temp.path(), 50)
+            .await
+            .unwrap();
+        let external = TextIndex::parse(external.map().await.unwrap()).unwrap();
+        assert_eq!(0, std::fs::read_dir(temp.path()).unwrap().count());
+
+        assert_eq!(in_memory.len(), external.len());
+        for token in ["word0", "word6", "word12", "shared", "missing"] {
+            assert_eq!(
+                in_memory.lookup(token).collect::<Vec<_>>(),
+                external.lookup(token).collect::<Vec<_>>()
+            );
+        }
+        assert_eq!(200, external.lookup("shared").count());
+    }
+}
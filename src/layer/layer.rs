@@ -4,6 +4,9 @@ use std::hash::Hash;
 
 use tdb_succinct::{TdbDataType, TypedDictEntry};
 
+use super::cursor::{TripleCursor, TripleCursorIterator};
+use super::stream::IdTripleStream;
+
 /// A layer containing dictionary entries and triples.
 ///
 /// A layer can be queried. To answer queries, layers will check their
@@ -53,6 +56,25 @@ pub trait Layer: Send + Sync {
         })
     }
 
+    /// The ids of all values from `from` up to but not including `to`, in the order of their values.
+    ///
+    /// Only values of the datatype of `from` are returned. Values are
+    /// ordered by their lexical form. Range queries over custom
+    /// datatypes with their own ordering have to go through
+    /// `DatatypeRegistry::value_ids_in_range` instead.
+    fn object_value_ids_in_range(
+        &self,
+        from: &TypedDictEntry,
+        to: &TypedDictEntry,
+    ) -> Box<dyn Iterator<Item = u64> + Send>;
+
+    /// The ids of all textual values containing every word of the query, in id order.
+    ///
+    /// The query and values are split into words on everything that is
+    /// not alphanumeric, and words are compared case-insensitively.
+    /// Only values that are still the object of some triple are returned.
+    fn search_text(&self, query: &str) -> Box<dyn Iterator<Item = u64> + Send>;
+
     /// Check if the given id refers to a node.
     ///
     /// This will return None if the id doesn't refer to anything.
@@ -114,6 +136,39 @@ pub trait Layer: Send + Sync {
 
     fn triples_o(&self, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send>;
 
+    /// Iterator over the triples matching the cursor's pattern that come after its last triple.
+    ///
+    /// The iterator keeps track of its position, so that iteration can be resumed later on.
+    fn triples_from_cursor(&self, cursor: &TripleCursor) -> TripleCursorIterator;
+
+    /// Stream over all triples known to this layer.
+    ///
+    /// Unlike `triples`, this stream will yield to the runtime in
+    /// between chunks of triples.
+    fn triples_stream(&self) -> IdTripleStream {
+        IdTripleStream::new(self.triples())
+    }
+
+    /// Stream over all triples with the given subject.
+    fn triples_s_stream(&self, subject: u64) -> IdTripleStream {
+        IdTripleStream::new(self.triples_s(subject))
+    }
+
+    /// Stream over all triples with the given subject and predicate.
+    fn triples_sp_stream(&self, subject: u64, predicate: u64) -> IdTripleStream {
+        IdTripleStream::new(self.triples_sp(subject, predicate))
+    }
+
+    /// Stream over all triples with the given predicate.
+    fn triples_p_stream(&self, predicate: u64) -> IdTripleStream {
+        IdTripleStream::new(self.triples_p(predicate))
+    }
+
+    /// Stream over all triples with the given object.
+    fn triples_o_stream(&self, object: u64) -> IdTripleStream {
+        IdTripleStream::new(self.triples_o(object))
+    }
+
     /// Convert all known strings in the given string triple to ids.
     fn value_triple_to_partially_resolved(&self, triple: ValueTriple) -> PartiallyResolvedTriple {
         PartiallyResolvedTriple {
@@ -162,7 +217,61 @@ pub trait Layer: Send + Sync {
         self.triple_addition_count() - self.triple_removal_count()
     }
 
+    /// Returns the amount of triples with the given subject.
+    ///
+    /// This is equivalent to `self.triples_s(subject).count()`, but
+    /// does not iterate over the triples.
+    fn count_s(&self, subject: u64) -> usize;
+
+    /// Returns the amount of triples with the given subject and predicate.
+    fn count_sp(&self, subject: u64, predicate: u64) -> usize;
+
+    /// Returns the amount of triples with the given predicate.
+    fn count_p(&self, predicate: u64) -> usize;
+
+    /// Returns the amount of triples with the given object.
+    fn count_o(&self, object: u64) -> usize;
+
     fn single_triple_sp(&self, subject: u64, predicate: u64) -> Option<IdTriple>;
+
+    /// Iterator over all quads in the given graph.
+    ///
+    /// A quad is only returned as long as its triple exists in this
+    /// layer, so removing a triple also hides it from every graph it
+    /// was put in. Layers that don't support graphs have no quads.
+    fn quads_g(&self, _graph: u64) -> Box<dyn Iterator<Item = IdQuadTriple> + Send> {
+        Box::new(std::iter::empty())
+    }
+
+    /// Returns the ids of all graphs that the given triple is in.
+    ///
+    /// Layers that don't support graphs have no graphs.
+    fn triple_graphs(&self, _triple: &IdTriple) -> Vec<u64> {
+        Vec::new()
+    }
+
+    /// Returns the id of the given graph name, or None if it is not a known node.
+    ///
+    /// Graph names share the dictionary of nodes.
+    fn graph_id(&self, graph: &str) -> Option<u64> {
+        self.object_node_id(graph)
+    }
+
+    /// Convert a `ValueQuadTriple` to an `IdQuadTriple`, returning None if any of the strings in the quad could not be resolved.
+    fn value_quad_to_id(&self, quad: &ValueQuadTriple) -> Option<IdQuadTriple> {
+        self.graph_id(&quad.graph).and_then(|graph| {
+            self.value_triple_to_id(&quad.triple)
+                .map(|triple| IdQuadTriple::new(graph, triple))
+        })
+    }
+
+    /// Convert an id quad to the corresponding string version, returning None if any of those ids could not be converted.
+    fn id_quad_to_string(&self, quad: &IdQuadTriple) -> Option<ValueQuadTriple> {
+        self.id_object_node(quad.graph).and_then(|graph| {
+            self.id_triple_to_string(&quad.triple)
+                .map(|triple| ValueQuadTriple { graph, triple })
+        })
+    }
 }
 
 pub struct LayerCounts {
@@ -251,6 +360,39 @@ impl ValueTriple {
     }
 }
 
+/// A triple in a graph, stored as numerical ids.
+///
+/// The graph is a node id. Quads are ordered by graph first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IdQuadTriple {
+    pub graph: u64,
+    pub triple: IdTriple,
+}
+
+impl IdQuadTriple {
+    /// Construct a new id quad.
+    pub fn new(graph: u64, triple: IdTriple) -> Self {
+        IdQuadTriple { graph, triple }
+    }
+}
+
+/// A triple in a graph, stored as strings.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ValueQuadTriple {
+    pub graph: String,
+    pub triple: ValueTriple,
+}
+
+impl ValueQuadTriple {
+    /// Construct a new quad putting the given triple in the given graph.
+    pub fn new(graph: &str, triple: ValueTriple) -> Self {
+        ValueQuadTriple {
+            graph: graph.to_owned(),
+            triple,
+        }
+    }
+}
+
 /// Either a resolved id or an unresolved inner type.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum PossiblyResolved<T: Clone + PartialEq + Eq + PartialOrd + Ord + Hash> {
@@ -405,6 +547,7 @@ impl ObjectType {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::layer::cursor::TriplePattern;
     use crate::layer::internal::base::base_tests::base_layer_files;
     use crate::layer::internal::base::BaseLayer;
     use crate::layer::internal::child::child_tests::child_layer_files;
@@ -656,4 +799,279 @@ mod tests {
 
         assert_eq!(expected, results);
     }
+
+    #[tokio::test]
+    async fn value_ranges_across_layers() {
+        let files = base_layer_files();
+        let mut builder = SimpleLayerBuilder::new([1, 2, 3, 4, 5], files.clone());
+        for feet in [4_u32, 2, 8] {
+            builder.add_value_triple(ValueTriple::new_value(
+                &format!("animal{}", feet),
+                "num_feet",
+                u32::make_entry(&feet),
+            ));
+        }
+        builder.add_value_triple(ValueTriple::new_string_value("animal4", "name", "cow"));
+        builder.commit().await.unwrap();
+
+        let base: Arc<InternalLayer> = Arc::new(
+            BaseLayer::load_from_files([1, 2, 3, 4, 5], &files)
+                .await
+                .unwrap(),
+        );
+
+        let files = child_layer_files();
+        let mut builder =
+            SimpleLayerBuilder::from_parent([5, 4, 3, 2, 1], base.clone(), files.clone());
+        builder.add_value_triple(ValueTriple::new_value(
+            "animal6",
+            "num_feet",
+            u32::make_entry(&6_u32),
+        ));
+        builder.commit().await.unwrap();
+
+        let child: Arc<InternalLayer> = Arc::new(
+            ChildLayer::load_from_files([5, 4, 3, 2, 1], base.clone(), &files)
+                .await
+                .unwrap(),
+        );
+
+        let feet_in_range = |layer: &InternalLayer, from: u32, to: u32| -> Vec<u32> {
+            layer
+                .object_value_ids_in_range(&u32::make_entry(&from), &u32::make_entry(&to))
+                .map(|id| layer.id_object_value(id).unwrap().as_val::<u32, u32>())
+                .collect()
+        };
+
+        assert_eq!(vec![2, 4], feet_in_range(&base, 0, 6));
+        assert_eq!(vec![4, 6, 8], feet_in_range(&child, 3, 9));
+        assert_eq!(vec![6], feet_in_range(&child, 5, 8));
+        assert!(feet_in_range(&child, 9, 20).is_empty());
+        assert!(feet_in_range(&child, 6, 6).is_empty());
+    }
+
+    #[tokio::test]
+    async fn search_text_across_layers() {
+        let files = base_layer_files();
+        let mut builder = SimpleLayerBuilder::new([1, 2, 3, 4, 5], files.clone());
+        builder.set_text_index(true);
+        builder.add_value_triple(ValueTriple::new_string_value("cow", "says", "Moo, moo!"));
+        builder.add_value_triple(ValueTriple::new_string_value("pig", "says", "oink"));
+        builder.add_value_triple(ValueTriple::new_string_value(
+            "cow",
+            "name",
+            "Bessie the cow",
+        ));
+        builder.add_value_triple(ValueTriple::new_value(
+            "cow",
+            "num_feet",
+            u32::make_entry(&4_u32),
+        ));
+        builder.commit().await.unwrap();
+
+        let base: Arc<InternalLayer> = Arc::new(
+            BaseLayer::load_from_files([1, 2, 3, 4, 5], &files)
+                .await
+                .unwrap(),
+        );
+        assert!(base.text_index().is_some());
+
+        let files = child_layer_files();
+        let mut builder =
+            SimpleLayerBuilder::from_parent([5, 4, 3, 2, 1], base.clone(), files.clone());
+        builder.set_text_index(true);
+        builder.add_value_triple(ValueTriple::new_string_value("duck", "says", "quack"));
+        builder.add_value_triple(ValueTriple::new_string_value("duck", "name", "the duck"));
+        builder.remove_value_triple(ValueTriple::new_string_value("pig", "says", "oink"));
+        builder.commit().await.unwrap();
+
+        let child: Arc<InternalLayer> = Arc::new(
+            ChildLayer::load_from_files([5, 4, 3, 2, 1], base.clone(), &files)
+                .await
+                .unwrap(),
+        );
+
+        let search = |layer: &InternalLayer, query: &str| -> Vec<String> {
+            let mut texts: Vec<String> = layer
+                .search_text(query)
+                .map(|id| {
+                    layer
+                        .id_object_value(id)
+                        .unwrap()
+                        .as_val::<String, String>()
+                })
+                .collect();
+            texts.sort();
+            texts
+        };
+
+        assert_eq!(vec!["Moo, moo!"], search(&base, "moo"));
+        assert_eq!(vec!["oink"], search(&base, "OINK"));
+        assert_eq!(vec!["Bessie the cow"], search(&child, "the cow"));
+        assert_eq!(vec!["Bessie the cow", "the duck"], search(&child, "the"));
+        assert!(search(&child, "oink").is_empty());
+        assert!(search(&child, "4").is_empty());
+        assert!(search(&child, "the horse").is_empty());
+        assert!(search(&child, " ,!").is_empty());
+    }
+
+    fn check_resume<F: Fn(&TripleCursor) -> TripleCursorIterator>(
+        pattern: TriplePattern,
+        expected: Vec<IdTriple>,
+        resume: F,
+    ) {
+        for i in 0..=expected.len() {
+            let cursor = match i {
+                0 => TripleCursor::new(pattern),
+                _ => TripleCursor::after(pattern, expected[i - 1]).unwrap(),
+            };
+            let cursor = TripleCursor::from_bytes(&cursor.to_bytes()).unwrap();
+            let resumed: Vec<_> = resume(&cursor).collect();
+            assert_eq!(expected[i..], resumed[..], "{:?}", cursor);
+        }
+
+        // the cursor of a partially consumed iterator continues where it left off
+        let mut iter = resume(&TripleCursor::new(pattern));
+        let first: Vec<_> = iter.by_ref().take(2).collect();
+        let rest: Vec<_> = resume(&iter.cursor()).collect();
+        assert_eq!(expected, [first, rest].concat());
+    }
+
+    async fn animal_layers() -> (Arc<InternalLayer>, Arc<InternalLayer>) {
+        let files = base_layer_files();
+        let mut builder = SimpleLayerBuilder::new([1, 2, 3, 4, 5], files.clone());
+        for subject in ["cow", "duck", "pig", "sheep"] {
+            for predicate in ["likes", "hates"] {
+                for object in ["cow", "duck", "pig", "sheep"] {
+                    if subject != object {
+                        builder.add_value_triple(ValueTriple::new_node(subject, predicate, object));
+                    }
+                }
+            }
+            builder.add_value_triple(ValueTriple::new_string_value(subject, "says", "hello"));
+        }
+        builder.commit().await.unwrap();
+
+        let base: Arc<InternalLayer> = Arc::new(
+            BaseLayer::load_from_files([1, 2, 3, 4, 5], &files)
+                .await
+                .unwrap(),
+        );
+
+        let files = child_layer_files();
+        let mut builder =
+            SimpleLayerBuilder::from_parent([5, 4, 3, 2, 1], base.clone(), files.clone());
+        builder.remove_value_triple(ValueTriple::new_node("cow", "likes", "pig"));
+        builder.remove_value_triple(ValueTriple::new_node("duck", "likes", "pig"));
+        builder.remove_value_triple(ValueTriple::new_node("duck", "hates", "cow"));
+        builder.remove_value_triple(ValueTriple::new_string_value("pig", "says", "hello"));
+        builder.add_value_triple(ValueTriple::new_node("horse", "likes", "cow"));
+        builder.add_value_triple(ValueTriple::new_node("cow", "likes", "horse"));
+        builder.add_value_triple(ValueTriple::new_string_value("pig", "says", "oink"));
+        builder.commit().await.unwrap();
+
+        let child: Arc<InternalLayer> = Arc::new(
+            ChildLayer::load_from_files([5, 4, 3, 2, 1], base.clone(), &files)
+                .await
+                .unwrap(),
+        );
+
+        (base, child)
+    }
+
+    fn all_patterns(layer: &InternalLayer) -> Vec<TriplePattern> {
+        let node_count = layer.node_and_value_count() as u64;
+        let predicate_count = layer.predicate_count() as u64;
+        let mut patterns = vec![TriplePattern::All];
+        for id in 1..=node_count + 1 {
+            patterns.push(TriplePattern::Subject(id));
+            patterns.push(TriplePattern::Object(id));
+            for predicate in 1..=predicate_count + 1 {
+                patterns.push(TriplePattern::SubjectPredicate(id, predicate));
+            }
+        }
+        for predicate in 1..=predicate_count + 1 {
+            patterns.push(TriplePattern::Predicate(predicate));
+        }
+
+        patterns
+    }
+
+    #[tokio::test]
+    async fn resume_triples_from_cursor() {
+        let (base, child) = animal_layers().await;
+        for pattern in all_patterns(&child) {
+            for layer in [&base, &child] {
+                let expected: Vec<_> = match pattern {
+                    TriplePattern::All => layer.triples().collect(),
+                    TriplePattern::Subject(s) => layer.triples_s(s).collect(),
+                    TriplePattern::SubjectPredicate(s, p) => layer.triples_sp(s, p).collect(),
+                    TriplePattern::Predicate(p) => layer.triples_p(p).collect(),
+                    TriplePattern::Object(o) => layer.triples_o(o).collect(),
+                };
+                check_resume(pattern, expected, |cursor| {
+                    layer.triples_from_cursor(cursor)
+                });
+            }
+
+            let changes = |additions: bool| -> Vec<IdTriple> {
+                let all = match additions {
+                    true => child.internal_triple_additions(),
+                    false => child.internal_triple_removals(),
+                };
+                match pattern {
+                    TriplePattern::Object(o) => {
+                        let mut triples: Vec<_> = all.filter(|t| t.object == o).collect();
+                        triples.sort_by_key(|t| (t.subject, t.predicate));
+                        triples
+                    }
+                    _ => all.filter(|t| pattern.matches(t)).collect(),
+                }
+            };
+            check_resume(pattern, changes(true), |cursor| {
+                child.internal_triple_additions_from_cursor(cursor)
+            });
+            check_resume(pattern, changes(false), |cursor| {
+                child.internal_triple_removals_from_cursor(cursor)
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn count_triples_matching_patterns() {
+        let (base, child) = animal_layers().await;
+        for pattern in all_patterns(&child) {
+            for layer in [&base, &child] {
+                let (count, expected) = match pattern {
+                    TriplePattern::All => (layer.triple_count(), layer.triples().count()),
+                    TriplePattern::Subject(s) => (layer.count_s(s), layer.triples_s(s).count()),
+                    TriplePattern::SubjectPredicate(s, p) => {
+                        (layer.count_sp(s, p), layer.triples_sp(s, p).count())
+                    }
+                    TriplePattern::Predicate(p) => (layer.count_p(p), layer.triples_p(p).count()),
+                    TriplePattern::Object(o) => (layer.count_o(o), layer.triples_o(o).count()),
+                };
+                assert_eq!(expected, count, "{:?}", pattern);
+
+                assert_eq!(
+                    layer
+                        .internal_triple_additions()
+                        .filter(|t| pattern.matches(t))
+                        .count(),
+                    layer.internal_triple_layer_addition_count_matching(pattern),
+                    "{:?}",
+                    pattern
+                );
+                assert_eq!(
+                    layer
+                        .internal_triple_removals()
+                        .filter(|t| pattern.matches(t))
+                        .count(),
+                    layer.internal_triple_layer_removal_count_matching(pattern),
+                    "{:?}",
+                    pattern
+                );
+            }
+        }
+    }
 }
@@ -4,12 +4,18 @@
 //! in such a stack is a base layer, which contains an intial data
 //! set. On top of that, each layer stores additions and removals.
 pub mod builder;
+mod cursor;
+mod datatype;
 pub mod id_map;
 mod internal;
 mod layer;
 mod simple_builder;
+mod stream;
 
+pub use cursor::*;
+pub use datatype::*;
 pub use id_map::*;
 pub use internal::*;
 pub use layer::*;
 pub use simple_builder::*;
+pub use stream::*;
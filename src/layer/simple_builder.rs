@@ -12,14 +12,16 @@
 use super::internal::*;
 use super::layer::*;
 use crate::storage::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io;
 use std::pin::Pin;
 use std::sync::Arc;
 
 use futures::future::Future;
+use tracing::Instrument;
 
 use bitvec::prelude::*;
+use rayon::prelude::*;
 
 /// A layer builder trait with no generic typing.
 ///
@@ -38,23 +40,85 @@ pub trait LayerBuilder: Send + Sync {
     fn remove_value_triple(&mut self, triple: ValueTriple);
     /// Remove an id triple
     fn remove_id_triple(&mut self, triple: IdTriple);
+    /// Add a string triple and put it in the given graph
+    fn add_value_quad(&mut self, quad: ValueQuadTriple);
+    /// Add an id triple and put it in the given graph
+    fn add_id_quad(&mut self, quad: IdQuadTriple);
+    /// Take a string triple out of the given graph, leaving the triple itself in place
+    fn remove_value_quad(&mut self, quad: ValueQuadTriple);
+    /// Take an id triple out of the given graph, leaving the triple itself in place
+    fn remove_id_quad(&mut self, quad: IdQuadTriple);
+    /// Returns the additions and removals made so far as string triples
+    ///
+    /// These are the changes as they were made, so they may still
+    /// contain duplicates and triples that cancel each other out.
+    fn value_triple_changes(&self) -> (Vec<ValueTriple>, Vec<ValueTriple>);
+    /// Merge a shard that was filled separately into this builder
+    ///
+    /// The shard should have been created with the same parent as
+    /// this builder. Returns an error, leaving this builder
+    /// unchanged, if the shard contains ids that can't be resolved.
+    fn merge_shard(&mut self, shard: LayerBuilderShard) -> io::Result<()> {
+        let (quad_additions, quad_removals) = shard.value_quads()?;
+        let (additions, removals) = shard.try_into_value_triples()?;
+        for triple in additions {
+            self.add_value_triple(triple);
+        }
+        for triple in removals {
+            self.remove_value_triple(triple);
+        }
+        for quad in quad_additions {
+            self.add_value_quad(quad);
+        }
+        for quad in quad_removals {
+            self.remove_value_quad(quad);
+        }
+
+        Ok(())
+    }
+    /// Write a text index for the values of this layer on commit
+    ///
+    /// Layers are built without a text index by default, in which
+    /// case text search falls back to scanning the value dictionary.
+    fn set_text_index(&mut self, _enabled: bool) {}
     /// Commit the layer to storage
     fn commit(self) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>>;
     /// Commit a boxed layer to storage
     fn commit_boxed(self: Box<Self>) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>>;
+    /// Commit a boxed layer to storage, reporting to and cancellable through `progress`
+    fn commit_boxed_with_progress(
+        self: Box<Self>,
+        progress: Progress,
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        if let Err(e) = progress.check() {
+            return Box::pin(futures::future::err(e));
+        }
+        let commit = self.commit_boxed();
+        Box::pin(async move {
+            commit.await?;
+            progress.done();
+
+            Ok(())
+        })
+    }
 }
 
-/// A layer builder
+/// The in-memory state of a layer builder.
 ///
-/// `SimpleLayerBuilder` provides methods for adding and removing
-/// triples, and for committing the layer builder to storage.
+/// A shard keeps its own dictionary of new nodes, predicates and
+/// values, and its own buffers of added and removed triples. Shards
+/// can therefore be filled independently, for example from different
+/// threads, and then be merged into a builder with `merge_shard`.
+///
+/// Id triples added to a shard should only refer to entries that
+/// already exist in the parent layer.
 #[derive(Clone)]
-pub struct SimpleLayerBuilder<F: 'static + FileLoad + FileStore + Clone> {
-    name: [u32; 5],
+pub struct LayerBuilderShard {
     parent: Option<Arc<dyn Layer>>,
-    files: LayerFiles<F>,
     id_additions: Vec<IdTriple>,
     id_removals: Vec<IdTriple>,
+    quad_additions: Vec<IdQuadTriple>,
+    quad_removals: Vec<IdQuadTriple>,
 
     nodes_values_map: HashMap<ObjectType, u64>,
     predicates_map: HashMap<String, u64>,
@@ -65,36 +129,20 @@ pub struct SimpleLayerBuilder<F: 'static + FileLoad + FileStore + Clone> {
     val_count: usize,
 }
 
-impl<F: 'static + FileLoad + FileStore + Clone> SimpleLayerBuilder<F> {
-    /// Construct a layer builder for a base layer
-    pub fn new(name: [u32; 5], files: BaseLayerFiles<F>) -> Self {
-        Self {
-            name,
-            parent: None,
-            files: LayerFiles::Base(files),
-            id_additions: Vec::with_capacity(0),
-            id_removals: Vec::with_capacity(0),
-
-            nodes_values_map: HashMap::new(),
-            predicates_map: HashMap::new(),
-            nodes_values_map_count: 0,
-            predicates_map_count: 0,
-            node_count: 0,
-            pred_count: 0,
-            val_count: 0,
-        }
-    }
-
-    /// Construct a layer builder for a child layer
-    pub fn from_parent(name: [u32; 5], parent: Arc<dyn Layer>, files: ChildLayerFiles<F>) -> Self {
-        let nodes_values_map_count = parent.node_and_value_count();
-        let predicates_map_count = parent.predicate_count();
+impl LayerBuilderShard {
+    /// Construct a shard for a layer with the given parent.
+    pub fn new(parent: Option<Arc<dyn Layer>>) -> Self {
+        let nodes_values_map_count = parent
+            .as_ref()
+            .map(|p| p.node_and_value_count())
+            .unwrap_or(0);
+        let predicates_map_count = parent.as_ref().map(|p| p.predicate_count()).unwrap_or(0);
         Self {
-            name,
-            parent: Some(parent),
-            files: LayerFiles::Child(files),
+            parent,
             id_additions: Vec::new(),
             id_removals: Vec::new(),
+            quad_additions: Vec::new(),
+            quad_removals: Vec::new(),
 
             nodes_values_map: HashMap::new(),
             predicates_map: HashMap::new(),
@@ -106,17 +154,348 @@ impl<F: 'static + FileLoad + FileStore + Clone> SimpleLayerBuilder<F> {
         }
     }
 
-    fn calculate_triple(&mut self, triple: ValueTriple) -> IdTriple {
-        let subject = ObjectType::Node(triple.subject);
-        let predicate = triple.predicate;
-        let object = triple.object;
-        let subject_id = if let Some(n) = self.nodes_values_map.get(&subject) {
+    /// Return the parent if it exists
+    pub fn parent(&self) -> Option<Arc<dyn Layer>> {
+        self.parent.clone()
+    }
+
+    /// Add a string triple
+    pub fn add_value_triple(&mut self, addition: ValueTriple) {
+        let triple = self.calculate_triple(addition);
+        self.id_additions.push(triple);
+    }
+
+    /// Add an id triple
+    pub fn add_id_triple(&mut self, triple: IdTriple) {
+        self.id_additions.push(triple);
+    }
+
+    /// Remove a string triple
+    pub fn remove_value_triple(&mut self, removal: ValueTriple) {
+        let triple = self.calculate_triple(removal);
+        self.id_removals.push(triple);
+    }
+
+    /// Remove an id triple
+    pub fn remove_id_triple(&mut self, triple: IdTriple) {
+        self.id_removals.push(triple);
+    }
+
+    /// Add a string triple and put it in the given graph
+    pub fn add_value_quad(&mut self, quad: ValueQuadTriple) {
+        let graph = self.calculate_node(quad.graph);
+        let triple = self.calculate_triple(quad.triple);
+        self.add_id_quad(IdQuadTriple::new(graph, triple));
+    }
+
+    /// Add an id triple and put it in the given graph
+    pub fn add_id_quad(&mut self, quad: IdQuadTriple) {
+        self.id_additions.push(quad.triple);
+        self.quad_additions.push(quad);
+    }
+
+    /// Take a string triple out of the given graph, leaving the triple itself in place
+    pub fn remove_value_quad(&mut self, quad: ValueQuadTriple) {
+        let graph = self.calculate_node(quad.graph);
+        let triple = self.calculate_triple(quad.triple);
+        self.remove_id_quad(IdQuadTriple::new(graph, triple));
+    }
+
+    /// Take an id triple out of the given graph, leaving the triple itself in place
+    pub fn remove_id_quad(&mut self, quad: IdQuadTriple) {
+        self.quad_removals.push(quad);
+    }
+
+    fn parent_offsets(&self) -> (usize, usize) {
+        (
+            self.nodes_values_map_count - self.node_count - self.val_count,
+            self.predicates_map_count - self.pred_count,
+        )
+    }
+
+    /// Returns an error if any triple or quad of this shard refers to an id that is neither in the parent nor in this shard.
+    fn check_ids(&self) -> io::Result<()> {
+        let node_value_count = self.nodes_values_map_count as u64;
+        let predicate_count = self.predicates_map_count as u64;
+        let check_triple = |triple: &IdTriple| -> io::Result<()> {
+            if triple.subject == 0
+                || triple.subject > node_value_count
+                || triple.predicate == 0
+                || triple.predicate > predicate_count
+                || triple.object == 0
+                || triple.object > node_value_count
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "shard triple {:?} refers to an id that doesn't exist",
+                        triple
+                    ),
+                ));
+            }
+
+            Ok(())
+        };
+        let check_quad = |quad: &IdQuadTriple| -> io::Result<()> {
+            if quad.graph == 0 || quad.graph > node_value_count {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("shard quad {:?} refers to a graph that doesn't exist", quad),
+                ));
+            }
+
+            check_triple(&quad.triple)
+        };
+
+        self.id_additions.iter().try_for_each(check_triple)?;
+        self.id_removals.iter().try_for_each(check_triple)?;
+        self.quad_additions.iter().try_for_each(check_quad)?;
+        self.quad_removals.iter().try_for_each(check_quad)
+    }
+
+    /// Merge another shard of a layer with the same parent into this one.
+    ///
+    /// Entries that are new in both shards are deduplicated, and the
+    /// triples of the other shard are converted to the ids of this
+    /// shard. Returns an error, leaving this shard unchanged, if the
+    /// other shard refers to ids that don't exist.
+    pub fn merge(&mut self, other: LayerBuilderShard) -> io::Result<()> {
+        other.check_ids()?;
+        let (node_value_offset, predicate_offset) = other.parent_offsets();
+        assert_eq!(
+            (node_value_offset, predicate_offset),
+            self.parent_offsets(),
+            "merged shards should have the same parent"
+        );
+
+        let mut node_value_map = vec![0_u64; other.node_count + other.val_count];
+        for (entry, id) in other.nodes_values_map.into_iter() {
+            if id as usize <= node_value_offset {
+                // ids from the parent are the same in every shard
+                continue;
+            }
+            let new_id = match self.nodes_values_map.get(&entry) {
+                Some(new_id) => *new_id,
+                None => {
+                    self.nodes_values_map_count += 1;
+                    match entry {
+                        ObjectType::Node(_) => self.node_count += 1,
+                        ObjectType::Value(_) => self.val_count += 1,
+                    }
+                    let new_id = self.nodes_values_map_count as u64;
+                    self.nodes_values_map.insert(entry, new_id);
+
+                    new_id
+                }
+            };
+            node_value_map[id as usize - node_value_offset - 1] = new_id;
+        }
+
+        let mut predicate_map = vec![0_u64; other.pred_count];
+        for (entry, id) in other.predicates_map.into_iter() {
+            if id as usize <= predicate_offset {
+                continue;
+            }
+            let new_id = match self.predicates_map.get(&entry) {
+                Some(new_id) => *new_id,
+                None => {
+                    self.predicates_map_count += 1;
+                    self.pred_count += 1;
+                    let new_id = self.predicates_map_count as u64;
+                    self.predicates_map.insert(entry, new_id);
+
+                    new_id
+                }
+            };
+            predicate_map[id as usize - predicate_offset - 1] = new_id;
+        }
+
+        let map_node_value = |id: u64| {
+            if id as usize <= node_value_offset {
+                id
+            } else {
+                node_value_map[id as usize - node_value_offset - 1]
+            }
+        };
+        let map_triple = |triple: IdTriple| {
+            IdTriple::new(
+                map_node_value(triple.subject),
+                if triple.predicate as usize <= predicate_offset {
+                    triple.predicate
+                } else {
+                    predicate_map[triple.predicate as usize - predicate_offset - 1]
+                },
+                map_node_value(triple.object),
+            )
+        };
+
+        self.id_additions
+            .par_extend(other.id_additions.into_par_iter().map(map_triple));
+        self.id_removals
+            .par_extend(other.id_removals.into_par_iter().map(map_triple));
+        let map_quad = |quad: IdQuadTriple| {
+            IdQuadTriple::new(map_node_value(quad.graph), map_triple(quad.triple))
+        };
+        self.quad_additions
+            .extend(other.quad_additions.into_iter().map(map_quad));
+        self.quad_removals
+            .extend(other.quad_removals.into_iter().map(map_quad));
+
+        Ok(())
+    }
+
+    /// Convert an id triple of this shard back to a string triple.
+    fn id_triple_to_string(&self, triple: IdTriple, names: &ShardNames) -> Option<ValueTriple> {
+        let (node_value_offset, predicate_offset) = self.parent_offsets();
+        let node_value = |id: u64| -> Option<ObjectType> {
+            if id as usize <= node_value_offset {
+                self.parent.as_ref().and_then(|p| p.id_object(id))
+            } else {
+                names
+                    .nodes_values
+                    .get(id as usize - node_value_offset - 1)
+                    .cloned()
+                    .flatten()
+            }
+        };
+        let subject = node_value(triple.subject)?.node()?;
+        let predicate = if triple.predicate as usize <= predicate_offset {
+            self.parent.as_ref()?.id_predicate(triple.predicate)?
+        } else {
+            names
+                .predicates
+                .get(triple.predicate as usize - predicate_offset - 1)
+                .cloned()
+                .flatten()?
+        };
+        let object = node_value(triple.object)?;
+
+        Some(ValueTriple {
+            subject,
+            predicate,
+            object,
+        })
+    }
+
+    fn names(&self) -> ShardNames {
+        let (node_value_offset, predicate_offset) = self.parent_offsets();
+        let mut names = ShardNames {
+            nodes_values: vec![None; self.node_count + self.val_count],
+            predicates: vec![None; self.pred_count],
+        };
+        for (entry, id) in self.nodes_values_map.iter() {
+            if *id as usize > node_value_offset {
+                names.nodes_values[*id as usize - node_value_offset - 1] = Some(entry.clone());
+            }
+        }
+        for (entry, id) in self.predicates_map.iter() {
+            if *id as usize > predicate_offset {
+                names.predicates[*id as usize - predicate_offset - 1] = Some(entry.clone());
+            }
+        }
+
+        names
+    }
+
+    fn resolve_triple(&self, triple: IdTriple, names: &ShardNames) -> io::Result<ValueTriple> {
+        self.id_triple_to_string(triple, names).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("shard triple {:?} refers to ids that don't exist", triple),
+            )
+        })
+    }
+
+    /// Returns all additions and removals of this shard as string triples.
+    ///
+    /// Triples that can't be resolved are skipped.
+    pub fn into_value_triples(self) -> (Vec<ValueTriple>, Vec<ValueTriple>) {
+        let names = self.names();
+        let additions = self
+            .id_additions
+            .iter()
+            .filter_map(|t| self.id_triple_to_string(*t, &names))
+            .collect();
+        let removals = self
+            .id_removals
+            .iter()
+            .filter_map(|t| self.id_triple_to_string(*t, &names))
+            .collect();
+
+        (additions, removals)
+    }
+
+    /// Returns all additions and removals of this shard as string triples.
+    ///
+    /// Returns an error if any triple refers to ids that can't be resolved.
+    pub fn try_into_value_triples(self) -> io::Result<(Vec<ValueTriple>, Vec<ValueTriple>)> {
+        let names = self.names();
+        let additions = self
+            .id_additions
+            .iter()
+            .map(|t| self.resolve_triple(*t, &names))
+            .collect::<io::Result<_>>()?;
+        let removals = self
+            .id_removals
+            .iter()
+            .map(|t| self.resolve_triple(*t, &names))
+            .collect::<io::Result<_>>()?;
+
+        Ok((additions, removals))
+    }
+
+    /// Returns the quads added to and removed from graphs in this shard as strings.
+    ///
+    /// Returns an error if any quad refers to ids that can't be resolved.
+    pub fn value_quads(&self) -> io::Result<(Vec<ValueQuadTriple>, Vec<ValueQuadTriple>)> {
+        let names = self.names();
+        let node_value_offset = self.parent_offsets().0;
+        let resolve_quad = |quad: &IdQuadTriple| -> io::Result<ValueQuadTriple> {
+            let graph = if quad.graph as usize <= node_value_offset {
+                self.parent.as_ref().and_then(|p| p.id_subject(quad.graph))
+            } else {
+                names
+                    .nodes_values
+                    .get(quad.graph as usize - node_value_offset - 1)
+                    .cloned()
+                    .flatten()
+                    .and_then(|g| g.node())
+            };
+            let graph = graph.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("shard quad {:?} refers to a graph that doesn't exist", quad),
+                )
+            })?;
+
+            Ok(ValueQuadTriple {
+                graph,
+                triple: self.resolve_triple(quad.triple, &names)?,
+            })
+        };
+        let additions = self
+            .quad_additions
+            .iter()
+            .map(resolve_quad)
+            .collect::<io::Result<_>>()?;
+        let removals = self
+            .quad_removals
+            .iter()
+            .map(resolve_quad)
+            .collect::<io::Result<_>>()?;
+
+        Ok((additions, removals))
+    }
+
+    fn calculate_node(&mut self, node: String) -> u64 {
+        let node = ObjectType::Node(node);
+        if let Some(n) = self.nodes_values_map.get(&node) {
             *n
         } else {
             let node_id = if let Some(node_id) = self
                 .parent
                 .as_ref()
-                .and_then(|p| p.subject_id(subject.node_ref().unwrap()))
+                .and_then(|p| p.subject_id(node.node_ref().unwrap()))
             {
                 node_id
             } else {
@@ -124,10 +503,16 @@ impl<F: 'static + FileLoad + FileStore + Clone> SimpleLayerBuilder<F> {
                 self.node_count += 1;
                 self.nodes_values_map_count as u64
             };
-            self.nodes_values_map.insert(subject, node_id);
+            self.nodes_values_map.insert(node, node_id);
 
             node_id
-        };
+        }
+    }
+
+    fn calculate_triple(&mut self, triple: ValueTriple) -> IdTriple {
+        let predicate = triple.predicate;
+        let object = triple.object;
+        let subject_id = self.calculate_node(triple.subject);
 
         let predicate_id = if let Some(p) = self.predicates_map.get(&predicate) {
             *p
@@ -188,49 +573,154 @@ impl<F: 'static + FileLoad + FileStore + Clone> SimpleLayerBuilder<F> {
     }
 }
 
+struct ShardNames {
+    nodes_values: Vec<Option<ObjectType>>,
+    predicates: Vec<Option<String>>,
+}
+
+/// A layer builder
+///
+/// `SimpleLayerBuilder` provides methods for adding and removing
+/// triples, and for committing the layer builder to storage.
+#[derive(Clone)]
+pub struct SimpleLayerBuilder<F: 'static + FileLoad + FileStore + Clone> {
+    name: [u32; 5],
+    files: LayerFiles<F>,
+    shard: LayerBuilderShard,
+    text_index: bool,
+}
+
+impl<F: 'static + FileLoad + FileStore + Clone> SimpleLayerBuilder<F> {
+    /// Construct a layer builder for a base layer
+    pub fn new(name: [u32; 5], files: BaseLayerFiles<F>) -> Self {
+        Self {
+            name,
+            files: LayerFiles::Base(files),
+            shard: LayerBuilderShard::new(None),
+            text_index: false,
+        }
+    }
+
+    /// Construct a layer builder for a child layer
+    pub fn from_parent(name: [u32; 5], parent: Arc<dyn Layer>, files: ChildLayerFiles<F>) -> Self {
+        Self {
+            name,
+            files: LayerFiles::Child(files),
+            shard: LayerBuilderShard::new(Some(parent)),
+            text_index: false,
+        }
+    }
+}
+
 impl<F: 'static + FileLoad + FileStore + Clone> LayerBuilder for SimpleLayerBuilder<F> {
     fn name(&self) -> [u32; 5] {
         self.name
     }
 
     fn parent(&self) -> Option<Arc<dyn Layer>> {
-        self.parent.clone()
+        self.shard.parent()
     }
 
     fn add_value_triple(&mut self, addition: ValueTriple) {
-        let triple = self.calculate_triple(addition);
-        self.id_additions.push(triple);
+        self.shard.add_value_triple(addition)
     }
 
     fn add_id_triple(&mut self, triple: IdTriple) {
-        self.id_additions.push(triple);
+        self.shard.add_id_triple(triple)
     }
 
     fn remove_value_triple(&mut self, removal: ValueTriple) {
-        let triple = self.calculate_triple(removal);
-        self.id_removals.push(triple);
+        self.shard.remove_value_triple(removal)
     }
 
     fn remove_id_triple(&mut self, triple: IdTriple) {
-        self.id_removals.push(triple);
+        self.shard.remove_id_triple(triple)
+    }
+
+    fn add_value_quad(&mut self, quad: ValueQuadTriple) {
+        self.shard.add_value_quad(quad)
+    }
+
+    fn add_id_quad(&mut self, quad: IdQuadTriple) {
+        self.shard.add_id_quad(quad)
+    }
+
+    fn remove_value_quad(&mut self, quad: ValueQuadTriple) {
+        self.shard.remove_value_quad(quad)
+    }
+
+    fn remove_id_quad(&mut self, quad: IdQuadTriple) {
+        self.shard.remove_id_quad(quad)
+    }
+
+    fn value_triple_changes(&self) -> (Vec<ValueTriple>, Vec<ValueTriple>) {
+        self.shard.clone().into_value_triples()
+    }
+
+    fn merge_shard(&mut self, shard: LayerBuilderShard) -> io::Result<()> {
+        self.shard.merge(shard)
+    }
+
+    fn set_text_index(&mut self, enabled: bool) {
+        self.text_index = enabled;
     }
 
     fn commit(self) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        self.commit_with_progress(Progress::default())
+    }
+
+    fn commit_boxed(self: Box<Self>) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        let builder = *self;
+        builder.commit()
+    }
+
+    fn commit_boxed_with_progress(
+        self: Box<Self>,
+        progress: Progress,
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        let builder = *self;
+        builder.commit_with_progress(progress)
+    }
+}
+
+impl<F: 'static + FileLoad + FileStore + Clone> SimpleLayerBuilder<F> {
+    /// Commit the layer to storage, reporting to and cancellable through `progress`.
+    ///
+    /// A cancelled commit returns an `Interrupted` error and leaves
+    /// the layer files incomplete.
+    pub fn commit_with_progress(
+        self,
+        progress: Progress,
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
         let SimpleLayerBuilder {
-            name: _,
-            parent,
+            name,
             files,
-            mut id_additions,
-            mut id_removals,
-
-            nodes_values_map,
-            predicates_map,
-            nodes_values_map_count: _,
-            predicates_map_count: _,
-            node_count,
-            pred_count,
-            val_count,
+            shard:
+                LayerBuilderShard {
+                    parent,
+                    mut id_additions,
+                    mut id_removals,
+                    mut quad_additions,
+                    mut quad_removals,
+
+                    nodes_values_map,
+                    predicates_map,
+                    nodes_values_map_count: _,
+                    predicates_map_count: _,
+                    node_count,
+                    pred_count,
+                    val_count,
+                },
+            text_index,
         } = self;
+        let span = tracing::debug_span!(
+            "commit",
+            layer = %name_to_string(name),
+            parent = parent.as_ref().map(|p| tracing::field::display(name_to_string(p.name()))),
+            additions = tracing::field::Empty,
+            removals = tracing::field::Empty
+        );
+        let _entered = span.enter();
         let parent_node_value_offset = parent
             .as_ref()
             .map(|p| p.node_and_value_count())
@@ -289,10 +779,51 @@ impl<F: 'static + FileLoad + FileStore + Clone> LayerBuilder for SimpleLayerBuil
             }
         }
 
+        // removing a triple also takes it out of every graph it is in
+        if let Some(parent) = parent.as_ref() {
+            for triple in id_removals.iter() {
+                if triple.subject == 0 {
+                    continue;
+                }
+                for graph in parent.triple_graphs(triple) {
+                    quad_removals.push(IdQuadTriple::new(graph, *triple));
+                }
+            }
+        }
+
+        // quads get the same treatment: adding and removing a quad
+        // cancels out, and quads with new entries can't be removed.
+        quad_additions.sort();
+        quad_additions.dedup();
+        quad_removals.sort();
+        quad_removals.dedup();
+        let cancelled: HashSet<IdQuadTriple> = quad_removals
+            .iter()
+            .filter(|quad| quad_additions.binary_search(quad).is_ok())
+            .copied()
+            .collect();
+        quad_additions.retain(|quad| !cancelled.contains(quad));
+        quad_removals.retain(|quad| {
+            !cancelled.contains(quad)
+                && quad.graph <= parent_node_value_offset as u64
+                && quad.triple.subject <= parent_node_value_offset as u64
+                && quad.triple.predicate <= parent_predicate_offset as u64
+                && quad.triple.object <= parent_node_value_offset as u64
+        });
+
         // some dict entries might now be unused. We need to do an existence check.
         let mut node_value_existences = bitvec![0;node_count + val_count];
         let mut predicate_existences = bitvec![0;pred_count];
-        for triple in id_additions.iter().chain(id_removals.iter()) {
+        for quad in quad_additions.iter() {
+            if quad.graph > parent_node_value_offset as u64 {
+                node_value_existences.set(quad.graph as usize - parent_node_value_offset - 1, true);
+            }
+        }
+        for triple in id_additions
+            .iter()
+            .chain(id_removals.iter())
+            .chain(quad_additions.iter().map(|quad| &quad.triple))
+        {
             if triple.subject > parent_node_value_offset as u64 {
                 node_value_existences
                     .set(triple.subject as usize - parent_node_value_offset - 1, true);
@@ -377,51 +908,104 @@ impl<F: 'static + FileLoad + FileStore + Clone> LayerBuilder for SimpleLayerBuil
                 triple.object = node_value_id_map[mapped_id];
             }
         }
+        let map_node_value = |id: u64| {
+            if id > parent_node_value_offset as u64 {
+                node_value_id_map[id as usize - parent_node_value_offset - 1]
+            } else {
+                id
+            }
+        };
+        for quad in quad_additions.iter_mut() {
+            quad.graph = map_node_value(quad.graph);
+            quad.triple.subject = map_node_value(quad.triple.subject);
+            if quad.triple.predicate > parent_predicate_offset as u64 {
+                let mapped_id = quad.triple.predicate as usize - parent_predicate_offset - 1;
+                quad.triple.predicate = predicate_id_map[mapped_id];
+            }
+            quad.triple.object = map_node_value(quad.triple.object);
+        }
         // and resort them
         id_additions.sort();
         id_removals.sort();
 
-        // great! everything is now in order. Let's stuff it into an actual builder
-        Box::pin(async {
-            match parent {
-                Some(parent) => {
-                    let files = files.into_child();
-                    let mut builder =
-                        ChildLayerFileBuilder::from_files(parent.clone(), &files).await?;
-
-                    builder.add_nodes(nodes.into_iter().map(|x| x.0));
-                    builder.add_predicates(predicates.into_iter().map(|x| x.0));
-                    builder.add_values(values.into_iter().map(|x| x.0));
+        if !span.is_disabled() {
+            let is_change = |t: &&IdTriple| t.subject != 0;
+            span.record("additions", id_additions.iter().filter(is_change).count());
+            span.record("removals", id_removals.iter().filter(is_change).count());
+        }
 
-                    let mut builder = builder.into_phase2().await?;
+        // great! everything is now in order. Let's stuff it into an actual builder
+        Box::pin(
+            async move {
+                progress.check()?;
+                match parent {
+                    Some(parent) => {
+                        let files = files.into_child();
+                        let mut builder =
+                            ChildLayerFileBuilder::from_files(parent.clone(), &files).await?;
+
+                        builder.add_nodes(nodes.into_iter().map(|x| x.0));
+                        builder.add_predicates(predicates.into_iter().map(|x| x.0));
+                        builder.add_values(values.into_iter().map(|x| x.0));
+
+                        let mut builder = builder.into_phase2().await?;
+
+                        progress.dictionaries_merged(3)?;
+
+                        builder
+                            .add_id_triples_with_progress(id_additions, &progress)
+                            .await?;
+                        builder
+                            .remove_id_triples_with_progress(id_removals, &progress)
+                            .await?;
+
+                        builder.finalize_with_progress(&progress).await?;
+
+                        if !quad_additions.is_empty() {
+                            write_graph_index(&files.pos_quads_file, quad_additions).await?;
+                        }
+                        if !quad_removals.is_empty() {
+                            write_graph_index(&files.neg_quads_file, quad_removals).await?;
+                        }
+                        if text_index {
+                            write_text_index(&files.value_dictionary_files, &files.text_index_file)
+                                .await?;
+                        }
+                    }
+                    None => {
+                        // TODO almost same as above, should be more generic
+                        let files = files.into_base();
+                        let mut builder = BaseLayerFileBuilder::from_files(&files).await?;
 
-                    builder.add_id_triples(id_additions).await?;
-                    builder.remove_id_triples(id_removals).await?;
+                        builder.add_nodes(nodes.into_iter().map(|x| x.0));
+                        builder.add_predicates(predicates.into_iter().map(|x| x.0));
+                        builder.add_values(values.into_iter().map(|x| x.0));
 
-                    builder.finalize().await
-                }
-                None => {
-                    // TODO almost same as above, should be more generic
-                    let files = files.into_base();
-                    let mut builder = BaseLayerFileBuilder::from_files(&files).await?;
+                        let mut builder = builder.into_phase2().await?;
 
-                    builder.add_nodes(nodes.into_iter().map(|x| x.0));
-                    builder.add_predicates(predicates.into_iter().map(|x| x.0));
-                    builder.add_values(values.into_iter().map(|x| x.0));
+                        progress.dictionaries_merged(3)?;
 
-                    let mut builder = builder.into_phase2().await?;
+                        builder
+                            .add_id_triples_with_progress(id_additions, &progress)
+                            .await?;
 
-                    builder.add_id_triples(id_additions).await?;
+                        builder.finalize_with_progress(&progress).await?;
 
-                    builder.finalize().await
+                        if !quad_additions.is_empty() {
+                            write_graph_index(&files.quads_file, quad_additions).await?;
+                        }
+                        if text_index {
+                            write_text_index(&files.value_dictionary_files, &files.text_index_file)
+                                .await?;
+                        }
+                    }
                 }
-            }
-        })
-    }
+                progress.done();
 
-    fn commit_boxed(self: Box<Self>) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
-        let builder = *self;
-        builder.commit()
+                Ok(())
+            }
+            .instrument(span.clone()),
+        )
     }
 }
 
@@ -499,6 +1083,78 @@ mod tests {
             .value_triple_exists(&ValueTriple::new_string_value("duck", "says", "quack")));
     }
 
+    #[tokio::test]
+    async fn child_layer_construction_from_shards() {
+        let base_layer = example_base_layer().await;
+        let files = new_child_files();
+        let name = [0, 0, 0, 0, 0];
+        let mut builder = SimpleLayerBuilder::from_parent(name, base_layer.clone(), files.clone());
+        let mut shard1 = LayerBuilderShard::new(Some(base_layer.clone()));
+        let mut shard2 = LayerBuilderShard::new(Some(base_layer.clone()));
+
+        builder.add_value_triple(ValueTriple::new_string_value("horse", "says", "neigh"));
+        shard1.add_value_triple(ValueTriple::new_node("horse", "likes", "cow"));
+        shard1.add_value_triple(ValueTriple::new_string_value("bird", "says", "tweet"));
+        shard2.add_value_triple(ValueTriple::new_node("bird", "likes", "horse"));
+        shard2.remove_value_triple(ValueTriple::new_string_value("duck", "says", "quack"));
+
+        builder.merge_shard(shard1).unwrap();
+        builder.merge_shard(shard2).unwrap();
+
+        let child_layer = Arc::new(
+            async {
+                builder.commit().await?;
+
+                ChildLayer::load_from_files(name, base_layer, &files).await
+            }
+            .await
+            .unwrap(),
+        );
+
+        assert!(child_layer
+            .value_triple_exists(&ValueTriple::new_string_value("horse", "says", "neigh")));
+        assert!(child_layer.value_triple_exists(&ValueTriple::new_node("horse", "likes", "cow")));
+        assert!(child_layer
+            .value_triple_exists(&ValueTriple::new_string_value("bird", "says", "tweet")));
+        assert!(child_layer.value_triple_exists(&ValueTriple::new_node("bird", "likes", "horse")));
+        assert!(!child_layer
+            .value_triple_exists(&ValueTriple::new_string_value("duck", "says", "quack")));
+        // horse and bird are added only once, along with neigh and tweet
+        assert_eq!(4, child_layer.node_and_value_count() - 6);
+    }
+
+    #[tokio::test]
+    async fn shard_converts_back_to_value_triples() {
+        let base_layer = example_base_layer().await;
+        let mut shard = LayerBuilderShard::new(Some(base_layer));
+        shard.add_value_triple(ValueTriple::new_node("horse", "likes", "cow"));
+        shard.remove_value_triple(ValueTriple::new_string_value("duck", "says", "quack"));
+
+        let (additions, removals) = shard.into_value_triples();
+        assert_eq!(
+            vec![ValueTriple::new_node("horse", "likes", "cow")],
+            additions
+        );
+        assert_eq!(
+            vec![ValueTriple::new_string_value("duck", "says", "quack")],
+            removals
+        );
+    }
+
+    #[tokio::test]
+    async fn shard_with_unresolvable_ids_fails_to_convert() {
+        let base_layer = example_base_layer().await;
+        let mut shard = LayerBuilderShard::new(Some(base_layer));
+        shard.add_value_triple(ValueTriple::new_node("horse", "likes", "cow"));
+        shard.add_id_triple(IdTriple::new(1, 1000, 1));
+
+        assert!(shard.value_quads().is_ok());
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            shard.try_into_value_triples().unwrap_err().kind()
+        );
+    }
+
     #[tokio::test]
     async fn multi_level_layers() {
         let base_layer = example_base_layer().await;
@@ -806,4 +1462,124 @@ mod tests {
             child_layer.value_triple_exists(&ValueTriple::new_string_value("cow", "says", "moo"))
         );
     }
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn commit_reports_a_span_with_triple_counts() {
+        let buffer = SharedBuffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let base_layer = example_base_layer().await;
+        let files = new_child_files();
+        let mut builder = SimpleLayerBuilder::from_parent([0, 0, 0, 0, 1], base_layer, files);
+        builder.add_value_triple(ValueTriple::new_string_value("cow", "says", "quack"));
+        builder.add_value_triple(ValueTriple::new_string_value("horse", "says", "neigh"));
+        builder.remove_value_triple(ValueTriple::new_string_value("cow", "says", "moo"));
+        builder.commit().await.unwrap();
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains(
+            "commit{layer=0000000000000000000000000000000000000001 \
+             parent=0000000100000002000000030000000400000005 additions=2 removals=1}: \
+             terminus_store::layer::simple_builder: close"
+        ));
+    }
+
+    fn graph_strings(layer: &InternalLayer, graph: &str) -> Vec<ValueTriple> {
+        let graph = match layer.graph_id(graph) {
+            None => return Vec::new(),
+            Some(graph) => graph,
+        };
+        let mut triples: Vec<_> = layer
+            .quads_g(graph)
+            .map(|quad| layer.id_quad_to_string(&quad).unwrap().triple)
+            .collect();
+        triples.sort();
+
+        triples
+    }
+
+    #[tokio::test]
+    async fn triple_only_layer_has_no_graph_index() {
+        let files = new_base_files();
+        let mut builder = SimpleLayerBuilder::new([1, 2, 3, 4, 5], files.clone());
+        builder.add_value_triple(ValueTriple::new_node("cow", "likes", "pig"));
+        builder.commit().await.unwrap();
+
+        assert!(!files.quads_file.exists().await.unwrap());
+        let layer = BaseLayer::load_from_files([1, 2, 3, 4, 5], &files)
+            .await
+            .unwrap();
+        assert!(layer.pos_graph_index().is_none());
+        assert!(graph_strings(&layer, "pig").is_empty());
+    }
+
+    #[tokio::test]
+    async fn base_and_child_layer_with_quads() {
+        let moo = ValueTriple::new_string_value("cow", "says", "moo");
+        let oink = ValueTriple::new_string_value("pig", "says", "oink");
+        let quack = ValueTriple::new_string_value("duck", "says", "quack");
+        let neigh = ValueTriple::new_string_value("horse", "says", "neigh");
+
+        let files = new_base_files();
+        let mut builder = SimpleLayerBuilder::new([1, 2, 3, 4, 5], files.clone());
+        builder.add_value_quad(ValueQuadTriple::new("farm", moo.clone()));
+        builder.add_value_quad(ValueQuadTriple::new("farm", oink.clone()));
+        builder.add_value_quad(ValueQuadTriple::new("pond", quack.clone()));
+        builder.add_value_triple(ValueTriple::new_string_value("cat", "says", "meow"));
+        builder.remove_value_quad(ValueQuadTriple::new("pond", moo.clone()));
+        builder.commit().await.unwrap();
+        let base: Arc<InternalLayer> = Arc::new(
+            BaseLayer::load_from_files([1, 2, 3, 4, 5], &files)
+                .await
+                .unwrap(),
+        );
+
+        assert_eq!(4, base.triple_count());
+        assert_eq!(
+            vec![moo.clone(), oink.clone()],
+            graph_strings(&base, "farm")
+        );
+        assert_eq!(vec![quack.clone()], graph_strings(&base, "pond"));
+        assert!(base.graph_id("barn").is_none());
+
+        let files = new_child_files();
+        let mut builder =
+            SimpleLayerBuilder::from_parent([0, 0, 0, 0, 1], base.clone(), files.clone());
+        builder.remove_value_quad(ValueQuadTriple::new("farm", oink.clone()));
+        builder.add_value_quad(ValueQuadTriple::new("pond", moo.clone()));
+        builder.add_value_quad(ValueQuadTriple::new("barn", neigh.clone()));
+        builder.remove_value_triple(quack);
+        builder.commit().await.unwrap();
+        let child: Arc<InternalLayer> = Arc::new(
+            ChildLayer::load_from_files([0, 0, 0, 0, 1], base.clone(), &files)
+                .await
+                .unwrap(),
+        );
+
+        assert!(child.value_triple_exists(&oink));
+        assert_eq!(vec![moo.clone()], graph_strings(&child, "farm"));
+        assert_eq!(vec![moo.clone()], graph_strings(&child, "pond"));
+        assert_eq!(vec![neigh], graph_strings(&child, "barn"));
+        assert_eq!(vec![moo, oink], graph_strings(&base, "farm"));
+    }
 }
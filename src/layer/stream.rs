@@ -0,0 +1,218 @@
+//! Asynchronous streams over triples.
+//!
+//! Triple iterators do all their work synchronously. Iterating over
+//! millions of triples inside a task will therefore block the
+//! executor. The streams in this module take triples from an iterator
+//! in chunks, and yield to the runtime in between chunks.
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::stream::{Stream, StreamExt};
+
+use super::layer::IdTriple;
+
+/// The default amount of triples taken from the underlying iterator before yielding to the runtime.
+pub const TRIPLE_STREAM_CHUNK_SIZE: usize = 1024;
+
+/// A stream of chunks of triples, taken from a triple iterator.
+///
+/// After producing a chunk, this stream yields to the runtime once
+/// before producing the next one. Chunks are never empty, and all
+/// chunks except for the last one contain exactly `chunk_size`
+/// triples.
+pub struct IdTripleChunkStream {
+    iter: Box<dyn Iterator<Item = IdTriple> + Send>,
+    chunk_size: usize,
+    should_yield: bool,
+    exhausted: bool,
+}
+
+impl IdTripleChunkStream {
+    /// Construct a chunk stream with the default chunk size.
+    pub fn new(iter: Box<dyn Iterator<Item = IdTriple> + Send>) -> Self {
+        Self::with_chunk_size(iter, TRIPLE_STREAM_CHUNK_SIZE)
+    }
+
+    /// Construct a chunk stream with the given chunk size.
+    ///
+    /// Panics if the chunk size is 0.
+    pub fn with_chunk_size(
+        iter: Box<dyn Iterator<Item = IdTriple> + Send>,
+        chunk_size: usize,
+    ) -> Self {
+        assert!(chunk_size > 0, "chunk size should be larger than 0");
+        Self {
+            iter,
+            chunk_size,
+            should_yield: false,
+            exhausted: false,
+        }
+    }
+}
+
+impl Stream for IdTripleChunkStream {
+    type Item = Vec<IdTriple>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Vec<IdTriple>>> {
+        if self.exhausted {
+            return Poll::Ready(None);
+        }
+
+        if self.should_yield {
+            // give other tasks the chance to run before doing the
+            // blocking work of retrieving the next chunk
+            self.should_yield = false;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+
+        let chunk_size = self.chunk_size;
+        let chunk: Vec<_> = self.iter.by_ref().take(chunk_size).collect();
+        if chunk.len() < chunk_size {
+            self.exhausted = true;
+        }
+        self.should_yield = true;
+
+        if chunk.is_empty() {
+            Poll::Ready(None)
+        } else {
+            Poll::Ready(Some(chunk))
+        }
+    }
+}
+
+/// A stream of triples, taken from a triple iterator in chunks.
+///
+/// This stream yields to the runtime in between chunks. See
+/// `IdTripleChunkStream`.
+pub struct IdTripleStream {
+    chunks: IdTripleChunkStream,
+    current: std::vec::IntoIter<IdTriple>,
+}
+
+impl IdTripleStream {
+    /// Construct a triple stream with the default chunk size.
+    pub fn new(iter: Box<dyn Iterator<Item = IdTriple> + Send>) -> Self {
+        Self::from_chunks(IdTripleChunkStream::new(iter))
+    }
+
+    /// Construct a triple stream with the given chunk size.
+    ///
+    /// Panics if the chunk size is 0.
+    pub fn with_chunk_size(
+        iter: Box<dyn Iterator<Item = IdTriple> + Send>,
+        chunk_size: usize,
+    ) -> Self {
+        Self::from_chunks(IdTripleChunkStream::with_chunk_size(iter, chunk_size))
+    }
+
+    fn from_chunks(chunks: IdTripleChunkStream) -> Self {
+        Self {
+            chunks,
+            current: Vec::new().into_iter(),
+        }
+    }
+
+    /// Turn this stream into a stream of chunks.
+    ///
+    /// Any triples of the current chunk that have not yet been
+    /// retrieved are returned as the first chunk.
+    pub fn chunks(self) -> impl Stream<Item = Vec<IdTriple>> + Send + Unpin {
+        let current: Vec<_> = self.current.collect();
+        let first = if current.is_empty() {
+            None
+        } else {
+            Some(current)
+        };
+
+        futures::stream::iter(first).chain(self.chunks)
+    }
+}
+
+impl Stream for IdTripleStream {
+    type Item = IdTriple;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<IdTriple>> {
+        loop {
+            if let Some(triple) = self.current.next() {
+                return Poll::Ready(Some(triple));
+            }
+
+            match Pin::new(&mut self.chunks).poll_next(cx) {
+                Poll::Ready(Some(chunk)) => self.current = chunk.into_iter(),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triples(count: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        Box::new((1..=count).map(|i| IdTriple::new(i, 1, i)))
+    }
+
+    #[tokio::test]
+    async fn stream_returns_all_triples_in_order() {
+        let result: Vec<_> = IdTripleStream::with_chunk_size(triples(10), 3)
+            .collect()
+            .await;
+
+        assert_eq!(triples(10).collect::<Vec<_>>(), result);
+    }
+
+    #[tokio::test]
+    async fn chunk_stream_returns_full_chunks() {
+        let result: Vec<_> = IdTripleChunkStream::with_chunk_size(triples(10), 3)
+            .map(|c| c.len())
+            .collect()
+            .await;
+        assert_eq!(vec![3, 3, 3, 1], result);
+
+        let result: Vec<_> = IdTripleChunkStream::with_chunk_size(triples(9), 3)
+            .map(|c| c.len())
+            .collect()
+            .await;
+        assert_eq!(vec![3, 3, 3], result);
+    }
+
+    #[tokio::test]
+    async fn partially_consumed_stream_turns_into_chunks() {
+        let mut stream = IdTripleStream::with_chunk_size(triples(5), 2);
+        assert_eq!(Some(IdTriple::new(1, 1, 1)), stream.next().await);
+
+        let result: Vec<_> = stream.chunks().collect().await;
+        assert_eq!(
+            vec![
+                vec![IdTriple::new(2, 1, 2)],
+                vec![IdTriple::new(3, 1, 3), IdTriple::new(4, 1, 4)],
+                vec![IdTriple::new(5, 1, 5)]
+            ],
+            result
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn stream_yields_to_other_tasks_between_chunks() {
+        let counter = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter2 = counter.clone();
+        let other = tokio::spawn(async move {
+            loop {
+                counter2.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                tokio::task::yield_now().await;
+            }
+        });
+
+        let mut stream = IdTripleChunkStream::with_chunk_size(triples(10), 5);
+        assert!(stream.next().await.is_some());
+        let before = counter.load(std::sync::atomic::Ordering::SeqCst);
+        assert!(stream.next().await.is_some());
+        let after = counter.load(std::sync::atomic::Ordering::SeqCst);
+        other.abort();
+
+        assert!(after > before);
+    }
+}
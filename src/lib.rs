@@ -28,15 +28,26 @@
 //! The `structure`, `layer`, and `storage` module expose the inner
 //! workings of terminus-store. They are useful for implementing new
 //! storage backends, or writing analysis and recovery tools.
+//!
+//! Diagnostics are emitted through the `tracing` crate. Layer loads,
+//! commits, rollups, squashes, base layer merges and pack
+//! import/export are wrapped in spans carrying layer names and triple
+//! counts. Nothing is reported unless the embedding application
+//! installs a `tracing` subscriber, which decides at runtime where
+//! this output goes.
 #[macro_use]
 extern crate lazy_static;
 
 pub mod layer;
-#[macro_use]
-pub(crate) mod logging;
 pub mod storage;
 pub mod store;
 
-pub use layer::{IdTriple, Layer, ObjectType, ValueTriple};
-pub use store::sync::{open_sync_archive_store, open_sync_directory_store, open_sync_memory_store};
-pub use store::{open_archive_store, open_directory_store, open_memory_store};
+pub use layer::{IdQuadTriple, IdTriple, Layer, ObjectType, ValueQuadTriple, ValueTriple};
+pub use store::sync::{
+    open_sync_archive_store, open_sync_archive_store_checked, open_sync_directory_store,
+    open_sync_directory_store_checked, open_sync_memory_store,
+};
+pub use store::{
+    open_archive_store, open_archive_store_checked, open_directory_store,
+    open_directory_store_checked, open_memory_store,
+};
@@ -1,7 +0,0 @@
-//! logging
-//!
-//! this module contains utilities for logging
-//
-
-#[macro_use]
-pub mod logging;
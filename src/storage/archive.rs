@@ -10,8 +10,12 @@ use std::{
     ops::Range,
     path::PathBuf,
     pin::Pin,
-    sync::{Arc, RwLock},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
     task::Poll,
+    time::SystemTime,
 };
 
 #[cfg(not(target_os = "windows"))]
@@ -22,6 +26,7 @@ use std::os::windows::fs::MetadataExt;
 use async_trait::async_trait;
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use lru::LruCache;
+use num_traits::FromPrimitive;
 use tokio::{
     fs::{self, File},
     io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt},
@@ -36,6 +41,7 @@ use tdb_succinct::{
 use super::{
     consts::{LayerFileEnum, FILENAME_ENUM_MAP},
     locking::{ExclusiveLockedFile, LockedFile},
+    metrics::{CacheCounters, CacheStats},
     name_to_string, string_to_name, FileLoad, FileStore, PersistentLayerStore, SyncableFile,
 };
 
@@ -55,6 +61,11 @@ pub trait ArchiveBackend: Clone + Send + Sync {
         file_type: LayerFileEnum,
         read_from: usize,
     ) -> io::Result<Self::Read>;
+
+    /// Return a snapshot of the counters of this backend's cache, if it has one.
+    async fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
 }
 
 #[async_trait]
@@ -71,6 +82,29 @@ pub trait ArchiveMetadataBackend: Clone + Send + Sync {
     async fn get_rollup(&self, id: [u32; 5]) -> io::Result<Option<[u32; 5]>>;
     async fn set_rollup(&self, id: [u32; 5], rollup: [u32; 5]) -> io::Result<()>;
     async fn get_parent(&self, id: [u32; 5]) -> io::Result<Option<[u32; 5]>>;
+
+    /// Return the size of every structure present in the given layer.
+    async fn get_layer_structure_sizes(
+        &self,
+        id: [u32; 5],
+    ) -> io::Result<Vec<(LayerFileEnum, usize)>> {
+        let mut result = Vec::new();
+        for file_type in (0..u64::BITS).filter_map(LayerFileEnum::from_u32) {
+            if self.layer_file_exists(id, file_type).await? {
+                result.push((
+                    file_type,
+                    self.get_layer_structure_size(id, file_type).await?,
+                ));
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Return when the given layer was written, if this backend keeps track of that.
+    async fn layer_created(&self, _id: [u32; 5]) -> io::Result<Option<SystemTime>> {
+        Ok(None)
+    }
 }
 
 pub struct BytesAsyncReader(Bytes);
@@ -170,10 +204,14 @@ impl ArchiveBackend for DirectoryArchiveBackend {
         directory_path.pop();
         fs::create_dir_all(&directory_path).await?;
 
+        // write to a temporary file first, so an interrupted write never leaves a partial layer behind
+        let mut temp_path = path.clone();
+        temp_path.set_extension("larch.tmp");
         let mut options = tokio::fs::OpenOptions::new();
         options.create(true);
         options.write(true);
-        let mut file = options.open(path).await?;
+        options.truncate(true);
+        let mut file = options.open(&temp_path).await?;
         while bytes.remaining() > 0 {
             let chunk = bytes.chunk();
             let written = file.write(chunk).await?;
@@ -182,6 +220,7 @@ impl ArchiveBackend for DirectoryArchiveBackend {
 
         file.flush().await?;
         file.sync_all().await?;
+        fs::rename(temp_path, path).await?;
 
         if cfg!(unix) {
             // ensure the underlying directory record is properly synchronized
@@ -223,19 +262,23 @@ impl ArchiveBackend for DirectoryArchiveBackend {
 #[async_trait]
 impl ArchiveMetadataBackend for DirectoryArchiveBackend {
     async fn get_layer_names(&self) -> io::Result<Vec<[u32; 5]>> {
-        let mut stream = fs::read_dir(&self.path).await?;
+        let mut prefixes = fs::read_dir(&self.path).await?;
         let mut result = Vec::new();
-        while let Some(direntry) = stream.next_entry().await? {
-            let os_name = direntry.file_name();
-            let name = os_name.to_str().ok_or_else(|| {
-                io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "unexpected non-utf8 directory name",
-                )
-            })?;
-            if name.ends_with(".larch") && direntry.file_type().await?.is_file() {
-                let name_component = &name[..name.len() - 6];
-                result.push(string_to_name(name_component)?);
+        // layers live in subdirectories named after the first characters of the layer name
+        while let Some(prefix) = prefixes.next_entry().await? {
+            if prefix.file_name().len() != PREFIX_DIR_SIZE || !prefix.file_type().await?.is_dir() {
+                continue;
+            }
+            let mut stream = fs::read_dir(prefix.path()).await?;
+            while let Some(direntry) = stream.next_entry().await? {
+                let os_name = direntry.file_name();
+                let name = os_name.to_str().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "unexpected non-utf8 file name")
+                })?;
+                if name.ends_with(".larch") && direntry.file_type().await?.is_file() {
+                    let name_component = &name[..name.len() - 6];
+                    result.push(string_to_name(name_component)?);
+                }
             }
         }
 
@@ -350,13 +393,34 @@ impl ArchiveMetadataBackend for DirectoryArchiveBackend {
             Ok(None)
         }
     }
+
+    async fn get_layer_structure_sizes(
+        &self,
+        id: [u32; 5],
+    ) -> io::Result<Vec<(LayerFileEnum, usize)>> {
+        let path = self.path_for_layer(id);
+        let mut options = tokio::fs::OpenOptions::new();
+        options.read(true);
+        let mut file = options.open(path).await?;
+        let header = ArchiveHeader::parse_from_reader(&mut file).await?;
+
+        Ok(header.sizes())
+    }
+
+    async fn layer_created(&self, id: [u32; 5]) -> io::Result<Option<SystemTime>> {
+        let metadata = tokio::fs::metadata(self.path_for_layer(id)).await?;
+        // not every platform records creation times
+        Ok(metadata.created().or_else(|_| metadata.modified()).ok())
+    }
 }
 
 #[derive(Clone)]
 pub struct LruArchiveBackend<M, D> {
     cache: Arc<tokio::sync::Mutex<LruCache<[u32; 5], CacheEntry>>>,
     limit: usize,
-    current: usize,
+    // only modified while holding the cache lock
+    current: Arc<AtomicUsize>,
+    counters: Arc<CacheCounters>,
     metadata_origin: M,
     data_origin: D,
 }
@@ -384,7 +448,8 @@ impl<M, D> LruArchiveBackend<M, D> {
         Self {
             cache,
             limit,
-            current: 0,
+            current: Default::default(),
+            counters: Default::default(),
             metadata_origin,
             data_origin,
         }
@@ -402,9 +467,17 @@ impl<M: ArchiveMetadataBackend, D: ArchiveBackend> LruArchiveBackend<M, D> {
     }
 }
 
-fn ensure_additional_cache_space(cache: &mut LruCache<[u32; 5], CacheEntry>, mut required: usize) {
+/// Pop resolved entries from the cache until at least `required` bytes were freed.
+///
+/// Returns the amount of entries and bytes that were dropped.
+fn ensure_additional_cache_space(
+    cache: &mut LruCache<[u32; 5], CacheEntry>,
+    mut required: usize,
+) -> (u64, usize) {
+    let mut evicted = 0;
+    let mut freed = 0;
     if required == 0 {
-        return;
+        return (evicted, freed);
     }
 
     loop {
@@ -423,9 +496,11 @@ fn ensure_additional_cache_space(cache: &mut LruCache<[u32; 5], CacheEntry>, mut
             .expect("cache is empty but stored entries were expected")
             .1;
         if let CacheEntry::Resolved(entry) = entry {
+            evicted += 1;
+            freed += entry.len();
             if entry.len() >= required {
                 // done!
-                return;
+                return (evicted, freed);
             }
 
             // more needs to be popped
@@ -439,7 +514,8 @@ fn ensure_additional_cache_space(cache: &mut LruCache<[u32; 5], CacheEntry>, mut
 fn ensure_enough_cache_space(
     cache: &mut LruCache<[u32; 5], CacheEntry>,
     limit: usize,
-    current: usize,
+    current: &AtomicUsize,
+    counters: &CacheCounters,
     required: usize,
 ) -> bool {
     if required > limit {
@@ -447,10 +523,12 @@ fn ensure_enough_cache_space(
         return false;
     }
 
-    let remaining = limit - current;
+    let remaining = limit - current.load(Ordering::Relaxed);
     if remaining < required {
         // we need to clean up some cache spacew to fit this entry
-        ensure_additional_cache_space(cache, required - remaining);
+        let (evicted, freed) = ensure_additional_cache_space(cache, required - remaining);
+        current.fetch_sub(freed, Ordering::Relaxed);
+        counters.evict(evicted);
     }
 
     true
@@ -470,9 +548,13 @@ impl<M: ArchiveMetadataBackend, D: ArchiveBackend> ArchiveBackend for LruArchive
         let cached = cache.get(&id).cloned();
 
         match cached {
-            Some(CacheEntry::Resolved(bytes)) => Ok(bytes),
+            Some(CacheEntry::Resolved(bytes)) => {
+                self.counters.hit();
+                Ok(bytes)
+            }
             Some(CacheEntry::Resolving(barrier)) => {
                 // someone is already looking up this layer. we'll wait for them to be done.
+                self.counters.hit();
                 std::mem::drop(cache);
                 let guard = barrier.read().await;
                 match guard.as_ref().unwrap() {
@@ -482,6 +564,7 @@ impl<M: ArchiveMetadataBackend, D: ArchiveBackend> ArchiveBackend for LruArchive
             }
             None => {
                 // nobody is looking this up yet, it is up to us.
+                self.counters.miss();
                 let barrier = Arc::new(tokio::sync::RwLock::new(None));
                 let mut result = barrier.write().await;
                 cache.get_or_insert(id, || CacheEntry::Resolving(barrier.clone()));
@@ -497,15 +580,17 @@ impl<M: ArchiveMetadataBackend, D: ArchiveBackend> ArchiveBackend for LruArchive
                 match lookup {
                     Ok(bytes) => {
                         if ensure_enough_cache_space(
-                            &mut *cache,
+                            &mut cache,
                             self.limit_bytes(),
-                            self.current,
+                            &self.current,
+                            &self.counters,
                             bytes.len(),
                         ) {
                             let cached = cache
                                 .get_mut(&id)
                                 .expect("layer resolving entry not found in cache");
                             *cached = CacheEntry::Resolved(bytes.clone());
+                            self.current.fetch_add(bytes.len(), Ordering::Relaxed);
                         } else {
                             // this entry is uncachable. Just remove the resolving entry
                             drop_from_cache(&mut *cache, id);
@@ -540,7 +625,18 @@ impl<M: ArchiveMetadataBackend, D: ArchiveBackend> ArchiveBackend for LruArchive
         self.data_origin.store_layer_file(id, bytes.clone()).await?;
 
         let mut cache = self.cache.lock().await;
-        cache.get_or_insert(id, move || CacheEntry::Resolved(bytes));
+        if !cache.contains(&id)
+            && ensure_enough_cache_space(
+                &mut cache,
+                self.limit_bytes(),
+                &self.current,
+                &self.counters,
+                bytes.len(),
+            )
+        {
+            self.current.fetch_add(bytes.len(), Ordering::Relaxed);
+            cache.put(id, CacheEntry::Resolved(bytes));
+        }
 
         Ok(())
     }
@@ -568,6 +664,15 @@ impl<M: ArchiveMetadataBackend, D: ArchiveBackend> ArchiveBackend for LruArchive
             ))
         }
     }
+
+    async fn cache_stats(&self) -> Option<CacheStats> {
+        let cache = self.cache.lock().await;
+        let entries = cache.iter().filter(|(_, e)| !e.is_resolving()).count();
+        Some(
+            self.counters
+                .snapshot(entries, self.current.load(Ordering::Relaxed)),
+        )
+    }
 }
 
 #[async_trait]
@@ -642,6 +747,22 @@ impl<M: ArchiveMetadataBackend, D: ArchiveBackend> ArchiveMetadataBackend
             Ok(None)
         }
     }
+
+    async fn get_layer_structure_sizes(
+        &self,
+        id: [u32; 5],
+    ) -> io::Result<Vec<(LayerFileEnum, usize)>> {
+        if let Some(CacheEntry::Resolved(bytes)) = self.cache.lock().await.peek(&id) {
+            let (header, _) = ArchiveHeader::parse(bytes.clone());
+            Ok(header.sizes())
+        } else {
+            self.metadata_origin.get_layer_structure_sizes(id).await
+        }
+    }
+
+    async fn layer_created(&self, id: [u32; 5]) -> io::Result<Option<SystemTime>> {
+        self.metadata_origin.layer_created(id).await
+    }
 }
 
 pub enum ConstructionFileState {
@@ -934,6 +1055,14 @@ impl ArchiveHeader {
     pub fn size_of(&self, file: LayerFileEnum) -> Option<usize> {
         self.range_for(file).map(|range| range.end - range.start)
     }
+
+    /// The size of every file present in this archive.
+    pub fn sizes(&self) -> Vec<(LayerFileEnum, usize)> {
+        (0..u64::BITS)
+            .filter_map(LayerFileEnum::from_u32)
+            .filter_map(|file| self.size_of(file).map(|size| (file, size)))
+            .collect()
+    }
 }
 
 pub struct Archive {
@@ -1363,6 +1492,10 @@ impl<M: ArchiveMetadataBackend + Unpin + 'static, D: ArchiveBackend + 'static> P
 {
     type File = ArchiveLayerHandle<M, D>;
 
+    async fn archive_cache_stats(&self) -> Option<CacheStats> {
+        self.data_backend.cache_stats().await
+    }
+
     async fn directories(&self) -> io::Result<Vec<[u32; 5]>> {
         let mut result = self.metadata_backend.get_layer_names().await?;
 
@@ -1465,6 +1598,18 @@ impl<M: ArchiveMetadataBackend + Unpin + 'static, D: ArchiveBackend + 'static> P
             .await
     }
 
+    async fn discard_directory(&self, directory: [u32; 5]) -> io::Result<()> {
+        let mut guard = self.construction.write().unwrap();
+        if guard.remove(&directory).is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "layer to be discarded is not under construction",
+            ));
+        }
+
+        Ok(())
+    }
+
     async fn finalize(&self, directory: [u32; 5]) -> io::Result<()> {
         let files = {
             let mut guard = self.construction.write().unwrap();
@@ -1506,6 +1651,41 @@ impl<M: ArchiveMetadataBackend + Unpin + 'static, D: ArchiveBackend + 'static> P
     async fn layer_parent(&self, name: [u32; 5]) -> io::Result<Option<[u32; 5]>> {
         self.metadata_backend.get_parent(name).await
     }
+
+    async fn layer_sections(&self, name: [u32; 5]) -> io::Result<Vec<(LayerFileEnum, usize)>> {
+        {
+            let guard = self.construction.read().unwrap();
+            if let Some(map) = guard.get(&name) {
+                let mut result: Vec<_> = map
+                    .iter()
+                    .filter(|(_, file)| file.is_finalized())
+                    .map(|(file_type, file)| (*file_type, file.clone().finalized_buf().len()))
+                    .collect();
+                result.sort();
+                return Ok(result);
+            }
+        }
+
+        self.metadata_backend.get_layer_structure_sizes(name).await
+    }
+
+    async fn layer_size(&self, name: [u32; 5]) -> io::Result<u64> {
+        if self.construction.read().unwrap().contains_key(&name) {
+            // not written yet, so the best we can do is count the sections
+            let sections = self.layer_sections(name).await?;
+            return Ok(sections.into_iter().map(|(_, size)| size as u64).sum());
+        }
+
+        self.metadata_backend.layer_size(name).await
+    }
+
+    async fn layer_created(&self, name: [u32; 5]) -> io::Result<Option<SystemTime>> {
+        if self.construction.read().unwrap().contains_key(&name) {
+            return Ok(None);
+        }
+
+        self.metadata_backend.layer_created(name).await
+    }
 }
 
 #[cfg(test)]
@@ -1531,4 +1711,48 @@ mod tests {
 
         assert!(!header.is_present(LayerFileEnum::NodeDictionaryOffsets));
     }
+
+    #[tokio::test]
+    async fn lru_backend_tracks_resident_bytes_and_evicts() {
+        let dir = tempfile::tempdir().unwrap();
+        let origin = DirectoryArchiveBackend::new(dir.path().into());
+        let backend = LruArchiveBackend::new(origin.clone(), origin, 1);
+        let half_mb = Bytes::from(vec![0_u8; 512 * 1024]);
+
+        backend
+            .store_layer_file([0, 0, 0, 0, 1], half_mb.clone())
+            .await
+            .unwrap();
+        backend
+            .store_layer_file([0, 0, 0, 0, 2], half_mb.clone())
+            .await
+            .unwrap();
+
+        let stats = backend.cache_stats().await.unwrap();
+        assert_eq!(2, stats.entries);
+        assert_eq!(1024 * 1024, stats.bytes_resident);
+        assert_eq!(0, stats.evictions);
+
+        backend
+            .store_layer_file([0, 0, 0, 0, 3], half_mb.clone())
+            .await
+            .unwrap();
+
+        let stats = backend.cache_stats().await.unwrap();
+        assert_eq!(2, stats.entries);
+        assert_eq!(1024 * 1024, stats.bytes_resident);
+        assert_eq!(1, stats.evictions);
+
+        // the most recently stored layer is still cached
+        backend.get_layer_bytes([0, 0, 0, 0, 3]).await.unwrap();
+        // but the first one was evicted, and loading it again evicts the second one
+        backend.get_layer_bytes([0, 0, 0, 0, 1]).await.unwrap();
+
+        let stats = backend.cache_stats().await.unwrap();
+        assert_eq!(1, stats.hits);
+        assert_eq!(1, stats.misses);
+        assert_eq!(2, stats.evictions);
+        assert_eq!(2, stats.entries);
+        assert_eq!(1024 * 1024, stats.bytes_resident);
+    }
 }
@@ -1,10 +1,17 @@
+use super::copy::LayerCopy;
+use super::info::*;
 use super::layer::*;
+use super::metrics::*;
+use super::progress::Progress;
 use crate::layer::*;
 use async_trait::async_trait;
+use futures::stream::BoxStream;
 use std::collections::HashMap;
 use std::io;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, RwLock, Weak};
+use std::time::Instant;
 use tdb_succinct::{StringDict, TypedDict};
 
 pub trait LayerCache: 'static + Send + Sync {
@@ -12,6 +19,11 @@ pub trait LayerCache: 'static + Send + Sync {
     fn cache_layer(&self, layer: Arc<InternalLayer>);
 
     fn invalidate(&self, name: [u32; 5]);
+
+    /// Return a snapshot of the counters of this cache.
+    fn stats(&self) -> CacheStats {
+        CacheStats::default()
+    }
 }
 
 pub struct NoCache;
@@ -36,6 +48,7 @@ lazy_static! {
 #[derive(Default)]
 pub struct LockingHashMapLayerCache {
     cache: RwLock<HashMap<[u32; 5], Weak<InternalLayer>>>,
+    counters: CacheCounters,
 }
 
 impl LockingHashMapLayerCache {
@@ -55,16 +68,28 @@ impl LayerCache for LockingHashMapLayerCache {
         std::mem::drop(cache);
 
         match result {
-            None => None,
+            None => {
+                self.counters.miss();
+                None
+            }
             Some(weak) => match weak.upgrade() {
                 None => {
-                    self.cache
+                    self.counters.miss();
+                    if self
+                        .cache
                         .write()
                         .expect("rwlock write should always succeed")
-                        .remove(&name);
+                        .remove(&name)
+                        .is_some()
+                    {
+                        self.counters.evict(1);
+                    }
                     None
                 }
-                Some(result) => Some(result),
+                Some(result) => {
+                    self.counters.hit();
+                    Some(result)
+                }
             },
         }
     }
@@ -84,7 +109,58 @@ impl LayerCache for LockingHashMapLayerCache {
             .write()
             .expect("rwlock read should always succeed");
 
-        cache.remove(&name);
+        if cache.remove(&name).is_some() {
+            self.counters.evict(1);
+        }
+    }
+
+    fn stats(&self) -> CacheStats {
+        let cache = self
+            .cache
+            .read()
+            .expect("rwlock read should always succeed");
+
+        let mut entries = 0;
+        let mut bytes_resident = 0;
+        for layer in cache.values().filter_map(|weak| weak.upgrade()) {
+            entries += 1;
+            // a rollup shares its data with the rolled-up layer, which is cached separately.
+            if !layer.is_rollup() {
+                bytes_resident += layer.mapped_size();
+            }
+        }
+
+        self.counters.snapshot(entries, bytes_resident)
+    }
+}
+
+/// A cache wrapper which records every layer that gets loaded into the wrapped cache.
+struct LoadRecordingLayerCache {
+    inner: Arc<dyn LayerCache>,
+    loads: Arc<LoadCounters>,
+    loaded: AtomicBool,
+}
+
+impl LayerCache for LoadRecordingLayerCache {
+    fn get_layer_from_cache(&self, name: [u32; 5]) -> Option<Arc<InternalLayer>> {
+        self.inner.get_layer_from_cache(name)
+    }
+
+    fn cache_layer(&self, layer: Arc<InternalLayer>) {
+        // layers only end up here right after being loaded from the backend.
+        if !layer.is_rollup() {
+            self.loads.layer_loaded(layer.mapped_size());
+            self.loaded.store(true, Ordering::Relaxed);
+        }
+        self.inner.cache_layer(layer);
+    }
+
+    fn invalidate(&self, name: [u32; 5]) {
+        self.inner.invalidate(name);
+    }
+
+    fn stats(&self) -> CacheStats {
+        self.inner.stats()
     }
 }
 
@@ -92,6 +168,7 @@ impl LayerCache for LockingHashMapLayerCache {
 pub struct CachedLayerStore {
     pub(crate) inner: Arc<dyn LayerStore>,
     pub(crate) cache: Arc<dyn LayerCache>,
+    loads: Arc<LoadCounters>,
 }
 
 impl CachedLayerStore {
@@ -99,12 +176,21 @@ impl CachedLayerStore {
         CachedLayerStore {
             inner: Arc::new(inner),
             cache: Arc::new(cache),
+            loads: Default::default(),
         }
     }
 
     pub fn invalidate(&self, name: [u32; 5]) {
         self.cache.invalidate(name);
     }
+
+    fn recording_cache(&self) -> Arc<LoadRecordingLayerCache> {
+        Arc::new(LoadRecordingLayerCache {
+            inner: self.cache.clone(),
+            loads: self.loads.clone(),
+            loaded: AtomicBool::new(false),
+        })
+    }
 }
 
 #[async_trait]
@@ -113,10 +199,31 @@ impl LayerStore for CachedLayerStore {
         self.inner.layers().await
     }
 
+    async fn layer_info(&self, name: [u32; 5]) -> io::Result<Option<LayerInfo>> {
+        self.inner.layer_info(name).await
+    }
+
+    async fn layers_info(&self) -> io::Result<Vec<LayerInfo>> {
+        self.inner.layers_info().await
+    }
+
+    async fn read_layer_copy(&self, name: [u32; 5]) -> io::Result<Option<LayerCopy>> {
+        self.inner.read_layer_copy(name).await
+    }
+
+    async fn write_layer_copy(&self, copy: &LayerCopy) -> io::Result<()> {
+        self.inner.write_layer_copy(copy).await
+    }
+
     async fn get_layer(&self, name: [u32; 5]) -> io::Result<Option<Arc<InternalLayer>>> {
-        self.inner
-            .get_layer_with_cache(name, self.cache.clone())
-            .await
+        let start = Instant::now();
+        let cache = self.recording_cache();
+        let result = self.inner.get_layer_with_cache(name, cache.clone()).await;
+        if cache.loaded.load(Ordering::Relaxed) {
+            self.loads.observe_latency(start.elapsed());
+        }
+
+        result
     }
 
     async fn get_layer_with_cache(
@@ -242,7 +349,7 @@ impl LayerStore for CachedLayerStore {
 
     async fn create_child_layer(&self, parent: [u32; 5]) -> io::Result<Box<dyn LayerBuilder>> {
         self.inner
-            .create_child_layer_with_cache(parent, self.cache.clone())
+            .create_child_layer_with_cache(parent, self.recording_cache())
             .await
     }
 
@@ -277,7 +384,7 @@ impl LayerStore for CachedLayerStore {
         upto: [u32; 5],
     ) -> io::Result<[u32; 5]> {
         self.inner
-            .perform_rollup_upto_with_cache(layer, upto, self.cache.clone())
+            .perform_rollup_upto_with_cache(layer, upto, self.recording_cache())
             .await
     }
 
@@ -298,7 +405,7 @@ impl LayerStore for CachedLayerStore {
         upto: [u32; 5],
     ) -> io::Result<[u32; 5]> {
         self.inner
-            .perform_imprecise_rollup_upto_with_cache(layer, upto, self.cache.clone())
+            .perform_imprecise_rollup_upto_with_cache(layer, upto, self.recording_cache())
             .await
     }
 
@@ -311,11 +418,46 @@ impl LayerStore for CachedLayerStore {
         Ok(())
     }
 
+    async fn get_layer_rollup(&self, name: [u32; 5]) -> io::Result<Option<[u32; 5]>> {
+        self.inner.get_layer_rollup(name).await
+    }
+
+    async fn layer_quad_changes(&self, layer: [u32; 5]) -> io::Result<QuadChanges> {
+        self.inner.layer_quad_changes(layer).await
+    }
+
     async fn rollup_upto(&self, layer: Arc<InternalLayer>, upto: [u32; 5]) -> io::Result<[u32; 5]> {
-        let cache = self.cache.clone();
+        let cache = self.recording_cache();
         self.rollup_upto_with_cache(layer, upto, cache).await
     }
 
+    async fn perform_rollup_upto_with_progress(
+        &self,
+        layer: Arc<InternalLayer>,
+        upto: [u32; 5],
+        cache: Arc<dyn LayerCache>,
+        progress: &Progress,
+    ) -> io::Result<[u32; 5]> {
+        self.inner
+            .perform_rollup_upto_with_progress(layer, upto, cache, progress)
+            .await
+    }
+
+    async fn rollup_upto_with_progress(
+        &self,
+        layer: Arc<InternalLayer>,
+        upto: [u32; 5],
+        progress: &Progress,
+    ) -> io::Result<[u32; 5]> {
+        let name = layer.name();
+        let rollup = self
+            .perform_rollup_upto_with_progress(layer, upto, self.recording_cache(), progress)
+            .await?;
+        self.register_rollup(name, rollup).await?;
+
+        Ok(rollup)
+    }
+
     async fn squash(&self, layer: Arc<InternalLayer>) -> io::Result<[u32; 5]> {
         self.inner.squash(layer).await
     }
@@ -324,10 +466,114 @@ impl LayerStore for CachedLayerStore {
         self.inner.squash_upto(layer, upto).await
     }
 
+    async fn squash_upto_with_progress(
+        &self,
+        layer: Arc<InternalLayer>,
+        upto: [u32; 5],
+        progress: &Progress,
+    ) -> io::Result<[u32; 5]> {
+        self.inner
+            .squash_upto_with_progress(layer, upto, progress)
+            .await
+    }
+
+    async fn squash_external(
+        &self,
+        layer: Arc<InternalLayer>,
+        temp_dir: &Path,
+    ) -> io::Result<[u32; 5]> {
+        self.inner.squash_external(layer, temp_dir).await
+    }
+
+    async fn squash_external_with_progress(
+        &self,
+        layer: Arc<InternalLayer>,
+        temp_dir: &Path,
+        progress: &Progress,
+    ) -> io::Result<[u32; 5]> {
+        self.inner
+            .squash_external_with_progress(layer, temp_dir, progress)
+            .await
+    }
+
+    async fn squash_upto_external(
+        &self,
+        layer: Arc<InternalLayer>,
+        upto: [u32; 5],
+        temp_dir: &Path,
+    ) -> io::Result<[u32; 5]> {
+        self.inner.squash_upto_external(layer, upto, temp_dir).await
+    }
+
+    async fn squash_upto_external_with_progress(
+        &self,
+        layer: Arc<InternalLayer>,
+        upto: [u32; 5],
+        temp_dir: &Path,
+        progress: &Progress,
+    ) -> io::Result<[u32; 5]> {
+        self.inner
+            .squash_upto_external_with_progress(layer, upto, temp_dir, progress)
+            .await
+    }
+
     async fn merge_base_layer(&self, layers: &[[u32; 5]], temp_dir: &Path) -> io::Result<[u32; 5]> {
         self.inner.merge_base_layer(layers, temp_dir).await
     }
 
+    async fn merge_base_layer_with_progress(
+        &self,
+        layers: &[[u32; 5]],
+        temp_dir: &Path,
+        progress: &Progress,
+    ) -> io::Result<[u32; 5]> {
+        self.inner
+            .merge_base_layer_with_progress(layers, temp_dir, progress)
+            .await
+    }
+
+    async fn bulk_load_base_layer(
+        &self,
+        triples: BoxStream<'_, ValueTriple>,
+        temp_dir: &Path,
+    ) -> io::Result<[u32; 5]> {
+        self.inner.bulk_load_base_layer(triples, temp_dir).await
+    }
+
+    async fn bulk_load_base_layer_with_progress(
+        &self,
+        triples: BoxStream<'_, ValueTriple>,
+        temp_dir: &Path,
+        progress: &Progress,
+    ) -> io::Result<[u32; 5]> {
+        self.inner
+            .bulk_load_base_layer_with_progress(triples, temp_dir, progress)
+            .await
+    }
+
+    async fn bulk_load_base_layer_quads(
+        &self,
+        quads: BoxStream<'_, ValueQuadTriple>,
+        temp_dir: &Path,
+    ) -> io::Result<[u32; 5]> {
+        self.inner.bulk_load_base_layer_quads(quads, temp_dir).await
+    }
+
+    async fn bulk_load_base_layer_quads_with_progress(
+        &self,
+        quads: BoxStream<'_, ValueQuadTriple>,
+        temp_dir: &Path,
+        progress: &Progress,
+    ) -> io::Result<[u32; 5]> {
+        self.inner
+            .bulk_load_base_layer_quads_with_progress(quads, temp_dir, progress)
+            .await
+    }
+
+    async fn discard_layer(&self, name: [u32; 5]) -> io::Result<()> {
+        self.inner.discard_layer(name).await
+    }
+
     async fn layer_is_ancestor_of(
         &self,
         descendant: [u32; 5],
@@ -518,6 +764,34 @@ impl LayerStore for CachedLayerStore {
         self.inner.triple_removals_o(layer, object).await
     }
 
+    async fn triple_additions_from_cursor(
+        &self,
+        layer: [u32; 5],
+        cursor: &TripleCursor,
+    ) -> io::Result<TripleCursorIterator> {
+        if let Some(cached) = self.cache.get_layer_from_cache(layer) {
+            if !cached.is_rollup() {
+                return Ok(cached.internal_triple_additions_from_cursor(cursor));
+            }
+        }
+
+        self.inner.triple_additions_from_cursor(layer, cursor).await
+    }
+
+    async fn triple_removals_from_cursor(
+        &self,
+        layer: [u32; 5],
+        cursor: &TripleCursor,
+    ) -> io::Result<TripleCursorIterator> {
+        if let Some(cached) = self.cache.get_layer_from_cache(layer) {
+            if !cached.is_rollup() {
+                return Ok(cached.internal_triple_removals_from_cursor(cursor));
+            }
+        }
+
+        self.inner.triple_removals_from_cursor(layer, cursor).await
+    }
+
     async fn triple_layer_addition_count(&self, layer: [u32; 5]) -> io::Result<usize> {
         if let Some(cached) = self.cache.get_layer_from_cache(layer) {
             if !cached.is_rollup() {
@@ -538,6 +812,38 @@ impl LayerStore for CachedLayerStore {
         self.inner.triple_layer_removal_count(layer).await
     }
 
+    async fn triple_layer_addition_count_matching(
+        &self,
+        layer: [u32; 5],
+        pattern: TriplePattern,
+    ) -> io::Result<usize> {
+        if let Some(cached) = self.cache.get_layer_from_cache(layer) {
+            if !cached.is_rollup() {
+                return Ok(cached.internal_triple_layer_addition_count_matching(pattern));
+            }
+        }
+
+        self.inner
+            .triple_layer_addition_count_matching(layer, pattern)
+            .await
+    }
+
+    async fn triple_layer_removal_count_matching(
+        &self,
+        layer: [u32; 5],
+        pattern: TriplePattern,
+    ) -> io::Result<usize> {
+        if let Some(cached) = self.cache.get_layer_from_cache(layer) {
+            if !cached.is_rollup() {
+                return Ok(cached.internal_triple_layer_removal_count_matching(pattern));
+            }
+        }
+
+        self.inner
+            .triple_layer_removal_count_matching(layer, pattern)
+            .await
+    }
+
     async fn retrieve_layer_stack_names(&self, name: [u32; 5]) -> io::Result<Vec<[u32; 5]>> {
         self.inner.retrieve_layer_stack_names(name).await
     }
@@ -549,6 +855,14 @@ impl LayerStore for CachedLayerStore {
     ) -> io::Result<Vec<[u32; 5]>> {
         self.inner.retrieve_layer_stack_names_upto(name, upto).await
     }
+
+    async fn metrics(&self) -> StoreMetrics {
+        let mut metrics = self.inner.metrics().await;
+        metrics.layer_cache = Some(self.cache.stats());
+        metrics.loads = self.loads.snapshot();
+
+        metrics
+    }
 }
 
 #[cfg(test)]
@@ -663,6 +977,54 @@ pub mod tests {
         assert_eq!(1, Arc::weak_count(&layer));
     }
 
+    #[tokio::test]
+    async fn cached_layer_store_reports_metrics() {
+        let store = CachedLayerStore::new(MemoryLayerStore::new(), LockingHashMapLayerCache::new());
+        let mut builder = store.create_base_layer().await.unwrap();
+        let base_name = builder.name();
+
+        builder.add_value_triple(ValueTriple::new_string_value("cow", "says", "moo"));
+        builder.commit_boxed().await.unwrap();
+
+        builder = store.create_child_layer(base_name).await.unwrap();
+        let child_name = builder.name();
+        builder.add_value_triple(ValueTriple::new_string_value("pig", "says", "oink"));
+        builder.commit_boxed().await.unwrap();
+
+        let metrics = store.metrics().await;
+        // creating the child layer loaded the base layer
+        assert_eq!(1, metrics.loads.layers_loaded);
+        assert_eq!(None, metrics.archive_cache);
+
+        let layer = store.get_layer(child_name).await.unwrap().unwrap();
+        let metrics = store.metrics().await;
+        let cache_stats = metrics.layer_cache.unwrap();
+        // the base layer was no longer in use, so it had to be loaded again
+        assert_eq!(3, metrics.loads.layers_loaded);
+        assert_eq!(1, metrics.loads.latency.count);
+        assert_eq!(2, cache_stats.entries);
+        assert!(cache_stats.bytes_resident > 0);
+        let base_size = layer.immediate_parent().unwrap().mapped_size();
+        assert_eq!(
+            (2 * base_size + layer.mapped_size()) as u64,
+            metrics.loads.bytes_loaded
+        );
+
+        let hits = cache_stats.hits;
+        let layer2 = store.get_layer(child_name).await.unwrap().unwrap();
+        let metrics = store.metrics().await;
+        assert_eq!(hits + 1, metrics.layer_cache.unwrap().hits);
+        // nothing had to be loaded, so no latency was recorded
+        assert_eq!(3, metrics.loads.layers_loaded);
+        assert_eq!(1, metrics.loads.latency.count);
+
+        std::mem::drop(layer);
+        std::mem::drop(layer2);
+        let metrics = store.metrics().await;
+        assert_eq!(0, metrics.layer_cache.unwrap().entries);
+        assert_eq!(0, metrics.layer_cache.unwrap().bytes_resident);
+    }
+
     #[test]
     fn retrieve_layer_stack_names_retrieves_correctly() {
         //let store = CachedLayerStore::new(MemoryLayerStore::new());
@@ -90,6 +90,12 @@ pub struct Filenames {
 
     pub parent: &'static str,
     pub rollup: &'static str,
+
+    pub base_quads: &'static str,
+    pub pos_quads: &'static str,
+    pub neg_quads: &'static str,
+
+    pub text_index: &'static str,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, FromPrimitive)]
@@ -156,6 +162,12 @@ pub enum LayerFileEnum {
 
     Parent,
     Rollup,
+
+    BaseQuads,
+    PosQuads,
+    NegQuads,
+
+    TextIndex,
 }
 
 pub const FILENAMES: Filenames = Filenames {
@@ -254,6 +266,12 @@ pub const FILENAMES: Filenames = Filenames {
 
     parent: "parent.hex",
     rollup: "rollup.hex",
+
+    base_quads: "base_quads.graphindex",
+    pos_quads: "pos_quads.graphindex",
+    neg_quads: "neg_quads.graphindex",
+
+    text_index: "values.textindex",
 };
 
 lazy_static! {
@@ -502,6 +520,10 @@ lazy_static! {
         ),
         ("parent.hex", LayerFileEnum::Parent),
         ("rollup.hex", LayerFileEnum::Rollup),
+        ("base_quads.graphindex", LayerFileEnum::BaseQuads),
+        ("pos_quads.graphindex", LayerFileEnum::PosQuads),
+        ("neg_quads.graphindex", LayerFileEnum::NegQuads),
+        ("values.textindex", LayerFileEnum::TextIndex),
     ]);
 }
 
@@ -544,8 +566,12 @@ pub const BASE_LAYER_REQUIRED_FILES: [&'static str; 15] = [
     FILENAMES.base_predicate_wavelet_tree_bit_index_sblocks,
 ];
 
-pub const BASE_LAYER_OPTIONAL_FILES: [&'static str; 2] =
-    [FILENAMES.base_subjects, FILENAMES.base_objects];
+pub const BASE_LAYER_OPTIONAL_FILES: [&'static str; 4] = [
+    FILENAMES.base_subjects,
+    FILENAMES.base_objects,
+    FILENAMES.base_quads,
+    FILENAMES.text_index,
+];
 
 pub const CHILD_LAYER_REQUIRED_FILES: [&'static str; 31] = [
     FILENAMES.parent,
@@ -581,9 +607,12 @@ pub const CHILD_LAYER_REQUIRED_FILES: [&'static str; 31] = [
     FILENAMES.neg_predicate_wavelet_tree_bit_index_sblocks,
 ];
 
-pub const CHILD_LAYER_OPTIONAL_FILES: [&'static str; 4] = [
+pub const CHILD_LAYER_OPTIONAL_FILES: [&'static str; 7] = [
     FILENAMES.pos_subjects,
     FILENAMES.pos_objects,
     FILENAMES.neg_subjects,
     FILENAMES.neg_objects,
+    FILENAMES.pos_quads,
+    FILENAMES.neg_quads,
+    FILENAMES.text_index,
 ];
@@ -1,6 +1,10 @@
 use std::io;
 
-use super::{BaseLayerFiles, ChildLayerFiles, FileLoad, FileStore, IdMapFiles};
+use super::memory::{base_layer_memory_files, child_layer_memory_files, MemoryBackedStore};
+use super::{
+    BaseLayerFiles, ChildLayerFiles, FileLoad, FileStore, IdMapFiles, LayerFiles,
+    PersistentLayerStore,
+};
 
 use tdb_succinct::storage::copy_file;
 
@@ -49,6 +53,8 @@ impl<F1: 'static + FileLoad + FileStore> BaseLayerFiles<F1> {
         self.predicate_wavelet_tree_files
             .copy_from(&from.predicate_wavelet_tree_files)
             .await?;
+        copy_file(&from.quads_file, &self.quads_file).await?;
+        copy_file(&from.text_index_file, &self.text_index_file).await?;
 
         Ok(())
     }
@@ -100,6 +106,91 @@ impl<F1: 'static + FileLoad + FileStore> ChildLayerFiles<F1> {
             .copy_from(&from.neg_predicate_wavelet_tree_files)
             .await?;
 
+        copy_file(&from.pos_quads_file, &self.pos_quads_file).await?;
+        copy_file(&from.neg_quads_file, &self.neg_quads_file).await?;
+        copy_file(&from.text_index_file, &self.text_index_file).await?;
+
         Ok(())
     }
 }
+
+/// All the files of a single layer, read into memory.
+///
+/// This is how layers are carried over from one layer store into
+/// another, regardless of how either store keeps its files.
+#[derive(Clone)]
+pub struct LayerCopy {
+    /// The name of the copied layer.
+    pub name: [u32; 5],
+    /// The parent of the copied layer, if it is a child layer.
+    pub parent: Option<[u32; 5]>,
+    /// The contents of the layer.
+    pub files: LayerFiles<MemoryBackedStore>,
+}
+
+impl LayerCopy {
+    pub(crate) async fn read_from<T: PersistentLayerStore>(
+        store: &T,
+        name: [u32; 5],
+    ) -> io::Result<Self> {
+        let parent = store.layer_parent(name).await?;
+        let files = match parent {
+            None => {
+                let files = base_layer_memory_files();
+                files
+                    .copy_from(&store.base_layer_files(name).await?)
+                    .await?;
+                LayerFiles::Base(files)
+            }
+            Some(_) => {
+                let files = child_layer_memory_files();
+                files
+                    .copy_from(&store.child_layer_files(name).await?)
+                    .await?;
+                LayerFiles::Child(files)
+            }
+        };
+
+        Ok(Self {
+            name,
+            parent,
+            files,
+        })
+    }
+
+    pub(crate) async fn write_into<T: PersistentLayerStore>(&self, store: &T) -> io::Result<()> {
+        store.create_named_directory(self.name).await?;
+        match self.write_files_into(store).await {
+            Ok(()) => store.finalize(self.name).await,
+            Err(e) => {
+                // don't leave a half-written layer behind
+                store.discard_directory(self.name).await?;
+                Err(e)
+            }
+        }
+    }
+
+    async fn write_files_into<T: PersistentLayerStore>(&self, store: &T) -> io::Result<()> {
+        match (&self.files, self.parent) {
+            (LayerFiles::Base(files), None) => {
+                store
+                    .base_layer_files(self.name)
+                    .await?
+                    .copy_from(files)
+                    .await
+            }
+            (LayerFiles::Child(files), Some(parent)) => {
+                store
+                    .child_layer_files(self.name)
+                    .await?
+                    .copy_from(files)
+                    .await?;
+                store.write_parent_file(self.name, parent).await
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "layer copy has a parent that doesn't match its files",
+            )),
+        }
+    }
+}
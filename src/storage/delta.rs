@@ -1,6 +1,6 @@
 use std::io;
 
-use crate::layer::builder::{build_indexes, TripleFileBuilder};
+use crate::layer::builder::{build_indexes, build_indexes_with_progress, TripleFileBuilder};
 use crate::layer::*;
 use crate::storage::*;
 use tdb_succinct::*;
@@ -44,6 +44,22 @@ async fn safe_upto_bound<S: LayerStore>(
     }
 }
 
+async fn write_quad_changes<F: FileStore>(
+    changes: QuadChanges,
+    pos_quads_file: &F,
+    neg_quads_file: &F,
+) -> io::Result<()> {
+    let (additions, removals) = changes.into_parts();
+    if !additions.is_empty() {
+        write_graph_index(pos_quads_file, additions).await?;
+    }
+    if !removals.is_empty() {
+        write_graph_index(neg_quads_file, removals).await?;
+    }
+
+    Ok(())
+}
+
 async fn get_node_dicts_from_disk<S: LayerStore>(
     store: &S,
     name: [u32; 5],
@@ -288,6 +304,14 @@ pub async fn delta_rollup<F: 'static + FileLoad + FileStore>(
     builder.add_id_triples(layer.triples()).await?;
     builder.finalize().await?;
 
+    let (quads, _) = layer.quad_changes_upto(None).into_parts();
+    if !quads.is_empty() {
+        write_graph_index(&files.quads_file, quads).await?;
+    }
+    if layer.text_index().is_some() {
+        write_text_index(&files.value_dictionary_files, &files.text_index_file).await?;
+    }
+
     build_indexes(
         files.s_p_adjacency_list_files.clone(),
         files.sp_o_adjacency_list_files.clone(),
@@ -345,6 +369,16 @@ pub async fn imprecise_delta_rollup_upto<S: LayerStore, F: 'static + FileLoad +
     neg_builder.add_id_triples(removals).await?;
     neg_builder.finalize().await?;
 
+    write_quad_changes(
+        layer.quad_changes_upto(Some(bound)),
+        &files.pos_quads_file,
+        &files.neg_quads_file,
+    )
+    .await?;
+    if layer.text_index().is_some() {
+        write_text_index(&files.value_dictionary_files, &files.text_index_file).await?;
+    }
+
     build_indexes(
         files.pos_s_p_adjacency_list_files.clone(),
         files.pos_sp_o_adjacency_list_files.clone(),
@@ -369,9 +403,21 @@ pub async fn delta_rollup_upto<S: LayerStore, F: 'static + FileLoad + FileStore>
     layer: &InternalLayer,
     upto: [u32; 5],
     files: ChildLayerFiles<F>,
+) -> io::Result<()> {
+    delta_rollup_upto_with_progress(store, layer, upto, files, &Progress::default()).await
+}
+
+/// Like `delta_rollup_upto`, reporting to and cancellable through `progress`.
+pub async fn delta_rollup_upto_with_progress<S: LayerStore, F: 'static + FileLoad + FileStore>(
+    store: &S,
+    layer: &InternalLayer,
+    upto: [u32; 5],
+    files: ChildLayerFiles<F>,
+    progress: &Progress,
 ) -> io::Result<()> {
     let bound = safe_upto_bound(store, layer, upto).await?;
     dictionary_rollup_upto(store, layer, bound, upto, &files).await?;
+    progress.dictionaries_merged(3)?;
 
     let counts = layer.all_counts();
 
@@ -408,27 +454,41 @@ pub async fn delta_rollup_upto<S: LayerStore, F: 'static + FileLoad + FileStore>
         .filter(|(sort, _)| *sort == TripleChange::Removal)
         .map(|(_, t)| t);
 
-    pos_builder.add_id_triples(additions).await?;
+    pos_builder
+        .add_id_triples_with_progress(additions, progress)
+        .await?;
     pos_builder.finalize().await?;
 
-    neg_builder.add_id_triples(removals).await?;
+    neg_builder
+        .add_id_triples_with_progress(removals, progress)
+        .await?;
     neg_builder.finalize().await?;
 
-    build_indexes(
+    let mut quad_changes = store.layer_quad_changes_upto(bound, upto).await?;
+    quad_changes.apply(layer.quad_changes_upto(Some(bound)));
+    write_quad_changes(quad_changes, &files.pos_quads_file, &files.neg_quads_file).await?;
+    if layer.text_index().is_some() {
+        write_text_index(&files.value_dictionary_files, &files.text_index_file).await?;
+    }
+    progress.triples_done()?;
+
+    build_indexes_with_progress(
         files.pos_s_p_adjacency_list_files.clone(),
         files.pos_sp_o_adjacency_list_files.clone(),
         files.pos_o_ps_adjacency_list_files.clone(),
         Some(files.pos_objects_file.clone()),
         files.pos_predicate_wavelet_tree_files.clone(),
+        progress,
     )
     .await?;
 
-    build_indexes(
+    build_indexes_with_progress(
         files.neg_s_p_adjacency_list_files.clone(),
         files.neg_sp_o_adjacency_list_files.clone(),
         files.neg_o_ps_adjacency_list_files.clone(),
         Some(files.neg_objects_file.clone()),
         files.neg_predicate_wavelet_tree_files.clone(),
+        progress,
     )
     .await
 }
@@ -3,20 +3,36 @@
 use locking::*;
 use std::collections::HashMap;
 use std::io::{self, SeekFrom};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::SystemTime;
 use tokio::fs;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::sync::RwLock;
 
 use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt};
+use notify::{RecursiveMode, Watcher};
 
 pub use tdb_succinct::storage::file::*;
 
+use super::consts::{LayerFileEnum, FILENAME_ENUM_MAP};
 use super::*;
 
 const PREFIX_DIR_SIZE: usize = 3;
 
+async fn subdirectories(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut stream = fs::read_dir(path).await?;
+    let mut result = Vec::new();
+    while let Some(direntry) = stream.next_entry().await? {
+        if direntry.file_type().await?.is_dir() {
+            result.push(direntry.path());
+        }
+    }
+
+    Ok(result)
+}
+
 #[derive(Clone)]
 pub struct DirectoryLayerStore {
     path: PathBuf,
@@ -26,23 +42,52 @@ impl DirectoryLayerStore {
     pub fn new<P: Into<PathBuf>>(path: P) -> DirectoryLayerStore {
         DirectoryLayerStore { path: path.into() }
     }
+
+    fn layer_path(&self, name: [u32; 5]) -> PathBuf {
+        let mut p = self.path.clone();
+        let name_str = name_to_string(name);
+        p.push(&name_str[0..PREFIX_DIR_SIZE]);
+        p.push(name_str);
+
+        p
+    }
+
+    /// Return the name and size of every file in the given layer.
+    async fn layer_files(&self, name: [u32; 5]) -> io::Result<Vec<(String, u64)>> {
+        let mut stream = fs::read_dir(self.layer_path(name)).await?;
+        let mut result = Vec::new();
+        while let Some(direntry) = stream.next_entry().await? {
+            let metadata = direntry.metadata().await?;
+            if metadata.is_file() {
+                let file_name = direntry.file_name().to_string_lossy().into_owned();
+                result.push((file_name, metadata.len()));
+            }
+        }
+
+        Ok(result)
+    }
 }
 
 #[async_trait]
 impl PersistentLayerStore for DirectoryLayerStore {
     type File = FileBackedStore;
     async fn directories(&self) -> io::Result<Vec<[u32; 5]>> {
-        let mut stream = fs::read_dir(&self.path).await?;
         let mut result = Vec::new();
-        while let Some(direntry) = stream.next_entry().await? {
-            if direntry.file_type().await?.is_dir() {
-                let os_name = direntry.file_name();
-                let name = os_name.to_str().ok_or_else(|| {
-                    io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "unexpected non-utf8 directory name",
-                    )
-                })?;
+        // layers live in subdirectories named after the first characters of the layer name
+        for prefix_dir in subdirectories(&self.path).await? {
+            if prefix_dir.file_name().map(|n| n.len()) != Some(PREFIX_DIR_SIZE) {
+                continue;
+            }
+            for layer_dir in subdirectories(&prefix_dir).await? {
+                let name = layer_dir
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "unexpected non-utf8 directory name",
+                        )
+                    })?;
                 result.push(string_to_name(name)?);
             }
         }
@@ -61,6 +106,15 @@ impl PersistentLayerStore for DirectoryLayerStore {
         Ok(name)
     }
 
+    async fn discard_directory(&self, name: [u32; 5]) -> io::Result<()> {
+        let mut p = self.path.clone();
+        let name_str = name_to_string(name);
+        p.push(&name_str[0..PREFIX_DIR_SIZE]);
+        p.push(name_str);
+
+        fs::remove_dir_all(p).await
+    }
+
     async fn directory_exists(&self, name: [u32; 5]) -> io::Result<bool> {
         let mut p = self.path.clone();
         let name = name_to_string(name);
@@ -94,6 +148,36 @@ impl PersistentLayerStore for DirectoryLayerStore {
             Err(_) => Ok(false),
         }
     }
+    async fn layer_sections(&self, name: [u32; 5]) -> io::Result<Vec<(LayerFileEnum, usize)>> {
+        let mut result: Vec<_> = self
+            .layer_files(name)
+            .await?
+            .into_iter()
+            .filter_map(|(file, size)| match FILENAME_ENUM_MAP.get(file.as_str()) {
+                Some(LayerFileEnum::Rollup) | None => None,
+                Some(section) => Some((*section, size as usize)),
+            })
+            .collect();
+        result.sort();
+
+        Ok(result)
+    }
+
+    async fn layer_size(&self, name: [u32; 5]) -> io::Result<u64> {
+        Ok(self
+            .layer_files(name)
+            .await?
+            .into_iter()
+            .map(|(_, size)| size)
+            .sum())
+    }
+
+    async fn layer_created(&self, name: [u32; 5]) -> io::Result<Option<SystemTime>> {
+        let metadata = fs::metadata(self.layer_path(name)).await?;
+        // not every platform records creation times
+        Ok(metadata.created().or_else(|_| metadata.modified()).ok())
+    }
+
     async fn finalize(&self, directory: [u32; 5]) -> io::Result<()> {
         if cfg!(unix) {
             // ensure the underlying directory record is properly synchronized
@@ -168,6 +252,15 @@ fn get_label_from_data(name: String, data: &[u8]) -> io::Result<Label> {
     }
 }
 
+fn label_file_contents(label: &Label) -> Vec<u8> {
+    match label.layer {
+        None => format!("{}\n\n", label.version).into_bytes(),
+        Some(layer) => {
+            format!("{}\n{}\n", label.version, layer::name_to_string(layer)).into_bytes()
+        }
+    }
+}
+
 async fn get_label_from_file<P: Into<PathBuf>>(path: P) -> io::Result<Label> {
     let path: PathBuf = path.into();
     let label = path.file_stem().unwrap().to_str().unwrap().to_owned();
@@ -195,6 +288,48 @@ async fn get_label_from_exclusive_locked_file<P: Into<PathBuf>>(
     Ok((label, file))
 }
 
+/// Watch a label file, yielding the label every time the file changes.
+///
+/// Returns None if the file system can't be watched.
+fn watch_label_file(directory: &Path, name: &str) -> Option<BoxStream<'static, Label>> {
+    let file_name = format!("{}.label", name);
+    let path = directory.join(&file_name);
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event {
+            if event
+                .paths
+                .iter()
+                .any(|p| p.file_name() == Some(file_name.as_ref()))
+            {
+                // the receiver is only gone once nobody is watching anymore
+                let _ = sender.send(());
+            }
+        }
+    })
+    .ok()?;
+    watcher.watch(directory, RecursiveMode::NonRecursive).ok()?;
+
+    let labels = stream::unfold(
+        (watcher, receiver, path),
+        |(watcher, mut receiver, path)| async move {
+            loop {
+                receiver.recv().await?;
+                // a single label write causes several events
+                while receiver.try_recv().is_ok() {}
+                match get_label_from_file(path.clone()).await {
+                    Ok(label) => return Some((label, (watcher, receiver, path))),
+                    // a deleted label ends the stream
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+                    Err(_) => continue,
+                }
+            }
+        },
+    );
+
+    Some(labels.boxed())
+}
+
 #[async_trait]
 impl LabelStore for DirectoryLabelStore {
     async fn labels(&self) -> io::Result<Vec<Label>> {
@@ -261,12 +396,7 @@ impl LabelStore for DirectoryLabelStore {
         layer: Option<[u32; 5]>,
     ) -> io::Result<Option<Label>> {
         let new_label = label.with_updated_layer(layer);
-        let contents = match new_label.layer {
-            None => format!("{}\n\n", new_label.version).into_bytes(),
-            Some(layer) => {
-                format!("{}\n{}\n", new_label.version, layer::name_to_string(layer)).into_bytes()
-            }
-        };
+        let contents = label_file_contents(&new_label);
 
         let mut p = self.path.clone();
         p.push(format!("{}.label", label.name));
@@ -304,6 +434,28 @@ impl LabelStore for DirectoryLabelStore {
             },
         }
     }
+
+    async fn import_label(&self, label: &Label) -> io::Result<()> {
+        let mut p = self.path.clone();
+        p.push(format!("{}.label", label.name));
+        let mut file = match ExclusiveLockedFile::open(p.clone()).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                ExclusiveLockedFile::create_and_open(p).await?
+            }
+            Err(e) => return Err(e),
+        };
+        file.truncate().await?;
+        file.write_all(&label_file_contents(label)).await?;
+        file.flush().await?;
+        file.sync_all().await?;
+
+        Ok(())
+    }
+
+    async fn watch_label(&self, name: &str) -> io::Result<Option<BoxStream<'static, Label>>> {
+        Ok(watch_label_file(&self.path, name))
+    }
 }
 
 /// A version of the directory label store that keeps all labels in
@@ -411,12 +563,7 @@ impl LabelStore for CachedDirectoryLabelStore {
         layer: Option<[u32; 5]>,
     ) -> io::Result<Option<Label>> {
         let new_label = label.with_updated_layer(layer);
-        let contents = match new_label.layer {
-            None => format!("{}\n\n", new_label.version).into_bytes(),
-            Some(layer) => {
-                format!("{}\n{}\n", new_label.version, layer::name_to_string(layer)).into_bytes()
-            }
-        };
+        let contents = label_file_contents(&new_label);
 
         let mut labels = self.labels.write().await;
         if let Some(retrieved_label) = labels.get(&label.name) {
@@ -454,6 +601,27 @@ impl LabelStore for CachedDirectoryLabelStore {
             Ok(false)
         }
     }
+
+    async fn import_label(&self, label: &Label) -> io::Result<()> {
+        let mut labels = self.labels.write().await;
+        let mut p = self.path.clone();
+        p.push(format!("{}.label", label.name));
+        let mut options = fs::OpenOptions::new();
+        options.create(true);
+        options.write(true);
+        options.truncate(true);
+        let mut file = options.open(p).await?;
+        file.write_all(&label_file_contents(label)).await?;
+        file.flush().await?;
+        file.sync_data().await?;
+
+        labels.insert(label.name.clone(), label.clone());
+        Ok(())
+    }
+
+    async fn watch_label(&self, name: &str) -> io::Result<Option<BoxStream<'static, Label>>> {
+        Ok(watch_label_file(&self.path, name))
+    }
 }
 
 #[cfg(test)]
@@ -32,6 +32,40 @@ impl<F: 'static + FileLoad + FileStore> IdMapFiles<F> {
     }
 }
 
+fn dictionary_maps_size(maps: &DictionaryMaps) -> usize {
+    maps.blocks_map.len() + maps.offsets_map.len()
+}
+
+fn typed_dictionary_maps_size(maps: &TypedDictionaryMaps) -> usize {
+    maps.types_present_map.len()
+        + maps.type_offsets_map.len()
+        + maps.blocks_map.len()
+        + maps.offsets_map.len()
+}
+
+fn bitindex_maps_size(maps: &BitIndexMaps) -> usize {
+    maps.bits_map.len() + maps.blocks_map.len() + maps.sblocks_map.len()
+}
+
+fn adjacency_list_maps_size(maps: &AdjacencyListMaps) -> usize {
+    bitindex_maps_size(&maps.bitindex_maps) + maps.nums_map.len()
+}
+
+impl IdMapMaps {
+    /// The total amount of bytes in these maps.
+    pub fn byte_size(&self) -> usize {
+        self.node_value_idmap_maps
+            .as_ref()
+            .map(bitindex_maps_size)
+            .unwrap_or(0)
+            + self
+                .predicate_idmap_maps
+                .as_ref()
+                .map(bitindex_maps_size)
+                .unwrap_or(0)
+    }
+}
+
 /// The files required for storing a layer
 #[derive(Clone)]
 pub enum LayerFiles<F: 'static + FileLoad + FileStore + Clone> {
@@ -93,6 +127,10 @@ pub struct BaseLayerFiles<F: 'static + FileLoad + FileStore> {
     pub o_ps_adjacency_list_files: AdjacencyListFiles<F>,
 
     pub predicate_wavelet_tree_files: BitIndexFiles<F>,
+
+    pub quads_file: F,
+
+    pub text_index_file: F,
 }
 
 #[derive(Clone)]
@@ -112,6 +150,28 @@ pub struct BaseLayerMaps {
     pub o_ps_adjacency_list_maps: AdjacencyListMaps,
 
     pub predicate_wavelet_tree_maps: BitIndexMaps,
+
+    pub quads_map: Option<Bytes>,
+
+    pub text_index_map: Option<Bytes>,
+}
+
+impl BaseLayerMaps {
+    /// The total amount of bytes in these maps.
+    pub fn byte_size(&self) -> usize {
+        dictionary_maps_size(&self.node_dictionary_maps)
+            + dictionary_maps_size(&self.predicate_dictionary_maps)
+            + typed_dictionary_maps_size(&self.value_dictionary_maps)
+            + self.id_map_maps.byte_size()
+            + self.subjects_map.as_ref().map(|m| m.len()).unwrap_or(0)
+            + self.objects_map.as_ref().map(|m| m.len()).unwrap_or(0)
+            + adjacency_list_maps_size(&self.s_p_adjacency_list_maps)
+            + adjacency_list_maps_size(&self.sp_o_adjacency_list_maps)
+            + adjacency_list_maps_size(&self.o_ps_adjacency_list_maps)
+            + bitindex_maps_size(&self.predicate_wavelet_tree_maps)
+            + self.quads_map.as_ref().map(|m| m.len()).unwrap_or(0)
+            + self.text_index_map.as_ref().map(|m| m.len()).unwrap_or(0)
+    }
 }
 
 impl<F: FileLoad + FileStore> BaseLayerFiles<F> {
@@ -131,6 +191,9 @@ impl<F: FileLoad + FileStore> BaseLayerFiles<F> {
 
         let predicate_wavelet_tree_maps = self.predicate_wavelet_tree_files.map_all().await?;
 
+        let quads_map = self.quads_file.map_if_exists().await?;
+        let text_index_map = self.text_index_file.map_if_exists().await?;
+
         Ok(BaseLayerMaps {
             node_dictionary_maps,
             predicate_dictionary_maps,
@@ -146,6 +209,10 @@ impl<F: FileLoad + FileStore> BaseLayerFiles<F> {
             o_ps_adjacency_list_maps,
 
             predicate_wavelet_tree_maps,
+
+            quads_map,
+
+            text_index_map,
         })
     }
 }
@@ -172,6 +239,11 @@ pub struct ChildLayerFiles<F: 'static + FileLoad + FileStore + Clone + Send + Sy
 
     pub pos_predicate_wavelet_tree_files: BitIndexFiles<F>,
     pub neg_predicate_wavelet_tree_files: BitIndexFiles<F>,
+
+    pub pos_quads_file: F,
+    pub neg_quads_file: F,
+
+    pub text_index_file: F,
 }
 
 #[derive(Clone)]
@@ -196,6 +268,36 @@ pub struct ChildLayerMaps {
 
     pub pos_predicate_wavelet_tree_maps: BitIndexMaps,
     pub neg_predicate_wavelet_tree_maps: BitIndexMaps,
+
+    pub pos_quads_map: Option<Bytes>,
+    pub neg_quads_map: Option<Bytes>,
+
+    pub text_index_map: Option<Bytes>,
+}
+
+impl ChildLayerMaps {
+    /// The total amount of bytes in these maps.
+    pub fn byte_size(&self) -> usize {
+        dictionary_maps_size(&self.node_dictionary_maps)
+            + dictionary_maps_size(&self.predicate_dictionary_maps)
+            + typed_dictionary_maps_size(&self.value_dictionary_maps)
+            + self.id_map_maps.byte_size()
+            + self.pos_subjects_map.len()
+            + self.pos_objects_map.len()
+            + self.neg_subjects_map.len()
+            + self.neg_objects_map.len()
+            + adjacency_list_maps_size(&self.pos_s_p_adjacency_list_maps)
+            + adjacency_list_maps_size(&self.pos_sp_o_adjacency_list_maps)
+            + adjacency_list_maps_size(&self.pos_o_ps_adjacency_list_maps)
+            + adjacency_list_maps_size(&self.neg_s_p_adjacency_list_maps)
+            + adjacency_list_maps_size(&self.neg_sp_o_adjacency_list_maps)
+            + adjacency_list_maps_size(&self.neg_o_ps_adjacency_list_maps)
+            + bitindex_maps_size(&self.pos_predicate_wavelet_tree_maps)
+            + bitindex_maps_size(&self.neg_predicate_wavelet_tree_maps)
+            + self.pos_quads_map.as_ref().map(|m| m.len()).unwrap_or(0)
+            + self.neg_quads_map.as_ref().map(|m| m.len()).unwrap_or(0)
+            + self.text_index_map.as_ref().map(|m| m.len()).unwrap_or(0)
+    }
 }
 
 impl<F: FileLoad + FileStore + Clone> ChildLayerFiles<F> {
@@ -224,6 +326,10 @@ impl<F: FileLoad + FileStore + Clone> ChildLayerFiles<F> {
         let neg_predicate_wavelet_tree_maps =
             self.neg_predicate_wavelet_tree_files.map_all().await?;
 
+        let pos_quads_map = self.pos_quads_file.map_if_exists().await?;
+        let neg_quads_map = self.neg_quads_file.map_if_exists().await?;
+        let text_index_map = self.text_index_file.map_if_exists().await?;
+
         Ok(ChildLayerMaps {
             node_dictionary_maps,
             predicate_dictionary_maps,
@@ -245,6 +351,11 @@ impl<F: FileLoad + FileStore + Clone> ChildLayerFiles<F> {
 
             pos_predicate_wavelet_tree_maps,
             neg_predicate_wavelet_tree_maps,
+
+            pos_quads_map,
+            neg_quads_map,
+
+            text_index_map,
         })
     }
 }
@@ -0,0 +1,109 @@
+//! Metadata about the layers in a layer store.
+//!
+//! A `LayerInfo` bundles everything that is cheap to know about a
+//! layer without loading it: its place in a layer stack, its rollup
+//! and how much space it takes up. It is retrieved through
+//! `LayerStore::layer_info` for a single layer, or
+//! `LayerStore::layers_info` for all layers in a store at once.
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use super::consts::LayerFileEnum;
+
+/// Whether a layer is a base layer or a child layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerKind {
+    Base,
+    Child,
+}
+
+/// Metadata about a single layer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerInfo {
+    /// The name of the layer.
+    pub name: [u32; 5],
+    /// Whether this is a base or a child layer.
+    pub kind: LayerKind,
+    /// The parent of this layer, if it is a child layer.
+    pub parent: Option<[u32; 5]>,
+    /// The rollup layer that is loaded in place of this layer, if any.
+    pub rollup: Option<[u32; 5]>,
+    /// The number of ancestors of this layer, which is 0 for a base layer.
+    pub depth: usize,
+    /// The size of this layer in storage, in bytes.
+    pub size: u64,
+    /// The size of every section present in this layer, in bytes.
+    pub sections: Vec<(LayerFileEnum, usize)>,
+    /// When this layer was written, if the store keeps track of that.
+    pub created: Option<SystemTime>,
+}
+
+impl LayerInfo {
+    /// The size of the given section, or None if it is not present in this layer.
+    pub fn section_size(&self, section: LayerFileEnum) -> Option<usize> {
+        self.sections
+            .iter()
+            .find(|(s, _)| *s == section)
+            .map(|(_, size)| *size)
+    }
+}
+
+/// Calculate the depth of every layer, given the parent of every layer.
+///
+/// A parent that is not in the map itself is counted as a base layer.
+pub(crate) fn layer_depths(
+    parents: &HashMap<[u32; 5], Option<[u32; 5]>>,
+) -> HashMap<[u32; 5], usize> {
+    let mut depths: HashMap<[u32; 5], usize> = HashMap::with_capacity(parents.len());
+    for name in parents.keys() {
+        // walk down until we find a layer whose depth we already know
+        let mut unknown = Vec::new();
+        let mut current = Some(*name);
+        let mut depth = loop {
+            match current {
+                None => break 0,
+                Some(c) => {
+                    if let Some(depth) = depths.get(&c) {
+                        break *depth + 1;
+                    }
+                    match parents.get(&c) {
+                        Some(parent) => {
+                            unknown.push(c);
+                            current = *parent;
+                        }
+                        None => break 1,
+                    }
+                }
+            }
+        };
+        for layer in unknown.into_iter().rev() {
+            depths.insert(layer, depth);
+            depth += 1;
+        }
+    }
+
+    depths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depths_of_branching_stacks() {
+        let parents = HashMap::from([
+            ([1, 0, 0, 0, 0], None),
+            ([2, 0, 0, 0, 0], Some([1, 0, 0, 0, 0])),
+            ([3, 0, 0, 0, 0], Some([2, 0, 0, 0, 0])),
+            ([4, 0, 0, 0, 0], Some([2, 0, 0, 0, 0])),
+            ([5, 0, 0, 0, 0], Some([9, 0, 0, 0, 0])),
+        ]);
+        let depths = layer_depths(&parents);
+
+        assert_eq!(0, depths[&[1, 0, 0, 0, 0]]);
+        assert_eq!(1, depths[&[2, 0, 0, 0, 0]]);
+        assert_eq!(2, depths[&[3, 0, 0, 0, 0]]);
+        assert_eq!(2, depths[&[4, 0, 0, 0, 0]]);
+        assert_eq!(1, depths[&[5, 0, 0, 0, 0]]);
+    }
+}
@@ -1,6 +1,7 @@
 use std::io;
 
 use async_trait::async_trait;
+use futures::stream::BoxStream;
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Label {
@@ -34,6 +35,15 @@ impl Label {
     }
 }
 
+/// A change of a label, as reported by `NamedGraph::watch`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LabelChange {
+    /// The label after the change.
+    pub label: Label,
+    /// The layer the label pointed at before the change.
+    pub previous_layer: Option<[u32; 5]>,
+}
+
 #[async_trait]
 pub trait LabelStore: Send + Sync {
     async fn labels(&self) -> io::Result<Vec<Label>>;
@@ -45,6 +55,20 @@ pub trait LabelStore: Send + Sync {
         layer: Option<[u32; 5]>,
     ) -> io::Result<Option<Label>>;
     async fn delete_label(&self, name: &str) -> io::Result<bool>;
+    /// Write the given label as is, creating it if it does not exist yet.
+    ///
+    /// Unlike `set_label_option`, this neither checks nor bumps the
+    /// version, so labels can be copied over from another store.
+    async fn import_label(&self, label: &Label) -> io::Result<()>;
+
+    /// Watch the given label, yielding it every time it may have changed.
+    ///
+    /// The stream ends when the label is deleted. Stores that can't
+    /// watch their labels return None, in which case callers have to
+    /// poll instead.
+    async fn watch_label(&self, _name: &str) -> io::Result<Option<BoxStream<'static, Label>>> {
+        Ok(None)
+    }
 
     async fn set_label(&self, label: &Label, layer: [u32; 5]) -> io::Result<Option<Label>> {
         self.set_label_option(label, Some(layer)).await
@@ -1,19 +1,32 @@
 use super::cache::*;
-use super::consts::FILENAMES;
+use super::consts::{LayerFileEnum, FILENAMES, FILENAME_ENUM_MAP};
+use super::copy::LayerCopy;
 use super::delta::*;
 use super::file::*;
+use super::info::*;
+use super::metrics::*;
 use super::pack::Packable;
-use crate::layer::base_merge::merge_base_layers;
+use super::progress::*;
+use crate::layer::base_bulk::{
+    bulk_load_base_layer_quads_with_progress, bulk_load_base_layer_with_progress,
+    BULK_LOAD_RUN_SIZE,
+};
+use crate::layer::base_merge::merge_base_layers_with_progress;
 use crate::layer::builder::DictionarySetFileBuilder;
+use crate::layer::squash_external::{
+    squash_external_with_progress, squash_upto_external_with_progress, SQUASH_RUN_SIZE,
+};
 use crate::layer::BaseLayerFileBuilder;
 use crate::layer::ChildLayerFileBuilderPhase2;
 use crate::layer::TripleChange;
 use crate::layer::{
-    layer_triple_exists, BaseLayer, ChildLayer, IdMap, IdTriple, InternalLayer,
-    InternalLayerTripleObjectIterator, InternalLayerTriplePredicateIterator,
-    InternalLayerTripleSubjectIterator, InternalTripleStackIterator, LayerBuilder,
-    OptInternalLayerTriplePredicateIterator, OptInternalLayerTripleSubjectIterator, RollupLayer,
-    SimpleLayerBuilder,
+    layer_triple_count_o, layer_triple_count_p, layer_triple_count_s, layer_triple_count_sp,
+    layer_triple_exists, write_graph_index, write_text_index, BaseLayer, ChildLayer, GraphIndex,
+    IdMap, IdQuadTriple, IdTriple, InternalLayer, InternalLayerTripleObjectIterator,
+    InternalLayerTriplePredicateIterator, InternalLayerTripleSubjectIterator,
+    InternalTripleStackIterator, LayerBuilder, OptInternalLayerTriplePredicateIterator,
+    OptInternalLayerTripleSubjectIterator, QuadChanges, RollupLayer, SimpleLayerBuilder,
+    TripleCursor, TripleCursorIterator, TriplePattern, ValueQuadTriple, ValueTriple,
 };
 use crate::Layer;
 use tdb_succinct::bitarray::bitarray_len_from_file;
@@ -24,14 +37,17 @@ use tdb_succinct::TypedDict;
 use tdb_succinct::{util, AdjacencyList, BitIndex, LogArray, MonotonicLogArray, WaveletTree};
 
 use bitvec::prelude::*;
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::io;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 use async_trait::async_trait;
+use futures::stream::BoxStream;
 
 macro_rules! walk_backwards_from_disk {
     ($store:ident, $name:ident, $current:ident, $body:block) => {
@@ -72,6 +88,14 @@ macro_rules! walk_backwards_from_disk_upto {
 #[async_trait]
 pub trait LayerStore: 'static + Packable + Send + Sync {
     async fn layers(&self) -> io::Result<Vec<[u32; 5]>>;
+    /// Return metadata about the given layer, or None if it does not exist.
+    async fn layer_info(&self, name: [u32; 5]) -> io::Result<Option<LayerInfo>>;
+    /// Return metadata about every layer in this store, ordered by name.
+    async fn layers_info(&self) -> io::Result<Vec<LayerInfo>>;
+    /// Read all files of the given layer into memory, or None if it does not exist.
+    async fn read_layer_copy(&self, name: [u32; 5]) -> io::Result<Option<LayerCopy>>;
+    /// Write a layer read from another store into this one, keeping its name and parent.
+    async fn write_layer_copy(&self, copy: &LayerCopy) -> io::Result<()>;
     async fn get_layer_with_cache(
         &self,
         name: [u32; 5],
@@ -145,6 +169,13 @@ pub trait LayerStore: 'static + Packable + Send + Sync {
     }
     async fn register_rollup(&self, layer: [u32; 5], rollup: [u32; 5]) -> io::Result<()>;
 
+    /// Returns the name of the rollup layer registered for the given layer, if any.
+    ///
+    /// Layer stores that can't look up rollups report none.
+    async fn get_layer_rollup(&self, _name: [u32; 5]) -> io::Result<Option<[u32; 5]>> {
+        Ok(None)
+    }
+
     /// Create a new rollup layer which rolls up all triples in the given layer, as well as all its ancestors.
     ///
     /// It is a good idea to keep layer stacks small, meaning, to only
@@ -218,11 +249,176 @@ pub trait LayerStore: 'static + Packable + Send + Sync {
             .await
     }
 
+    /// Like `perform_rollup_upto_with_cache`, reporting to and cancellable through `progress`.
+    async fn perform_rollup_upto_with_progress(
+        &self,
+        layer: Arc<InternalLayer>,
+        upto: [u32; 5],
+        cache: Arc<dyn LayerCache>,
+        progress: &Progress,
+    ) -> io::Result<[u32; 5]> {
+        progress.check()?;
+        let rollup = self
+            .perform_rollup_upto_with_cache(layer, upto, cache)
+            .await?;
+        progress.done();
+
+        Ok(rollup)
+    }
+
+    /// Like `rollup_upto`, reporting to and cancellable through `progress`.
+    ///
+    /// The rollup is only registered once it has been fully
+    /// written. If the operation is cancelled, the partially written
+    /// rollup layer is discarded.
+    async fn rollup_upto_with_progress(
+        &self,
+        layer: Arc<InternalLayer>,
+        upto: [u32; 5],
+        progress: &Progress,
+    ) -> io::Result<[u32; 5]> {
+        let name = layer.name();
+        let rollup = self
+            .perform_rollup_upto_with_progress(layer, upto, NOCACHE.clone(), progress)
+            .await?;
+        self.register_rollup(name, rollup).await?;
+
+        Ok(rollup)
+    }
+
     async fn squash(&self, layer: Arc<InternalLayer>) -> io::Result<[u32; 5]>;
     async fn squash_upto(&self, layer: Arc<InternalLayer>, upto: [u32; 5]) -> io::Result<[u32; 5]>;
 
+    /// Like `squash_upto`, reporting to and cancellable through `progress`.
+    async fn squash_upto_with_progress(
+        &self,
+        layer: Arc<InternalLayer>,
+        upto: [u32; 5],
+        progress: &Progress,
+    ) -> io::Result<[u32; 5]> {
+        progress.check()?;
+        let squashed = self.squash_upto(layer, upto).await?;
+        progress.done();
+
+        Ok(squashed)
+    }
+
+    /// Like `squash`, keeping memory use bounded by writing intermediate results to `temp_dir`.
+    async fn squash_external(
+        &self,
+        layer: Arc<InternalLayer>,
+        temp_dir: &Path,
+    ) -> io::Result<[u32; 5]>;
+
+    /// Like `squash_external`, reporting to and cancellable through `progress`.
+    async fn squash_external_with_progress(
+        &self,
+        layer: Arc<InternalLayer>,
+        temp_dir: &Path,
+        progress: &Progress,
+    ) -> io::Result<[u32; 5]> {
+        progress.check()?;
+        let squashed = self.squash_external(layer, temp_dir).await?;
+        progress.done();
+
+        Ok(squashed)
+    }
+
+    /// Like `squash_upto`, keeping memory use bounded by writing intermediate results to `temp_dir`.
+    async fn squash_upto_external(
+        &self,
+        layer: Arc<InternalLayer>,
+        upto: [u32; 5],
+        temp_dir: &Path,
+    ) -> io::Result<[u32; 5]>;
+
+    /// Like `squash_upto_external`, reporting to and cancellable through `progress`.
+    async fn squash_upto_external_with_progress(
+        &self,
+        layer: Arc<InternalLayer>,
+        upto: [u32; 5],
+        temp_dir: &Path,
+        progress: &Progress,
+    ) -> io::Result<[u32; 5]> {
+        progress.check()?;
+        let squashed = self.squash_upto_external(layer, upto, temp_dir).await?;
+        progress.done();
+
+        Ok(squashed)
+    }
+
     async fn merge_base_layer(&self, layers: &[[u32; 5]], temp_dir: &Path) -> io::Result<[u32; 5]>;
 
+    /// Like `merge_base_layer`, reporting to and cancellable through `progress`.
+    async fn merge_base_layer_with_progress(
+        &self,
+        layers: &[[u32; 5]],
+        temp_dir: &Path,
+        progress: &Progress,
+    ) -> io::Result<[u32; 5]> {
+        progress.check()?;
+        let merged = self.merge_base_layer(layers, temp_dir).await?;
+        progress.done();
+
+        Ok(merged)
+    }
+
+    /// Create a base layer out of a stream of triples that may not fit in memory.
+    ///
+    /// Sorted runs of triples are spilled to `temp_dir` and merged
+    /// into the new layer.
+    async fn bulk_load_base_layer(
+        &self,
+        triples: BoxStream<'_, ValueTriple>,
+        temp_dir: &Path,
+    ) -> io::Result<[u32; 5]>;
+
+    /// Like `bulk_load_base_layer`, reporting to and cancellable through `progress`.
+    async fn bulk_load_base_layer_with_progress(
+        &self,
+        triples: BoxStream<'_, ValueTriple>,
+        temp_dir: &Path,
+        progress: &Progress,
+    ) -> io::Result<[u32; 5]> {
+        progress.check()?;
+        let loaded = self.bulk_load_base_layer(triples, temp_dir).await?;
+        progress.done();
+
+        Ok(loaded)
+    }
+
+    /// Create a base layer out of a stream of quads that may not fit in memory.
+    ///
+    /// Like `bulk_load_base_layer`, but also puts every triple in the
+    /// graph of its quad. Layer stores that don't support graphs
+    /// return an error.
+    async fn bulk_load_base_layer_quads(
+        &self,
+        quads: BoxStream<'_, ValueQuadTriple>,
+        temp_dir: &Path,
+    ) -> io::Result<[u32; 5]> {
+        self.bulk_load_base_layer_quads_with_progress(quads, temp_dir, &Progress::default())
+            .await
+    }
+
+    /// Like `bulk_load_base_layer_quads`, reporting to and cancellable through `progress`.
+    async fn bulk_load_base_layer_quads_with_progress(
+        &self,
+        _quads: BoxStream<'_, ValueQuadTriple>,
+        _temp_dir: &Path,
+        _progress: &Progress,
+    ) -> io::Result<[u32; 5]> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this layer store does not support bulk loading quads",
+        ))
+    }
+
+    /// Throw away a layer that was created but never finalized, for example because its commit failed.
+    async fn discard_layer(&self, _name: [u32; 5]) -> io::Result<()> {
+        Ok(())
+    }
+
     async fn layer_is_ancestor_of(
         &self,
         descendant: [u32; 5],
@@ -305,10 +501,38 @@ pub trait LayerStore: 'static + Packable + Send + Sync {
         predicate: u64,
     ) -> io::Result<Box<dyn Iterator<Item = IdTriple> + Send>>;
 
+    /// Iterator over the additions of the given layer matching the cursor's pattern that come after its last triple.
+    async fn triple_additions_from_cursor(
+        &self,
+        layer: [u32; 5],
+        cursor: &TripleCursor,
+    ) -> io::Result<TripleCursorIterator>;
+
+    /// Iterator over the removals of the given layer matching the cursor's pattern that come after its last triple.
+    async fn triple_removals_from_cursor(
+        &self,
+        layer: [u32; 5],
+        cursor: &TripleCursor,
+    ) -> io::Result<TripleCursorIterator>;
+
     async fn triple_layer_addition_count(&self, layer: [u32; 5]) -> io::Result<usize>;
 
     async fn triple_layer_removal_count(&self, layer: [u32; 5]) -> io::Result<usize>;
 
+    /// Returns the amount of additions in the given layer that match the pattern.
+    async fn triple_layer_addition_count_matching(
+        &self,
+        layer: [u32; 5],
+        pattern: TriplePattern,
+    ) -> io::Result<usize>;
+
+    /// Returns the amount of removals in the given layer that match the pattern.
+    async fn triple_layer_removal_count_matching(
+        &self,
+        layer: [u32; 5],
+        pattern: TriplePattern,
+    ) -> io::Result<usize>;
+
     async fn retrieve_layer_stack_names(&self, name: [u32; 5]) -> io::Result<Vec<[u32; 5]>>;
 
     async fn retrieve_layer_stack_names_upto(
@@ -350,6 +574,37 @@ pub trait LayerStore: 'static + Packable + Send + Sync {
             positives, negatives,
         ))
     }
+
+    /// Returns the quads that were added and removed in the given layer itself.
+    ///
+    /// Layer stores that don't support graphs report no changes.
+    async fn layer_quad_changes(&self, _layer: [u32; 5]) -> io::Result<QuadChanges> {
+        Ok(QuadChanges::new())
+    }
+
+    /// Returns the quad changes of the given layer and its ancestors, up to but not including `upto`.
+    async fn layer_quad_changes_upto(
+        &self,
+        name: [u32; 5],
+        upto: [u32; 5],
+    ) -> io::Result<QuadChanges> {
+        let mut layer_changes = Vec::new();
+        walk_backwards_from_disk_upto!(self, name, upto, current, {
+            layer_changes.push(self.layer_quad_changes(current).await?);
+        });
+
+        let mut changes = QuadChanges::new();
+        for layer_change in layer_changes.into_iter().rev() {
+            changes.apply(layer_change);
+        }
+
+        Ok(changes)
+    }
+
+    /// Return a snapshot of the caching and loading metrics of this store.
+    async fn metrics(&self) -> StoreMetrics {
+        StoreMetrics::default()
+    }
 }
 
 #[async_trait]
@@ -370,6 +625,48 @@ pub trait PersistentLayerStore: 'static + Send + Sync + Clone {
         Ok(())
     }
 
+    /// Remove a directory that was created but never finalized.
+    async fn discard_directory(&self, _directory: [u32; 5]) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Return a snapshot of the counters of the byte cache underneath this store, if there is one.
+    async fn archive_cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
+
+    /// Return the size of every section present in the given layer.
+    ///
+    /// The rollup of a layer is not a section of the layer itself, and
+    /// is therefore not included.
+    async fn layer_sections(&self, name: [u32; 5]) -> io::Result<Vec<(LayerFileEnum, usize)>> {
+        let mut result = Vec::new();
+        for (file, section) in FILENAME_ENUM_MAP.iter() {
+            if *section != LayerFileEnum::Rollup && self.file_exists(name, file).await? {
+                let size = self.get_file(name, file).await?.size().await?;
+                result.push((*section, size));
+            }
+        }
+        result.sort();
+
+        Ok(result)
+    }
+
+    /// Return the size of the given layer in storage, in bytes.
+    async fn layer_size(&self, name: [u32; 5]) -> io::Result<u64> {
+        Ok(self
+            .layer_sections(name)
+            .await?
+            .into_iter()
+            .map(|(_, size)| size as u64)
+            .sum())
+    }
+
+    /// Return when the given layer was written, if this store keeps track of that.
+    async fn layer_created(&self, _name: [u32; 5]) -> io::Result<Option<SystemTime>> {
+        Ok(None)
+    }
+
     async fn layer_has_rollup(&self, name: [u32; 5]) -> io::Result<bool> {
         self.file_exists(name, FILENAMES.rollup).await
     }
@@ -427,6 +724,8 @@ pub trait PersistentLayerStore: 'static + Send + Sync + Clone {
             FILENAMES.base_predicate_wavelet_tree_bits,
             FILENAMES.base_predicate_wavelet_tree_bit_index_blocks,
             FILENAMES.base_predicate_wavelet_tree_bit_index_sblocks,
+            FILENAMES.base_quads,
+            FILENAMES.text_index,
         ];
 
         let mut files = Vec::with_capacity(filenames.len());
@@ -496,6 +795,8 @@ pub trait PersistentLayerStore: 'static + Send + Sync + Clone {
                 blocks_file: files[29].clone(),
                 sblocks_file: files[30].clone(),
             },
+            quads_file: files[31].clone(),
+            text_index_file: files[32].clone(),
         })
     }
 
@@ -549,6 +850,9 @@ pub trait PersistentLayerStore: 'static + Send + Sync + Clone {
             FILENAMES.neg_predicate_wavelet_tree_bits,
             FILENAMES.neg_predicate_wavelet_tree_bit_index_blocks,
             FILENAMES.neg_predicate_wavelet_tree_bit_index_sblocks,
+            FILENAMES.pos_quads,
+            FILENAMES.neg_quads,
+            FILENAMES.text_index,
         ];
 
         let mut files = Vec::with_capacity(filenames.len());
@@ -648,6 +952,9 @@ pub trait PersistentLayerStore: 'static + Send + Sync + Clone {
                 blocks_file: files[46].clone(),
                 sblocks_file: files[47].clone(),
             },
+            pos_quads_file: files[48].clone(),
+            neg_quads_file: files[49].clone(),
+            text_index_file: files[50].clone(),
         })
     }
 
@@ -1384,6 +1691,16 @@ pub trait PersistentLayerStore: 'static + Send + Sync + Clone {
     }
 }
 
+fn trace_loaded_layer(layer: &InternalLayer) {
+    tracing::debug!(
+        layer = %name_to_string(layer.name()),
+        rollup = layer.is_rollup(),
+        additions = layer.internal_triple_layer_addition_count(),
+        removals = layer.internal_triple_layer_removal_count(),
+        "loaded layer"
+    );
+}
+
 pub fn name_to_string(name: [u32; 5]) -> String {
     format!(
         "{:08x}{:08x}{:08x}{:08x}{:08x}",
@@ -1422,6 +1739,34 @@ pub fn bytes_to_name(bytes: &[u8]) -> Result<[u32; 5], std::io::Error> {
     }
 }
 
+async fn collect_layer_info<T: PersistentLayerStore>(
+    store: &T,
+    name: [u32; 5],
+    parent: Option<[u32; 5]>,
+    depth: usize,
+) -> io::Result<LayerInfo> {
+    let rollup = if store.layer_has_rollup(name).await? {
+        Some(store.read_rollup_file(name).await?)
+    } else {
+        None
+    };
+
+    Ok(LayerInfo {
+        name,
+        kind: if parent.is_some() {
+            LayerKind::Child
+        } else {
+            LayerKind::Base
+        },
+        parent,
+        rollup,
+        depth,
+        size: store.layer_size(name).await?,
+        sections: store.layer_sections(name).await?,
+        created: store.layer_created(name).await?,
+    })
+}
+
 #[async_trait]
 impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStore<File = F>>
     LayerStore for T
@@ -1430,6 +1775,60 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
         self.directories().await
     }
 
+    async fn layer_info(&self, name: [u32; 5]) -> io::Result<Option<LayerInfo>> {
+        if !self.directory_exists(name).await? {
+            return Ok(None);
+        }
+
+        let parent = self.layer_parent(name).await?;
+        let mut depth = 0;
+        let mut ancestor = parent;
+        while let Some(a) = ancestor {
+            depth += 1;
+            ancestor = self.layer_parent(a).await?;
+        }
+
+        Ok(Some(collect_layer_info(self, name, parent, depth).await?))
+    }
+
+    async fn layers_info(&self) -> io::Result<Vec<LayerInfo>> {
+        // look up every parent just once, so depths can be calculated without walking each stack
+        let names = self.directories().await?;
+        let mut parents = HashMap::with_capacity(names.len());
+        for name in names {
+            parents.insert(name, self.layer_parent(name).await?);
+        }
+        let depths = layer_depths(&parents);
+
+        let mut result = Vec::with_capacity(parents.len());
+        for (name, parent) in parents {
+            result.push(collect_layer_info(self, name, parent, depths[&name]).await?);
+        }
+        result.sort_by_key(|info| info.name);
+
+        Ok(result)
+    }
+
+    async fn read_layer_copy(&self, name: [u32; 5]) -> io::Result<Option<LayerCopy>> {
+        if !self.directory_exists(name).await? {
+            return Ok(None);
+        }
+
+        Ok(Some(LayerCopy::read_from(self, name).await?))
+    }
+
+    async fn write_layer_copy(&self, copy: &LayerCopy) -> io::Result<()> {
+        if self.directory_exists(copy.name).await? {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("layer {} already exists", name_to_string(copy.name)),
+            ));
+        }
+
+        copy.write_into(self).await
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(layer = %name_to_string(name)))]
     async fn get_layer_with_cache(
         &self,
         name: [u32; 5],
@@ -1542,6 +1941,7 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
                 }
             }
 
+            trace_loaded_layer(&layer);
             cache.cache_layer(layer.clone());
             ancestor = Some(layer);
         }
@@ -1577,6 +1977,7 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
                 }
             }
 
+            trace_loaded_layer(&layer);
             cache.cache_layer(layer.clone());
             ancestor = layer;
         }
@@ -1724,6 +2125,11 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
         )) as Box<dyn LayerBuilder>)
     }
 
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(layer = %name_to_string(layer.name()), triples = layer.triple_count())
+    )]
     async fn perform_rollup(&self, layer: Arc<InternalLayer>) -> io::Result<[u32; 5]> {
         if layer.parent_name().is_none() {
             // we're already a base layer. there's nothing that can be rolled up.
@@ -1754,6 +2160,22 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
         layer: Arc<InternalLayer>,
         upto: [u32; 5],
         cache: Arc<dyn LayerCache>,
+    ) -> io::Result<[u32; 5]> {
+        self.perform_rollup_upto_with_progress(layer, upto, cache, &Progress::default())
+            .await
+    }
+
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(layer = %name_to_string(layer.name()), upto = %name_to_string(upto))
+    )]
+    async fn perform_rollup_upto_with_progress(
+        &self,
+        layer: Arc<InternalLayer>,
+        upto: [u32; 5],
+        cache: Arc<dyn LayerCache>,
+        progress: &Progress,
     ) -> io::Result<[u32; 5]> {
         if layer.name() == upto {
             // rolling up upto ourselves is pretty pointless. Let's not do that.
@@ -1779,11 +2201,23 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
         let (layer_dir, _parent_layer, child_layer_files) = self
             .create_child_layer_files_with_cache(upto, cache)
             .await?;
-        delta_rollup_upto(self, &layer, upto, child_layer_files).await?;
+        if let Err(e) =
+            delta_rollup_upto_with_progress(self, &layer, upto, child_layer_files, progress).await
+        {
+            self.discard_directory(layer_dir).await?;
+            return Err(e);
+        }
         self.finalize(layer_dir).await?;
+        progress.done();
+
         Ok(layer_dir)
     }
 
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(layer = %name_to_string(layer.name()), upto = %name_to_string(upto))
+    )]
     async fn perform_imprecise_rollup_upto_with_cache(
         &self,
         layer: Arc<InternalLayer>,
@@ -1828,6 +2262,23 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
         }
     }
 
+    async fn get_layer_rollup(&self, name: [u32; 5]) -> io::Result<Option<[u32; 5]>> {
+        if self.layer_has_rollup(name).await? {
+            Ok(Some(self.read_rollup_file(name).await?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(
+            layer = %name_to_string(layer.name()),
+            output = tracing::field::Empty,
+            triples = tracing::field::Empty
+        )
+    )]
     async fn squash(&self, layer: Arc<InternalLayer>) -> io::Result<[u32; 5]> {
         // we create a new base layer
         // we then build a new set of dictionaries by sorting what we got in all layers
@@ -1853,6 +2304,15 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
         // figure out what keys are actually used through a prepass over all triples
         let mut node_value_existences = bitvec![0;node_count+value_count+1];
         let mut predicate_existences = bitvec![0;predicate_count+1];
+        // graphs only need to be kept for quads whose triple still exists
+        let quads: Vec<IdQuadTriple> = layer
+            .quad_changes_upto(None)
+            .additions()
+            .filter(|quad| layer.id_triple_exists(quad.triple))
+            .collect();
+        for quad in quads.iter() {
+            node_value_existences.set(quad.graph as usize, true);
+        }
         let mut num_triples = 0;
         for triple in layer.triples() {
             num_triples += 1;
@@ -1942,6 +2402,19 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
         builder.add_predicates_bytes(predicates.into_iter().map(|(x, _)| x.to_bytes()));
         builder.add_values(values.into_iter().map(|(x, _)| x));
         let mut builder = builder.into_phase2().await?;
+        let quads: Vec<IdQuadTriple> = quads
+            .into_iter()
+            .map(|quad| {
+                IdQuadTriple::new(
+                    node_value_map[quad.graph as usize],
+                    IdTriple::new(
+                        node_value_map[quad.triple.subject as usize],
+                        pred_map[quad.triple.predicate as usize],
+                        node_value_map[quad.triple.object as usize],
+                    ),
+                )
+            })
+            .collect();
         let mut triples = Vec::with_capacity(num_triples);
         triples.extend(layer.triples().map(move |t| {
             IdTriple::new(
@@ -1954,13 +2427,44 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
 
         builder.add_id_triples(triples.into_iter()).await?;
         builder.finalize().await?;
+        if !quads.is_empty() {
+            write_graph_index(&base_layer_files.quads_file, quads).await?;
+        }
+        if layer.text_index().is_some() {
+            write_text_index(
+                &base_layer_files.value_dictionary_files,
+                &base_layer_files.text_index_file,
+            )
+            .await?;
+        }
 
         self.finalize_layer(layer_name).await?;
+        tracing::Span::current()
+            .record(
+                "output",
+                tracing::field::display(name_to_string(layer_name)),
+            )
+            .record("triples", num_triples);
 
         Ok(layer_name)
     }
 
-    async fn squash_upto(&self, layer: Arc<InternalLayer>, upto: [u32; 5]) -> io::Result<[u32; 5]> {
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(
+            layer = %name_to_string(layer.name()),
+            upto = %name_to_string(upto),
+            output = tracing::field::Empty,
+            changes = tracing::field::Empty
+        )
+    )]
+    async fn squash_upto_with_progress(
+        &self,
+        layer: Arc<InternalLayer>,
+        upto: [u32; 5],
+        progress: &Progress,
+    ) -> io::Result<[u32; 5]> {
         let mut base_node_count = 0;
         let mut base_pred_count = 0;
         let mut base_value_count = 0;
@@ -2012,6 +2516,17 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
         let mut predicate_existences = bitvec![0;stack_pred_count as usize+1];
         let mut num_triple_changes = 0;
         let layer_changes_upto = self.layer_changes_upto(layer.name(), upto).await?;
+        let quad_changes = self.layer_quad_changes_upto(layer.name(), upto).await?;
+        for quad in quad_changes.additions() {
+            for id in [quad.graph, quad.triple.subject, quad.triple.object] {
+                if id >= base_node_value_count {
+                    node_value_existences.set((id - base_node_value_count) as usize, true);
+                }
+            }
+            if quad.triple.predicate >= base_pred_count {
+                predicate_existences.set((quad.triple.predicate - base_pred_count) as usize, true);
+            }
+        }
         for (change_type, triple) in layer_changes_upto.clone() {
             num_triple_changes += 1;
             if change_type == TripleChange::Removal {
@@ -2111,86 +2626,255 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
         let predicate_count = predicates.len();
         let value_count = values.len();
 
-        let upto_layer = self.get_layer(upto).await?.expect("expected upto to exist");
-        let layer_name = self.create_directory().await?;
-        let child_layer_files = self.child_layer_files(layer_name).await?;
-        let mut builder = DictionarySetFileBuilder::from_files(
-            child_layer_files.node_dictionary_files.clone(),
-            child_layer_files.predicate_dictionary_files.clone(),
-            child_layer_files.value_dictionary_files.clone(),
-        )
-        .await?;
-
-        builder.add_nodes_bytes(nodes.into_iter().map(|(x, _)| x.to_bytes()));
-        builder.add_predicates_bytes(predicates.into_iter().map(|(x, _)| x.to_bytes()));
-        builder.add_values(values.into_iter().map(|(x, _)| x));
-        builder.finalize().await?;
-
-        // TODO use more inner stuff to avoid parent checks as they are unnecessary here
-        let mut builder = ChildLayerFileBuilderPhase2::new(
-            upto_layer,
-            child_layer_files,
-            node_count,
-            predicate_count,
-            value_count,
-        )
-        .await?;
-        let mut triple_changes = Vec::with_capacity(num_triple_changes);
-        for (change_type, t) in layer_changes_upto {
-            let mapped_subject = if t.subject <= base_node_value_count {
-                t.subject
+        let map_node_value = |id: u64| {
+            if id <= base_node_value_count {
+                id
             } else {
-                node_value_map[(t.subject - base_node_value_count) as usize]
-            };
-            let mapped_predicate = if t.predicate <= base_pred_count {
+                node_value_map[(id - base_node_value_count) as usize]
+            }
+        };
+        let map_triple = |t: IdTriple| {
+            let predicate = if t.predicate <= base_pred_count {
                 t.predicate
             } else {
                 pred_map[(t.predicate - base_pred_count) as usize]
             };
-            let mapped_object = if t.object <= base_node_value_count {
-                t.object
-            } else {
-                node_value_map[(t.object - base_node_value_count) as usize]
-            };
-            triple_changes.push((
-                change_type,
-                IdTriple::new(mapped_subject, mapped_predicate, mapped_object),
-            ));
-        }
-        triple_changes.sort();
-        for (change_type, mapped_triple) in triple_changes.into_iter() {
-            match change_type {
-                TripleChange::Addition => {
-                    builder
-                        .add_triple_unchecked(
-                            mapped_triple.subject,
-                            mapped_triple.predicate,
-                            mapped_triple.object,
-                        )
-                        .await?
-                }
-                TripleChange::Removal => {
-                    builder
-                        .remove_triple_unchecked(
-                            mapped_triple.subject,
-                            mapped_triple.predicate,
-                            mapped_triple.object,
-                        )
-                        .await?
+            IdTriple::new(
+                map_node_value(t.subject),
+                predicate,
+                map_node_value(t.object),
+            )
+        };
+        let (quad_additions, quad_removals) = quad_changes.into_parts();
+        let quad_additions: Vec<_> = quad_additions
+            .into_iter()
+            .map(|quad| IdQuadTriple::new(map_node_value(quad.graph), map_triple(quad.triple)))
+            .collect();
+        // only quads that were in a graph below upto can be removed from it
+        let quad_removals: Vec<_> = quad_removals
+            .into_iter()
+            .filter(|quad| {
+                quad.graph <= base_node_value_count
+                    && quad.triple.subject <= base_node_value_count
+                    && quad.triple.predicate <= base_pred_count
+                    && quad.triple.object <= base_node_value_count
+            })
+            .collect();
+
+        let upto_layer = self.get_layer(upto).await?.expect("expected upto to exist");
+        let layer_name = self.create_directory().await?;
+        let child_layer_files = self.child_layer_files(layer_name).await?;
+        let result: io::Result<()> = async {
+            let mut builder = DictionarySetFileBuilder::from_files(
+                child_layer_files.node_dictionary_files.clone(),
+                child_layer_files.predicate_dictionary_files.clone(),
+                child_layer_files.value_dictionary_files.clone(),
+            )
+            .await?;
+
+            builder.add_nodes_bytes(nodes.into_iter().map(|(x, _)| x.to_bytes()));
+            builder.add_predicates_bytes(predicates.into_iter().map(|(x, _)| x.to_bytes()));
+            builder.add_values(values.into_iter().map(|(x, _)| x));
+            builder.finalize().await?;
+            progress.dictionaries_merged(3)?;
+            if layer.text_index().is_some() {
+                write_text_index(
+                    &child_layer_files.value_dictionary_files,
+                    &child_layer_files.text_index_file,
+                )
+                .await?;
+            }
+
+            if !quad_additions.is_empty() {
+                write_graph_index(&child_layer_files.pos_quads_file, quad_additions).await?;
+            }
+            if !quad_removals.is_empty() {
+                write_graph_index(&child_layer_files.neg_quads_file, quad_removals).await?;
+            }
+
+            // TODO use more inner stuff to avoid parent checks as they are unnecessary here
+            let mut builder = ChildLayerFileBuilderPhase2::new(
+                upto_layer,
+                child_layer_files,
+                node_count,
+                predicate_count,
+                value_count,
+            )
+            .await?;
+            let mut triple_changes = Vec::with_capacity(num_triple_changes);
+            for (change_type, t) in layer_changes_upto {
+                triple_changes.push((change_type, map_triple(t)));
+            }
+            triple_changes.sort();
+            for (change_type, mapped_triple) in triple_changes.into_iter() {
+                match change_type {
+                    TripleChange::Addition => {
+                        builder
+                            .add_triple_unchecked(
+                                mapped_triple.subject,
+                                mapped_triple.predicate,
+                                mapped_triple.object,
+                            )
+                            .await?
+                    }
+                    TripleChange::Removal => {
+                        builder
+                            .remove_triple_unchecked(
+                                mapped_triple.subject,
+                                mapped_triple.predicate,
+                                mapped_triple.object,
+                            )
+                            .await?
+                    }
                 }
+                progress.triple_written()?;
             }
+            builder.finalize_with_progress(progress).await
+        }
+        .await;
+        if let Err(e) = result {
+            self.discard_directory(layer_name).await?;
+            return Err(e);
         }
-        builder.finalize().await?;
         self.write_parent_file(layer_name, upto).await?;
         self.finalize_layer(layer_name).await?;
+        tracing::Span::current()
+            .record(
+                "output",
+                tracing::field::display(name_to_string(layer_name)),
+            )
+            .record("changes", num_triple_changes);
+        progress.done();
 
         Ok(layer_name)
     }
 
+    async fn squash_upto(&self, layer: Arc<InternalLayer>, upto: [u32; 5]) -> io::Result<[u32; 5]> {
+        self.squash_upto_with_progress(layer, upto, &Progress::default())
+            .await
+    }
+
+    async fn squash_external(
+        &self,
+        layer: Arc<InternalLayer>,
+        temp_path: &Path,
+    ) -> io::Result<[u32; 5]> {
+        self.squash_external_with_progress(layer, temp_path, &Progress::default())
+            .await
+    }
+
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(layer = %name_to_string(layer.name()), output = tracing::field::Empty)
+    )]
+    async fn squash_external_with_progress(
+        &self,
+        layer: Arc<InternalLayer>,
+        temp_path: &Path,
+        progress: &Progress,
+    ) -> io::Result<[u32; 5]> {
+        let output_name = self.create_directory().await?;
+        let output_layer_files = self.base_layer_files(output_name).await?;
+        tracing::Span::current().record(
+            "output",
+            tracing::field::display(name_to_string(output_name)),
+        );
+
+        if let Err(e) = squash_external_with_progress(
+            &layer,
+            output_layer_files,
+            temp_path,
+            SQUASH_RUN_SIZE,
+            progress,
+        )
+        .await
+        {
+            self.discard_directory(output_name).await?;
+            return Err(e);
+        }
+
+        self.finalize_layer(output_name).await?;
+
+        Ok(output_name)
+    }
+
+    async fn squash_upto_external(
+        &self,
+        layer: Arc<InternalLayer>,
+        upto: [u32; 5],
+        temp_path: &Path,
+    ) -> io::Result<[u32; 5]> {
+        self.squash_upto_external_with_progress(layer, upto, temp_path, &Progress::default())
+            .await
+    }
+
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(
+            layer = %name_to_string(layer.name()),
+            upto = %name_to_string(upto),
+            output = tracing::field::Empty
+        )
+    )]
+    async fn squash_upto_external_with_progress(
+        &self,
+        layer: Arc<InternalLayer>,
+        upto: [u32; 5],
+        temp_path: &Path,
+        progress: &Progress,
+    ) -> io::Result<[u32; 5]> {
+        let upto_layer = self
+            .get_layer(upto)
+            .await?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "upto layer not found"))?;
+        let output_name = self.create_directory().await?;
+        let output_layer_files = self.child_layer_files(output_name).await?;
+        tracing::Span::current().record(
+            "output",
+            tracing::field::display(name_to_string(output_name)),
+        );
+
+        if let Err(e) = squash_upto_external_with_progress(
+            &layer,
+            upto_layer,
+            output_layer_files,
+            temp_path,
+            SQUASH_RUN_SIZE,
+            progress,
+        )
+        .await
+        {
+            self.discard_directory(output_name).await?;
+            return Err(e);
+        }
+
+        self.write_parent_file(output_name, upto).await?;
+        self.finalize_layer(output_name).await?;
+
+        Ok(output_name)
+    }
+
     async fn merge_base_layer(
         &self,
         layers: &[[u32; 5]],
         temp_path: &Path,
+    ) -> io::Result<[u32; 5]> {
+        self.merge_base_layer_with_progress(layers, temp_path, &Progress::default())
+            .await
+    }
+
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(layers = layers.len(), output = tracing::field::Empty)
+    )]
+    async fn merge_base_layer_with_progress(
+        &self,
+        layers: &[[u32; 5]],
+        temp_path: &Path,
+        progress: &Progress,
     ) -> io::Result<[u32; 5]> {
         let mut layer_files = Vec::with_capacity(layers.len());
         for layer in layers {
@@ -2208,14 +2892,101 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
 
         let output_name = self.create_directory().await?;
         let output_layer_files = self.base_layer_files(output_name).await?;
+        tracing::Span::current().record(
+            "output",
+            tracing::field::display(name_to_string(output_name)),
+        );
+
+        if let Err(e) =
+            merge_base_layers_with_progress(&layer_files, output_layer_files, temp_path, progress)
+                .await
+        {
+            self.discard_directory(output_name).await?;
+            return Err(e);
+        }
+
+        self.finalize(output_name).await?;
+
+        Ok(output_name)
+    }
+
+    async fn bulk_load_base_layer(
+        &self,
+        triples: BoxStream<'_, ValueTriple>,
+        temp_path: &Path,
+    ) -> io::Result<[u32; 5]> {
+        self.bulk_load_base_layer_with_progress(triples, temp_path, &Progress::default())
+            .await
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(output = tracing::field::Empty))]
+    async fn bulk_load_base_layer_with_progress(
+        &self,
+        triples: BoxStream<'_, ValueTriple>,
+        temp_path: &Path,
+        progress: &Progress,
+    ) -> io::Result<[u32; 5]> {
+        let output_name = self.create_directory().await?;
+        let output_layer_files = self.base_layer_files(output_name).await?;
+        tracing::Span::current().record(
+            "output",
+            tracing::field::display(name_to_string(output_name)),
+        );
+
+        if let Err(e) = bulk_load_base_layer_with_progress(
+            triples,
+            output_layer_files,
+            temp_path,
+            BULK_LOAD_RUN_SIZE,
+            progress,
+        )
+        .await
+        {
+            self.discard_directory(output_name).await?;
+            return Err(e);
+        }
+
+        self.finalize(output_name).await?;
+
+        Ok(output_name)
+    }
 
-        merge_base_layers(&layer_files, output_layer_files, temp_path).await?;
+    #[tracing::instrument(level = "debug", skip_all, fields(output = tracing::field::Empty))]
+    async fn bulk_load_base_layer_quads_with_progress(
+        &self,
+        quads: BoxStream<'_, ValueQuadTriple>,
+        temp_path: &Path,
+        progress: &Progress,
+    ) -> io::Result<[u32; 5]> {
+        let output_name = self.create_directory().await?;
+        let output_layer_files = self.base_layer_files(output_name).await?;
+        tracing::Span::current().record(
+            "output",
+            tracing::field::display(name_to_string(output_name)),
+        );
+
+        if let Err(e) = bulk_load_base_layer_quads_with_progress(
+            quads,
+            output_layer_files,
+            temp_path,
+            BULK_LOAD_RUN_SIZE,
+            progress,
+        )
+        .await
+        {
+            self.discard_directory(output_name).await?;
+            return Err(e);
+        }
 
         self.finalize(output_name).await?;
 
         Ok(output_name)
     }
 
+    async fn discard_layer(&self, name: [u32; 5]) -> io::Result<()> {
+        self.discard_directory(name).await
+    }
+
     async fn layer_is_ancestor_of(
         &self,
         mut descendant: [u32; 5],
@@ -2307,6 +3078,32 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
         }
     }
 
+    async fn layer_quad_changes(&self, layer: [u32; 5]) -> io::Result<QuadChanges> {
+        let (additions_file, removals_file) = if self.layer_has_parent(layer).await? {
+            (FILENAMES.pos_quads, Some(FILENAMES.neg_quads))
+        } else {
+            (FILENAMES.base_quads, None)
+        };
+
+        let mut changes = QuadChanges::new();
+        if let Some(removals_file) = removals_file {
+            let file = self.get_file(layer, removals_file).await?;
+            if let Some(map) = file.map_if_exists().await? {
+                GraphIndex::parse(map)?
+                    .quads()
+                    .for_each(|quad| changes.remove(quad));
+            }
+        }
+        let file = self.get_file(layer, additions_file).await?;
+        if let Some(map) = file.map_if_exists().await? {
+            GraphIndex::parse(map)?
+                .quads()
+                .for_each(|quad| changes.add(quad));
+        }
+
+        Ok(changes)
+    }
+
     async fn triple_additions_s(
         &self,
         layer: [u32; 5],
@@ -2469,6 +3266,121 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
         }
     }
 
+    async fn triple_additions_from_cursor(
+        &self,
+        layer: [u32; 5],
+        cursor: &TripleCursor,
+    ) -> io::Result<TripleCursorIterator> {
+        let position = cursor.resume_position();
+        Ok(match cursor.pattern() {
+            TriplePattern::Predicate(predicate) => {
+                let (subjects_file, s_p_aj_files, sp_o_aj_files) =
+                    self.triple_addition_files(layer).await?;
+                let predicate_wavelet_files = self.predicate_wavelet_addition_files(layer).await?;
+                cursor.resume(
+                    file_triple_iterator_by_predicate(
+                        subjects_file,
+                        s_p_aj_files,
+                        sp_o_aj_files,
+                        predicate_wavelet_files,
+                        predicate,
+                    )
+                    .await?
+                    .seek_triple(position),
+                )
+            }
+            TriplePattern::Object(object) => {
+                let (subjects_file, objects_file, o_ps_aj_files, s_p_aj_files) =
+                    self.triple_addition_files_by_object(layer).await?;
+                cursor.resume(
+                    file_triple_iterator_by_object(
+                        subjects_file,
+                        objects_file,
+                        o_ps_aj_files,
+                        s_p_aj_files,
+                        object,
+                    )
+                    .await?
+                    .seek_triple(position),
+                )
+            }
+            _ => {
+                let (subjects_file, s_p_aj_files, sp_o_aj_files) =
+                    self.triple_addition_files(layer).await?;
+                cursor.resume(
+                    file_triple_iterator(subjects_file, s_p_aj_files, sp_o_aj_files)
+                        .await?
+                        .seek_triple(position),
+                )
+            }
+        })
+    }
+
+    async fn triple_removals_from_cursor(
+        &self,
+        layer: [u32; 5],
+        cursor: &TripleCursor,
+    ) -> io::Result<TripleCursorIterator> {
+        let position = cursor.resume_position();
+        Ok(match cursor.pattern() {
+            TriplePattern::Predicate(predicate) => {
+                if let (
+                    Some((subjects_file, s_p_aj_files, sp_o_aj_files)),
+                    Some(predicate_wavelet_files),
+                ) = (
+                    self.triple_removal_files(layer).await?,
+                    self.predicate_wavelet_removal_files(layer).await?,
+                ) {
+                    cursor.resume(
+                        file_triple_iterator_by_predicate(
+                            subjects_file,
+                            s_p_aj_files,
+                            sp_o_aj_files,
+                            predicate_wavelet_files,
+                            predicate,
+                        )
+                        .await?
+                        .seek_triple(position),
+                    )
+                } else {
+                    cursor.resume(std::iter::empty())
+                }
+            }
+            TriplePattern::Object(object) => {
+                if let Some((subjects_file, objects_file, o_ps_aj_files, s_p_aj_files)) =
+                    self.triple_removal_files_by_object(layer).await?
+                {
+                    cursor.resume(
+                        file_triple_iterator_by_object(
+                            subjects_file,
+                            objects_file,
+                            o_ps_aj_files,
+                            s_p_aj_files,
+                            object,
+                        )
+                        .await?
+                        .seek_triple(position),
+                    )
+                } else {
+                    cursor.resume(std::iter::empty())
+                }
+            }
+            _ => {
+                if let Some((subjects_file, s_p_aj_files, sp_o_aj_files)) =
+                    self.triple_removal_files(layer).await?
+                {
+                    cursor.resume(
+                        file_triple_iterator(subjects_file, s_p_aj_files, sp_o_aj_files)
+                            .await?
+                            .seek_triple(position),
+                    )
+                } else {
+                    cursor.resume(std::iter::empty())
+                }
+            }
+        })
+    }
+
     async fn triple_layer_addition_count(&self, layer: [u32; 5]) -> io::Result<usize> {
         let (s_p_nums_file, sp_o_bits_file, predicate_wavelet_files) =
             self.triple_layer_addition_count_files(layer).await?;
@@ -2485,6 +3397,87 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
         }
     }
 
+    async fn triple_layer_addition_count_matching(
+        &self,
+        layer: [u32; 5],
+        pattern: TriplePattern,
+    ) -> io::Result<usize> {
+        match pattern {
+            TriplePattern::All => self.triple_layer_addition_count(layer).await,
+            TriplePattern::Subject(_) | TriplePattern::SubjectPredicate(_, _) => {
+                let (subjects_file, s_p_aj_files, sp_o_aj_files) =
+                    self.triple_addition_files(layer).await?;
+                file_triple_count_by_subject(subjects_file, s_p_aj_files, sp_o_aj_files, pattern)
+                    .await
+            }
+            TriplePattern::Predicate(predicate) => {
+                let (_, s_p_aj_files, sp_o_aj_files) = self.triple_addition_files(layer).await?;
+                let predicate_wavelet_files = self.predicate_wavelet_addition_files(layer).await?;
+                file_triple_count_p(
+                    s_p_aj_files,
+                    sp_o_aj_files,
+                    predicate_wavelet_files,
+                    predicate,
+                )
+                .await
+            }
+            TriplePattern::Object(object) => {
+                let (_, objects_file, o_ps_aj_files, _) =
+                    self.triple_addition_files_by_object(layer).await?;
+                file_triple_count_o(objects_file, o_ps_aj_files, object).await
+            }
+        }
+    }
+
+    async fn triple_layer_removal_count_matching(
+        &self,
+        layer: [u32; 5],
+        pattern: TriplePattern,
+    ) -> io::Result<usize> {
+        match pattern {
+            TriplePattern::All => self.triple_layer_removal_count(layer).await,
+            TriplePattern::Subject(_) | TriplePattern::SubjectPredicate(_, _) => {
+                match self.triple_removal_files(layer).await? {
+                    Some((subjects_file, s_p_aj_files, sp_o_aj_files)) => {
+                        file_triple_count_by_subject(
+                            subjects_file,
+                            s_p_aj_files,
+                            sp_o_aj_files,
+                            pattern,
+                        )
+                        .await
+                    }
+                    None => Ok(0),
+                }
+            }
+            TriplePattern::Predicate(predicate) => {
+                match (
+                    self.triple_removal_files(layer).await?,
+                    self.predicate_wavelet_removal_files(layer).await?,
+                ) {
+                    (Some((_, s_p_aj_files, sp_o_aj_files)), Some(predicate_wavelet_files)) => {
+                        file_triple_count_p(
+                            s_p_aj_files,
+                            sp_o_aj_files,
+                            predicate_wavelet_files,
+                            predicate,
+                        )
+                        .await
+                    }
+                    _ => Ok(0),
+                }
+            }
+            TriplePattern::Object(object) => {
+                match self.triple_removal_files_by_object(layer).await? {
+                    Some((_, objects_file, o_ps_aj_files, _)) => {
+                        file_triple_count_o(objects_file, o_ps_aj_files, object).await
+                    }
+                    None => Ok(0),
+                }
+            }
+        }
+    }
+
     async fn retrieve_layer_stack_names(&self, name: [u32; 5]) -> io::Result<Vec<[u32; 5]>> {
         let mut result = vec![name];
 
@@ -2525,6 +3518,13 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
         result.reverse();
         Ok(result)
     }
+
+    async fn metrics(&self) -> StoreMetrics {
+        StoreMetrics {
+            archive_cache: self.archive_cache_stats().await,
+            ..Default::default()
+        }
+    }
 }
 
 pub(crate) async fn file_triple_exists<F: FileLoad + FileStore>(
@@ -2581,7 +3581,7 @@ pub(crate) async fn file_triple_iterator_by_predicate<F: FileLoad + FileStore>(
     sp_o_adjacency_list_files: AdjacencyListFiles<F>,
     predicate_wavelet_files: BitIndexFiles<F>,
     predicate: u64,
-) -> io::Result<impl Iterator<Item = IdTriple> + Send> {
+) -> io::Result<OptInternalLayerTriplePredicateIterator> {
     let s_p_maps = s_p_adjacency_list_files.map_all().await?;
     let sp_o_maps = sp_o_adjacency_list_files.map_all().await?;
     let predicate_wavelet_maps = predicate_wavelet_files.map_all().await?;
@@ -2610,7 +3610,7 @@ pub(crate) async fn file_triple_iterator_by_object<F: FileLoad + FileStore>(
     o_ps_adjacency_list_files: AdjacencyListFiles<F>,
     s_p_adjacency_list_files: AdjacencyListFiles<F>,
     object: u64,
-) -> io::Result<impl Iterator<Item = IdTriple> + Send> {
+) -> io::Result<InternalLayerTripleObjectIterator> {
     let subjects: Option<MonotonicLogArray> = subjects_file
         .map_if_exists()
         .await?
@@ -2648,6 +3648,67 @@ pub(crate) async fn file_triple_layer_count<F: FileLoad + FileStore>(
     Ok(bits_len - wtree.lookup(0).map(|l| l.len()).unwrap_or(0))
 }
 
+async fn file_triple_count_by_subject<F: FileLoad + FileStore>(
+    subjects_file: F,
+    s_p_adjacency_list_files: AdjacencyListFiles<F>,
+    sp_o_adjacency_list_files: AdjacencyListFiles<F>,
+    pattern: TriplePattern,
+) -> io::Result<usize> {
+    let s_p_maps = s_p_adjacency_list_files.map_all().await?;
+    let sp_o_maps = sp_o_adjacency_list_files.map_all().await?;
+
+    let subjects: Option<MonotonicLogArray> = subjects_file
+        .map_if_exists()
+        .await?
+        .map(|l| LogArray::parse(l).unwrap().into());
+    let s_p_aj = s_p_maps.into();
+    let sp_o_aj = sp_o_maps.into();
+
+    Ok(match pattern {
+        TriplePattern::Subject(subject) => {
+            layer_triple_count_s(subjects.as_ref(), &s_p_aj, &sp_o_aj, subject)
+        }
+        TriplePattern::SubjectPredicate(subject, predicate) => {
+            layer_triple_count_sp(subjects.as_ref(), &s_p_aj, &sp_o_aj, subject, predicate)
+        }
+        _ => panic!("expected a pattern with a subject"),
+    })
+}
+
+async fn file_triple_count_p<F: FileLoad + FileStore>(
+    s_p_adjacency_list_files: AdjacencyListFiles<F>,
+    sp_o_adjacency_list_files: AdjacencyListFiles<F>,
+    predicate_wavelet_files: BitIndexFiles<F>,
+    predicate: u64,
+) -> io::Result<usize> {
+    let s_p_maps = s_p_adjacency_list_files.map_all().await?;
+    let sp_o_maps = sp_o_adjacency_list_files.map_all().await?;
+    let predicate_wavelet_maps = predicate_wavelet_files.map_all().await?;
+
+    let s_p_aj: AdjacencyList = s_p_maps.into();
+    let sp_o_aj: AdjacencyList = sp_o_maps.into();
+    let width = s_p_aj.nums().width();
+    let wavelet_bits = predicate_wavelet_maps.into();
+    let wtree = WaveletTree::from_parts(wavelet_bits, width);
+
+    Ok(layer_triple_count_p(&wtree, &sp_o_aj, predicate))
+}
+
+async fn file_triple_count_o<F: FileLoad + FileStore>(
+    objects_file: F,
+    o_ps_adjacency_list_files: AdjacencyListFiles<F>,
+    object: u64,
+) -> io::Result<usize> {
+    let objects: Option<MonotonicLogArray> = objects_file
+        .map_if_exists()
+        .await?
+        .map(|l| LogArray::parse(l).unwrap().into());
+    let o_ps_maps = o_ps_adjacency_list_files.map_all().await?;
+    let o_ps_aj: AdjacencyList = o_ps_maps.into();
+
+    Ok(layer_triple_count_o(objects.as_ref(), &o_ps_aj, object))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
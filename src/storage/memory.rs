@@ -2,9 +2,13 @@
 
 use std::collections::HashMap;
 use std::io;
+use std::time::SystemTime;
 
 use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt};
+use tokio::sync::broadcast;
 
+use super::consts::{LayerFileEnum, FILENAME_ENUM_MAP};
 use super::file::*;
 use super::label::*;
 use super::layer::*;
@@ -14,6 +18,7 @@ pub use tdb_succinct::storage::memory::*;
 #[derive(Clone, Default)]
 pub struct MemoryLayerStore {
     layers: futures_locks::RwLock<HashMap<[u32; 5], HashMap<String, MemoryBackedStore>>>,
+    created: futures_locks::RwLock<HashMap<[u32; 5], SystemTime>>,
 }
 
 impl MemoryLayerStore {
@@ -38,6 +43,51 @@ impl PersistentLayerStore for MemoryLayerStore {
         Ok(name)
     }
 
+    async fn finalize(&self, name: [u32; 5]) -> io::Result<()> {
+        let mut guard = self.created.write().await;
+        guard.insert(name, SystemTime::now());
+
+        Ok(())
+    }
+
+    async fn discard_directory(&self, name: [u32; 5]) -> io::Result<()> {
+        let mut guard = self.layers.write().await;
+        guard.remove(&name);
+
+        Ok(())
+    }
+
+    async fn layer_sections(&self, name: [u32; 5]) -> io::Result<Vec<(LayerFileEnum, usize)>> {
+        let files: Vec<_> = {
+            let guard = self.layers.read().await;
+            match guard.get(&name) {
+                Some(files) => files
+                    .iter()
+                    .filter_map(|(file, store)| match FILENAME_ENUM_MAP.get(file.as_str()) {
+                        Some(LayerFileEnum::Rollup) | None => None,
+                        Some(section) => Some((*section, store.clone())),
+                    })
+                    .collect(),
+                None => return Err(io::Error::new(io::ErrorKind::NotFound, "layer not found")),
+            }
+        };
+
+        let mut result = Vec::with_capacity(files.len());
+        for (section, file) in files {
+            if file.exists().await? {
+                result.push((section, file.size().await?));
+            }
+        }
+        result.sort();
+
+        Ok(result)
+    }
+
+    async fn layer_created(&self, name: [u32; 5]) -> io::Result<Option<SystemTime>> {
+        let guard = self.created.read().await;
+        Ok(guard.get(&name).cloned())
+    }
+
     async fn directory_exists(&self, name: [u32; 5]) -> io::Result<bool> {
         let guard = self.layers.read().await;
         Ok(guard.contains_key(&name))
@@ -76,15 +126,35 @@ impl PersistentLayerStore for MemoryLayerStore {
     }
 }
 
-#[derive(Clone, Default)]
+/// The amount of label changes a watcher can fall behind on before it only gets the current label.
+const LABEL_CHANGES_CAPACITY: usize = 64;
+
+#[derive(Clone)]
 pub struct MemoryLabelStore {
     labels: futures_locks::RwLock<HashMap<String, Label>>,
+    // every label write is broadcast, with None for a deleted label
+    changes: broadcast::Sender<(String, Option<Label>)>,
+}
+
+impl Default for MemoryLabelStore {
+    fn default() -> Self {
+        let (changes, _) = broadcast::channel(LABEL_CHANGES_CAPACITY);
+        Self {
+            labels: Default::default(),
+            changes,
+        }
+    }
 }
 
 impl MemoryLabelStore {
     pub fn new() -> MemoryLabelStore {
         Default::default()
     }
+
+    fn announce(&self, name: &str, label: Option<Label>) {
+        // there being nobody watching is fine
+        let _ = self.changes.send((name.to_string(), label));
+    }
 }
 
 #[async_trait]
@@ -134,6 +204,7 @@ impl LabelStore for MemoryLabelStore {
                     Ok(None)
                 } else {
                     labels.insert(new_label.name.clone(), new_label.clone());
+                    self.announce(&new_label.name, Some(new_label.clone()));
 
                     Ok(Some(new_label))
                 }
@@ -143,12 +214,54 @@ impl LabelStore for MemoryLabelStore {
 
     async fn delete_label(&self, name: &str) -> io::Result<bool> {
         let mut labels = self.labels.write().await;
+        let removed = labels.remove(name).is_some();
+        if removed {
+            self.announce(name, None);
+        }
 
-        Ok(labels.remove(name).is_some())
+        Ok(removed)
+    }
+
+    async fn import_label(&self, label: &Label) -> io::Result<()> {
+        let mut labels = self.labels.write().await;
+        labels.insert(label.name.clone(), label.clone());
+        self.announce(&label.name, Some(label.clone()));
+
+        Ok(())
+    }
+
+    async fn watch_label(&self, name: &str) -> io::Result<Option<BoxStream<'static, Label>>> {
+        let name = name.to_string();
+        let receiver = self.changes.subscribe();
+        let all_labels = self.labels.clone();
+        let labels = stream::unfold(receiver, move |mut receiver| {
+            let name = name.clone();
+            let all_labels = all_labels.clone();
+            async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok((changed, label)) if changed == name => {
+                            // a deleted label ends the stream
+                            return label.map(|label| (label, receiver));
+                        }
+                        Ok(_) => continue,
+                        Err(broadcast::error::RecvError::Lagged(_)) => {
+                            // a watcher that fell behind may have missed
+                            // changes to its label, so it gets the
+                            // label as it is now
+                            let label = all_labels.read().await.get(&name).cloned();
+                            return label.map(|label| (label, receiver));
+                        }
+                        Err(broadcast::error::RecvError::Closed) => return None,
+                    }
+                }
+            }
+        });
+
+        Ok(Some(labels.boxed()))
     }
 }
 
-#[cfg(test)]
 pub fn base_layer_memory_files() -> BaseLayerFiles<MemoryBackedStore> {
     BaseLayerFiles {
         node_dictionary_files: DictionaryFiles {
@@ -211,10 +324,11 @@ pub fn base_layer_memory_files() -> BaseLayerFiles<MemoryBackedStore> {
             blocks_file: MemoryBackedStore::new(),
             sblocks_file: MemoryBackedStore::new(),
         },
+        quads_file: MemoryBackedStore::new(),
+        text_index_file: MemoryBackedStore::new(),
     }
 }
 
-#[cfg(test)]
 pub fn child_layer_memory_files() -> ChildLayerFiles<MemoryBackedStore> {
     ChildLayerFiles {
         node_dictionary_files: DictionaryFiles {
@@ -308,6 +422,9 @@ pub fn child_layer_memory_files() -> ChildLayerFiles<MemoryBackedStore> {
             blocks_file: MemoryBackedStore::new(),
             sblocks_file: MemoryBackedStore::new(),
         },
+        pos_quads_file: MemoryBackedStore::new(),
+        neg_quads_file: MemoryBackedStore::new(),
+        text_index_file: MemoryBackedStore::new(),
     }
 }
 
@@ -448,4 +565,31 @@ mod tests {
 
         assert!(!store.delete_label("foo").await.unwrap());
     }
+
+    #[tokio::test]
+    async fn lagging_watcher_gets_current_label() {
+        let store = MemoryLabelStore::new();
+        let watched = store.create_label("watched").await.unwrap();
+        let mut busy = store.create_label("busy").await.unwrap();
+        let mut changes = store.watch_label("watched").await.unwrap().unwrap();
+
+        let watched = store
+            .set_label(&watched, [1, 2, 3, 4, 5])
+            .await
+            .unwrap()
+            .unwrap();
+        for _ in 0..LABEL_CHANGES_CAPACITY * 2 {
+            busy = store
+                .set_label(&busy, [5, 4, 3, 2, 1])
+                .await
+                .unwrap()
+                .unwrap();
+        }
+
+        // the change of the watched label itself was pushed out of the channel
+        let next = tokio::time::timeout(std::time::Duration::from_secs(5), changes.next())
+            .await
+            .expect("watcher should be notified of the missed change");
+        assert_eq!(Some(watched), next);
+    }
 }
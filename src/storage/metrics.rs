@@ -0,0 +1,228 @@
+//! Metrics for the caching and loading behavior of a store.
+//!
+//! Cache levels and layer stores keep a set of cheap atomic counters
+//! around. A `StoreMetrics` is a point-in-time snapshot of these
+//! counters, which can be retrieved through `Store::metrics`, or
+//! periodically pushed into an external metrics system through a
+//! `MetricsHook`.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds of the buckets of the layer load latency histogram.
+pub const LOAD_LATENCY_BUCKETS: [Duration; 7] = [
+    Duration::from_micros(100),
+    Duration::from_millis(1),
+    Duration::from_millis(10),
+    Duration::from_millis(100),
+    Duration::from_secs(1),
+    Duration::from_secs(10),
+    Duration::from_secs(60),
+];
+
+/// A snapshot of the counters of a single cache level.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// The amount of lookups that were answered from cache.
+    pub hits: u64,
+    /// The amount of lookups that had to go to the next level.
+    pub misses: u64,
+    /// The amount of entries that were dropped from the cache.
+    pub evictions: u64,
+    /// The amount of entries currently in the cache.
+    pub entries: usize,
+    /// The amount of bytes currently held by cached entries.
+    pub bytes_resident: usize,
+}
+
+impl CacheStats {
+    /// The fraction of lookups that were answered from cache, or None if no lookups were done yet.
+    pub fn hit_ratio(&self) -> Option<f64> {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            None
+        } else {
+            Some(self.hits as f64 / total as f64)
+        }
+    }
+}
+
+/// A snapshot of a latency histogram.
+///
+/// `counts` has one more element than `bounds`. The element at index
+/// i counts the observations that were at most `bounds[i]` and
+/// larger than the previous bound. The final element counts the
+/// observations that were larger than all bounds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    pub bounds: Vec<Duration>,
+    pub counts: Vec<u64>,
+    pub count: u64,
+    pub sum: Duration,
+}
+
+impl LatencyHistogram {
+    /// The mean latency, or None if nothing was observed.
+    pub fn mean(&self) -> Option<Duration> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.sum / self.count as u32)
+        }
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            bounds: LOAD_LATENCY_BUCKETS.to_vec(),
+            counts: vec![0; LOAD_LATENCY_BUCKETS.len() + 1],
+            count: 0,
+            sum: Duration::default(),
+        }
+    }
+}
+
+/// A snapshot of the layer loading counters.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoadStats {
+    /// The amount of layers that were loaded from the backend.
+    pub layers_loaded: u64,
+    /// The total mapped size of the layers that were loaded, not
+    /// counting their parents. This is what loading made resident,
+    /// not the amount of bytes the backend actually read.
+    pub bytes_loaded: u64,
+    /// Latency of layer retrievals that could not be fully answered from cache.
+    pub latency: LatencyHistogram,
+}
+
+/// A snapshot of all metrics of a store.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StoreMetrics {
+    /// Counters for the cache of loaded layers, if the layer store has one.
+    pub layer_cache: Option<CacheStats>,
+    /// Counters for the archive byte cache, if the layer store has one.
+    pub archive_cache: Option<CacheStats>,
+    /// Counters for layer loading.
+    pub loads: LoadStats,
+}
+
+/// A hook for pushing metric snapshots into an external metrics system.
+pub trait MetricsHook: 'static + Send + Sync {
+    fn report(&self, metrics: &StoreMetrics);
+}
+
+impl<F: 'static + Fn(&StoreMetrics) + Send + Sync> MetricsHook for F {
+    fn report(&self, metrics: &StoreMetrics) {
+        self(metrics)
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct CacheCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl CacheCounters {
+    pub(crate) fn hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn evict(&self, count: u64) {
+        self.evictions.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self, entries: usize, bytes_resident: usize) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            entries,
+            bytes_resident,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct LoadCounters {
+    layers_loaded: AtomicU64,
+    bytes_loaded: AtomicU64,
+    latency_counts: [AtomicU64; LOAD_LATENCY_BUCKETS.len() + 1],
+    latency_count: AtomicU64,
+    latency_sum_nanos: AtomicU64,
+}
+
+impl LoadCounters {
+    pub(crate) fn layer_loaded(&self, bytes: usize) {
+        self.layers_loaded.fetch_add(1, Ordering::Relaxed);
+        self.bytes_loaded.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn observe_latency(&self, latency: Duration) {
+        let bucket = LOAD_LATENCY_BUCKETS
+            .iter()
+            .position(|bound| latency <= *bound)
+            .unwrap_or(LOAD_LATENCY_BUCKETS.len());
+        self.latency_counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.latency_count.fetch_add(1, Ordering::Relaxed);
+        self.latency_sum_nanos
+            .fetch_add(latency.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> LoadStats {
+        LoadStats {
+            layers_loaded: self.layers_loaded.load(Ordering::Relaxed),
+            bytes_loaded: self.bytes_loaded.load(Ordering::Relaxed),
+            latency: LatencyHistogram {
+                bounds: LOAD_LATENCY_BUCKETS.to_vec(),
+                counts: self
+                    .latency_counts
+                    .iter()
+                    .map(|c| c.load(Ordering::Relaxed))
+                    .collect(),
+                count: self.latency_count.load(Ordering::Relaxed),
+                sum: Duration::from_nanos(self.latency_sum_nanos.load(Ordering::Relaxed)),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latency_lands_in_the_right_bucket() {
+        let counters = LoadCounters::default();
+        counters.observe_latency(Duration::from_micros(50));
+        counters.observe_latency(Duration::from_millis(5));
+        counters.observe_latency(Duration::from_millis(5));
+        counters.observe_latency(Duration::from_secs(120));
+
+        let stats = counters.snapshot();
+        assert_eq!(vec![1, 0, 2, 0, 0, 0, 0, 1], stats.latency.counts);
+        assert_eq!(4, stats.latency.count);
+        assert_eq!(
+            Duration::from_micros(50) + Duration::from_millis(10) + Duration::from_secs(120),
+            stats.latency.sum
+        );
+    }
+
+    #[test]
+    fn hit_ratio_is_computed_from_hits_and_misses() {
+        let counters = CacheCounters::default();
+        assert_eq!(None, counters.snapshot(0, 0).hit_ratio());
+
+        counters.hit();
+        counters.hit();
+        counters.hit();
+        counters.miss();
+
+        assert_eq!(Some(0.75), counters.snapshot(0, 0).hit_ratio());
+    }
+}
@@ -26,6 +26,7 @@ mod cache;
 pub mod consts;
 pub mod directory;
 mod file;
+mod info;
 mod label;
 #[macro_use]
 mod layer;
@@ -34,11 +35,17 @@ mod copy;
 pub mod delta;
 mod locking;
 pub mod memory;
+mod metrics;
 pub mod pack;
+mod progress;
 
 pub use cache::*;
+pub use copy::*;
 pub use delta::*;
 pub use file::*;
+pub use info::*;
 pub use label::*;
 pub use layer::*;
+pub use metrics::*;
 pub use pack::*;
+pub use progress::*;
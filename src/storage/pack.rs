@@ -39,6 +39,7 @@ pub trait Packable {
 
 #[async_trait]
 impl<T: PersistentLayerStore> Packable for T {
+    #[tracing::instrument(level = "debug", skip_all, fields(layers = tracing::field::Empty))]
     async fn export_layers(
         &self,
         layer_ids: Box<dyn Iterator<Item = [u32; 5]> + Send>,
@@ -51,15 +52,24 @@ impl<T: PersistentLayerStore> Packable for T {
         let mut enc = GzEncoder::new(Vec::new(), Compression::default());
         {
             let mut tar = tar::Builder::new(&mut enc);
+            let mut count: usize = 0;
             for id in layer_ids {
+                tracing::trace!(layer = %name_to_string(id), "exporting layer");
                 tar_append_layer(&mut tar, self, id, mtime).await?;
+                count += 1;
             }
             tar.finish().unwrap();
+            tracing::Span::current().record("layers", count);
         }
         // TODO: Proper error handling
         Ok(enc.finish().unwrap())
     }
 
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(pack_size = pack.len(), layers = tracing::field::Empty)
+    )]
     async fn import_layers(
         &self,
         pack: &[u8],
@@ -70,6 +80,7 @@ impl<T: PersistentLayerStore> Packable for T {
             layer_id_set.insert(name_to_string(id));
             self.create_named_directory(id).await?;
         }
+        tracing::Span::current().record("layers", layer_id_set.len());
 
         let handle = tokio::runtime::Handle::current();
         tokio::task::block_in_place(|| {
@@ -127,6 +138,7 @@ impl<T: PersistentLayerStore> Packable for T {
             for layer_id in layer_id_set {
                 let layer_id_arr = string_to_name(&layer_id).unwrap();
                 handle.block_on(self.finalize_layer(layer_id_arr))?;
+                tracing::trace!(layer = %layer_id, "imported layer");
             }
 
             Ok(())
@@ -0,0 +1,244 @@
+//! Progress reporting and cancellation for long-running operations.
+//!
+//! Operations like merging base layers, squashing, rolling up and
+//! committing large layers can take a long time. Their `_with_progress`
+//! variants take a `Progress`, which reports what phase the operation
+//! is in and how much work was done through a `ProgressReporter`, and
+//! which allows the operation to be aborted through a
+//! `CancellationToken`.
+//!
+//! A cancelled operation returns an error of kind
+//! `io::ErrorKind::Interrupted`. Any layer that was under construction
+//! is discarded, and no rollup is registered.
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+pub use tokio_util::sync::CancellationToken;
+
+/// The amount of triples written between two progress reports.
+pub const TRIPLE_REPORT_INTERVAL: u64 = 1_000_000;
+
+/// The amount of triples written between two cancellation checks.
+const CANCELLATION_CHECK_INTERVAL: u64 = 4096;
+
+/// The phase a long-running operation is in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressPhase {
+    /// Dictionaries are being merged or written.
+    Dictionaries,
+    /// Triples are being written.
+    Triples,
+    /// Indexes are being built.
+    Indexes,
+    /// The operation has completed.
+    Done,
+}
+
+/// A progress report.
+///
+/// The counts are cumulative over the lifetime of the `Progress` that
+/// produced this report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgressEvent {
+    pub phase: ProgressPhase,
+    pub dictionaries_merged: u64,
+    pub triples_written: u64,
+    pub indexes_built: u64,
+}
+
+/// A receiver of progress reports.
+pub trait ProgressReporter: 'static + Send + Sync {
+    fn report(&self, event: &ProgressEvent);
+}
+
+impl<F: 'static + Fn(&ProgressEvent) + Send + Sync> ProgressReporter for F {
+    fn report(&self, event: &ProgressEvent) {
+        self(event)
+    }
+}
+
+impl ProgressReporter for tokio::sync::mpsc::UnboundedSender<ProgressEvent> {
+    fn report(&self, event: &ProgressEvent) {
+        // a receiver that went away is no reason to stop the operation
+        let _ = self.send(*event);
+    }
+}
+
+#[derive(Default)]
+struct ProgressCounters {
+    dictionaries_merged: AtomicU64,
+    triples_written: AtomicU64,
+    indexes_built: AtomicU64,
+}
+
+/// Progress reporting and cancellation for a long-running operation.
+///
+/// The default `Progress` reports nothing and can't be cancelled.
+#[derive(Clone, Default)]
+pub struct Progress {
+    reporter: Option<Arc<dyn ProgressReporter>>,
+    token: Option<CancellationToken>,
+    counters: Arc<ProgressCounters>,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send progress reports to the given reporter.
+    pub fn with_reporter<R: ProgressReporter>(mut self, reporter: R) -> Self {
+        self.reporter = Some(Arc::new(reporter));
+        self
+    }
+
+    /// Abort the operation once the given token is cancelled.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Returns true if the cancellation token of this progress was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.token
+            .as_ref()
+            .map(|t| t.is_cancelled())
+            .unwrap_or(false)
+    }
+
+    /// Returns a report of the current counts in the given phase.
+    pub fn event(&self, phase: ProgressPhase) -> ProgressEvent {
+        ProgressEvent {
+            phase,
+            dictionaries_merged: self.counters.dictionaries_merged.load(Ordering::Relaxed),
+            triples_written: self.counters.triples_written.load(Ordering::Relaxed),
+            indexes_built: self.counters.indexes_built.load(Ordering::Relaxed),
+        }
+    }
+
+    fn report(&self, phase: ProgressPhase) {
+        if let Some(reporter) = self.reporter.as_ref() {
+            reporter.report(&self.event(phase));
+        }
+    }
+
+    /// Returns an `Interrupted` error if the operation was cancelled.
+    pub(crate) fn check(&self) -> io::Result<()> {
+        if self.is_cancelled() {
+            Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "operation was cancelled",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn dictionaries_merged(&self, count: u64) -> io::Result<()> {
+        self.counters
+            .dictionaries_merged
+            .fetch_add(count, Ordering::Relaxed);
+        self.report(ProgressPhase::Dictionaries);
+        self.check()
+    }
+
+    pub(crate) fn triple_written(&self) -> io::Result<()> {
+        let count = self
+            .counters
+            .triples_written
+            .fetch_add(1, Ordering::Relaxed)
+            + 1;
+        if count % TRIPLE_REPORT_INTERVAL == 0 {
+            self.report(ProgressPhase::Triples);
+        }
+        if count % CANCELLATION_CHECK_INTERVAL == 0 {
+            self.check()
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn triples_done(&self) -> io::Result<()> {
+        self.report(ProgressPhase::Triples);
+        self.check()
+    }
+
+    pub(crate) fn index_built(&self) -> io::Result<()> {
+        self.counters.indexes_built.fetch_add(1, Ordering::Relaxed);
+        self.report(ProgressPhase::Indexes);
+        self.check()
+    }
+
+    pub(crate) fn done(&self) {
+        self.report(ProgressPhase::Done);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_cumulative_counts() {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let progress = Progress::new().with_reporter(sender);
+
+        progress.dictionaries_merged(3).unwrap();
+        for _ in 0..10 {
+            progress.triple_written().unwrap();
+        }
+        progress.triples_done().unwrap();
+        progress.index_built().unwrap();
+        progress.done();
+
+        let mut events = Vec::new();
+        while let Ok(event) = receiver.try_recv() {
+            events.push(event);
+        }
+
+        assert_eq!(
+            vec![
+                ProgressEvent {
+                    phase: ProgressPhase::Dictionaries,
+                    dictionaries_merged: 3,
+                    triples_written: 0,
+                    indexes_built: 0
+                },
+                ProgressEvent {
+                    phase: ProgressPhase::Triples,
+                    dictionaries_merged: 3,
+                    triples_written: 10,
+                    indexes_built: 0
+                },
+                ProgressEvent {
+                    phase: ProgressPhase::Indexes,
+                    dictionaries_merged: 3,
+                    triples_written: 10,
+                    indexes_built: 1
+                },
+                ProgressEvent {
+                    phase: ProgressPhase::Done,
+                    dictionaries_merged: 3,
+                    triples_written: 10,
+                    indexes_built: 1
+                },
+            ],
+            events
+        );
+    }
+
+    #[test]
+    fn cancelled_progress_returns_interrupted() {
+        let token = CancellationToken::new();
+        let progress = Progress::new().with_cancellation(token.clone());
+        assert!(progress.check().is_ok());
+
+        token.cancel();
+        assert!(progress.is_cancelled());
+        assert_eq!(
+            io::ErrorKind::Interrupted,
+            progress.dictionaries_merged(1).unwrap_err().kind()
+        );
+    }
+}
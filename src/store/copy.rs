@@ -0,0 +1,281 @@
+//! Copying the contents of one store into another.
+//!
+//! This is how a store is migrated between backends, for example from
+//! a directory store to an archive store. Every layer is copied under
+//! its own name, so labels keep pointing at the same layers, and a copy
+//! that got interrupted can simply be started again.
+use std::collections::HashSet;
+use std::io;
+
+use super::Store;
+use crate::storage::name_to_string;
+
+/// Options for `Store::copy_into`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CopyOptions {
+    /// Overwrite labels that already exist in the target store with a different layer or version.
+    ///
+    /// If this is not set, such labels make the copy fail before anything is copied.
+    pub overwrite_labels: bool,
+}
+
+impl CopyOptions {
+    /// Construct the default options, which leave existing labels alone.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Overwrite labels that differ in the target store.
+    pub fn with_overwrite_labels(mut self, overwrite_labels: bool) -> Self {
+        self.overwrite_labels = overwrite_labels;
+        self
+    }
+}
+
+/// What was done by `Store::copy_into`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CopyStats {
+    /// The amount of layers that were copied.
+    pub layers_copied: usize,
+    /// The amount of layers that were already present in the target store.
+    pub layers_skipped: usize,
+    /// The amount of labels that were written to the target store.
+    pub labels_copied: usize,
+}
+
+pub(super) async fn copy_store(
+    source: &Store,
+    target: &Store,
+    options: &CopyOptions,
+) -> io::Result<CopyStats> {
+    let mut stats = CopyStats::default();
+
+    // find conflicting labels before doing any work
+    let labels = source.label_store.labels().await?;
+    let mut changed_labels = Vec::with_capacity(labels.len());
+    for label in labels {
+        match target.label_store.get_label(&label.name).await? {
+            Some(existing) if existing == label => {}
+            Some(_) if !options.overwrite_labels => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("label {} already exists in the target store", label.name),
+                ));
+            }
+            _ => changed_labels.push(label),
+        }
+    }
+
+    // parents have to be copied before their children
+    let mut layers = source.layer_store.layers_info().await?;
+    layers.sort_by_key(|info| info.depth);
+    let existing: HashSet<_> = target.layer_store.layers().await?.into_iter().collect();
+    for info in layers.iter() {
+        if existing.contains(&info.name) {
+            // a layer that is incomplete was left behind by an interrupted copy
+            match target.layer_store.layer_info(info.name).await? {
+                Some(copied) if copied.sections == info.sections => {
+                    stats.layers_skipped += 1;
+                    continue;
+                }
+                _ => target.layer_store.discard_layer(info.name).await?,
+            }
+        }
+
+        let copy = source
+            .layer_store
+            .read_layer_copy(info.name)
+            .await?
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "layer {} disappeared during copy",
+                        name_to_string(info.name)
+                    ),
+                )
+            })?;
+        target.layer_store.write_layer_copy(&copy).await?;
+        stats.layers_copied += 1;
+    }
+
+    // rollups can only be registered once the rollup layer itself is there
+    for info in layers {
+        if let Some(rollup) = info.rollup {
+            if target.layer_store.get_layer_rollup(info.name).await? != Some(rollup) {
+                target
+                    .layer_store
+                    .register_rollup(info.name, rollup)
+                    .await?;
+            }
+        }
+    }
+
+    // labels go last, so they never point at a layer that wasn't copied yet
+    for label in changed_labels {
+        target.label_store.import_label(&label).await?;
+        stats.labels_copied += 1;
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::{Layer, ValueTriple};
+    use crate::storage::consts::FILENAMES;
+    use crate::store::{open_archive_store, open_directory_store, open_memory_store};
+    use tempfile::tempdir;
+
+    async fn populate(store: &Store) -> [u32; 5] {
+        let graph = store.create("foo").await.unwrap();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+
+        let builder = base.open_write().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("pig", "says", "oink"))
+            .unwrap();
+        let child = builder.commit().await.unwrap();
+        graph.set_head(&base).await.unwrap();
+        graph.set_head(&child).await.unwrap();
+        child.rollup().await.unwrap();
+
+        store.create("bar").await.unwrap();
+
+        child.name()
+    }
+
+    async fn check_copy(source: &Store, target: &Store, top: [u32; 5]) {
+        assert_eq!(
+            source.layer_store.layers_info().await.unwrap().len(),
+            target.layer_store.layers_info().await.unwrap().len()
+        );
+
+        let (layer, version) = target
+            .open("foo")
+            .await
+            .unwrap()
+            .unwrap()
+            .head_version()
+            .await
+            .unwrap();
+        let layer = layer.unwrap();
+        assert_eq!(top, layer.name());
+        assert_eq!(2, version);
+        assert!(layer.value_triple_exists(&ValueTriple::new_string_value("cow", "says", "moo")));
+        assert!(layer.value_triple_exists(&ValueTriple::new_string_value("pig", "says", "oink")));
+        assert_eq!(
+            source.layer_store.get_layer_rollup(top).await.unwrap(),
+            target.layer_store.get_layer_rollup(top).await.unwrap()
+        );
+
+        let (layer, version) = target
+            .open("bar")
+            .await
+            .unwrap()
+            .unwrap()
+            .head_version()
+            .await
+            .unwrap();
+        assert!(layer.is_none());
+        assert_eq!(0, version);
+    }
+
+    #[tokio::test]
+    async fn copy_directory_store_into_archive_store() {
+        let dir = tempdir().unwrap();
+        let source = open_directory_store(dir.path().join("source"));
+        tokio::fs::create_dir(dir.path().join("source"))
+            .await
+            .unwrap();
+        tokio::fs::create_dir(dir.path().join("target"))
+            .await
+            .unwrap();
+        let target = open_archive_store(dir.path().join("target"), 512);
+        let top = populate(&source).await;
+
+        let stats = source.copy_into(&target, CopyOptions::new()).await.unwrap();
+        assert_eq!(3, stats.layers_copied);
+        assert_eq!(0, stats.layers_skipped);
+        assert_eq!(2, stats.labels_copied);
+        check_copy(&source, &target, top).await;
+
+        // copying again has nothing left to do
+        let stats = source.copy_into(&target, CopyOptions::new()).await.unwrap();
+        assert_eq!(
+            CopyStats {
+                layers_copied: 0,
+                layers_skipped: 3,
+                labels_copied: 0
+            },
+            stats
+        );
+    }
+
+    #[tokio::test]
+    async fn interrupted_copy_can_be_resumed() {
+        let dir = tempdir().unwrap();
+        let source = open_memory_store();
+        let target = open_directory_store(dir.path());
+        let top = populate(&source).await;
+
+        // pretend a previous copy got as far as writing part of the top layer
+        let copy = source
+            .layer_store
+            .read_layer_copy(top)
+            .await
+            .unwrap()
+            .unwrap();
+        let base = source
+            .layer_store
+            .read_layer_copy(copy.parent.unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        target.layer_store.write_layer_copy(&base).await.unwrap();
+        target.layer_store.write_layer_copy(&copy).await.unwrap();
+        let name = name_to_string(top);
+        tokio::fs::remove_file(
+            dir.path()
+                .join(&name[..3])
+                .join(&name)
+                .join(FILENAMES.pos_objects),
+        )
+        .await
+        .unwrap();
+
+        let stats = source.copy_into(&target, CopyOptions::new()).await.unwrap();
+        assert_eq!(2, stats.layers_copied);
+        assert_eq!(1, stats.layers_skipped);
+        check_copy(&source, &target, top).await;
+    }
+
+    #[tokio::test]
+    async fn differing_labels_are_only_overwritten_on_request() {
+        let source = open_memory_store();
+        let target = open_memory_store();
+        let top = populate(&source).await;
+        target.create("foo").await.unwrap();
+
+        assert_eq!(
+            io::ErrorKind::AlreadyExists,
+            source
+                .copy_into(&target, CopyOptions::new())
+                .await
+                .unwrap_err()
+                .kind()
+        );
+        assert!(target.layer_store.layers().await.unwrap().is_empty());
+
+        source
+            .copy_into(&target, CopyOptions::new().with_overwrite_labels(true))
+            .await
+            .unwrap();
+        check_copy(&source, &target, top).await;
+    }
+}
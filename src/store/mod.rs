@@ -1,21 +1,46 @@
 //! High-level API for working with terminus-store.
 //!
 //! It is expected that most users of this library will work exclusively with the types contained in this module.
+mod copy;
+mod rollup;
 pub mod sync;
+mod validate;
+mod version;
+mod watch;
+
+pub use copy::{CopyOptions, CopyStats};
+use rollup::RollupScheduler;
+pub use rollup::{RollupInfo, RollupPolicy};
+use validate::validate_commit;
+pub use validate::{CommitValidator, PendingLayer, ValidationError, Violation};
+use version::VersionCheck;
+pub use version::{
+    read_store_version, upgrade_store, StoreKind, StoreVersion, CURRENT_FORMAT_VERSION,
+    LEGACY_FORMAT_VERSION, STORE_VERSION_FILE,
+};
+pub use watch::LABEL_POLL_INTERVAL;
 
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 
-use crate::layer::{IdTriple, Layer, LayerBuilder, LayerCounts, ObjectType, ValueTriple};
+use crate::layer::{
+    IdQuadTriple, IdTriple, IdTripleStream, InternalLayer, Layer, LayerBuilder, LayerBuilderShard,
+    LayerCounts, ObjectType, TripleChange, TripleCursor, TripleCursorIterator, TriplePattern,
+    ValueQuadTriple, ValueTriple,
+};
 use crate::storage::archive::{ArchiveLayerStore, DirectoryArchiveBackend, LruArchiveBackend};
 use crate::storage::directory::{DirectoryLabelStore, DirectoryLayerStore};
 use crate::storage::memory::{MemoryLabelStore, MemoryLayerStore};
-use crate::storage::{CachedLayerStore, LabelStore, LayerStore, LockingHashMapLayerCache};
+use crate::storage::{
+    name_to_string, CachedLayerStore, LabelChange, LabelStore, LayerInfo, LayerStore,
+    LockingHashMapLayerCache, MetricsHook, Progress, StoreMetrics,
+};
 use tdb_succinct::TypedDictEntry;
 
 use std::io;
 
 use async_trait::async_trait;
+use futures::stream::{Stream, StreamExt};
 use rayon::prelude::*;
 
 /// A store, storing a set of layers and database labels pointing to these layers.
@@ -23,6 +48,10 @@ use rayon::prelude::*;
 pub struct Store {
     label_store: Arc<dyn LabelStore>,
     layer_store: Arc<dyn LayerStore>,
+    rollups: Option<Arc<RollupScheduler>>,
+    validators: Vec<Arc<dyn CommitValidator>>,
+    text_index: bool,
+    version_check: Option<Arc<VersionCheck>>,
 }
 
 /// A wrapper over a SimpleLayerBuilder, providing a thread-safe sharable interface.
@@ -34,31 +63,100 @@ pub struct Store {
 /// between threads. Also, rather than consuming itself on commit,
 /// this wrapper will simply mark itself as having committed,
 /// returning errors on further calls.
+///
+/// For ingesting from many threads at once, use `shard` to give each
+/// thread its own handle to add triples through, rather than
+/// contending on this builder's lock.
 #[derive(Clone)]
 pub struct StoreLayerBuilder {
     parent: Option<Arc<dyn Layer>>,
     builder: Arc<RwLock<Option<Box<dyn LayerBuilder>>>>,
+    shards: Arc<Mutex<Vec<ShardSlot>>>,
     name: [u32; 5],
     store: Store,
 }
 
+/// A shard of a builder, which is taken out when the builder is committed.
+type ShardSlot = Arc<Mutex<Option<LayerBuilderShard>>>;
+
+/// A handle for adding and removing triples on one shard of a `StoreLayerBuilder`.
+///
+/// Each shard has its own dictionaries and triple buffers, so shards
+/// can be filled from different threads without contending with
+/// each other. All shards are merged into the builder when it is
+/// committed. After that, any further changes through the shard
+/// will return an error.
+pub struct StoreLayerBuilderShard {
+    shard: ShardSlot,
+}
+
+impl StoreLayerBuilderShard {
+    fn with_shard<R, F: FnOnce(&mut LayerBuilderShard) -> R>(&self, f: F) -> io::Result<R> {
+        let mut shard = self.shard.lock().expect("mutex lock should always succeed");
+        match (*shard).as_mut() {
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "builder has already been committed",
+            )),
+            Some(shard) => Ok(f(shard)),
+        }
+    }
+
+    /// Add a string triple.
+    pub fn add_value_triple(&self, triple: ValueTriple) -> io::Result<()> {
+        self.with_shard(move |s| s.add_value_triple(triple))
+    }
+
+    /// Add an id triple.
+    pub fn add_id_triple(&self, triple: IdTriple) -> io::Result<()> {
+        self.with_shard(move |s| s.add_id_triple(triple))
+    }
+
+    /// Remove a string triple.
+    pub fn remove_value_triple(&self, triple: ValueTriple) -> io::Result<()> {
+        self.with_shard(move |s| s.remove_value_triple(triple))
+    }
+
+    /// Remove an id triple.
+    pub fn remove_id_triple(&self, triple: IdTriple) -> io::Result<()> {
+        self.with_shard(move |s| s.remove_id_triple(triple))
+    }
+
+    /// Add a string triple and put it in the given graph.
+    pub fn add_value_quad(&self, quad: ValueQuadTriple) -> io::Result<()> {
+        self.with_shard(move |s| s.add_value_quad(quad))
+    }
+
+    /// Add an id triple and put it in the given graph.
+    pub fn add_id_quad(&self, quad: IdQuadTriple) -> io::Result<()> {
+        self.with_shard(move |s| s.add_id_quad(quad))
+    }
+
+    /// Take a string triple out of the given graph, leaving the triple itself in place.
+    pub fn remove_value_quad(&self, quad: ValueQuadTriple) -> io::Result<()> {
+        self.with_shard(move |s| s.remove_value_quad(quad))
+    }
+
+    /// Take an id triple out of the given graph, leaving the triple itself in place.
+    pub fn remove_id_quad(&self, quad: IdQuadTriple) -> io::Result<()> {
+        self.with_shard(move |s| s.remove_id_quad(quad))
+    }
+}
+
 impl StoreLayerBuilder {
     async fn new(store: Store) -> io::Result<Self> {
         let builder = store.layer_store.create_base_layer().await?;
 
-        Ok(Self {
-            parent: builder.parent(),
-            name: builder.name(),
-            builder: Arc::new(RwLock::new(Some(builder))),
-            store,
-        })
+        Ok(Self::wrap(builder, store))
     }
 
-    fn wrap(builder: Box<dyn LayerBuilder>, store: Store) -> Self {
+    fn wrap(mut builder: Box<dyn LayerBuilder>, store: Store) -> Self {
+        builder.set_text_index(store.text_index);
         StoreLayerBuilder {
             parent: builder.parent(),
             name: builder.name(),
             builder: Arc::new(RwLock::new(Some(builder))),
+            shards: Arc::new(Mutex::new(Vec::new())),
             store,
         }
     }
@@ -112,6 +210,54 @@ impl StoreLayerBuilder {
         self.with_builder(move |b| b.remove_id_triple(triple))
     }
 
+    /// Add a string triple and put it in the given graph.
+    pub fn add_value_quad(&self, quad: ValueQuadTriple) -> Result<(), io::Error> {
+        self.with_builder(move |b| b.add_value_quad(quad))
+    }
+
+    /// Add an id triple and put it in the given graph.
+    pub fn add_id_quad(&self, quad: IdQuadTriple) -> Result<(), io::Error> {
+        self.with_builder(move |b| b.add_id_quad(quad))
+    }
+
+    /// Take a string triple out of the given graph, leaving the triple itself in place.
+    pub fn remove_value_quad(&self, quad: ValueQuadTriple) -> Result<(), io::Error> {
+        self.with_builder(move |b| b.remove_value_quad(quad))
+    }
+
+    /// Take an id triple out of the given graph, leaving the triple itself in place.
+    pub fn remove_id_quad(&self, quad: IdQuadTriple) -> Result<(), io::Error> {
+        self.with_builder(move |b| b.remove_id_quad(quad))
+    }
+
+    /// Create a new shard of this builder.
+    ///
+    /// Triples can be added to and removed from the shard
+    /// independently of this builder and its other shards. Each
+    /// thread should use its own shard.
+    pub fn shard(&self) -> io::Result<StoreLayerBuilderShard> {
+        // Holding the builder lock while registering the shard ensures
+        // that a commit either sees this shard or happens before it.
+        let builder = self
+            .builder
+            .read()
+            .expect("rwlock read should always succeed");
+        if builder.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "builder has already been committed",
+            ));
+        }
+
+        let shard = Arc::new(Mutex::new(Some(LayerBuilderShard::new(self.parent()))));
+        self.shards
+            .lock()
+            .expect("mutex lock should always succeed")
+            .push(shard.clone());
+
+        Ok(StoreLayerBuilderShard { shard })
+    }
+
     /// Returns true if this layer has been committed, and false otherwise.
     pub fn committed(&self) -> bool {
         self.builder
@@ -122,7 +268,13 @@ impl StoreLayerBuilder {
 
     /// Commit the layer to storage without loading the resulting layer.
     pub async fn commit_no_load(&self) -> io::Result<()> {
+        self.commit_no_load_with_progress(&Progress::default())
+            .await
+    }
+
+    async fn commit_no_load_with_progress(&self, progress: &Progress) -> io::Result<()> {
         let mut builder = None;
+        let shards: Vec<_>;
         {
             let mut guard = self
                 .builder
@@ -131,6 +283,24 @@ impl StoreLayerBuilder {
 
             // Setting the builder to None ensures that committed() detects we already committed (or tried to do so anyway)
             std::mem::swap(&mut builder, &mut guard);
+
+            // Taking the shards while still holding the builder lock
+            // ensures no shard gets created after this without
+            // noticing the builder was committed. Taking each shard out
+            // of its slot makes any further changes through its handle
+            // fail.
+            shards = self
+                .shards
+                .lock()
+                .expect("mutex lock should always succeed")
+                .drain(..)
+                .filter_map(|shard| {
+                    shard
+                        .lock()
+                        .expect("mutex lock should always succeed")
+                        .take()
+                })
+                .collect();
         }
 
         match builder {
@@ -140,9 +310,27 @@ impl StoreLayerBuilder {
                     "builder has already been committed",
                 ))
             }
-            Some(builder) => {
+            Some(mut builder) => {
                 let id = builder.name();
-                builder.commit_boxed().await?;
+                for shard in shards {
+                    if let Err(e) = builder.merge_shard(shard) {
+                        self.store.layer_store.discard_layer(id).await?;
+                        return Err(e);
+                    }
+                }
+
+                if !self.store.validators.is_empty() {
+                    let (additions, removals) = builder.value_triple_changes();
+                    let layer = PendingLayer::new(id, builder.parent(), additions, removals);
+                    if let Err(e) = validate_commit(&self.store.validators, &layer) {
+                        self.store.layer_store.discard_layer(id).await?;
+                        return Err(e);
+                    }
+                }
+                if let Err(e) = builder.commit_boxed_with_progress(progress.clone()).await {
+                    self.store.layer_store.discard_layer(id).await?;
+                    return Err(e);
+                }
                 self.store.layer_store.finalize_layer(id).await
             }
         }
@@ -150,14 +338,26 @@ impl StoreLayerBuilder {
 
     /// Commit the layer to storage.
     pub async fn commit(&self) -> io::Result<StoreLayer> {
+        self.commit_with_progress(&Progress::default()).await
+    }
+
+    /// Commit the layer to storage, reporting to and cancellable through `progress`.
+    ///
+    /// If the commit fails or is cancelled, the partially written
+    /// layer is discarded. The builder is considered committed either
+    /// way.
+    pub async fn commit_with_progress(&self, progress: &Progress) -> io::Result<StoreLayer> {
         let name = self.name;
-        self.commit_no_load().await?;
+        self.commit_no_load_with_progress(progress).await?;
 
         let layer = self.store.layer_store.get_layer(name).await?;
-        Ok(StoreLayer::wrap(
+        let layer = StoreLayer::wrap(
             layer.expect("layer that was just created was not found in store"),
             self.store.clone(),
-        ))
+        );
+        self.store.schedule_rollup(&layer);
+
+        Ok(layer)
     }
 
     /// Apply all triples added and removed by a layer to this builder.
@@ -169,59 +369,137 @@ impl StoreLayerBuilder {
         // first check what dictionary entries we don't know about, add those
         let triple_additions = delta.triple_additions().await?;
         let triple_removals = delta.triple_removals().await?;
-        rayon::join(
+        let (additions_result, removals_result) = rayon::join(
             move || {
-                triple_additions.par_bridge().for_each(|t| {
-                    delta
-                        .id_triple_to_string(&t)
-                        .map(|st| self.add_value_triple(st));
-                });
+                triple_additions.par_bridge().try_for_each_init(
+                    || self.shard(),
+                    |shard, t| {
+                        let shard = shard
+                            .as_ref()
+                            .map_err(|e| io::Error::new(e.kind(), e.to_string()))?;
+                        match delta.id_triple_to_string(&t) {
+                            Some(st) => shard.add_value_triple(st),
+                            None => Ok(()),
+                        }
+                    },
+                )
             },
             move || {
-                triple_removals.par_bridge().for_each(|t| {
-                    delta
-                        .id_triple_to_string(&t)
-                        .map(|st| self.remove_value_triple(st));
-                })
+                triple_removals.par_bridge().try_for_each_init(
+                    || self.shard(),
+                    |shard, t| {
+                        let shard = shard
+                            .as_ref()
+                            .map_err(|e| io::Error::new(e.kind(), e.to_string()))?;
+                        match delta.id_triple_to_string(&t) {
+                            Some(st) => shard.remove_value_triple(st),
+                            None => Ok(()),
+                        }
+                    },
+                )
             },
         );
 
-        Ok(())
+        additions_result?;
+        removals_result
     }
 
     /// Apply the changes required to change our parent layer into the given layer.
     pub fn apply_diff(&self, other: &StoreLayer) -> Result<(), io::Error> {
         // create a child builder and use it directly
         // first check what dictionary entries we don't know about, add those
-        rayon::join(
-            || {
-                if let Some(this) = self.parent() {
-                    this.triples().par_bridge().for_each(|t| {
+        let (removals_result, additions_result) = rayon::join(
+            || match self.parent() {
+                Some(this) => this.triples().par_bridge().try_for_each_init(
+                    || self.shard(),
+                    |shard, t| -> io::Result<()> {
+                        let shard = shard
+                            .as_ref()
+                            .map_err(|e| io::Error::new(e.kind(), e.to_string()))?;
                         if let Some(st) = this.id_triple_to_string(&t) {
                             if !other.value_triple_exists(&st) {
-                                self.remove_value_triple(st).unwrap()
+                                shard.remove_value_triple(st)?;
                             }
                         }
-                    })
-                };
+
+                        Ok(())
+                    },
+                ),
+                None => Ok(()),
             },
             || {
-                other.triples().par_bridge().for_each(|t| {
-                    if let Some(st) = other.id_triple_to_string(&t) {
-                        if let Some(this) = self.parent() {
-                            if !this.value_triple_exists(&st) {
-                                self.add_value_triple(st).unwrap()
+                other.triples().par_bridge().try_for_each_init(
+                    || self.shard(),
+                    |shard, t| -> io::Result<()> {
+                        let shard = shard
+                            .as_ref()
+                            .map_err(|e| io::Error::new(e.kind(), e.to_string()))?;
+                        if let Some(st) = other.id_triple_to_string(&t) {
+                            match self.parent() {
+                                Some(this) if this.value_triple_exists(&st) => {}
+                                _ => shard.add_value_triple(st)?,
                             }
-                        } else {
-                            self.add_value_triple(st).unwrap()
-                        };
-                    }
-                })
+                        }
+
+                        Ok(())
+                    },
+                )
             },
         );
 
-        Ok(())
+        removals_result?;
+        additions_result
+    }
+}
+
+/// The changes a single layer made to the triples of a subject, as returned by `StoreLayer::subject_history`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerSubjectChanges {
+    /// The name of the layer that made the changes.
+    pub layer: [u32; 5],
+    /// The triples with the subject that this layer added.
+    pub additions: Vec<IdTriple>,
+    /// The triples with the subject that this layer removed.
+    pub removals: Vec<IdTriple>,
+}
+
+/// A builder that undoes the changes of a layer, as created by `StoreLayer::open_revert`.
+pub struct Revert {
+    /// The builder containing the undone changes.
+    pub builder: StoreLayerBuilder,
+    /// Changes of the reverted layer that were changed again later, and are therefore not undone.
+    pub skipped: Vec<(TripleChange, ValueTriple)>,
+}
+
+/// Returns true if the given triple was added or removed in any of the given layers.
+async fn changed_in_any(layers: &[StoreLayer], triple: &ValueTriple) -> io::Result<bool> {
+    for layer in layers {
+        if let Some(t) = layer.value_triple_to_id(triple) {
+            if layer
+                .triple_addition_exists(t.subject, t.predicate, t.object)
+                .await?
+                || layer
+                    .triple_removal_exists(t.subject, t.predicate, t.object)
+                    .await?
+            {
+                return Ok(true);
+            }
+        }
     }
+
+    Ok(false)
+}
+
+/// A layer that was replayed on top of a new parent by `Store::rebase`.
+pub struct RebasedLayer {
+    /// The name of the layer that was replayed.
+    pub original: [u32; 5],
+    /// The newly built layer.
+    pub layer: StoreLayer,
+    /// Changes of the original layer that the new parent already contained.
+    pub noops: Vec<(TripleChange, ValueTriple)>,
+    /// Changes of the original layer that undo a change between the old and the new base.
+    pub conflicts: Vec<(TripleChange, ValueTriple)>,
 }
 
 /// A layer that keeps track of the store it came out of, allowing the creation of a layer builder on top of this layer.
@@ -238,12 +516,12 @@ impl StoreLayerBuilder {
 #[derive(Clone)]
 pub struct StoreLayer {
     // TODO this Arc here is not great
-    layer: Arc<dyn Layer>,
+    layer: Arc<InternalLayer>,
     store: Store,
 }
 
 impl StoreLayer {
-    fn wrap(layer: Arc<dyn Layer>, store: Store) -> Self {
+    fn wrap(layer: Arc<InternalLayer>, store: Store) -> Self {
         StoreLayer { layer, store }
     }
 
@@ -258,6 +536,73 @@ impl StoreLayer {
         Ok(StoreLayerBuilder::wrap(layer, self.store.clone()))
     }
 
+    /// Create a layer builder on top of this layer that undoes the changes of `target`.
+    ///
+    /// The builder removes what `target` added and adds back what
+    /// `target` removed. `target` has to be this layer or one of its
+    /// ancestors. Changes to triples that were added or removed again
+    /// by any layer after `target` are not undone. Instead, they are
+    /// reported as skipped, as undoing them would also undo those
+    /// later changes.
+    ///
+    /// Like with `open_write`, more changes can be made to the
+    /// returned builder before committing it.
+    pub async fn open_revert(&self, target: &StoreLayer) -> io::Result<Revert> {
+        let mut later = Vec::new();
+        if self.name() != target.name() {
+            if !self
+                .store
+                .layer_store
+                .layer_is_ancestor_of(self.name(), target.name())
+                .await?
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "layer to revert is not an ancestor of this layer",
+                ));
+            }
+
+            for name in self
+                .store
+                .layer_store
+                .retrieve_layer_stack_names_upto(self.name(), target.name())
+                .await?
+            {
+                later.push(
+                    self.store
+                        .get_layer_from_id(name)
+                        .await?
+                        .expect("layer in stack should exist"),
+                );
+            }
+        }
+
+        let builder = self.open_write().await?;
+        let mut skipped = Vec::new();
+        for triple in target.triple_additions().await? {
+            let triple = target
+                .id_triple_to_string(&triple)
+                .expect("added triple should resolve in its own layer");
+            if changed_in_any(&later, &triple).await? {
+                skipped.push((TripleChange::Addition, triple));
+            } else {
+                builder.remove_value_triple(triple)?;
+            }
+        }
+        for triple in target.triple_removals().await? {
+            let triple = target
+                .id_triple_to_string(&triple)
+                .expect("removed triple should resolve in its own layer");
+            if changed_in_any(&later, &triple).await? {
+                skipped.push((TripleChange::Removal, triple));
+            } else {
+                builder.add_value_triple(triple)?;
+            }
+        }
+
+        Ok(Revert { builder, skipped })
+    }
+
     /// Returns the parent of this layer, if any, or None if this layer has no parent.
     pub async fn parent(&self) -> io::Result<Option<StoreLayer>> {
         let parent_name = self.layer.parent_name();
@@ -274,14 +619,228 @@ impl StoreLayer {
         }
     }
 
+    /// Returns the ancestor `n` layers below this one, or None if the stack is not that deep.
+    ///
+    /// `ancestor(0)` returns this layer, and `ancestor(1)` returns its
+    /// parent. The returned layer reuses the layers that were already
+    /// loaded for this layer, rather than loading them from the store
+    /// again. Only layers that are hidden by a rollup are loaded.
+    pub async fn ancestor(&self, n: usize) -> io::Result<Option<StoreLayer>> {
+        let mut depth = 0;
+        self.find_ancestor(|_| {
+            let found = depth == n;
+            depth += 1;
+            found
+        })
+        .await
+    }
+
+    /// Returns the ancestor with the given name, or None if no layer in this stack has that name.
+    ///
+    /// Like `ancestor`, this reuses the layers that were already
+    /// loaded for this layer where possible.
+    pub async fn ancestor_by_name(&self, name: [u32; 5]) -> io::Result<Option<StoreLayer>> {
+        self.find_ancestor(|n| n == name).await
+    }
+
+    /// Walk down the stack, returning the first layer for which `found` returns true.
+    async fn find_ancestor<F: FnMut([u32; 5]) -> bool>(
+        &self,
+        mut found: F,
+    ) -> io::Result<Option<StoreLayer>> {
+        for (name, layer) in self.logical_stack().await? {
+            if found(name) {
+                return match layer {
+                    Some(layer) => Ok(Some(StoreLayer::wrap(layer, self.store.clone()))),
+                    None => self.store.get_layer_from_id(name).await,
+                };
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the names of all layers in this stack, from this layer down to the base layer.
+    ///
+    /// Each name comes with the layer as it was loaded for this
+    /// stack, or None if the layer is hidden by a rollup.
+    async fn logical_stack(&self) -> io::Result<Vec<([u32; 5], Option<Arc<InternalLayer>>)>> {
+        let mut result = Vec::new();
+        let mut layer = self.layer.clone();
+        loop {
+            result.push((layer.name(), Some(layer.clone())));
+
+            if layer.is_rollup() {
+                // the layers between a rollup and its immediate parent
+                // are not part of the loaded stack, so they have to
+                // be looked up separately.
+                let boundary = layer.immediate_parent().map(|p| p.name());
+                let mut current = layer.parent_name();
+                while current.is_some() && current != boundary {
+                    let name = current.unwrap();
+                    result.push((name, None));
+                    current = self.store.layer_store.get_layer_parent_name(name).await?;
+                }
+            }
+
+            match layer.immediate_parent_arc() {
+                Some(parent) => layer = parent.clone(),
+                None => return Ok(result),
+            }
+        }
+    }
+
+    /// Returns all changes to the given triple in this stack, from the oldest to the newest change.
+    ///
+    /// Each change is returned along with the name of the layer that
+    /// made it. Layers that are hidden by a rollup are consulted
+    /// through their original files.
+    pub async fn triple_history(
+        &self,
+        triple: &IdTriple,
+    ) -> io::Result<Vec<([u32; 5], TripleChange)>> {
+        let mut history = Vec::new();
+        for (name, layer) in self.logical_stack().await? {
+            let (added, removed) = match layer {
+                Some(layer) if !layer.is_rollup() => (
+                    layer.internal_triple_addition_exists(
+                        triple.subject,
+                        triple.predicate,
+                        triple.object,
+                    ),
+                    layer.internal_triple_removal_exists(
+                        triple.subject,
+                        triple.predicate,
+                        triple.object,
+                    ),
+                ),
+                _ => {
+                    let layer_store = &self.store.layer_store;
+                    (
+                        layer_store
+                            .triple_addition_exists(
+                                name,
+                                triple.subject,
+                                triple.predicate,
+                                triple.object,
+                            )
+                            .await?,
+                        layer_store
+                            .triple_removal_exists(
+                                name,
+                                triple.subject,
+                                triple.predicate,
+                                triple.object,
+                            )
+                            .await?,
+                    )
+                }
+            };
+
+            // a layer never both adds and removes the same triple
+            if added {
+                history.push((name, TripleChange::Addition));
+            } else if removed {
+                history.push((name, TripleChange::Removal));
+            }
+        }
+
+        history.reverse();
+        Ok(history)
+    }
+
+    /// Returns all changes to the given triple in this stack, from the oldest to the newest change.
+    ///
+    /// See `triple_history`. If the triple cannot be resolved in this
+    /// layer, it was never added, and the history is empty.
+    pub async fn value_triple_history(
+        &self,
+        triple: &ValueTriple,
+    ) -> io::Result<Vec<([u32; 5], TripleChange)>> {
+        match self.value_triple_to_id(triple) {
+            Some(triple) => self.triple_history(&triple).await,
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Returns the triples with the given subject that were added or removed in this stack.
+    ///
+    /// The changes are grouped by layer, from the oldest to the newest
+    /// layer. Layers that did not change any triple with this subject
+    /// are left out. Layers that are hidden by a rollup are consulted
+    /// through their original files.
+    pub async fn subject_history(&self, subject: u64) -> io::Result<Vec<LayerSubjectChanges>> {
+        let mut history = Vec::new();
+        for (name, layer) in self.logical_stack().await? {
+            let (additions, removals) = match layer {
+                Some(layer) if !layer.is_rollup() => (
+                    layer.internal_triple_additions_s(subject).collect(),
+                    layer.internal_triple_removals_s(subject).collect(),
+                ),
+                _ => {
+                    let layer_store = &self.store.layer_store;
+                    (
+                        layer_store
+                            .triple_additions_s(name, subject)
+                            .await?
+                            .collect(),
+                        layer_store
+                            .triple_removals_s(name, subject)
+                            .await?
+                            .collect(),
+                    )
+                }
+            };
+
+            let changes = LayerSubjectChanges {
+                layer: name,
+                additions,
+                removals,
+            };
+            if !changes.additions.is_empty() || !changes.removals.is_empty() {
+                history.push(changes);
+            }
+        }
+
+        history.reverse();
+        Ok(history)
+    }
+
+    /// Returns the rollup registered for this layer, if any.
+    pub async fn rollup_info(&self) -> io::Result<Option<RollupInfo>> {
+        self.store.layer_rollup_info(self.name()).await
+    }
+
+    /// Returns the rollups registered for this layer and all its ancestors, starting at this layer.
+    pub async fn rollups(&self) -> io::Result<Vec<RollupInfo>> {
+        let mut result = Vec::new();
+        for (name, _) in self.logical_stack().await? {
+            if let Some(info) = self.store.layer_rollup_info(name).await? {
+                result.push(info);
+            }
+        }
+
+        Ok(result)
+    }
+
     pub async fn squash_upto(&self, upto: &StoreLayer) -> io::Result<StoreLayer> {
+        self.squash_upto_with_progress(upto, &Progress::default())
+            .await
+    }
+
+    /// Like `squash_upto`, reporting to and cancellable through `progress`.
+    pub async fn squash_upto_with_progress(
+        &self,
+        upto: &StoreLayer,
+        progress: &Progress,
+    ) -> io::Result<StoreLayer> {
         let layer_opt = self.store.layer_store.get_layer(self.name()).await?;
         let layer =
             layer_opt.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "layer not found"))?;
         let name = self
             .store
             .layer_store
-            .squash_upto(layer, upto.name())
+            .squash_upto_with_progress(layer, upto.name(), progress)
             .await?;
         Ok(self
             .store
@@ -310,6 +869,68 @@ impl StoreLayer {
             .expect("layer that was just created doesn't exist"))
     }
 
+    /// Like `squash`, keeping memory use bounded by writing intermediate results to `temp_dir`.
+    ///
+    /// This is slower than `squash`, but works for stacks whose
+    /// dictionaries and triples don't fit in memory.
+    pub async fn squash_external(&self, temp_dir: &Path) -> io::Result<StoreLayer> {
+        self.squash_external_with_progress(temp_dir, &Progress::default())
+            .await
+    }
+
+    /// Like `squash_external`, reporting to and cancellable through `progress`.
+    pub async fn squash_external_with_progress(
+        &self,
+        temp_dir: &Path,
+        progress: &Progress,
+    ) -> io::Result<StoreLayer> {
+        let layer_opt = self.store.layer_store.get_layer(self.name()).await?;
+        let layer =
+            layer_opt.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "layer not found"))?;
+        let name = self
+            .store
+            .layer_store
+            .squash_external_with_progress(layer, temp_dir, progress)
+            .await?;
+        Ok(self
+            .store
+            .get_layer_from_id(name)
+            .await?
+            .expect("layer that was just created doesn't exist"))
+    }
+
+    /// Like `squash_upto`, keeping memory use bounded by writing intermediate results to `temp_dir`.
+    pub async fn squash_upto_external(
+        &self,
+        upto: &StoreLayer,
+        temp_dir: &Path,
+    ) -> io::Result<StoreLayer> {
+        self.squash_upto_external_with_progress(upto, temp_dir, &Progress::default())
+            .await
+    }
+
+    /// Like `squash_upto_external`, reporting to and cancellable through `progress`.
+    pub async fn squash_upto_external_with_progress(
+        &self,
+        upto: &StoreLayer,
+        temp_dir: &Path,
+        progress: &Progress,
+    ) -> io::Result<StoreLayer> {
+        let layer_opt = self.store.layer_store.get_layer(self.name()).await?;
+        let layer =
+            layer_opt.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "layer not found"))?;
+        let name = self
+            .store
+            .layer_store
+            .squash_upto_external_with_progress(layer, upto.name(), temp_dir, progress)
+            .await?;
+        Ok(self
+            .store
+            .get_layer_from_id(name)
+            .await?
+            .expect("layer that was just created doesn't exist"))
+    }
+
     /// Create a new rollup layer which rolls up all triples in this layer, as well as all its ancestors.
     ///
     /// It is a good idea to keep layer stacks small, meaning, to only
@@ -346,6 +967,24 @@ impl StoreLayer {
         Ok(())
     }
 
+    /// Like `rollup_upto`, reporting to and cancellable through `progress`.
+    ///
+    /// A cancelled rollup is not registered.
+    pub async fn rollup_upto_with_progress(
+        &self,
+        upto: &StoreLayer,
+        progress: &Progress,
+    ) -> io::Result<()> {
+        let layer_opt = self.store.layer_store.get_layer(self.name()).await?;
+        let layer =
+            layer_opt.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "label not found"))?;
+        self.store
+            .layer_store
+            .rollup_upto_with_progress(layer, upto.name(), progress)
+            .await?;
+        Ok(())
+    }
+
     /// Like rollup_upto, rolls up upto the given layer. However, if
     /// this layer is a rollup layer, this will roll up upto that
     /// rollup.
@@ -420,6 +1059,20 @@ impl StoreLayer {
         Ok(Box::new(result) as Box<dyn Iterator<Item = _> + Send>)
     }
 
+    /// Returns a future that yields a stream over all layer additions.
+    ///
+    /// The stream yields to the runtime in between chunks of triples.
+    pub async fn triple_additions_stream(&self) -> io::Result<IdTripleStream> {
+        Ok(IdTripleStream::new(self.triple_additions().await?))
+    }
+
+    /// Returns a future that yields a stream over all layer removals.
+    ///
+    /// The stream yields to the runtime in between chunks of triples.
+    pub async fn triple_removals_stream(&self) -> io::Result<IdTripleStream> {
+        Ok(IdTripleStream::new(self.triple_removals().await?))
+    }
+
     /// Returns a future that yields an iterator over all layer additions that share a particular subject.
     ///
     /// Since this operation will involve io when this layer is a
@@ -534,6 +1187,34 @@ impl StoreLayer {
             .await
     }
 
+    /// Returns a future that yields an iterator over the layer additions matching the cursor's pattern that come after its last triple.
+    ///
+    /// Since this operation will involve io when this layer is a
+    /// rollup layer, io errors may occur.
+    pub async fn triple_additions_from_cursor(
+        &self,
+        cursor: &TripleCursor,
+    ) -> io::Result<TripleCursorIterator> {
+        self.store
+            .layer_store
+            .triple_additions_from_cursor(self.layer.name(), cursor)
+            .await
+    }
+
+    /// Returns a future that yields an iterator over the layer removals matching the cursor's pattern that come after its last triple.
+    ///
+    /// Since this operation will involve io when this layer is a
+    /// rollup layer, io errors may occur.
+    pub async fn triple_removals_from_cursor(
+        &self,
+        cursor: &TripleCursor,
+    ) -> io::Result<TripleCursorIterator> {
+        self.store
+            .layer_store
+            .triple_removals_from_cursor(self.layer.name(), cursor)
+            .await
+    }
+
     /// Returns a future that yields the amount of triples that this layer adds.
     ///
     /// Since this operation will involve io when this layer is a
@@ -556,10 +1237,38 @@ impl StoreLayer {
             .await
     }
 
-    /// Returns a future that yields a vector of layer stack names describing the history of this layer, starting from the base layer up to and including the name of this layer itself.
-    pub async fn retrieve_layer_stack_names(&self) -> io::Result<Vec<[u32; 5]>> {
-        self.store
-            .layer_store
+    /// Returns a future that yields the amount of triples that this layer adds which match the pattern.
+    ///
+    /// Since this operation will involve io when this layer is a
+    /// rollup layer, io errors may occur.
+    pub async fn triple_layer_addition_count_matching(
+        &self,
+        pattern: TriplePattern,
+    ) -> io::Result<usize> {
+        self.store
+            .layer_store
+            .triple_layer_addition_count_matching(self.layer.name(), pattern)
+            .await
+    }
+
+    /// Returns a future that yields the amount of triples that this layer removes which match the pattern.
+    ///
+    /// Since this operation will involve io when this layer is a
+    /// rollup layer, io errors may occur.
+    pub async fn triple_layer_removal_count_matching(
+        &self,
+        pattern: TriplePattern,
+    ) -> io::Result<usize> {
+        self.store
+            .layer_store
+            .triple_layer_removal_count_matching(self.layer.name(), pattern)
+            .await
+    }
+
+    /// Returns a future that yields a vector of layer stack names describing the history of this layer, starting from the base layer up to and including the name of this layer itself.
+    pub async fn retrieve_layer_stack_names(&self) -> io::Result<Vec<[u32; 5]>> {
+        self.store
+            .layer_store
             .retrieve_layer_stack_names(self.name())
             .await
     }
@@ -652,6 +1361,10 @@ impl Layer for StoreLayer {
         self.layer.triples_o(object)
     }
 
+    fn triples_from_cursor(&self, cursor: &TripleCursor) -> TripleCursorIterator {
+        self.layer.triples_from_cursor(cursor)
+    }
+
     fn clone_boxed(&self) -> Box<dyn Layer> {
         Box::new(self.clone())
     }
@@ -664,6 +1377,22 @@ impl Layer for StoreLayer {
         self.layer.triple_removal_count()
     }
 
+    fn count_s(&self, subject: u64) -> usize {
+        self.layer.count_s(subject)
+    }
+
+    fn count_sp(&self, subject: u64, predicate: u64) -> usize {
+        self.layer.count_sp(subject, predicate)
+    }
+
+    fn count_p(&self, predicate: u64) -> usize {
+        self.layer.count_p(predicate)
+    }
+
+    fn count_o(&self, object: u64) -> usize {
+        self.layer.count_o(object)
+    }
+
     fn all_counts(&self) -> LayerCounts {
         self.layer.all_counts()
     }
@@ -671,6 +1400,26 @@ impl Layer for StoreLayer {
     fn single_triple_sp(&self, subject: u64, predicate: u64) -> Option<IdTriple> {
         self.layer.single_triple_sp(subject, predicate)
     }
+
+    fn quads_g(&self, graph: u64) -> Box<dyn Iterator<Item = IdQuadTriple> + Send> {
+        self.layer.quads_g(graph)
+    }
+
+    fn triple_graphs(&self, triple: &IdTriple) -> Vec<u64> {
+        self.layer.triple_graphs(triple)
+    }
+
+    fn object_value_ids_in_range(
+        &self,
+        from: &TypedDictEntry,
+        to: &TypedDictEntry,
+    ) -> Box<dyn Iterator<Item = u64> + Send> {
+        self.layer.object_value_ids_in_range(from, to)
+    }
+
+    fn search_text(&self, query: &str) -> Box<dyn Iterator<Item = u64> + Send> {
+        self.layer.search_text(query)
+    }
 }
 
 /// A named graph in terminus-store.
@@ -751,12 +1500,17 @@ impl NamedGraph {
         };
 
         if set_is_ok {
-            Ok(self
+            let was_set = self
                 .store
                 .label_store
                 .set_label(&label, layer_name)
                 .await?
-                .is_some())
+                .is_some();
+            if was_set {
+                self.store.schedule_rollup(layer);
+            }
+
+            Ok(was_set)
         } else {
             Ok(false)
         }
@@ -818,6 +1572,29 @@ impl NamedGraph {
     pub async fn delete(&self) -> io::Result<()> {
         self.store.delete(&self.label).await.map(|_| ())
     }
+
+    /// Watch this database label, yielding a change every time it is set.
+    ///
+    /// Every change carries the layer the label pointed at before as
+    /// well as the new label, so subscribers can look at the delta
+    /// between them directly. Changes made in quick succession may be
+    /// reported as a single change. The stream ends when the label is
+    /// deleted.
+    pub async fn watch(&self) -> io::Result<impl Stream<Item = LabelChange> + Send> {
+        // start watching before reading the label, so no change can slip through in between
+        let labels = match self.store.label_store.watch_label(&self.label).await? {
+            Some(labels) => labels,
+            None => watch::poll_label(self.store.label_store.clone(), self.label.clone()),
+        };
+        let current = self
+            .store
+            .label_store
+            .get_label(&self.label)
+            .await?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "database not found"))?;
+
+        Ok(watch::label_changes(current, labels))
+    }
 }
 
 impl Store {
@@ -829,6 +1606,170 @@ impl Store {
         Store {
             label_store: Arc::new(label_store),
             layer_store: Arc::new(layer_store),
+            rollups: None,
+            validators: Vec::new(),
+            text_index: false,
+            version_check: None,
+        }
+    }
+
+    /// Check the version of the store on disk the first time the store is accessed.
+    fn with_version_check(mut self, path: PathBuf, kind: StoreKind) -> Store {
+        self.version_check = Some(Arc::new(VersionCheck::new(path, kind)));
+        self
+    }
+
+    /// Check the version of the store on disk, if this store has one.
+    ///
+    /// The check only happens once. Until it succeeds, it is repeated
+    /// on every access.
+    async fn check_version(&self) -> io::Result<()> {
+        match &self.version_check {
+            Some(check) => check.check().await,
+            None => Ok(()),
+        }
+    }
+
+    /// Roll up layer stacks automatically according to the given policy.
+    ///
+    /// Whenever a layer is committed or set as the head of a
+    /// database, its stack is checked against the policy, and rolled
+    /// up in the background if needed. This requires a tokio
+    /// runtime. Without one, the policy is only applied through
+    /// `apply_rollup_policy`.
+    pub fn with_rollup_policy(mut self, policy: RollupPolicy) -> Store {
+        self.rollups = Some(Arc::new(RollupScheduler::new(policy)));
+        self
+    }
+
+    /// Check every layer committed through a builder with the given validator.
+    ///
+    /// Validators run in the order they were added, before the layer
+    /// is written. If any of them reports a violation, the commit
+    /// fails with a `ValidationError` and the layer is discarded.
+    /// Layers that are not built through a `StoreLayerBuilder`, such
+    /// as rollups, merges and bulk loads, are not validated.
+    pub fn with_commit_validator<V: 'static + CommitValidator>(mut self, validator: V) -> Store {
+        self.validators.push(Arc::new(validator));
+        self
+    }
+
+    /// Write a text index for every layer committed through a builder.
+    ///
+    /// Without a text index, `search_text` scans the value
+    /// dictionaries of the stack instead. Rollups and squashes keep
+    /// the index if the layers they are built from have one, merges
+    /// if any of their inputs has one. Bulk loaded layers have none.
+    pub fn with_text_index(mut self, enabled: bool) -> Store {
+        self.text_index = enabled;
+        self
+    }
+
+    /// Returns the rollup policy of this store, if it has one.
+    pub fn rollup_policy(&self) -> Option<&RollupPolicy> {
+        self.rollups.as_ref().map(|r| r.policy())
+    }
+
+    /// Roll up the given layer if its stack exceeds the rollup policy, returning whether it was rolled up.
+    ///
+    /// The layer is rolled up to the nearest rollup or base layer
+    /// below it, so only the changes made since then are rolled up.
+    ///
+    /// This returns false without doing anything if the store has no
+    /// rollup policy, or if a rollup of this layer is already running.
+    pub async fn apply_rollup_policy(&self, layer: &StoreLayer) -> io::Result<bool> {
+        let rollups = match &self.rollups {
+            Some(rollups) => rollups,
+            None => return Ok(false),
+        };
+        let upto = match rollups.policy().rollup_target(&layer.layer) {
+            Some(upto) => upto,
+            None => return Ok(false),
+        };
+        if !rollups.start(layer.name()) {
+            return Ok(false);
+        }
+
+        let result = self
+            .layer_store
+            .rollup_upto(layer.layer.clone(), upto)
+            .await;
+        rollups.finish(layer.name());
+
+        result.map(|_| true)
+    }
+
+    /// Returns metadata about the given layer, or None if it does not exist.
+    pub async fn layer_info(&self, layer: [u32; 5]) -> io::Result<Option<LayerInfo>> {
+        self.check_version().await?;
+        self.layer_store.layer_info(layer).await
+    }
+
+    /// Returns metadata about every layer in this store, ordered by name.
+    pub async fn layers_info(&self) -> io::Result<Vec<LayerInfo>> {
+        self.check_version().await?;
+        self.layer_store.layers_info().await
+    }
+
+    /// Copy every layer and label of this store into the target store.
+    ///
+    /// Layers keep their names, parents and rollups, and labels keep
+    /// their versions. Layers that are already in the target store are
+    /// not copied again, so an interrupted copy can be resumed by
+    /// calling this again.
+    pub async fn copy_into(&self, target: &Store, options: CopyOptions) -> io::Result<CopyStats> {
+        self.check_version().await?;
+        target.check_version().await?;
+        copy::copy_store(self, target, &options).await
+    }
+
+    /// Returns the rollup registered for the given layer, if any.
+    pub async fn layer_rollup_info(&self, layer: [u32; 5]) -> io::Result<Option<RollupInfo>> {
+        self.check_version().await?;
+        match self.layer_store.get_layer_rollup(layer).await? {
+            None => Ok(None),
+            Some(rollup) => Ok(Some(RollupInfo {
+                layer,
+                rollup,
+                upto: self.layer_store.get_layer_parent_name(rollup).await?,
+            })),
+        }
+    }
+
+    /// Check the given layer against the rollup policy in a background task.
+    fn schedule_rollup(&self, layer: &StoreLayer) {
+        if let Some(rollups) = &self.rollups {
+            if !rollups.policy().should_roll_up(&layer.layer) {
+                return;
+            }
+
+            if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+                let store = self.clone();
+                let layer = layer.clone();
+                let task = runtime.spawn(async move {
+                    if let Err(e) = store.apply_rollup_policy(&layer).await {
+                        tracing::warn!(
+                            layer = %name_to_string(layer.name()),
+                            "automatic rollup failed: {}",
+                            e
+                        );
+                    }
+                });
+                rollups.spawned(task);
+            }
+        }
+    }
+
+    /// Wait for all rollups that the rollup policy scheduled in the background so far to finish.
+    ///
+    /// Failed rollups are logged rather than returned, as they are when nobody waits for them.
+    pub async fn wait_for_scheduled_rollups(&self) {
+        if let Some(rollups) = &self.rollups {
+            for task in rollups.take_tasks() {
+                if let Err(e) = task.await {
+                    tracing::warn!("automatic rollup task failed: {}", e);
+                }
+            }
         }
     }
 
@@ -836,12 +1777,14 @@ impl Store {
     ///
     /// If the database already exists, this will return an error.
     pub async fn create(&self, label: &str) -> io::Result<NamedGraph> {
+        self.check_version().await?;
         let label = self.label_store.create_label(label).await?;
         Ok(NamedGraph::new(label.name, self.clone()))
     }
 
     /// Open an existing database with the given name, or None if it does not exist.
     pub async fn open(&self, label: &str) -> io::Result<Option<NamedGraph>> {
+        self.check_version().await?;
         let label = self.label_store.get_label(label).await?;
         Ok(label.map(|label| NamedGraph::new(label.name, self.clone())))
     }
@@ -849,17 +1792,20 @@ impl Store {
     /// Delete an existing database with the given name. Returns true if this database was deleted
     /// and false otherwise.
     pub async fn delete(&self, label: &str) -> io::Result<bool> {
+        self.check_version().await?;
         self.label_store.delete_label(label).await
     }
 
     /// Return list of names of all existing databases.
     pub async fn labels(&self) -> io::Result<Vec<String>> {
+        self.check_version().await?;
         let labels = self.label_store.labels().await?;
         Ok(labels.iter().map(|label| label.name.to_string()).collect())
     }
 
     /// Retrieve a layer with the given name from the layer store this Store was initialized with.
     pub async fn get_layer_from_id(&self, layer: [u32; 5]) -> io::Result<Option<StoreLayer>> {
+        self.check_version().await?;
         let layer = self.layer_store.get_layer(layer).await?;
         Ok(layer.map(|layer| StoreLayer::wrap(layer, self.clone())))
     }
@@ -868,6 +1814,7 @@ impl Store {
     ///
     /// After having committed it, use `set_head` on a `NamedGraph` to attach it.
     pub async fn create_base_layer(&self) -> io::Result<StoreLayerBuilder> {
+        self.check_version().await?;
         StoreLayerBuilder::new(self.clone()).await
     }
 
@@ -876,14 +1823,260 @@ impl Store {
         layers: &[[u32; 5]],
         temp_dir: &Path,
     ) -> io::Result<[u32; 5]> {
+        self.check_version().await?;
         self.layer_store.merge_base_layer(layers, temp_dir).await
     }
 
+    /// Like `merge_base_layers`, reporting to and cancellable through `progress`.
+    ///
+    /// A cancelled merge removes its temporary directory and does not
+    /// leave a merged layer behind.
+    pub async fn merge_base_layers_with_progress(
+        &self,
+        layers: &[[u32; 5]],
+        temp_dir: &Path,
+        progress: &Progress,
+    ) -> io::Result<[u32; 5]> {
+        self.check_version().await?;
+        self.layer_store
+            .merge_base_layer_with_progress(layers, temp_dir, progress)
+            .await
+    }
+
+    /// Merge several child layers of the same parent into a single child layer of that parent.
+    ///
+    /// The additions and removals of all given layers are combined
+    /// into one new layer, whose name is returned. This is useful
+    /// when multiple workers independently produce changes on top of
+    /// the same layer.
+    ///
+    /// Each of the given layers only adds triples that are not in the
+    /// parent, and only removes triples that are. So if one layer
+    /// removes a triple that another layer tried to add again, the
+    /// removal wins, as that addition was a no-op against the
+    /// parent. Likewise, if one layer adds a triple that another
+    /// layer tried to remove, the addition wins. In other words, the
+    /// change that had an actual effect on top of the parent always
+    /// wins, regardless of the order of the layers.
+    ///
+    /// The combined changes are collected in memory before being
+    /// written, just like with a layer builder.
+    pub async fn merge_child_layers(
+        &self,
+        parent: [u32; 5],
+        layers: &[[u32; 5]],
+    ) -> io::Result<[u32; 5]> {
+        let parent_layer = self
+            .get_layer_from_id(parent)
+            .await?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "parent layer not found"))?;
+
+        let mut children = Vec::with_capacity(layers.len());
+        for name in layers {
+            let layer = self.get_layer_from_id(*name).await?.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("layer not found: {}", name_to_string(*name)),
+                )
+            })?;
+            if layer.parent_name() != Some(parent) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "given layer is not a child of the given parent: {}",
+                        name_to_string(*name)
+                    ),
+                ));
+            }
+            children.push(layer);
+        }
+
+        let builder = parent_layer.open_write().await?;
+        for layer in children {
+            // ids of new nodes and values are local to each layer, so
+            // the triples have to go through their strings
+            for triple in layer.triple_additions().await? {
+                let triple = layer
+                    .id_triple_to_string(&triple)
+                    .expect("added triple should resolve in its own layer");
+                builder.add_value_triple(triple)?;
+            }
+            for triple in layer.triple_removals().await? {
+                let triple = layer
+                    .id_triple_to_string(&triple)
+                    .expect("removed triple should resolve in its own layer");
+                builder.remove_value_triple(triple)?;
+            }
+        }
+
+        let name = builder.name();
+        builder.commit_no_load().await?;
+
+        Ok(name)
+    }
+
+    /// Replay the layers between `old_base` and `stack_top` on top of `new_base`.
+    ///
+    /// Each layer above `old_base`, up to and including `stack_top`,
+    /// is replayed as a new layer. The first one is built on top of
+    /// `new_base`, and every next one on top of the layer replayed
+    /// before it. The results are returned in the same order, from
+    /// bottom to top. The last returned layer is the new top of the
+    /// stack.
+    ///
+    /// For each replayed layer, the result reports which of its
+    /// changes became no-ops, because the new parent already
+    /// contained the addition or removal. It also reports which
+    /// changes conflict with the changes between `old_base` and
+    /// `new_base`, meaning that the triple was added or removed on
+    /// that side, but is changed back by the replayed layer.
+    /// Conflicting changes are still applied.
+    ///
+    /// `old_base` has to be an ancestor of `stack_top`.
+    pub async fn rebase(
+        &self,
+        stack_top: &StoreLayer,
+        old_base: &StoreLayer,
+        new_base: &StoreLayer,
+    ) -> io::Result<Vec<RebasedLayer>> {
+        if stack_top.name() == old_base.name() {
+            return Ok(Vec::new());
+        }
+        if !self
+            .layer_store
+            .layer_is_ancestor_of(stack_top.name(), old_base.name())
+            .await?
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "old base is not an ancestor of the stack top",
+            ));
+        }
+
+        let names = self
+            .layer_store
+            .retrieve_layer_stack_names_upto(stack_top.name(), old_base.name())
+            .await?;
+
+        let changed_by_other_side = |triple: &ValueTriple| {
+            old_base.value_triple_exists(triple) != new_base.value_triple_exists(triple)
+        };
+
+        let mut result: Vec<RebasedLayer> = Vec::with_capacity(names.len());
+        for name in names {
+            let original = self
+                .get_layer_from_id(name)
+                .await?
+                .expect("layer in stack should exist");
+            let parent = result.last().map(|r| &r.layer).unwrap_or(new_base);
+            let builder = parent.open_write().await?;
+            let mut noops = Vec::new();
+            let mut conflicts = Vec::new();
+
+            for triple in original.triple_additions().await? {
+                let triple = original
+                    .id_triple_to_string(&triple)
+                    .expect("added triple should resolve in its own layer");
+                if parent.value_triple_exists(&triple) {
+                    noops.push((TripleChange::Addition, triple));
+                } else {
+                    if changed_by_other_side(&triple) {
+                        conflicts.push((TripleChange::Addition, triple.clone()));
+                    }
+                    builder.add_value_triple(triple)?;
+                }
+            }
+            for triple in original.triple_removals().await? {
+                let triple = original
+                    .id_triple_to_string(&triple)
+                    .expect("removed triple should resolve in its own layer");
+                if !parent.value_triple_exists(&triple) {
+                    noops.push((TripleChange::Removal, triple));
+                } else {
+                    if changed_by_other_side(&triple) {
+                        conflicts.push((TripleChange::Removal, triple.clone()));
+                    }
+                    builder.remove_value_triple(triple)?;
+                }
+            }
+
+            let layer = builder.commit().await?;
+            result.push(RebasedLayer {
+                original: name,
+                layer,
+                noops,
+                conflicts,
+            });
+        }
+
+        Ok(result)
+    }
+
+    /// Create a base layer out of a stream of triples, without keeping all of them in memory.
+    ///
+    /// Unlike a layer builder, which keeps all added triples and
+    /// strings in memory until commit, this sorts bounded runs of
+    /// triples and spills them to `temp_dir`. The runs are then merged
+    /// into a new base layer, whose name is returned.
+    pub async fn bulk_load_base_layer<S: Stream<Item = ValueTriple> + Send>(
+        &self,
+        triples: S,
+        temp_dir: &Path,
+    ) -> io::Result<[u32; 5]> {
+        self.check_version().await?;
+        self.layer_store
+            .bulk_load_base_layer(triples.boxed(), temp_dir)
+            .await
+    }
+
+    /// Like `bulk_load_base_layer`, reporting to and cancellable through `progress`.
+    pub async fn bulk_load_base_layer_with_progress<S: Stream<Item = ValueTriple> + Send>(
+        &self,
+        triples: S,
+        temp_dir: &Path,
+        progress: &Progress,
+    ) -> io::Result<[u32; 5]> {
+        self.check_version().await?;
+        self.layer_store
+            .bulk_load_base_layer_with_progress(triples.boxed(), temp_dir, progress)
+            .await
+    }
+
+    /// Create a base layer out of a stream of quads, without keeping all of them in memory.
+    ///
+    /// Like `bulk_load_base_layer`, but also puts every triple in the graph of its quad.
+    pub async fn bulk_load_base_layer_quads<S: Stream<Item = ValueQuadTriple> + Send>(
+        &self,
+        quads: S,
+        temp_dir: &Path,
+    ) -> io::Result<[u32; 5]> {
+        self.check_version().await?;
+        self.layer_store
+            .bulk_load_base_layer_quads(quads.boxed(), temp_dir)
+            .await
+    }
+
+    /// Like `bulk_load_base_layer_quads`, reporting to and cancellable through `progress`.
+    pub async fn bulk_load_base_layer_quads_with_progress<
+        S: Stream<Item = ValueQuadTriple> + Send,
+    >(
+        &self,
+        quads: S,
+        temp_dir: &Path,
+        progress: &Progress,
+    ) -> io::Result<[u32; 5]> {
+        self.check_version().await?;
+        self.layer_store
+            .bulk_load_base_layer_quads_with_progress(quads.boxed(), temp_dir, progress)
+            .await
+    }
+
     /// Export the given layers by creating a pack, a Vec<u8> that can later be used with `import_layers` on a different store.
     pub async fn export_layers(
         &self,
         layer_ids: Box<dyn Iterator<Item = [u32; 5]> + Send>,
     ) -> io::Result<Vec<u8>> {
+        self.check_version().await?;
         self.layer_store.export_layers(layer_ids).await
     }
 
@@ -897,8 +2090,32 @@ impl Store {
         pack: &'a [u8],
         layer_ids: Box<dyn Iterator<Item = [u32; 5]> + Send>,
     ) -> io::Result<()> {
+        self.check_version().await?;
         self.layer_store.import_layers(pack, layer_ids).await
     }
+
+    /// Return a snapshot of the cache and layer loading metrics of this store.
+    pub async fn metrics(&self) -> StoreMetrics {
+        self.layer_store.metrics().await
+    }
+
+    /// Periodically push a snapshot of the metrics of this store into the given hook.
+    ///
+    /// The reporter runs as a background task until the returned handle is aborted.
+    pub fn spawn_metrics_reporter<H: MetricsHook>(
+        &self,
+        hook: H,
+        interval: std::time::Duration,
+    ) -> tokio::task::JoinHandle<()> {
+        let layer_store = self.layer_store.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                hook.report(&layer_store.metrics().await);
+            }
+        })
+    }
 }
 
 /// Open a store that is entirely in memory.
@@ -916,6 +2133,11 @@ pub fn open_memory_store() -> Store {
 /// cache_size specifies in megabytes how large the LRU cache should
 /// be. Loaded layers will stick around in the LRU cache to speed up
 /// subsequent loads.
+///
+/// The store version is checked the first time the store is
+/// accessed, so opening a directory store or a store in a format this
+/// version can't read fails on first use. Use
+/// `open_archive_store_checked` to check it right away.
 pub fn open_archive_store<P: Into<PathBuf>>(path: P, cache_size: usize) -> Store {
     let p = path.into();
     let directory_archive_backend = DirectoryArchiveBackend::new(p.clone());
@@ -925,41 +2147,90 @@ pub fn open_archive_store<P: Into<PathBuf>>(path: P, cache_size: usize) -> Store
         cache_size,
     );
     Store::new(
-        DirectoryLabelStore::new(p),
+        DirectoryLabelStore::new(p.clone()),
         CachedLayerStore::new(
             ArchiveLayerStore::new(archive_backend.clone(), archive_backend),
             LockingHashMapLayerCache::new(),
         ),
     )
+    .with_version_check(p, StoreKind::Archive)
 }
 
 /// Open a store that stores its data in the given directory as archive files.
 ///
-/// This version doesn't use lru caching.
+/// This version doesn't use lru caching. Like `open_archive_store`,
+/// the store version is checked on first use.
 pub fn open_raw_archive_store<P: Into<PathBuf>>(path: P) -> Store {
     let p = path.into();
     let archive_backend = DirectoryArchiveBackend::new(p.clone());
     Store::new(
-        DirectoryLabelStore::new(p),
+        DirectoryLabelStore::new(p.clone()),
         CachedLayerStore::new(
             ArchiveLayerStore::new(archive_backend.clone(), archive_backend),
             LockingHashMapLayerCache::new(),
         ),
     )
+    .with_version_check(p, StoreKind::Archive)
 }
 
 /// Open a store that stores its data in the given directory.
+///
+/// The store version is checked the first time the store is
+/// accessed, so opening an archive store or a store in a format this
+/// version can't read fails on first use. Use
+/// `open_directory_store_checked` to check it right away.
 pub fn open_directory_store<P: Into<PathBuf>>(path: P) -> Store {
     let p = path.into();
     Store::new(
         DirectoryLabelStore::new(p.clone()),
-        CachedLayerStore::new(DirectoryLayerStore::new(p), LockingHashMapLayerCache::new()),
+        CachedLayerStore::new(
+            DirectoryLayerStore::new(p.clone()),
+            LockingHashMapLayerCache::new(),
+        ),
     )
+    .with_version_check(p, StoreKind::Directory)
+}
+
+/// Open an archive store like `open_archive_store`, after checking the store version.
+///
+/// The directory is created if it doesn't exist yet. Returns an error
+/// if the directory holds a directory store, or a store in a format
+/// this version of terminus-store can't read.
+pub async fn open_archive_store_checked<P: Into<PathBuf>>(
+    path: P,
+    cache_size: usize,
+) -> io::Result<Store> {
+    let store = open_archive_store(path, cache_size);
+    store.check_version().await?;
+
+    Ok(store)
+}
+
+/// Open an archive store like `open_raw_archive_store`, after checking the store version.
+pub async fn open_raw_archive_store_checked<P: Into<PathBuf>>(path: P) -> io::Result<Store> {
+    let store = open_raw_archive_store(path);
+    store.check_version().await?;
+
+    Ok(store)
+}
+
+/// Open a directory store like `open_directory_store`, after checking the store version.
+///
+/// The directory is created if it doesn't exist yet. Returns an error
+/// if the directory holds an archive store, or a store in a format
+/// this version of terminus-store can't read.
+pub async fn open_directory_store_checked<P: Into<PathBuf>>(path: P) -> io::Result<Store> {
+    let store = open_directory_store(path);
+    store.check_version().await?;
+
+    Ok(store)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::consts::LayerFileEnum;
+    use crate::storage::{CancellationToken, LayerKind, ProgressPhase};
     use tempfile::tempdir;
 
     async fn create_and_manipulate_database(store: Store) {
@@ -1590,50 +2861,206 @@ mod tests {
         );
     }
 
-    #[tokio::test]
-    async fn apply_a_base_delta() {
-        let store = open_memory_store();
+    async fn check_layers_info(store: Store) {
         let builder = store.create_base_layer().await.unwrap();
-
         builder
             .add_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
             .unwrap();
-
-        let layer = builder.commit().await.unwrap();
-
-        let builder2 = layer.open_write().await.unwrap();
-
-        builder2
-            .add_value_triple(ValueTriple::new_string_value("dog", "says", "woof"))
-            .unwrap();
-
-        let layer2 = builder2.commit().await.unwrap();
-
-        let delta_builder_1 = store.create_base_layer().await.unwrap();
-
-        delta_builder_1
-            .add_value_triple(ValueTriple::new_string_value("dog", "says", "woof"))
+        let base = builder.commit().await.unwrap();
+        let builder = base.open_write().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_node("cow", "likes", "duck"))
             .unwrap();
-        delta_builder_1
-            .add_value_triple(ValueTriple::new_string_value("cat", "says", "meow"))
+        let child = builder.commit().await.unwrap();
+        child.rollup().await.unwrap();
+        let rollup = store
+            .layer_rollup_info(child.name())
+            .await
+            .unwrap()
             .unwrap();
 
-        let delta_1 = delta_builder_1.commit().await.unwrap();
-
-        let delta_builder_2 = delta_1.open_write().await.unwrap();
-
-        delta_builder_2
-            .add_value_triple(ValueTriple::new_string_value("crow", "says", "caw"))
-            .unwrap();
-        delta_builder_2
-            .remove_value_triple(ValueTriple::new_string_value("cat", "says", "meow"))
-            .unwrap();
+        let info = store.layer_info(child.name()).await.unwrap().unwrap();
+        assert_eq!(LayerKind::Child, info.kind);
+        assert_eq!(Some(base.name()), info.parent);
+        assert_eq!(Some(rollup.rollup), info.rollup);
+        assert_eq!(1, info.depth);
+        assert!(info.created.is_some());
+        assert!(info.section_size(LayerFileEnum::PosSubjects).unwrap() > 0);
+        let section_total: usize = info.sections.iter().map(|(_, size)| size).sum();
+        assert!(info.size >= section_total as u64);
+
+        let infos = store.layers_info().await.unwrap();
+        let mut names = vec![base.name(), child.name(), rollup.rollup];
+        names.sort();
+        assert_eq!(
+            names,
+            infos.iter().map(|info| info.name).collect::<Vec<_>>()
+        );
+        assert!(infos.contains(&info));
+        let base_info = infos.iter().find(|i| i.name == base.name()).unwrap();
+        assert_eq!(LayerKind::Base, base_info.kind);
+        assert_eq!(0, base_info.depth);
+        assert_eq!(None, base_info.rollup);
+        assert_eq!(None, base_info.section_size(LayerFileEnum::NegSubjects));
+
+        assert_eq!(None, store.layer_info([0; 5]).await.unwrap());
+    }
 
-        let delta = delta_builder_2.commit().await.unwrap();
+    #[tokio::test]
+    async fn layers_info_on_all_store_kinds() {
+        check_layers_info(open_memory_store()).await;
+        let dir = tempdir().unwrap();
+        check_layers_info(open_directory_store(dir.path())).await;
+        let dir = tempdir().unwrap();
+        check_layers_info(open_archive_store(dir.path(), 10)).await;
+    }
 
-        let rebase_builder = layer2.open_write().await.unwrap();
+    fn dictionary_contents(layer: &InternalLayer) -> Vec<Vec<bytes::Bytes>> {
+        vec![
+            layer
+                .node_dictionary()
+                .iter()
+                .map(|b| b.to_bytes())
+                .collect(),
+            layer
+                .predicate_dictionary()
+                .iter()
+                .map(|b| b.to_bytes())
+                .collect(),
+            layer
+                .value_dictionary()
+                .iter()
+                .map(|b| b.to_bytes())
+                .collect(),
+        ]
+    }
 
-        let _ = rebase_builder.apply_delta(&delta).await.unwrap();
+    #[tokio::test]
+    async fn external_squashes_match_in_memory_squashes() {
+        let store = open_memory_store();
+        let temp = tempdir().unwrap();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_node("foo", "bar", "baz"))
+            .unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("foo", "baz", "hai"))
+            .unwrap();
+        let base_layer = builder.commit().await.unwrap();
+        let builder = base_layer.open_write().await.unwrap();
+        builder
+            .remove_value_triple(ValueTriple::new_string_value("foo", "baz", "hai"))
+            .unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_node("a", "b", "anode"))
+            .unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("a", "c", "astring"))
+            .unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_node("foo", "c", "baz"))
+            .unwrap();
+        let child_layer1 = builder.commit().await.unwrap();
+        let builder = child_layer1.open_write().await.unwrap();
+        builder
+            .remove_value_triple(ValueTriple::new_node("a", "b", "anode"))
+            .unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("z", "b", "another"))
+            .unwrap();
+        let child_layer2 = builder.commit().await.unwrap();
+
+        let squashes = vec![
+            (
+                child_layer2.squash().await.unwrap(),
+                child_layer2.squash_external(temp.path()).await.unwrap(),
+            ),
+            (
+                child_layer2.squash_upto(&base_layer).await.unwrap(),
+                child_layer2
+                    .squash_upto_external(&base_layer, temp.path())
+                    .await
+                    .unwrap(),
+            ),
+        ];
+        for (expected, squashed) in squashes {
+            assert_eq!(expected.parent_name(), squashed.parent_name());
+            let expected = store
+                .layer_store
+                .get_layer(expected.name())
+                .await
+                .unwrap()
+                .unwrap();
+            let squashed = store
+                .layer_store
+                .get_layer(squashed.name())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(
+                dictionary_contents(&expected),
+                dictionary_contents(&squashed)
+            );
+            for (expected, squashed) in [
+                (
+                    expected.internal_triple_additions(),
+                    squashed.internal_triple_additions(),
+                ),
+                (
+                    expected.internal_triple_removals(),
+                    squashed.internal_triple_removals(),
+                ),
+            ] {
+                assert_eq!(expected.collect::<Vec<_>>(), squashed.collect::<Vec<_>>());
+            }
+        }
+        assert_eq!(0, std::fs::read_dir(temp.path()).unwrap().count());
+    }
+
+    #[tokio::test]
+    async fn apply_a_base_delta() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+
+        builder
+            .add_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+
+        let layer = builder.commit().await.unwrap();
+
+        let builder2 = layer.open_write().await.unwrap();
+
+        builder2
+            .add_value_triple(ValueTriple::new_string_value("dog", "says", "woof"))
+            .unwrap();
+
+        let layer2 = builder2.commit().await.unwrap();
+
+        let delta_builder_1 = store.create_base_layer().await.unwrap();
+
+        delta_builder_1
+            .add_value_triple(ValueTriple::new_string_value("dog", "says", "woof"))
+            .unwrap();
+        delta_builder_1
+            .add_value_triple(ValueTriple::new_string_value("cat", "says", "meow"))
+            .unwrap();
+
+        let delta_1 = delta_builder_1.commit().await.unwrap();
+
+        let delta_builder_2 = delta_1.open_write().await.unwrap();
+
+        delta_builder_2
+            .add_value_triple(ValueTriple::new_string_value("crow", "says", "caw"))
+            .unwrap();
+        delta_builder_2
+            .remove_value_triple(ValueTriple::new_string_value("cat", "says", "meow"))
+            .unwrap();
+
+        let delta = delta_builder_2.commit().await.unwrap();
+
+        let rebase_builder = layer2.open_write().await.unwrap();
+
+        let _ = rebase_builder.apply_delta(&delta).await.unwrap();
 
         let rebase_layer = rebase_builder.commit().await.unwrap();
 
@@ -1861,4 +3288,1448 @@ mod tests {
         left.sort();
         assert_eq!(left, two);
     }
+
+    #[tokio::test]
+    async fn archive_store_reports_both_cache_levels() {
+        let dir = tempdir().unwrap();
+        let store = open_archive_store(dir.path(), 10);
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+        let name = builder.name();
+        builder.commit_no_load().await.unwrap();
+
+        let _layer = store.get_layer_from_id(name).await.unwrap().unwrap();
+        let metrics = store.metrics().await;
+
+        assert_eq!(1, metrics.layer_cache.unwrap().entries);
+        assert_eq!(1, metrics.loads.layers_loaded);
+        let archive_cache = metrics.archive_cache.unwrap();
+        assert_eq!(1, archive_cache.entries);
+        assert!(archive_cache.bytes_resident > 0);
+    }
+
+    #[tokio::test]
+    async fn metrics_reporter_pushes_snapshots() {
+        let store = open_memory_store();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let handle = store.spawn_metrics_reporter(
+            move |metrics: &StoreMetrics| {
+                let _ = sender.send(metrics.clone());
+            },
+            std::time::Duration::from_millis(10),
+        );
+
+        let metrics = receiver.recv().await.unwrap();
+        assert_eq!(0, metrics.loads.layers_loaded);
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn commit_with_progress_reports_phases() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("pig", "says", "oink"))
+            .unwrap();
+
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let progress = Progress::new().with_reporter(sender);
+        builder.commit_with_progress(&progress).await.unwrap();
+
+        let mut events = Vec::new();
+        while let Ok(event) = receiver.try_recv() {
+            events.push(event);
+        }
+
+        assert_eq!(ProgressPhase::Dictionaries, events[0].phase);
+        let last = events.last().unwrap();
+        assert_eq!(ProgressPhase::Done, last.phase);
+        assert_eq!(3, last.dictionaries_merged);
+        assert_eq!(2, last.triples_written);
+        assert!(last.indexes_built > 0);
+    }
+
+    #[tokio::test]
+    async fn cancelled_commit_leaves_no_layer() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let progress = Progress::new().with_cancellation(token);
+        let error = builder.commit_with_progress(&progress).await.err().unwrap();
+
+        assert_eq!(io::ErrorKind::Interrupted, error.kind());
+        assert!(builder.committed());
+        assert!(store.layer_store.layers().await.unwrap().is_empty());
+    }
+
+    async fn three_layer_stack(store: &Store) -> (StoreLayer, StoreLayer) {
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+        let base_layer = builder.commit().await.unwrap();
+        let builder = base_layer.open_write().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("pig", "says", "oink"))
+            .unwrap();
+        let child_layer = builder.commit().await.unwrap();
+        let builder = child_layer.open_write().await.unwrap();
+        builder
+            .remove_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+        let top_layer = builder.commit().await.unwrap();
+
+        (base_layer, top_layer)
+    }
+
+    #[tokio::test]
+    async fn cancelled_squash_upto_leaves_no_layer() {
+        let store = open_memory_store();
+        let (base_layer, top_layer) = three_layer_stack(&store).await;
+        let layers_before = store.layer_store.layers().await.unwrap();
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let progress = Progress::new().with_cancellation(token);
+        let error = top_layer
+            .squash_upto_with_progress(&base_layer, &progress)
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(io::ErrorKind::Interrupted, error.kind());
+        let mut layers_after = store.layer_store.layers().await.unwrap();
+        let mut layers_before = layers_before;
+        layers_before.sort();
+        layers_after.sort();
+        assert_eq!(layers_before, layers_after);
+    }
+
+    #[tokio::test]
+    async fn cancelled_rollup_upto_registers_no_rollup() {
+        let store = open_memory_store();
+        let (base_layer, top_layer) = three_layer_stack(&store).await;
+        let layer_count = store.layer_store.layers().await.unwrap().len();
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let progress = Progress::new().with_cancellation(token);
+        let error = top_layer
+            .rollup_upto_with_progress(&base_layer, &progress)
+            .await
+            .unwrap_err();
+
+        assert_eq!(io::ErrorKind::Interrupted, error.kind());
+        assert_eq!(layer_count, store.layer_store.layers().await.unwrap().len());
+
+        top_layer
+            .rollup_upto_with_progress(&base_layer, &Progress::new())
+            .await
+            .unwrap();
+        assert_eq!(
+            layer_count + 1,
+            store.layer_store.layers().await.unwrap().len()
+        );
+    }
+
+    #[tokio::test]
+    async fn cancelled_merge_base_layers_leaves_no_layer() {
+        let temp = tempdir().unwrap();
+        let store = open_memory_store();
+        let mut names = Vec::new();
+        for animal in ["cow", "pig"].iter() {
+            let builder = store.create_base_layer().await.unwrap();
+            builder
+                .add_value_triple(ValueTriple::new_string_value(animal, "is", "animal"))
+                .unwrap();
+            names.push(builder.commit().await.unwrap().name());
+        }
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let progress = Progress::new().with_cancellation(token);
+        let error = store
+            .merge_base_layers_with_progress(&names, temp.path(), &progress)
+            .await
+            .unwrap_err();
+
+        assert_eq!(io::ErrorKind::Interrupted, error.kind());
+        assert_eq!(2, store.layer_store.layers().await.unwrap().len());
+        assert_eq!(0, std::fs::read_dir(temp.path()).unwrap().count());
+    }
+
+    #[tokio::test]
+    async fn triple_streams_match_iterators() {
+        let store = open_memory_store();
+        let (base_layer, top_layer) = three_layer_stack(&store).await;
+
+        let triples: Vec<_> = top_layer.triples_stream().collect().await;
+        assert_eq!(top_layer.triples().collect::<Vec<_>>(), triples);
+
+        let additions: Vec<_> = base_layer
+            .triple_additions_stream()
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(
+            base_layer
+                .triple_additions()
+                .await
+                .unwrap()
+                .collect::<Vec<_>>(),
+            additions
+        );
+
+        let removals: Vec<_> = top_layer
+            .triple_removals_stream()
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(1, removals.len());
+    }
+
+    #[tokio::test]
+    async fn bulk_loaded_layer_matches_built_layer() {
+        let temp = tempdir().unwrap();
+        let store = open_memory_store();
+        let triples: Vec<_> = (0..100)
+            .map(|i| {
+                if i % 3 == 0 {
+                    ValueTriple::new_node(&format!("n{}", i % 7), "links", &format!("n{}", i))
+                } else {
+                    ValueTriple::new_string_value(
+                        &format!("n{}", i % 11),
+                        "has",
+                        &format!("v{}", i % 13),
+                    )
+                }
+            })
+            .collect();
+
+        let builder = store.create_base_layer().await.unwrap();
+        for triple in triples.iter() {
+            builder.add_value_triple(triple.clone()).unwrap();
+        }
+        let built = builder.commit().await.unwrap();
+
+        let name = store
+            .bulk_load_base_layer(futures::stream::iter(triples), temp.path())
+            .await
+            .unwrap();
+        let loaded = store.get_layer_from_id(name).await.unwrap().unwrap();
+
+        assert_eq!(built.node_and_value_count(), loaded.node_and_value_count());
+        assert_eq!(built.predicate_count(), loaded.predicate_count());
+        assert_eq!(
+            built.triples().collect::<Vec<_>>(),
+            loaded.triples().collect::<Vec<_>>()
+        );
+        assert_eq!(0, std::fs::read_dir(temp.path()).unwrap().count());
+    }
+
+    #[tokio::test]
+    async fn bulk_loaded_quads_match_built_quads() {
+        let temp = tempdir().unwrap();
+        let store = open_memory_store();
+        let quads: Vec<_> = (0..100)
+            .map(|i| {
+                ValueQuadTriple::new(
+                    &format!("g{}", i % 3),
+                    ValueTriple::new_string_value(
+                        &format!("n{}", i % 11),
+                        "has",
+                        &format!("v{}", i % 13),
+                    ),
+                )
+            })
+            .collect();
+
+        let builder = store.create_base_layer().await.unwrap();
+        for quad in quads.iter() {
+            builder.add_value_quad(quad.clone()).unwrap();
+        }
+        let built = builder.commit().await.unwrap();
+
+        let name = store
+            .bulk_load_base_layer_quads(futures::stream::iter(quads), temp.path())
+            .await
+            .unwrap();
+        let loaded = store.get_layer_from_id(name).await.unwrap().unwrap();
+
+        assert_eq!(built.node_and_value_count(), loaded.node_and_value_count());
+        for graph in ["g0", "g1", "g2"] {
+            assert_eq!(
+                built
+                    .quads_g(built.graph_id(graph).unwrap())
+                    .collect::<Vec<_>>(),
+                loaded
+                    .quads_g(loaded.graph_id(graph).unwrap())
+                    .collect::<Vec<_>>()
+            );
+        }
+        assert_eq!(0, std::fs::read_dir(temp.path()).unwrap().count());
+    }
+
+    #[tokio::test]
+    async fn merge_child_layers_unions_changes() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("pig", "says", "oink"))
+            .unwrap();
+        let base_layer = builder.commit().await.unwrap();
+
+        let builder = base_layer.open_write().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("duck", "says", "quack"))
+            .unwrap();
+        builder
+            .remove_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+        let worker1 = builder.commit().await.unwrap();
+
+        let builder = base_layer.open_write().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_node("duck", "likes", "pig"))
+            .unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("duck", "says", "quack"))
+            .unwrap();
+        let worker2 = builder.commit().await.unwrap();
+
+        let name = store
+            .merge_child_layers(base_layer.name(), &[worker1.name(), worker2.name()])
+            .await
+            .unwrap();
+        let merged = store.get_layer_from_id(name).await.unwrap().unwrap();
+
+        assert_eq!(Some(base_layer.name()), merged.parent_name());
+        let mut result: Vec<_> = merged
+            .triples()
+            .map(|t| merged.id_triple_to_string(&t).unwrap())
+            .collect();
+        result.sort();
+        assert_eq!(
+            vec![
+                ValueTriple::new_node("duck", "likes", "pig"),
+                ValueTriple::new_string_value("duck", "says", "quack"),
+                ValueTriple::new_string_value("pig", "says", "oink"),
+            ],
+            result
+        );
+        assert_eq!(2, merged.triple_layer_addition_count().await.unwrap());
+        assert_eq!(1, merged.triple_layer_removal_count().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn merge_child_layers_keeps_the_effective_change() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+        let base_layer = builder.commit().await.unwrap();
+
+        // the first worker removes an existing triple and adds a new one
+        let builder = base_layer.open_write().await.unwrap();
+        builder
+            .remove_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("pig", "says", "oink"))
+            .unwrap();
+        let worker1 = builder.commit().await.unwrap();
+
+        // the second worker tries to do the opposite, which is a no-op against the parent
+        let builder = base_layer.open_write().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .remove_value_triple(ValueTriple::new_string_value("pig", "says", "oink"))
+            .unwrap();
+        let worker2 = builder.commit().await.unwrap();
+
+        for order in [
+            [worker1.name(), worker2.name()],
+            [worker2.name(), worker1.name()],
+        ]
+        .iter()
+        {
+            let name = store
+                .merge_child_layers(base_layer.name(), order)
+                .await
+                .unwrap();
+            let merged = store.get_layer_from_id(name).await.unwrap().unwrap();
+            assert!(
+                !merged.value_triple_exists(&ValueTriple::new_string_value("cow", "says", "moo"))
+            );
+            assert!(
+                merged.value_triple_exists(&ValueTriple::new_string_value("pig", "says", "oink"))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn merge_child_layers_rejects_other_parents() {
+        let store = open_memory_store();
+        let base_layer = store
+            .create_base_layer()
+            .await
+            .unwrap()
+            .commit()
+            .await
+            .unwrap();
+        let child = base_layer
+            .open_write()
+            .await
+            .unwrap()
+            .commit()
+            .await
+            .unwrap();
+        let grandchild = child.open_write().await.unwrap().commit().await.unwrap();
+
+        let error = store
+            .merge_child_layers(base_layer.name(), &[child.name(), grandchild.name()])
+            .await
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, error.kind());
+    }
+
+    #[tokio::test]
+    async fn sharded_builder_matches_single_builder() {
+        let store = open_memory_store();
+        let base_layer = {
+            let builder = store.create_base_layer().await.unwrap();
+            builder
+                .add_value_triple(ValueTriple::new_node("n0", "links", "n1"))
+                .unwrap();
+            builder
+                .add_value_triple(ValueTriple::new_string_value("n1", "has", "v1"))
+                .unwrap();
+            builder.commit().await.unwrap()
+        };
+
+        let triples: Vec<_> = (0..400)
+            .map(|i| {
+                if i % 2 == 0 {
+                    ValueTriple::new_node(&format!("n{}", i % 17), "links", &format!("n{}", i))
+                } else {
+                    ValueTriple::new_string_value(
+                        &format!("n{}", i % 19),
+                        &format!("p{}", i % 3),
+                        &format!("v{}", i % 23),
+                    )
+                }
+            })
+            .collect();
+        let removal = ValueTriple::new_node("n0", "links", "n1");
+
+        let single = base_layer.open_write().await.unwrap();
+        for triple in triples.iter() {
+            single.add_value_triple(triple.clone()).unwrap();
+        }
+        single.remove_value_triple(removal.clone()).unwrap();
+        let single = single.commit().await.unwrap();
+
+        let sharded = base_layer.open_write().await.unwrap();
+        let handles: Vec<_> = triples
+            .chunks(100)
+            .map(|chunk| {
+                let shard = sharded.shard().unwrap();
+                let chunk = chunk.to_vec();
+                std::thread::spawn(move || {
+                    for triple in chunk {
+                        shard.add_value_triple(triple).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        sharded
+            .shard()
+            .unwrap()
+            .remove_value_triple(removal)
+            .unwrap();
+        let shard = sharded.shard().unwrap();
+        let sharded = sharded.commit().await.unwrap();
+
+        assert_eq!(
+            single.node_and_value_count(),
+            sharded.node_and_value_count()
+        );
+        assert_eq!(single.predicate_count(), sharded.predicate_count());
+        assert_eq!(
+            single.triples().collect::<Vec<_>>(),
+            sharded.triples().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            single.triple_layer_removal_count().await.unwrap(),
+            sharded.triple_layer_removal_count().await.unwrap()
+        );
+        assert!(shard
+            .add_value_triple(ValueTriple::new_node("a", "b", "c"))
+            .is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn shards_created_during_commit_are_not_lost() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let builder = builder.clone();
+                std::thread::spawn(move || {
+                    let mut added = Vec::new();
+                    for i in 0.. {
+                        let shard = match builder.shard() {
+                            Ok(shard) => shard,
+                            Err(_) => break,
+                        };
+                        let triple =
+                            ValueTriple::new_node(&format!("t{}", t), "links", &format!("n{}", i));
+                        if shard.add_value_triple(triple.clone()).is_ok() {
+                            added.push(triple);
+                        }
+                    }
+
+                    added
+                })
+            })
+            .collect();
+        let layer = builder.commit().await.unwrap();
+
+        for handle in handles {
+            for triple in handle.join().unwrap() {
+                assert!(layer.value_triple_exists(&triple));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn commit_fails_on_shard_with_unknown_ids() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_node("cow", "likes", "pig"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+
+        let builder = base.open_write().await.unwrap();
+        let name = builder.name();
+        let shard = builder.shard().unwrap();
+        shard.add_id_triple(IdTriple::new(1, 1000, 1)).unwrap();
+
+        match builder.commit().await {
+            Ok(_) => panic!("commit with unknown ids should fail"),
+            Err(e) => assert_eq!(io::ErrorKind::InvalidData, e.kind()),
+        }
+        assert!(store.get_layer_from_id(name).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn apply_delta_through_shards() {
+        let store = open_memory_store();
+        let (base_layer, top_layer) = three_layer_stack(&store).await;
+
+        let builder = base_layer.open_write().await.unwrap();
+        builder.apply_delta(&top_layer).await.unwrap();
+        let applied = builder.commit().await.unwrap();
+
+        assert!(!applied.value_triple_exists(&ValueTriple::new_string_value("cow", "says", "moo")));
+        assert_eq!(0, applied.triple_layer_addition_count().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn rebase_replays_layers_and_reports_noops_and_conflicts() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("pig", "says", "oink"))
+            .unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("duck", "says", "quack"))
+            .unwrap();
+        let base_layer = builder.commit().await.unwrap();
+
+        // someone else moved the branch forward
+        let builder = base_layer.open_write().await.unwrap();
+        builder
+            .remove_value_triple(ValueTriple::new_string_value("pig", "says", "oink"))
+            .unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("horse", "says", "neigh"))
+            .unwrap();
+        let new_base = builder.commit().await.unwrap();
+
+        // while we built two layers of our own
+        let builder = base_layer.open_write().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("horse", "says", "neigh"))
+            .unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("bird", "says", "tweet"))
+            .unwrap();
+        builder
+            .remove_value_triple(ValueTriple::new_string_value("pig", "says", "oink"))
+            .unwrap();
+        builder
+            .remove_value_triple(ValueTriple::new_string_value("duck", "says", "quack"))
+            .unwrap();
+        let ours1 = builder.commit().await.unwrap();
+        let builder = ours1.open_write().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("pig", "says", "oink"))
+            .unwrap();
+        let ours2 = builder.commit().await.unwrap();
+
+        let rebased = store.rebase(&ours2, &base_layer, &new_base).await.unwrap();
+
+        assert_eq!(2, rebased.len());
+        assert_eq!(ours1.name(), rebased[0].original);
+        assert_eq!(ours2.name(), rebased[1].original);
+        assert_eq!(Some(new_base.name()), rebased[0].layer.parent_name());
+        assert_eq!(
+            Some(rebased[0].layer.name()),
+            rebased[1].layer.parent_name()
+        );
+
+        let mut noops = rebased[0].noops.clone();
+        noops.sort();
+        assert_eq!(
+            vec![
+                (
+                    TripleChange::Addition,
+                    ValueTriple::new_string_value("horse", "says", "neigh")
+                ),
+                (
+                    TripleChange::Removal,
+                    ValueTriple::new_string_value("pig", "says", "oink")
+                ),
+            ],
+            noops
+        );
+        assert!(rebased[0].conflicts.is_empty());
+        assert!(rebased[1].noops.is_empty());
+        assert_eq!(
+            vec![(
+                TripleChange::Addition,
+                ValueTriple::new_string_value("pig", "says", "oink")
+            )],
+            rebased[1].conflicts
+        );
+
+        let top = &rebased[1].layer;
+        let mut result: Vec<_> = top
+            .triples()
+            .map(|t| top.id_triple_to_string(&t).unwrap())
+            .collect();
+        result.sort();
+        assert_eq!(
+            vec![
+                ValueTriple::new_string_value("bird", "says", "tweet"),
+                ValueTriple::new_string_value("cow", "says", "moo"),
+                ValueTriple::new_string_value("horse", "says", "neigh"),
+                ValueTriple::new_string_value("pig", "says", "oink"),
+            ],
+            result
+        );
+    }
+
+    #[tokio::test]
+    async fn rebase_requires_old_base_to_be_an_ancestor() {
+        let store = open_memory_store();
+        let (base_layer, top_layer) = three_layer_stack(&store).await;
+        let other = store
+            .create_base_layer()
+            .await
+            .unwrap()
+            .commit()
+            .await
+            .unwrap();
+
+        let error = store
+            .rebase(&top_layer, &other, &base_layer)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(io::ErrorKind::InvalidInput, error.kind());
+        assert!(store
+            .rebase(&top_layer, &top_layer, &base_layer)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn revert_undoes_changes_and_skips_later_changes() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("horse", "says", "neigh"))
+            .unwrap();
+        let base_layer = builder.commit().await.unwrap();
+
+        let builder = base_layer.open_write().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("pig", "says", "oink"))
+            .unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("duck", "says", "quack"))
+            .unwrap();
+        builder
+            .remove_value_triple(ValueTriple::new_string_value("horse", "says", "neigh"))
+            .unwrap();
+        let target = builder.commit().await.unwrap();
+
+        let builder = target.open_write().await.unwrap();
+        builder
+            .remove_value_triple(ValueTriple::new_string_value("pig", "says", "oink"))
+            .unwrap();
+        let top_layer = builder.commit().await.unwrap();
+
+        let revert = top_layer.open_revert(&target).await.unwrap();
+        assert_eq!(
+            vec![(
+                TripleChange::Addition,
+                ValueTriple::new_string_value("pig", "says", "oink")
+            )],
+            revert.skipped
+        );
+        let reverted = revert.builder.commit().await.unwrap();
+        assert_eq!(Some(top_layer.name()), reverted.parent_name());
+
+        let mut result: Vec<_> = reverted
+            .triples()
+            .map(|t| reverted.id_triple_to_string(&t).unwrap())
+            .collect();
+        result.sort();
+        assert_eq!(
+            vec![
+                ValueTriple::new_string_value("cow", "says", "moo"),
+                ValueTriple::new_string_value("horse", "says", "neigh"),
+            ],
+            result
+        );
+    }
+
+    #[tokio::test]
+    async fn revert_of_own_changes_and_of_non_ancestor() {
+        let store = open_memory_store();
+        let (base_layer, top_layer) = three_layer_stack(&store).await;
+
+        let revert = top_layer.open_revert(&top_layer).await.unwrap();
+        assert!(revert.skipped.is_empty());
+        let reverted = revert.builder.commit().await.unwrap();
+        assert!(reverted.value_triple_exists(&ValueTriple::new_string_value("cow", "says", "moo")));
+
+        let error = base_layer.open_revert(&top_layer).await.err().unwrap();
+        assert_eq!(io::ErrorKind::InvalidInput, error.kind());
+    }
+
+    async fn stack_of_depth(store: &Store, depth: usize) -> Vec<StoreLayer> {
+        let mut layers: Vec<StoreLayer> = Vec::with_capacity(depth);
+        for i in 0..depth {
+            let builder = match layers.last() {
+                None => store.create_base_layer().await.unwrap(),
+                Some(parent) => parent.open_write().await.unwrap(),
+            };
+            builder
+                .add_value_triple(ValueTriple::new_string_value("cow", "says", &i.to_string()))
+                .unwrap();
+            layers.push(builder.commit().await.unwrap());
+        }
+
+        layers
+    }
+
+    #[tokio::test]
+    async fn ancestors_of_a_layer_stack() {
+        let store = open_memory_store();
+        let layers = stack_of_depth(&store, 4).await;
+        let top = &layers[3];
+
+        for (n, layer) in layers.iter().rev().enumerate() {
+            let ancestor = top.ancestor(n).await.unwrap().unwrap();
+            assert_eq!(layer.name(), ancestor.name());
+            assert_eq!(n, 4 - ancestor.triples().count());
+            let ancestor = top.ancestor_by_name(layer.name()).await.unwrap().unwrap();
+            assert_eq!(layer.name(), ancestor.name());
+        }
+        assert!(top.ancestor(4).await.unwrap().is_none());
+        assert!(layers[1]
+            .ancestor_by_name(top.name())
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn ancestors_of_a_rolled_up_layer_stack() {
+        // no cache, so the top layer is loaded again on top of the rollup
+        let store = Store::new(MemoryLabelStore::new(), MemoryLayerStore::new());
+        let layers = stack_of_depth(&store, 5).await;
+        layers[3].rollup_upto(&layers[1]).await.unwrap();
+        let top = store
+            .get_layer_from_id(layers[4].name())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(top.layer.immediate_parent().unwrap().is_rollup());
+
+        for (n, layer) in layers.iter().rev().enumerate() {
+            let ancestor = top.ancestor(n).await.unwrap().unwrap();
+            assert_eq!(layer.name(), ancestor.name());
+            assert_eq!(n, 5 - ancestor.triples().count());
+            assert_eq!(layer.parent_name(), ancestor.parent_name());
+            let ancestor = top.ancestor_by_name(layer.name()).await.unwrap().unwrap();
+            assert_eq!(layer.name(), ancestor.name());
+        }
+        assert!(top.ancestor(5).await.unwrap().is_none());
+    }
+
+    async fn history_stack(store: &Store) -> Vec<StoreLayer> {
+        let moo = ValueTriple::new_string_value("cow", "says", "moo");
+        let oink = ValueTriple::new_string_value("pig", "says", "oink");
+        let builder = store.create_base_layer().await.unwrap();
+        builder.add_value_triple(moo.clone()).unwrap();
+        let base_layer = builder.commit().await.unwrap();
+        let builder = base_layer.open_write().await.unwrap();
+        builder.remove_value_triple(moo.clone()).unwrap();
+        let removal_layer = builder.commit().await.unwrap();
+        let builder = removal_layer.open_write().await.unwrap();
+        builder.add_value_triple(oink).unwrap();
+        let unrelated_layer = builder.commit().await.unwrap();
+        let builder = unrelated_layer.open_write().await.unwrap();
+        builder.add_value_triple(moo).unwrap();
+        let readd_layer = builder.commit().await.unwrap();
+        let top_layer = readd_layer
+            .open_write()
+            .await
+            .unwrap()
+            .commit()
+            .await
+            .unwrap();
+
+        vec![
+            base_layer,
+            removal_layer,
+            unrelated_layer,
+            readd_layer,
+            top_layer,
+        ]
+    }
+
+    #[tokio::test]
+    async fn triple_history_lists_changes_in_order() {
+        let store = open_memory_store();
+        let layers = history_stack(&store).await;
+        let top = &layers[4];
+
+        let moo = ValueTriple::new_string_value("cow", "says", "moo");
+        let expected = vec![
+            (layers[0].name(), TripleChange::Addition),
+            (layers[1].name(), TripleChange::Removal),
+            (layers[3].name(), TripleChange::Addition),
+        ];
+        assert_eq!(expected, top.value_triple_history(&moo).await.unwrap());
+        let id_triple = top.value_triple_to_id(&moo).unwrap();
+        assert_eq!(expected, top.triple_history(&id_triple).await.unwrap());
+
+        assert!(top
+            .value_triple_history(&ValueTriple::new_string_value("duck", "says", "quack"))
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn triple_history_through_rollup() {
+        // no cache, so the top layer is loaded again on top of the rollup
+        let store = Store::new(MemoryLabelStore::new(), MemoryLayerStore::new());
+        let layers = history_stack(&store).await;
+        layers[3].rollup_upto(&layers[0]).await.unwrap();
+        let top = store
+            .get_layer_from_id(layers[4].name())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(top.layer.immediate_parent().unwrap().is_rollup());
+
+        let moo = ValueTriple::new_string_value("cow", "says", "moo");
+        assert_eq!(
+            vec![
+                (layers[0].name(), TripleChange::Addition),
+                (layers[1].name(), TripleChange::Removal),
+                (layers[3].name(), TripleChange::Addition),
+            ],
+            top.value_triple_history(&moo).await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn subject_history_groups_changes_per_layer() {
+        let store = Store::new(MemoryLabelStore::new(), MemoryLayerStore::new());
+        let layers = history_stack(&store).await;
+        let builder = layers[4].open_write().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_node("cow", "likes", "pig"))
+            .unwrap();
+        let top = builder.commit().await.unwrap();
+        let cow = top.subject_id("cow").unwrap();
+        let moo = top
+            .value_triple_to_id(&ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+        let likes = top
+            .value_triple_to_id(&ValueTriple::new_node("cow", "likes", "pig"))
+            .unwrap();
+
+        let expected = vec![
+            LayerSubjectChanges {
+                layer: layers[0].name(),
+                additions: vec![moo],
+                removals: vec![],
+            },
+            LayerSubjectChanges {
+                layer: layers[1].name(),
+                additions: vec![],
+                removals: vec![moo],
+            },
+            LayerSubjectChanges {
+                layer: layers[3].name(),
+                additions: vec![moo],
+                removals: vec![],
+            },
+            LayerSubjectChanges {
+                layer: top.name(),
+                additions: vec![likes],
+                removals: vec![],
+            },
+        ];
+        assert_eq!(expected, top.subject_history(cow).await.unwrap());
+
+        layers[3].rollup_upto(&layers[0]).await.unwrap();
+        // no cache, so the top layer is loaded again on top of the rollup
+        let top = store.get_layer_from_id(top.name()).await.unwrap().unwrap();
+        assert_eq!(expected, top.subject_history(cow).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn commit_rolls_up_according_to_policy() {
+        let store = open_memory_store().with_rollup_policy(RollupPolicy::new().with_max_depth(2));
+        let layers = stack_of_depth(&store, 4).await;
+        store.wait_for_scheduled_rollups().await;
+
+        let info = layers[3].rollup_info().await.unwrap().unwrap();
+        assert_eq!(layers[3].name(), info.layer);
+        assert_eq!(Some(layers[0].name()), info.upto);
+        assert!(layers[2].rollup_info().await.unwrap().is_none());
+
+        let top = store
+            .get_layer_from_id(layers[3].name())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(top.layer.is_rollup());
+        assert!(!store.apply_rollup_policy(&top).await.unwrap());
+        assert_eq!(vec![info], top.rollups().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn set_head_rolls_up_to_nearest_rollup() {
+        // no cache, so the top layer is loaded again on top of the rollup
+        let store = Store::new(MemoryLabelStore::new(), MemoryLayerStore::new());
+        let layers = stack_of_depth(&store, 6).await;
+        layers[3].rollup_upto(&layers[1]).await.unwrap();
+        assert_eq!(
+            vec![RollupInfo {
+                layer: layers[3].name(),
+                rollup: layers[3].rollup_info().await.unwrap().unwrap().rollup,
+                upto: Some(layers[1].name()),
+            }],
+            layers[5].rollups().await.unwrap()
+        );
+
+        let store = store.with_rollup_policy(RollupPolicy::new().with_max_changes(0));
+        assert_eq!(
+            Some(&RollupPolicy::new().with_max_changes(0)),
+            store.rollup_policy()
+        );
+        let graph = store.create("foo").await.unwrap();
+        let top = store
+            .get_layer_from_id(layers[5].name())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(graph.set_head(&top).await.unwrap());
+        store.wait_for_scheduled_rollups().await;
+
+        let info = top.rollup_info().await.unwrap().unwrap();
+        assert_eq!(Some(layers[3].name()), info.upto);
+        assert_eq!(2, top.rollups().await.unwrap().len());
+
+        let top = store
+            .get_layer_from_id(layers[5].name())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(top.layer.is_rollup());
+        assert_eq!(
+            layers[5].triples().collect::<Vec<_>>(),
+            top.triples().collect::<Vec<_>>()
+        );
+    }
+
+    async fn quad_stack(store: &Store) -> Vec<StoreLayer> {
+        let moo = ValueTriple::new_string_value("cow", "says", "moo");
+        let oink = ValueTriple::new_string_value("pig", "says", "oink");
+        let quack = ValueTriple::new_string_value("duck", "says", "quack");
+
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_value_quad(ValueQuadTriple::new("farm", moo.clone()))
+            .unwrap();
+        builder
+            .add_value_quad(ValueQuadTriple::new("farm", oink.clone()))
+            .unwrap();
+        builder
+            .add_value_quad(ValueQuadTriple::new("pond", quack.clone()))
+            .unwrap();
+        let base_layer = builder.commit().await.unwrap();
+
+        let builder = base_layer.open_write().await.unwrap();
+        builder
+            .remove_value_quad(ValueQuadTriple::new("farm", oink.clone()))
+            .unwrap();
+        builder
+            .add_value_quad(ValueQuadTriple::new("pond", moo))
+            .unwrap();
+        let layer1 = builder.commit().await.unwrap();
+
+        let neigh = ValueTriple::new_string_value("horse", "says", "neigh");
+        let builder = layer1.open_write().await.unwrap();
+        builder
+            .add_value_quad(ValueQuadTriple::new("farm", oink))
+            .unwrap();
+        builder
+            .remove_value_quad(ValueQuadTriple::new("pond", quack))
+            .unwrap();
+        builder
+            .add_value_quad(ValueQuadTriple::new("farm", neigh.clone()))
+            .unwrap();
+        let layer2 = builder.commit().await.unwrap();
+
+        // removing the triple also takes it out of its graph
+        let builder = layer2.open_write().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("cat", "says", "meow"))
+            .unwrap();
+        builder.remove_value_triple(neigh).unwrap();
+        let layer3 = builder.commit().await.unwrap();
+
+        vec![base_layer, layer1, layer2, layer3]
+    }
+
+    fn assert_quad_stack_graphs(layer: &StoreLayer) {
+        let graph_triples = |graph: &str| {
+            let mut triples: Vec<_> = layer
+                .quads_g(layer.graph_id(graph).unwrap())
+                .map(|quad| layer.id_quad_to_string(&quad).unwrap().triple)
+                .collect();
+            triples.sort();
+            triples
+        };
+
+        assert_eq!(
+            vec![
+                ValueTriple::new_string_value("cow", "says", "moo"),
+                ValueTriple::new_string_value("pig", "says", "oink")
+            ],
+            graph_triples("farm")
+        );
+        assert_eq!(
+            vec![ValueTriple::new_string_value("cow", "says", "moo")],
+            graph_triples("pond")
+        );
+    }
+
+    #[tokio::test]
+    async fn quads_survive_rollups_and_squash() {
+        // no cache, so reloading a rolled up layer loads its rollup
+        let store = Store::new(MemoryLabelStore::new(), MemoryLayerStore::new());
+        let layers = quad_stack(&store).await;
+        assert_quad_stack_graphs(&layers[3]);
+
+        layers[3].rollup_upto(&layers[0]).await.unwrap();
+        let top = store
+            .get_layer_from_id(layers[3].name())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(top.layer.is_rollup());
+        assert_quad_stack_graphs(&top);
+
+        top.rollup().await.unwrap();
+        let top = store
+            .get_layer_from_id(layers[3].name())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(top.layer.is_rollup());
+        assert_eq!(None, top.layer.immediate_parent_arc().map(|p| p.name()));
+        assert_quad_stack_graphs(&top);
+
+        let squashed = layers[3].squash().await.unwrap();
+        assert_quad_stack_graphs(&squashed);
+
+        let squashed = layers[3].squash_upto(&layers[1]).await.unwrap();
+        assert_eq!(Some(layers[1].name()), squashed.parent_name());
+        assert_quad_stack_graphs(&squashed);
+    }
+
+    #[tokio::test]
+    async fn quads_survive_merging_base_layers() {
+        let temp = tempdir().unwrap();
+        let store = open_memory_store();
+        let base1 = store.create_base_layer().await.unwrap();
+        base1
+            .add_value_quad(ValueQuadTriple::new(
+                "farm",
+                ValueTriple::new_string_value("cow", "says", "moo"),
+            ))
+            .unwrap();
+        base1
+            .add_value_quad(ValueQuadTriple::new(
+                "pond",
+                ValueTriple::new_string_value("duck", "says", "quack"),
+            ))
+            .unwrap();
+        let base1 = base1.commit().await.unwrap();
+        let base2 = store.create_base_layer().await.unwrap();
+        base2
+            .add_value_quad(ValueQuadTriple::new(
+                "farm",
+                ValueTriple::new_string_value("pig", "says", "oink"),
+            ))
+            .unwrap();
+        base2
+            .add_value_triple(ValueTriple::new_node("cow", "likes", "pig"))
+            .unwrap();
+        let base2 = base2.commit().await.unwrap();
+
+        let merged = store
+            .merge_base_layers(&[base1.name(), base2.name()], temp.path())
+            .await
+            .unwrap();
+        let merged = store.get_layer_from_id(merged).await.unwrap().unwrap();
+        let graph_triples = |graph: &str| {
+            let mut triples: Vec<_> = merged
+                .quads_g(merged.graph_id(graph).unwrap())
+                .map(|quad| merged.id_quad_to_string(&quad).unwrap().triple)
+                .collect();
+            triples.sort();
+            triples
+        };
+
+        assert_eq!(
+            3,
+            merged
+                .triples()
+                .filter(|t| merged.triple_graphs(t).len() == 1)
+                .count()
+        );
+        assert_eq!(
+            vec![
+                ValueTriple::new_string_value("cow", "says", "moo"),
+                ValueTriple::new_string_value("pig", "says", "oink")
+            ],
+            graph_triples("farm")
+        );
+        assert_eq!(
+            vec![ValueTriple::new_string_value("duck", "says", "quack")],
+            graph_triples("pond")
+        );
+    }
+
+    #[tokio::test]
+    async fn quads_in_archive_store() {
+        let dir = tempdir().unwrap();
+        let store = open_archive_store(dir.path(), 512);
+        let layers = quad_stack(&store).await;
+        layers[3].rollup_upto(&layers[1]).await.unwrap();
+
+        let store = open_archive_store(dir.path(), 512);
+        let top = store
+            .get_layer_from_id(layers[3].name())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(top.layer.is_rollup());
+        assert_quad_stack_graphs(&top);
+
+        let unrelated = store.create_base_layer().await.unwrap();
+        unrelated
+            .add_value_triple(ValueTriple::new_node("cow", "likes", "pig"))
+            .unwrap();
+        let unrelated = unrelated.commit().await.unwrap();
+        assert_eq!(0, unrelated.quads_g(1).count());
+    }
+
+    fn search_text_strings(layer: &StoreLayer, query: &str) -> Vec<String> {
+        let mut texts: Vec<String> = layer
+            .search_text(query)
+            .map(|id| {
+                layer
+                    .id_object_value(id)
+                    .unwrap()
+                    .as_val::<String, String>()
+            })
+            .collect();
+        texts.sort();
+        texts
+    }
+
+    #[tokio::test]
+    async fn search_text_after_rollup_and_squash() {
+        let dir = tempdir().unwrap();
+        let store = open_archive_store(dir.path(), 512).with_text_index(true);
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("pig", "says", "oink"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+        let builder = base.open_write().await.unwrap();
+        builder
+            .remove_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("cow", "says", "moo moo"))
+            .unwrap();
+        let child = builder.commit().await.unwrap();
+
+        let info = store.layer_info(child.name()).await.unwrap().unwrap();
+        assert!(info.section_size(LayerFileEnum::TextIndex).is_some());
+        assert_eq!(vec!["moo moo"], search_text_strings(&child, "moo"));
+
+        child.rollup().await.unwrap();
+        let store = open_archive_store(dir.path(), 512);
+        let rolled_up = store
+            .get_layer_from_id(child.name())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(rolled_up.layer.is_rollup());
+        assert!(rolled_up.layer.text_index().is_some());
+        assert_eq!(vec!["moo moo"], search_text_strings(&rolled_up, "moo"));
+        assert_eq!(vec!["oink"], search_text_strings(&rolled_up, "oink"));
+
+        let squashed = child.squash().await.unwrap();
+        assert!(squashed.layer.text_index().is_some());
+        assert_eq!(vec!["moo moo"], search_text_strings(&squashed, "moo"));
+    }
+
+    #[tokio::test]
+    async fn text_index_is_opt_in() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+
+        let info = store.layer_info(base.name()).await.unwrap().unwrap();
+        assert!(info.section_size(LayerFileEnum::TextIndex).is_none());
+        assert!(base.layer.text_index().is_none());
+        assert_eq!(vec!["moo"], search_text_strings(&base, "moo"));
+
+        let squashed = base.squash().await.unwrap();
+        assert!(squashed.layer.text_index().is_none());
+    }
+
+    async fn rolled_up_animal_layer() -> StoreLayer {
+        // no cache, so changes of the rolled up layer are read from its files
+        let store = Store::new(MemoryLabelStore::new(), MemoryLayerStore::new());
+        let builder = store.create_base_layer().await.unwrap();
+        for animal in ["cow", "duck", "pig"] {
+            builder
+                .add_value_triple(ValueTriple::new_string_value(animal, "says", "hello"))
+                .unwrap();
+            builder
+                .add_value_triple(ValueTriple::new_node(animal, "likes", "cow"))
+                .unwrap();
+        }
+        let base = builder.commit().await.unwrap();
+        let builder = base.open_write().await.unwrap();
+        for animal in ["cow", "pig"] {
+            builder
+                .remove_value_triple(ValueTriple::new_node(animal, "likes", "cow"))
+                .unwrap();
+            builder
+                .add_value_triple(ValueTriple::new_node(animal, "likes", "duck"))
+                .unwrap();
+        }
+        let child = builder.commit().await.unwrap();
+        child.rollup().await.unwrap();
+        let child = store
+            .get_layer_from_id(child.name())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(child.layer.is_rollup());
+
+        child
+    }
+
+    #[tokio::test]
+    async fn count_matching_over_rollup() {
+        let child = rolled_up_animal_layer().await;
+        let builder = child.open_write().await.unwrap();
+        builder
+            .remove_value_triple(ValueTriple::new_node("duck", "likes", "cow"))
+            .unwrap();
+        builder
+            .remove_value_triple(ValueTriple::new_string_value("pig", "says", "hello"))
+            .unwrap();
+        let top = builder.commit().await.unwrap();
+
+        let likes = top.predicate_id("likes").unwrap();
+        let says = top.predicate_id("says").unwrap();
+        let cow = top.subject_id("cow").unwrap();
+        let pig = top.subject_id("pig").unwrap();
+        let duck = top.object_node_id("duck").unwrap();
+        let cow_object = top.object_node_id("cow").unwrap();
+
+        for (count, triples) in [
+            (top.count_p(likes), top.triples_p(likes).count()),
+            (top.count_p(says), top.triples_p(says).count()),
+            (top.count_s(cow), top.triples_s(cow).count()),
+            (top.count_s(pig), top.triples_s(pig).count()),
+            (top.count_sp(pig, says), top.triples_sp(pig, says).count()),
+            (top.count_o(duck), top.triples_o(duck).count()),
+            (top.count_o(cow_object), top.triples_o(cow_object).count()),
+        ] {
+            assert_eq!(triples, count);
+        }
+        assert_eq!(2, top.count_p(likes));
+        assert_eq!(0, top.count_o(cow_object));
+        assert_eq!(1, top.count_s(pig));
+    }
+
+    #[tokio::test]
+    async fn page_through_layer_changes_after_rollup() {
+        let child = rolled_up_animal_layer().await;
+        let likes = child.predicate_id("likes").unwrap();
+        let cow = child.object_node_id("cow").unwrap();
+        for pattern in [
+            TriplePattern::All,
+            TriplePattern::Predicate(likes),
+            TriplePattern::Object(cow),
+        ] {
+            for additions in [true, false] {
+                let expected: Vec<_> = match additions {
+                    true => child.triple_additions().await.unwrap(),
+                    false => child.triple_removals().await.unwrap(),
+                }
+                .filter(|t| pattern.matches(t))
+                .collect();
+
+                // fetch one triple at a time, carrying the cursor over as bytes
+                let mut paged = Vec::new();
+                let mut cursor = TripleCursor::new(pattern).to_bytes();
+                loop {
+                    let cursor_in = TripleCursor::from_bytes(&cursor).unwrap();
+                    let mut iter = match additions {
+                        true => child.triple_additions_from_cursor(&cursor_in).await,
+                        false => child.triple_removals_from_cursor(&cursor_in).await,
+                    }
+                    .unwrap();
+                    match iter.next() {
+                        Some(triple) => paged.push(triple),
+                        None => break,
+                    }
+                    cursor = iter.cursor().to_bytes();
+                }
+                assert_eq!(expected, paged);
+            }
+
+            let expected: Vec<_> = child.triples().filter(|t| pattern.matches(t)).collect();
+            let mut iter = child.triples_from_cursor(&TripleCursor::new(pattern));
+            let first = iter.next().unwrap();
+            let rest: Vec<_> = child.triples_from_cursor(&iter.cursor()).collect();
+            assert_eq!(expected, [vec![first], rest].concat());
+        }
+    }
+
+    #[tokio::test]
+    async fn count_layer_changes_after_rollup() {
+        let child = rolled_up_animal_layer().await;
+        let likes = child.predicate_id("likes").unwrap();
+        let cow = child.object_node_id("cow").unwrap();
+        let pig = child.subject_id("pig").unwrap();
+        for pattern in [
+            TriplePattern::All,
+            TriplePattern::Subject(pig),
+            TriplePattern::SubjectPredicate(pig, likes),
+            TriplePattern::Predicate(likes),
+            TriplePattern::Object(cow),
+        ] {
+            let additions = child
+                .triple_additions()
+                .await
+                .unwrap()
+                .filter(|t| pattern.matches(t))
+                .count();
+            let removals = child
+                .triple_removals()
+                .await
+                .unwrap()
+                .filter(|t| pattern.matches(t))
+                .count();
+            assert_eq!(
+                additions,
+                child
+                    .triple_layer_addition_count_matching(pattern)
+                    .await
+                    .unwrap()
+            );
+            assert_eq!(
+                removals,
+                child
+                    .triple_layer_removal_count_matching(pattern)
+                    .await
+                    .unwrap()
+            );
+        }
+
+        assert_eq!(3, child.count_p(likes));
+        assert_eq!(1, child.count_o(cow));
+        assert_eq!(2, child.count_s(pig));
+        assert_eq!(1, child.count_sp(pig, likes));
+    }
 }
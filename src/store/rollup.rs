@@ -0,0 +1,253 @@
+//! Automatic rollups of layer stacks.
+//!
+//! Queries get slower the more layers a stack has. A `RollupPolicy`
+//! set on a `Store` decides when a stack has grown enough to be
+//! rolled up, after which the store rolls it up in the background.
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use tokio::task::JoinHandle;
+
+use crate::layer::{InternalLayer, Layer};
+
+/// A policy deciding when a layer stack is rolled up automatically.
+///
+/// A stack is measured from its top layer down to the nearest rollup
+/// or base layer. Once any of the configured limits is exceeded, the
+/// top layer gets rolled up to that nearest rollup or base layer, so
+/// the cost of a rollup depends on the changes made since then rather
+/// than on the size of the whole stack. Limits that are not set are
+/// not checked, so the default policy never rolls up anything.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RollupPolicy {
+    /// The maximum amount of layers on top of the nearest rollup or base layer.
+    pub max_depth: Option<usize>,
+    /// The maximum amount of triple additions and removals in the layers on top of the nearest rollup or base layer.
+    pub max_changes: Option<usize>,
+    /// The maximum ratio between the triple additions and removals on top of the nearest rollup or base layer, and the amount of triples in that layer.
+    ///
+    /// This is not checked while that layer has no triples.
+    pub max_change_ratio: Option<f64>,
+}
+
+impl RollupPolicy {
+    /// Construct a policy without any limits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Roll up once there are more than `depth` layers on top of the nearest rollup or base layer.
+    pub fn with_max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Roll up once the layers on top of the nearest rollup or base layer made more than `changes` changes.
+    pub fn with_max_changes(mut self, changes: usize) -> Self {
+        self.max_changes = Some(changes);
+        self
+    }
+
+    /// Roll up once the changes on top of the nearest rollup or base layer exceed `ratio` times its size.
+    pub fn with_max_change_ratio(mut self, ratio: f64) -> Self {
+        self.max_change_ratio = Some(ratio);
+        self
+    }
+
+    /// Returns true if the stack of the given layer should be rolled up.
+    pub fn should_roll_up(&self, layer: &InternalLayer) -> bool {
+        self.rollup_target(layer).is_some()
+    }
+
+    /// Returns the ancestor to roll the given layer up to, or None if it should not be rolled up.
+    ///
+    /// This is the nearest rollup or base layer below the given layer.
+    pub(crate) fn rollup_target(&self, layer: &InternalLayer) -> Option<[u32; 5]> {
+        let mut depth = 0;
+        let mut changes = 0;
+        let mut current = layer;
+        while let InternalLayer::Child(_) = current {
+            depth += 1;
+            changes += current.internal_triple_layer_addition_count()
+                + current.internal_triple_layer_removal_count();
+            current = current
+                .immediate_parent()
+                .expect("child layer should have a parent");
+        }
+
+        if depth < 2 {
+            // a single layer on top of a rollup or base layer gains nothing from a rollup
+            return None;
+        }
+
+        let size = current.triple_count();
+        let exceeded = self.max_depth.map(|max| depth > max).unwrap_or(false)
+            || self.max_changes.map(|max| changes > max).unwrap_or(false)
+            || self
+                .max_change_ratio
+                .map(|max| size != 0 && changes as f64 > max * size as f64)
+                .unwrap_or(false);
+
+        if exceeded {
+            Some(current.name())
+        } else {
+            None
+        }
+    }
+}
+
+/// A rollup that was registered for a layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RollupInfo {
+    /// The layer that was rolled up.
+    pub layer: [u32; 5],
+    /// The rollup layer, which is loaded in place of the rolled up layer.
+    pub rollup: [u32; 5],
+    /// The ancestor that the stack was rolled up to, or None if the whole stack was rolled up.
+    pub upto: Option<[u32; 5]>,
+}
+
+/// Keeps track of the rollups that a store is performing because of its policy.
+pub(crate) struct RollupScheduler {
+    policy: RollupPolicy,
+    running: Mutex<HashSet<[u32; 5]>>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl RollupScheduler {
+    pub(crate) fn new(policy: RollupPolicy) -> Self {
+        Self {
+            policy,
+            running: Mutex::new(HashSet::new()),
+            tasks: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Keep track of a rollup task that was spawned in the background.
+    pub(crate) fn spawned(&self, task: JoinHandle<()>) {
+        let mut tasks = self.tasks.lock().expect("mutex lock should always succeed");
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(task);
+    }
+
+    /// Take all background rollup tasks that were spawned so far.
+    pub(crate) fn take_tasks(&self) -> Vec<JoinHandle<()>> {
+        std::mem::take(&mut *self.tasks.lock().expect("mutex lock should always succeed"))
+    }
+
+    pub(crate) fn policy(&self) -> &RollupPolicy {
+        &self.policy
+    }
+
+    /// Mark a rollup of the given layer as running, returning false if it already was.
+    pub(crate) fn start(&self, layer: [u32; 5]) -> bool {
+        self.running
+            .lock()
+            .expect("mutex lock should always succeed")
+            .insert(layer)
+    }
+
+    /// Mark a rollup of the given layer as no longer running.
+    pub(crate) fn finish(&self, layer: [u32; 5]) {
+        self.running
+            .lock()
+            .expect("mutex lock should always succeed")
+            .remove(&layer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::ValueTriple;
+    use crate::storage::memory::MemoryLayerStore;
+    use crate::storage::LayerStore;
+
+    async fn stack(changes_per_layer: &[usize]) -> std::sync::Arc<InternalLayer> {
+        let store = MemoryLayerStore::new();
+        let mut parent: Option<[u32; 5]> = None;
+        for (i, changes) in changes_per_layer.iter().enumerate() {
+            let mut builder = match parent {
+                None => store.create_base_layer().await.unwrap(),
+                Some(parent) => store.create_child_layer(parent).await.unwrap(),
+            };
+            for j in 0..*changes {
+                builder.add_value_triple(ValueTriple::new_string_value(
+                    &format!("s{}", i),
+                    "p",
+                    &j.to_string(),
+                ));
+            }
+            let name = builder.name();
+            builder.commit_boxed().await.unwrap();
+            store.finalize_layer(name).await.unwrap();
+            parent = Some(name);
+        }
+
+        store.get_layer(parent.unwrap()).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn default_policy_never_rolls_up() {
+        let layer = stack(&[10, 1, 1, 1, 1]).await;
+        assert!(!RollupPolicy::new().should_roll_up(&layer));
+    }
+
+    #[tokio::test]
+    async fn policy_limits() {
+        let layer = stack(&[10, 1, 2, 3]).await;
+        assert!(RollupPolicy::new().with_max_depth(2).should_roll_up(&layer));
+        assert!(!RollupPolicy::new().with_max_depth(3).should_roll_up(&layer));
+        assert!(RollupPolicy::new()
+            .with_max_changes(5)
+            .should_roll_up(&layer));
+        assert!(!RollupPolicy::new()
+            .with_max_changes(6)
+            .should_roll_up(&layer));
+        assert!(RollupPolicy::new()
+            .with_max_change_ratio(0.5)
+            .should_roll_up(&layer));
+        assert!(!RollupPolicy::new()
+            .with_max_change_ratio(0.6)
+            .should_roll_up(&layer));
+    }
+
+    #[tokio::test]
+    async fn base_layer_is_never_rolled_up() {
+        let layer = stack(&[10]).await;
+        assert!(!RollupPolicy::new().with_max_depth(0).should_roll_up(&layer));
+    }
+
+    #[tokio::test]
+    async fn single_layer_is_never_rolled_up() {
+        let layer = stack(&[10, 100]).await;
+        assert!(!RollupPolicy::new().with_max_depth(0).should_roll_up(&layer));
+        assert!(!RollupPolicy::new()
+            .with_max_changes(0)
+            .should_roll_up(&layer));
+    }
+
+    #[tokio::test]
+    async fn change_ratio_is_not_checked_on_empty_base_layer() {
+        let layer = stack(&[0, 1, 1]).await;
+        assert!(!RollupPolicy::new()
+            .with_max_change_ratio(0.5)
+            .should_roll_up(&layer));
+        assert!(RollupPolicy::new()
+            .with_max_changes(1)
+            .should_roll_up(&layer));
+    }
+
+    #[tokio::test]
+    async fn rollup_target_is_nearest_base_layer() {
+        let layer = stack(&[10, 1, 2, 3]).await;
+        let mut base = layer.as_ref();
+        while let Some(parent) = base.immediate_parent() {
+            base = parent;
+        }
+        assert_eq!(
+            Some(base.name()),
+            RollupPolicy::new().with_max_depth(2).rollup_target(&layer)
+        );
+    }
+}
@@ -5,19 +5,27 @@
 //! without any futures. This is done by wrapping all the async calls
 //! in a sync wrapper that runs on a tokio runtime managed by this
 //! module.
-use futures::Future;
+use futures::{Future, StreamExt};
 use tokio::runtime::Runtime;
 
 use std::io;
 use std::path::{Path, PathBuf};
 
-use crate::layer::{IdTriple, Layer, LayerBuilder, LayerCounts, ObjectType, ValueTriple};
+use crate::layer::{
+    IdQuadTriple, IdTriple, IdTripleStream, Layer, LayerBuilder, LayerCounts, ObjectType,
+    TripleChange, TripleCursor, TripleCursorIterator, TriplePattern, ValueQuadTriple, ValueTriple,
+};
+use crate::storage::{LabelChange, LayerInfo, Progress, StoreMetrics};
 use crate::store::{
-    open_directory_store, open_memory_store, NamedGraph, Store, StoreLayer, StoreLayerBuilder,
+    open_directory_store, open_memory_store, CopyOptions, CopyStats, LayerSubjectChanges,
+    NamedGraph, RollupInfo, Store, StoreLayer, StoreLayerBuilder, StoreLayerBuilderShard,
 };
 use tdb_succinct::TypedDictEntry;
 
-use super::{open_archive_store, open_raw_archive_store};
+use super::{
+    open_archive_store, open_archive_store_checked, open_directory_store_checked,
+    open_raw_archive_store,
+};
 
 lazy_static! {
     static ref RUNTIME: Runtime = Runtime::new().unwrap();
@@ -83,6 +91,33 @@ impl SyncStoreLayerBuilder {
         self.inner.remove_id_triple(triple)
     }
 
+    /// Add a string triple and put it in the given graph.
+    pub fn add_value_quad(&self, quad: ValueQuadTriple) -> Result<(), io::Error> {
+        self.inner.add_value_quad(quad)
+    }
+
+    /// Add an id triple and put it in the given graph.
+    pub fn add_id_quad(&self, quad: IdQuadTriple) -> Result<(), io::Error> {
+        self.inner.add_id_quad(quad)
+    }
+
+    /// Take a string triple out of the given graph, leaving the triple itself in place.
+    pub fn remove_value_quad(&self, quad: ValueQuadTriple) -> Result<(), io::Error> {
+        self.inner.remove_value_quad(quad)
+    }
+
+    /// Take an id triple out of the given graph, leaving the triple itself in place.
+    pub fn remove_id_quad(&self, quad: IdQuadTriple) -> Result<(), io::Error> {
+        self.inner.remove_id_quad(quad)
+    }
+
+    /// Create a new shard of this builder, to add triples through from a single thread.
+    ///
+    /// All shards are merged into this builder when it is committed.
+    pub fn shard(&self) -> Result<StoreLayerBuilderShard, io::Error> {
+        self.inner.shard()
+    }
+
     /// Returns a boolean result which is true if this builder has been committed, and false otherwise.
     pub fn committed(&self) -> bool {
         self.inner.committed()
@@ -100,6 +135,13 @@ impl SyncStoreLayerBuilder {
         inner.map(SyncStoreLayer::wrap)
     }
 
+    /// Commit the layer to storage, reporting to and cancellable through `progress`.
+    pub fn commit_with_progress(&self, progress: &Progress) -> Result<SyncStoreLayer, io::Error> {
+        let inner = task_sync(self.inner.commit_with_progress(progress));
+
+        inner.map(SyncStoreLayer::wrap)
+    }
+
     /// Apply all triples added and removed by a layer to this builder.
     ///
     /// This is a way to 'cherry-pick' a layer on top of another
@@ -143,18 +185,90 @@ impl SyncStoreLayer {
         inner.map(SyncStoreLayerBuilder::wrap)
     }
 
+    /// Create a layer builder on top of this layer that undoes the changes of `target`.
+    ///
+    /// See `StoreLayer::open_revert`.
+    pub fn open_revert(&self, target: &SyncStoreLayer) -> Result<SyncRevert, io::Error> {
+        let revert = task_sync(self.inner.open_revert(&target.inner))?;
+
+        Ok(SyncRevert {
+            builder: SyncStoreLayerBuilder::wrap(revert.builder),
+            skipped: revert.skipped,
+        })
+    }
+
     /// Returns the parent of this layer, if any, or None if this layer has no parent.
     pub fn parent(&self) -> Result<Option<SyncStoreLayer>, io::Error> {
         let inner = task_sync(self.inner.parent());
         inner.map(|p| p.map(|p| SyncStoreLayer { inner: p }))
     }
 
+    /// Returns the ancestor `n` layers below this one, or None if the stack is not that deep.
+    ///
+    /// See `StoreLayer::ancestor`.
+    pub fn ancestor(&self, n: usize) -> Result<Option<SyncStoreLayer>, io::Error> {
+        let inner = task_sync(self.inner.ancestor(n));
+        inner.map(|l| l.map(SyncStoreLayer::wrap))
+    }
+
+    /// Returns the ancestor with the given name, or None if no layer in this stack has that name.
+    pub fn ancestor_by_name(&self, name: [u32; 5]) -> Result<Option<SyncStoreLayer>, io::Error> {
+        let inner = task_sync(self.inner.ancestor_by_name(name));
+        inner.map(|l| l.map(SyncStoreLayer::wrap))
+    }
+
+    /// Returns all changes to the given triple in this stack, from the oldest to the newest change.
+    ///
+    /// See `StoreLayer::triple_history`.
+    pub fn triple_history(
+        &self,
+        triple: &IdTriple,
+    ) -> Result<Vec<([u32; 5], TripleChange)>, io::Error> {
+        task_sync(self.inner.triple_history(triple))
+    }
+
+    /// Returns all changes to the given triple in this stack, from the oldest to the newest change.
+    pub fn value_triple_history(
+        &self,
+        triple: &ValueTriple,
+    ) -> Result<Vec<([u32; 5], TripleChange)>, io::Error> {
+        task_sync(self.inner.value_triple_history(triple))
+    }
+
+    /// Returns the triples with the given subject that were added or removed in this stack.
+    ///
+    /// See `StoreLayer::subject_history`.
+    pub fn subject_history(&self, subject: u64) -> Result<Vec<LayerSubjectChanges>, io::Error> {
+        task_sync(self.inner.subject_history(subject))
+    }
+
+    /// Returns the rollup registered for this layer, if any.
+    pub fn rollup_info(&self) -> Result<Option<RollupInfo>, io::Error> {
+        task_sync(self.inner.rollup_info())
+    }
+
+    /// Returns the rollups registered for this layer and all its ancestors, starting at this layer.
+    pub fn rollups(&self) -> Result<Vec<RollupInfo>, io::Error> {
+        task_sync(self.inner.rollups())
+    }
+
     pub fn squash_upto(&self, upto: &SyncStoreLayer) -> Result<SyncStoreLayer, io::Error> {
         let inner = task_sync(self.inner.clone().squash_upto(&upto.inner));
 
         inner.map(SyncStoreLayer::wrap)
     }
 
+    /// Like `squash_upto`, reporting to and cancellable through `progress`.
+    pub fn squash_upto_with_progress(
+        &self,
+        upto: &SyncStoreLayer,
+        progress: &Progress,
+    ) -> Result<SyncStoreLayer, io::Error> {
+        let inner = task_sync(self.inner.squash_upto_with_progress(&upto.inner, progress));
+
+        inner.map(SyncStoreLayer::wrap)
+    }
+
     /// Create a new base layer consisting of all triples in this layer, as well as all its ancestors.
     ///
     /// It is a good idea to keep layer stacks small, meaning, to only
@@ -169,6 +283,24 @@ impl SyncStoreLayer {
         inner.map(SyncStoreLayer::wrap)
     }
 
+    /// Like `squash`, keeping memory use bounded by writing intermediate results to `temp_dir`.
+    pub fn squash_external(&self, temp_dir: &Path) -> Result<SyncStoreLayer, io::Error> {
+        let inner = task_sync(self.inner.squash_external(temp_dir));
+
+        inner.map(SyncStoreLayer::wrap)
+    }
+
+    /// Like `squash_upto`, keeping memory use bounded by writing intermediate results to `temp_dir`.
+    pub fn squash_upto_external(
+        &self,
+        upto: &SyncStoreLayer,
+        temp_dir: &Path,
+    ) -> Result<SyncStoreLayer, io::Error> {
+        let inner = task_sync(self.inner.squash_upto_external(&upto.inner, temp_dir));
+
+        inner.map(SyncStoreLayer::wrap)
+    }
+
     /// Create a new rollup layer which rolls up all triples in this layer, as well as all its ancestors.
     ///
     /// It is a good idea to keep layer stacks small, meaning, to only
@@ -191,6 +323,15 @@ impl SyncStoreLayer {
         task_sync(self.inner.clone().rollup_upto(&upto.inner))
     }
 
+    /// Like `rollup_upto`, reporting to and cancellable through `progress`.
+    pub fn rollup_upto_with_progress(
+        &self,
+        upto: &SyncStoreLayer,
+        progress: &Progress,
+    ) -> Result<(), io::Error> {
+        task_sync(self.inner.rollup_upto_with_progress(&upto.inner, progress))
+    }
+
     /// Like rollup_upto, rolls up upto the given layer. However, if
     /// this layer is a rollup layer, this will roll up upto that
     /// rollup.
@@ -243,6 +384,22 @@ impl SyncStoreLayer {
         task_sync(self.inner.triple_removals())
     }
 
+    /// Returns a stream over all layer additions.
+    ///
+    /// The stream yields to the runtime in between chunks of triples,
+    /// making it suitable for consumption from async code.
+    pub fn triple_additions_stream(&self) -> io::Result<IdTripleStream> {
+        self.triple_additions().map(IdTripleStream::new)
+    }
+
+    /// Returns a stream over all layer removals.
+    ///
+    /// The stream yields to the runtime in between chunks of triples,
+    /// making it suitable for consumption from async code.
+    pub fn triple_removals_stream(&self) -> io::Result<IdTripleStream> {
+        self.triple_removals().map(IdTripleStream::new)
+    }
+
     /// Returns an iterator over all layer additions that share a particular subject.
     ///
     /// Since this operation will involve io when this layer is a
@@ -333,6 +490,28 @@ impl SyncStoreLayer {
         task_sync(self.inner.triple_removals_o(object))
     }
 
+    /// Returns an iterator over the layer additions matching the cursor's pattern that come after its last triple.
+    ///
+    /// Since this operation will involve io when this layer is a
+    /// rollup layer, io errors may occur.
+    pub fn triple_additions_from_cursor(
+        &self,
+        cursor: &TripleCursor,
+    ) -> io::Result<TripleCursorIterator> {
+        task_sync(self.inner.triple_additions_from_cursor(cursor))
+    }
+
+    /// Returns an iterator over the layer removals matching the cursor's pattern that come after its last triple.
+    ///
+    /// Since this operation will involve io when this layer is a
+    /// rollup layer, io errors may occur.
+    pub fn triple_removals_from_cursor(
+        &self,
+        cursor: &TripleCursor,
+    ) -> io::Result<TripleCursorIterator> {
+        task_sync(self.inner.triple_removals_from_cursor(cursor))
+    }
+
     /// Returns the amount of triples that this layer adds.
     ///
     /// Since this operation will involve io when this layer is a
@@ -349,6 +528,25 @@ impl SyncStoreLayer {
         task_sync(self.inner.triple_layer_removal_count())
     }
 
+    /// Returns the amount of triples that this layer adds which match the pattern.
+    ///
+    /// Since this operation will involve io when this layer is a
+    /// rollup layer, io errors may occur.
+    pub fn triple_layer_addition_count_matching(
+        &self,
+        pattern: TriplePattern,
+    ) -> io::Result<usize> {
+        task_sync(self.inner.triple_layer_addition_count_matching(pattern))
+    }
+
+    /// Returns the amount of triples that this layer removes which match the pattern.
+    ///
+    /// Since this operation will involve io when this layer is a
+    /// rollup layer, io errors may occur.
+    pub fn triple_layer_removal_count_matching(&self, pattern: TriplePattern) -> io::Result<usize> {
+        task_sync(self.inner.triple_layer_removal_count_matching(pattern))
+    }
+
     /// Returns a vector of layer stack names describing the history of this layer, starting from the base layer up to and including the name of this layer itself.
     pub fn retrieve_layer_stack_names(&self) -> io::Result<Vec<[u32; 5]>> {
         task_sync(self.inner.retrieve_layer_stack_names())
@@ -440,6 +638,10 @@ impl Layer for SyncStoreLayer {
         self.inner.triples_o(object)
     }
 
+    fn triples_from_cursor(&self, cursor: &TripleCursor) -> TripleCursorIterator {
+        self.inner.triples_from_cursor(cursor)
+    }
+
     fn clone_boxed(&self) -> Box<dyn Layer> {
         Box::new(self.clone())
     }
@@ -452,6 +654,22 @@ impl Layer for SyncStoreLayer {
         self.inner.triple_removal_count()
     }
 
+    fn count_s(&self, subject: u64) -> usize {
+        self.inner.count_s(subject)
+    }
+
+    fn count_sp(&self, subject: u64, predicate: u64) -> usize {
+        self.inner.count_sp(subject, predicate)
+    }
+
+    fn count_p(&self, predicate: u64) -> usize {
+        self.inner.count_p(predicate)
+    }
+
+    fn count_o(&self, object: u64) -> usize {
+        self.inner.count_o(object)
+    }
+
     fn all_counts(&self) -> LayerCounts {
         self.inner.all_counts()
     }
@@ -459,6 +677,26 @@ impl Layer for SyncStoreLayer {
     fn single_triple_sp(&self, subject: u64, predicate: u64) -> Option<IdTriple> {
         self.inner.single_triple_sp(subject, predicate)
     }
+
+    fn quads_g(&self, graph: u64) -> Box<dyn Iterator<Item = IdQuadTriple> + Send> {
+        self.inner.quads_g(graph)
+    }
+
+    fn triple_graphs(&self, triple: &IdTriple) -> Vec<u64> {
+        self.inner.triple_graphs(triple)
+    }
+
+    fn object_value_ids_in_range(
+        &self,
+        from: &TypedDictEntry,
+        to: &TypedDictEntry,
+    ) -> Box<dyn Iterator<Item = u64> + Send> {
+        self.inner.object_value_ids_in_range(from, to)
+    }
+
+    fn search_text(&self, query: &str) -> Box<dyn Iterator<Item = u64> + Send> {
+        self.inner.search_text(query)
+    }
 }
 
 /// A named graph in terminus-store.
@@ -515,6 +753,14 @@ impl SyncNamedGraph {
     pub fn delete(&self) -> io::Result<()> {
         task_sync(self.inner.delete())
     }
+
+    /// Watch this database label, blocking on every change until the next one comes in.
+    ///
+    /// The iterator ends when the label is deleted.
+    pub fn watch(&self) -> io::Result<impl Iterator<Item = LabelChange>> {
+        let mut changes = Box::pin(task_sync(self.inner.watch())?);
+        Ok(std::iter::from_fn(move || task_sync(changes.next())))
+    }
 }
 
 /// A store, storing a set of layers and database labels pointing to these layers.
@@ -523,6 +769,26 @@ pub struct SyncStore {
     inner: Store,
 }
 
+/// A builder that undoes the changes of a layer, as created by `SyncStoreLayer::open_revert`.
+pub struct SyncRevert {
+    /// The builder containing the undone changes.
+    pub builder: SyncStoreLayerBuilder,
+    /// Changes of the reverted layer that were changed again later, and are therefore not undone.
+    pub skipped: Vec<(TripleChange, ValueTriple)>,
+}
+
+/// A layer that was replayed on top of a new parent by `SyncStore::rebase`.
+pub struct SyncRebasedLayer {
+    /// The name of the layer that was replayed.
+    pub original: [u32; 5],
+    /// The newly built layer.
+    pub layer: SyncStoreLayer,
+    /// Changes of the original layer that the new parent already contained.
+    pub noops: Vec<(TripleChange, ValueTriple)>,
+    /// Changes of the original layer that undo a change between the old and the new base.
+    pub conflicts: Vec<(TripleChange, ValueTriple)>,
+}
+
 impl SyncStore {
     /// wrap an asynchronous `Store`, running all futures on a lazily-constructed tokio runtime.
     pub fn wrap(inner: Store) -> Self {
@@ -584,6 +850,105 @@ impl SyncStore {
         Ok(inner)
     }
 
+    /// Like `merge_base_layers`, reporting to and cancellable through `progress`.
+    pub fn merge_base_layers_with_progress(
+        &self,
+        layers: &[[u32; 5]],
+        temp_dir: &Path,
+        progress: &Progress,
+    ) -> Result<[u32; 5], io::Error> {
+        task_sync(
+            self.inner
+                .merge_base_layers_with_progress(layers, temp_dir, progress),
+        )
+    }
+
+    /// Replay the layers between `old_base` and `stack_top` on top of `new_base`.
+    ///
+    /// See `Store::rebase` for what is reported for each replayed layer.
+    pub fn rebase(
+        &self,
+        stack_top: &SyncStoreLayer,
+        old_base: &SyncStoreLayer,
+        new_base: &SyncStoreLayer,
+    ) -> Result<Vec<SyncRebasedLayer>, io::Error> {
+        let rebased = task_sync(self.inner.rebase(
+            &stack_top.inner,
+            &old_base.inner,
+            &new_base.inner,
+        ))?;
+
+        Ok(rebased
+            .into_iter()
+            .map(|r| SyncRebasedLayer {
+                original: r.original,
+                layer: SyncStoreLayer::wrap(r.layer),
+                noops: r.noops,
+                conflicts: r.conflicts,
+            })
+            .collect())
+    }
+
+    /// Roll up the given layer if its stack exceeds the rollup policy, returning whether it was rolled up.
+    ///
+    /// See `Store::apply_rollup_policy`.
+    pub fn apply_rollup_policy(&self, layer: &SyncStoreLayer) -> Result<bool, io::Error> {
+        task_sync(self.inner.apply_rollup_policy(&layer.inner))
+    }
+
+    /// Merge several child layers of the same parent into a single child layer of that parent.
+    ///
+    /// See `Store::merge_child_layers` for how conflicting changes are resolved.
+    pub fn merge_child_layers(
+        &self,
+        parent: [u32; 5],
+        layers: &[[u32; 5]],
+    ) -> Result<[u32; 5], io::Error> {
+        task_sync(self.inner.merge_child_layers(parent, layers))
+    }
+
+    /// Create a base layer out of an iterator of triples, without keeping all of them in memory.
+    ///
+    /// See `Store::bulk_load_base_layer`.
+    pub fn bulk_load_base_layer<I: Iterator<Item = ValueTriple> + Send>(
+        &self,
+        triples: I,
+        temp_dir: &Path,
+    ) -> Result<[u32; 5], io::Error> {
+        task_sync(
+            self.inner
+                .bulk_load_base_layer(futures::stream::iter(triples), temp_dir),
+        )
+    }
+
+    /// Like `bulk_load_base_layer`, reporting to and cancellable through `progress`.
+    pub fn bulk_load_base_layer_with_progress<I: Iterator<Item = ValueTriple> + Send>(
+        &self,
+        triples: I,
+        temp_dir: &Path,
+        progress: &Progress,
+    ) -> Result<[u32; 5], io::Error> {
+        task_sync(self.inner.bulk_load_base_layer_with_progress(
+            futures::stream::iter(triples),
+            temp_dir,
+            progress,
+        ))
+    }
+
+    /// Create a base layer out of an iterator of quads, without keeping all of them in memory.
+    ///
+    /// See `Store::bulk_load_base_layer_quads`.
+    pub fn bulk_load_base_layer_quads<I: Iterator<Item = ValueQuadTriple> + Send>(
+        &self,
+        quads: I,
+        temp_dir: &Path,
+    ) -> Result<[u32; 5], io::Error> {
+        task_sync(
+            self.inner
+                .bulk_load_base_layer_quads(futures::stream::iter(quads), temp_dir),
+        )
+    }
+
     /// Export the given layers by creating a pack, a Vec<u8> that can later be used with `import_layers` on a different store.
     pub fn export_layers(
         &self,
@@ -604,6 +969,28 @@ impl SyncStore {
     ) -> io::Result<()> {
         task_sync(self.inner.layer_store.import_layers(pack, layer_ids))
     }
+
+    /// Returns metadata about the given layer, or None if it does not exist.
+    pub fn layer_info(&self, layer: [u32; 5]) -> io::Result<Option<LayerInfo>> {
+        task_sync(self.inner.layer_info(layer))
+    }
+
+    /// Returns metadata about every layer in this store, ordered by name.
+    pub fn layers_info(&self) -> io::Result<Vec<LayerInfo>> {
+        task_sync(self.inner.layers_info())
+    }
+
+    /// Copy every layer and label of this store into the target store.
+    ///
+    /// An interrupted copy can be resumed by calling this again.
+    pub fn copy_into(&self, target: &SyncStore, options: CopyOptions) -> io::Result<CopyStats> {
+        task_sync(self.inner.copy_into(&target.inner, options))
+    }
+
+    /// Return a snapshot of the cache and layer loading metrics of this store.
+    pub fn metrics(&self) -> StoreMetrics {
+        task_sync(self.inner.metrics())
+    }
 }
 
 /// Open a store that is entirely in memory.
@@ -634,6 +1021,21 @@ pub fn open_sync_raw_archive_store<P: Into<PathBuf>>(path: P) -> SyncStore {
     SyncStore::wrap(open_raw_archive_store(path))
 }
 
+/// Open a directory store like `open_sync_directory_store`, after checking the store version.
+pub fn open_sync_directory_store_checked<P: Into<PathBuf>>(path: P) -> io::Result<SyncStore> {
+    let path = path.into();
+    task_sync(open_directory_store_checked(path)).map(SyncStore::wrap)
+}
+
+/// Open an archive store like `open_sync_archive_store`, after checking the store version.
+pub fn open_sync_archive_store_checked<P: Into<PathBuf>>(
+    path: P,
+    cache_size: usize,
+) -> io::Result<SyncStore> {
+    let path = path.into();
+    task_sync(open_archive_store_checked(path, cache_size)).map(SyncStore::wrap)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -784,4 +1186,41 @@ mod tests {
         assert!(result_layer
             .value_triple_exists(&ValueTriple::new_string_value("horse", "says", "neigh")));
     }
+
+    #[test]
+    fn sync_triple_streams_match_iterators() {
+        let store = open_sync_memory_store();
+        let builder = store.create_base_layer().unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_node("cow", "likes", "pig"))
+            .unwrap();
+        let layer = builder.commit().unwrap();
+
+        let triples: Vec<_> = futures::executor::block_on_stream(layer.triples_stream()).collect();
+        assert_eq!(layer.triples().collect::<Vec<_>>(), triples);
+
+        let additions: Vec<_> =
+            futures::executor::block_on_stream(layer.triple_additions_stream().unwrap()).collect();
+        assert_eq!(triples, additions);
+    }
+
+    #[test]
+    fn watch_sync_database() {
+        let store = open_sync_memory_store();
+        let database = store.create("foodb").unwrap();
+        let mut changes = database.watch().unwrap();
+
+        let layer = store.create_base_layer().unwrap().commit().unwrap();
+        assert!(database.set_head(&layer).unwrap());
+
+        let change = changes.next().unwrap();
+        assert_eq!(None, change.previous_layer);
+        assert_eq!(Some(layer.name()), change.label.layer);
+
+        database.delete().unwrap();
+        assert!(changes.next().is_none());
+    }
 }
@@ -0,0 +1,474 @@
+//! Validating layers before they are committed.
+//!
+//! A `CommitValidator` registered on a store gets to see every layer
+//! that is committed through a `StoreLayerBuilder`, before anything is
+//! written. If any validator reports a violation, the commit fails
+//! with a `ValidationError`, and the layer never comes into existence.
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use crate::layer::{IdTriple, Layer, ObjectType, ValueTriple};
+use crate::storage::name_to_string;
+
+/// A check that every layer committed to a store has to pass.
+///
+/// Validators are registered with `Store::with_commit_validator`.
+pub trait CommitValidator: Send + Sync {
+    /// Check the layer that is about to be committed, returning everything that is wrong with it.
+    ///
+    /// An empty result lets the commit through.
+    fn validate(&self, layer: &PendingLayer) -> Vec<Violation>;
+}
+
+impl<F: Fn(&PendingLayer) -> Vec<Violation> + Send + Sync> CommitValidator for F {
+    fn validate(&self, layer: &PendingLayer) -> Vec<Violation> {
+        self(layer)
+    }
+}
+
+/// A layer that is about to be committed, as seen by a `CommitValidator`.
+///
+/// Besides the changes made in this layer, this gives access to the
+/// graph as it will be once the layer is committed.
+pub struct PendingLayer {
+    name: [u32; 5],
+    parent: Option<Arc<dyn Layer>>,
+    additions: Vec<ValueTriple>,
+    removals: Vec<ValueTriple>,
+}
+
+impl PendingLayer {
+    /// Construct a pending layer out of the changes made in a builder.
+    ///
+    /// Just like on commit, a triple that is both added and removed is
+    /// left alone, and changes that have no effect on the parent are
+    /// dropped.
+    pub(super) fn new(
+        name: [u32; 5],
+        parent: Option<Arc<dyn Layer>>,
+        mut additions: Vec<ValueTriple>,
+        mut removals: Vec<ValueTriple>,
+    ) -> Self {
+        additions.sort();
+        additions.dedup();
+        removals.sort();
+        removals.dedup();
+
+        let in_parent = |triple: &ValueTriple| {
+            parent
+                .as_ref()
+                .map(|p| p.value_triple_exists(triple))
+                .unwrap_or(false)
+        };
+        let effective_additions = additions
+            .iter()
+            .filter(|t| removals.binary_search(t).is_err() && !in_parent(t))
+            .cloned()
+            .collect();
+        let effective_removals = removals
+            .iter()
+            .filter(|t| additions.binary_search(t).is_err() && in_parent(t))
+            .cloned()
+            .collect();
+
+        Self {
+            name,
+            parent,
+            additions: effective_additions,
+            removals: effective_removals,
+        }
+    }
+
+    /// Returns the name of the layer being committed.
+    pub fn name(&self) -> [u32; 5] {
+        self.name
+    }
+
+    /// Returns the layer this layer is built on top of, if any.
+    pub fn parent(&self) -> Option<&Arc<dyn Layer>> {
+        self.parent.as_ref()
+    }
+
+    /// Returns the triples this layer adds, in sorted order.
+    pub fn additions(&self) -> &[ValueTriple] {
+        &self.additions
+    }
+
+    /// Returns the triples this layer removes, in sorted order.
+    pub fn removals(&self) -> &[ValueTriple] {
+        &self.removals
+    }
+
+    /// Returns true if the given triple will exist once this layer is committed.
+    pub fn value_triple_exists(&self, triple: &ValueTriple) -> bool {
+        self.additions.binary_search(triple).is_ok()
+            || (self.removals.binary_search(triple).is_err()
+                && self
+                    .parent
+                    .as_ref()
+                    .map(|p| p.value_triple_exists(triple))
+                    .unwrap_or(false))
+    }
+
+    /// Returns true if the given node will be the subject of any triple once this layer is committed.
+    pub fn subject_exists(&self, subject: &str) -> bool {
+        self.value_triples_s(subject).next().is_some()
+    }
+
+    /// Iterate over all triples that will exist once this layer is committed.
+    ///
+    /// The remaining triples of the parent come first, followed by the additions.
+    pub fn value_triples(&self) -> Box<dyn Iterator<Item = ValueTriple> + '_> {
+        self.resulting_triples(self.parent.as_ref().map(|p| p.triples()), |_| true)
+    }
+
+    /// Iterate over all triples with the given subject that will exist once this layer is committed.
+    pub fn value_triples_s<'a>(
+        &'a self,
+        subject: &'a str,
+    ) -> Box<dyn Iterator<Item = ValueTriple> + 'a> {
+        let existing = self
+            .parent
+            .as_ref()
+            .and_then(|p| Some(p.triples_s(p.subject_id(subject)?)));
+        self.resulting_triples(existing, move |t| t.subject == subject)
+    }
+
+    /// Iterate over all triples with the given subject and predicate that will exist once this layer is committed.
+    pub fn value_triples_sp<'a>(
+        &'a self,
+        subject: &'a str,
+        predicate: &'a str,
+    ) -> Box<dyn Iterator<Item = ValueTriple> + 'a> {
+        let existing = self
+            .parent
+            .as_ref()
+            .and_then(|p| Some(p.triples_sp(p.subject_id(subject)?, p.predicate_id(predicate)?)));
+        self.resulting_triples(existing, move |t| {
+            t.subject == subject && t.predicate == predicate
+        })
+    }
+
+    /// Iterate over all triples with the given predicate that will exist once this layer is committed.
+    pub fn value_triples_p<'a>(
+        &'a self,
+        predicate: &'a str,
+    ) -> Box<dyn Iterator<Item = ValueTriple> + 'a> {
+        let existing = self
+            .parent
+            .as_ref()
+            .and_then(|p| Some(p.triples_p(p.predicate_id(predicate)?)));
+        self.resulting_triples(existing, move |t| t.predicate == predicate)
+    }
+
+    /// Iterate over all triples with the given object that will exist once this layer is committed.
+    pub fn value_triples_o<'a>(
+        &'a self,
+        object: &'a ObjectType,
+    ) -> Box<dyn Iterator<Item = ValueTriple> + 'a> {
+        let existing = self.parent.as_ref().and_then(|p| {
+            let id = match object {
+                ObjectType::Node(node) => p.object_node_id(node),
+                ObjectType::Value(value) => p.object_value_id(value),
+            };
+            Some(p.triples_o(id?))
+        });
+        self.resulting_triples(existing, move |t| &t.object == object)
+    }
+
+    /// Combine triples from the parent with the additions that match the filter, leaving out removals.
+    fn resulting_triples<'a, F: 'a + Fn(&ValueTriple) -> bool>(
+        &'a self,
+        existing: Option<Box<dyn Iterator<Item = IdTriple> + Send>>,
+        filter: F,
+    ) -> Box<dyn Iterator<Item = ValueTriple> + 'a> {
+        let existing = existing
+            .into_iter()
+            .flatten()
+            .filter_map(move |t| self.parent.as_ref()?.id_triple_to_string(&t))
+            .filter(move |t| self.removals.binary_search(t).is_err());
+        let added = self.additions.iter().filter(move |t| filter(t)).cloned();
+
+        Box::new(existing.chain(added))
+    }
+}
+
+/// Something wrong with a triple, as reported by a `CommitValidator`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// The triple that violates the validator's invariant.
+    pub triple: ValueTriple,
+    /// A description of what is wrong with the triple.
+    pub message: String,
+}
+
+impl Violation {
+    /// Construct a violation of the given triple.
+    pub fn new<S: Into<String>>(triple: ValueTriple, message: S) -> Self {
+        Self {
+            triple,
+            message: message.into(),
+        }
+    }
+}
+
+/// The error a commit fails with when validators reported violations.
+///
+/// It is returned wrapped in an `io::Error` of kind `InvalidData`,
+/// out of which it can be taken with `ValidationError::from_io_error`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// The name of the layer that was rejected.
+    pub layer: [u32; 5],
+    /// Everything that was reported by the validators.
+    pub violations: Vec<Violation>,
+}
+
+impl ValidationError {
+    /// Returns the validation error wrapped in the given error, if there is one.
+    pub fn from_io_error(error: &io::Error) -> Option<&ValidationError> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "commit of layer {} was rejected with {} violation(s)",
+            name_to_string(self.layer),
+            self.violations.len()
+        )?;
+        for violation in self.violations.iter() {
+            write!(f, "; {}", violation.message)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for ValidationError {}
+
+/// Run all validators against the given layer, failing if any of them report a violation.
+pub(super) fn validate_commit(
+    validators: &[Arc<dyn CommitValidator>],
+    layer: &PendingLayer,
+) -> io::Result<()> {
+    let violations: Vec<_> = validators
+        .iter()
+        .flat_map(|validator| validator.validate(layer))
+        .collect();
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            ValidationError {
+                layer: layer.name(),
+                violations,
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{open_directory_store, open_memory_store};
+    use tempfile::tempdir;
+
+    /// Every node that is used as an object has to be the subject of some triple.
+    fn no_dangling_nodes(layer: &PendingLayer) -> Vec<Violation> {
+        let mut referencing: Vec<ValueTriple> = layer
+            .additions()
+            .iter()
+            .filter(|t| matches!(&t.object, ObjectType::Node(node) if !layer.subject_exists(node)))
+            .cloned()
+            .collect();
+        for removed in layer.removals() {
+            if !layer.subject_exists(&removed.subject) {
+                let object = ObjectType::Node(removed.subject.clone());
+                referencing.extend(layer.value_triples_o(&object));
+            }
+        }
+        referencing.sort();
+        referencing.dedup();
+
+        referencing
+            .into_iter()
+            .map(|t| Violation::new(t.clone(), format!("{} refers to a missing node", t.subject)))
+            .collect()
+    }
+
+    /// A subject can have at most one name.
+    fn single_name(layer: &PendingLayer) -> Vec<Violation> {
+        layer
+            .additions()
+            .iter()
+            .filter(|t| {
+                t.predicate == "name" && layer.value_triples_sp(&t.subject, "name").count() > 1
+            })
+            .map(|t| Violation::new(t.clone(), format!("{} has more than one name", t.subject)))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn violations_reject_the_commit() {
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path()).with_commit_validator(single_name);
+
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("cow", "name", "Bessie"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+
+        let builder = base.open_write().await.unwrap();
+        let name = builder.name();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("cow", "name", "Daisy"))
+            .unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("pig", "name", "Wilbur"))
+            .unwrap();
+        let error = builder.commit().await.err().unwrap();
+
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        let validation = ValidationError::from_io_error(&error).unwrap();
+        assert_eq!(name, validation.layer);
+        assert_eq!(
+            vec![Violation::new(
+                ValueTriple::new_string_value("cow", "name", "Daisy"),
+                "cow has more than one name"
+            )],
+            validation.violations
+        );
+        assert!(builder.committed());
+        assert_eq!(
+            vec![base.name()],
+            store
+                .layers_info()
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|info| info.name)
+                .collect::<Vec<_>>()
+        );
+        assert!(store.get_layer_from_id(name).await.unwrap().is_none());
+
+        // replacing the name instead is fine
+        let builder = base.open_write().await.unwrap();
+        builder
+            .remove_value_triple(ValueTriple::new_string_value("cow", "name", "Bessie"))
+            .unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("cow", "name", "Daisy"))
+            .unwrap();
+        builder.commit().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn validators_see_the_resulting_graph() {
+        let store = open_memory_store().with_commit_validator(no_dangling_nodes);
+
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_node("cow", "likes", "pig"))
+            .unwrap();
+        let error = builder.commit().await.err().unwrap();
+        assert_eq!(
+            vec![ValueTriple::new_node("cow", "likes", "pig")],
+            ValidationError::from_io_error(&error)
+                .unwrap()
+                .violations
+                .iter()
+                .map(|v| v.triple.clone())
+                .collect::<Vec<_>>()
+        );
+
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_node("cow", "likes", "pig"))
+            .unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("pig", "says", "oink"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+
+        // pig is still around in the parent
+        let builder = base.open_write().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_node("horse", "likes", "pig"))
+            .unwrap();
+        let child = builder.commit().await.unwrap();
+
+        // removing everything about pig leaves both references dangling
+        let builder = child.open_write().await.unwrap();
+        builder
+            .remove_value_triple(ValueTriple::new_string_value("pig", "says", "oink"))
+            .unwrap();
+        let error = builder.commit().await.err().unwrap();
+        assert_eq!(
+            2,
+            ValidationError::from_io_error(&error)
+                .unwrap()
+                .violations
+                .len()
+        );
+    }
+
+    #[tokio::test]
+    async fn validators_see_effective_changes() {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen2 = seen.clone();
+        let store = open_memory_store().with_commit_validator(move |layer: &PendingLayer| {
+            seen2
+                .lock()
+                .unwrap()
+                .push((layer.additions().to_vec(), layer.removals().to_vec()));
+            Vec::new()
+        });
+
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+
+        let builder = base.open_write().await.unwrap();
+        // already there
+        builder
+            .add_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+        // not there to begin with
+        builder
+            .remove_value_triple(ValueTriple::new_string_value("cow", "says", "oink"))
+            .unwrap();
+        // cancels out
+        builder
+            .add_value_triple(ValueTriple::new_string_value("pig", "says", "oink"))
+            .unwrap();
+        builder
+            .remove_value_triple(ValueTriple::new_string_value("pig", "says", "oink"))
+            .unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("duck", "says", "quack"))
+            .unwrap();
+        builder
+            .remove_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+        builder.commit().await.unwrap();
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(2, seen.len());
+        assert_eq!(
+            (
+                vec![ValueTriple::new_string_value("duck", "says", "quack")],
+                Vec::new()
+            ),
+            seen[1]
+        );
+    }
+}
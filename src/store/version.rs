@@ -0,0 +1,552 @@
+//! Store format versions and upgrades between them.
+//!
+//! Every store on disk carries a version file recording what kind of
+//! store it is, and the format its layers are written in. Stores
+//! opened with `open_*_store` check this file the first time they are
+//! accessed, and the `open_*_store_checked` functions check it right
+//! away, so that a store is never read as something it isn't. A store
+//! can be converted to another kind or a newer format with
+//! `upgrade_store`.
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tokio::fs;
+use tokio::sync::OnceCell;
+
+use super::{CopyOptions, Store};
+use crate::storage::archive::{ArchiveLayerStore, DirectoryArchiveBackend};
+use crate::storage::consts::FILENAMES;
+use crate::storage::directory::{DirectoryLabelStore, DirectoryLayerStore};
+use crate::storage::PersistentLayerStore;
+
+/// The name of the file in the store directory that holds the store version.
+pub const STORE_VERSION_FILE: &str = "STORE_VERSION";
+
+/// The layer format written by this version of terminus-store.
+pub const CURRENT_FORMAT_VERSION: u32 = 1;
+
+/// The layer format of terminus-store 0.19 and earlier, from before typed value dictionaries.
+pub const LEGACY_FORMAT_VERSION: u32 = 0;
+
+const PREFIX_DIR_SIZE: usize = 3;
+
+/// The way a store on disk keeps its layers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StoreKind {
+    /// Every layer is a directory of separate files, as written by `open_directory_store`.
+    Directory,
+    /// Every layer is a single `.larch` archive file, as written by `open_archive_store`.
+    Archive,
+}
+
+impl StoreKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Directory => "directory",
+            Self::Archive => "archive",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "directory" => Some(Self::Directory),
+            "archive" => Some(Self::Archive),
+            _ => None,
+        }
+    }
+
+    fn open_function(self) -> &'static str {
+        match self {
+            Self::Directory => "open_directory_store",
+            Self::Archive => "open_archive_store",
+        }
+    }
+}
+
+impl fmt::Display for StoreKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The kind of a store on disk, and the format its layers are written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StoreVersion {
+    pub kind: StoreKind,
+    pub format: u32,
+}
+
+impl StoreVersion {
+    /// Construct a store version from a kind and a format version.
+    pub fn new(kind: StoreKind, format: u32) -> Self {
+        Self { kind, format }
+    }
+
+    /// The version of a store of the given kind, as written by this version of terminus-store.
+    pub fn current(kind: StoreKind) -> Self {
+        Self::new(kind, CURRENT_FORMAT_VERSION)
+    }
+
+    fn parse(contents: &str) -> Option<Self> {
+        let mut parts = contents.split_whitespace();
+        let kind = StoreKind::from_str(parts.next()?)?;
+        let format = parts.next()?.parse().ok()?;
+        if parts.next().is_some() {
+            return None;
+        }
+
+        Some(Self::new(kind, format))
+    }
+}
+
+impl fmt::Display for StoreVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} store in format version {}", self.kind, self.format)
+    }
+}
+
+/// Read the version of the store at the given path.
+///
+/// Stores created before version files existed are recognized by the
+/// way their layers are written. Returns None if there is no store at
+/// the given path, or if it doesn't contain any layers yet.
+pub async fn read_store_version<P: AsRef<Path>>(path: P) -> io::Result<Option<StoreVersion>> {
+    let path = path.as_ref();
+    match fs::read_to_string(path.join(STORE_VERSION_FILE)).await {
+        Ok(contents) => {
+            let version = StoreVersion::parse(&contents).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "store at {} has an invalid version file: {}",
+                        path.display(),
+                        contents.trim()
+                    ),
+                )
+            })?;
+
+            Ok(Some(version))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => detect_store_version(path).await,
+        Err(e) => Err(e),
+    }
+}
+
+async fn detect_store_version(path: &Path) -> io::Result<Option<StoreVersion>> {
+    let mut prefixes = match fs::read_dir(path).await {
+        Ok(prefixes) => prefixes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    while let Some(prefix) = prefixes.next_entry().await? {
+        if prefix.file_name().len() != PREFIX_DIR_SIZE || !prefix.file_type().await?.is_dir() {
+            continue;
+        }
+        let mut entries = fs::read_dir(prefix.path()).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_dir() {
+                let format = if is_legacy_layer_directory(&entry.path()).await? {
+                    LEGACY_FORMAT_VERSION
+                } else {
+                    CURRENT_FORMAT_VERSION
+                };
+                return Ok(Some(StoreVersion::new(StoreKind::Directory, format)));
+            } else if entry.file_name().to_string_lossy().ends_with(".larch") {
+                return Ok(Some(StoreVersion::current(StoreKind::Archive)));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Returns true if the given layer directory was written by terminus-store 0.19 or earlier.
+///
+/// Those layers have prefix compressed dictionaries, stored in `.pfc`
+/// files, and a value dictionary without types.
+async fn is_legacy_layer_directory(path: &Path) -> io::Result<bool> {
+    let mut has_node_dictionary = false;
+    let mut has_typed_values = false;
+    let mut files = fs::read_dir(path).await?;
+    while let Some(file) = files.next_entry().await? {
+        let name = file.file_name();
+        let name = name.to_string_lossy();
+        if name.ends_with(".pfc") {
+            return Ok(true);
+        }
+        has_node_dictionary |= name == FILENAMES.node_dictionary_offsets;
+        has_typed_values |= name == FILENAMES.value_dictionary_types_present;
+    }
+
+    Ok(has_node_dictionary && !has_typed_values)
+}
+
+async fn write_store_version(path: &Path, version: StoreVersion) -> io::Result<()> {
+    fs::create_dir_all(path).await?;
+    fs::write(
+        path.join(STORE_VERSION_FILE),
+        format!("{} {}\n", version.kind, version.format),
+    )
+    .await
+}
+
+fn version_mismatch(path: &Path, found: StoreVersion, kind: StoreKind) -> io::Error {
+    let message = if found.format == LEGACY_FORMAT_VERSION {
+        format!(
+            "store at {} was written by terminus-store 0.19 or earlier, whose layers can't be read anymore; see upgrade_store for how to convert it",
+            path.display()
+        )
+    } else if found.format > CURRENT_FORMAT_VERSION {
+        format!(
+            "store at {} has format version {}, but only format version {} can be read; it was written by a newer version of terminus-store",
+            path.display(),
+            found.format,
+            CURRENT_FORMAT_VERSION
+        )
+    } else if found.kind != kind {
+        let description = match found.kind {
+            StoreKind::Directory => "a directory store",
+            StoreKind::Archive => "an archive store",
+        };
+        format!(
+            "store at {} is {}, open it with {}",
+            path.display(),
+            description,
+            found.kind.open_function()
+        )
+    } else {
+        format!(
+            "store at {} has format version {}, but only format version {} can be read; use upgrade_store to convert it",
+            path.display(),
+            found.format,
+            CURRENT_FORMAT_VERSION
+        )
+    };
+
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Check that the store at the given path is of the given kind and in the current format.
+///
+/// A new store gets its version written to it, as does a store from
+/// before version files existed.
+pub(crate) async fn check_store_version(path: &Path, kind: StoreKind) -> io::Result<()> {
+    let expected = StoreVersion::current(kind);
+    let has_version_file = fs::metadata(path.join(STORE_VERSION_FILE))
+        .await
+        .map(|m| m.is_file())
+        .unwrap_or(false);
+    match read_store_version(path).await? {
+        Some(found) if found != expected => Err(version_mismatch(path, found, kind)),
+        _ if has_version_file => Ok(()),
+        _ => write_store_version(path, expected).await,
+    }
+}
+
+/// A version check of a store on disk that only succeeds once.
+pub(crate) struct VersionCheck {
+    path: PathBuf,
+    kind: StoreKind,
+    checked: OnceCell<()>,
+}
+
+impl VersionCheck {
+    pub(crate) fn new(path: PathBuf, kind: StoreKind) -> Self {
+        Self {
+            path,
+            kind,
+            checked: OnceCell::new(),
+        }
+    }
+
+    /// Check the store version, unless an earlier check already succeeded.
+    pub(crate) async fn check(&self) -> io::Result<()> {
+        self.checked
+            .get_or_try_init(|| check_store_version(&self.path, self.kind))
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Upgrade the store at the given path from one version to another.
+///
+/// Currently, this converts directory stores into archive stores.
+/// Stores written by terminus-store 0.19 or earlier can't be upgraded
+/// in place. They have to be converted with
+/// [terminusdb-10-to-11](https://github.com/terminusdb/terminusdb-10-to-11/).
+///
+/// The version file is only updated once the upgrade is done. An
+/// upgrade that got interrupted can be continued by calling this again
+/// with the same arguments.
+///
+/// The store should not be opened while it is being upgraded.
+pub async fn upgrade_store<P: Into<PathBuf>>(
+    path: P,
+    from: StoreVersion,
+    to: StoreVersion,
+) -> io::Result<()> {
+    let path = path.into();
+    if let Some(found) = read_store_version(&path).await? {
+        if found != from {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "store at {} has version '{}', not '{}'",
+                    path.display(),
+                    found,
+                    from
+                ),
+            ));
+        }
+    }
+    if from.format == LEGACY_FORMAT_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "stores written by terminus-store 0.19 or earlier can't be upgraded in place; convert them with terminusdb-10-to-11 (https://github.com/terminusdb/terminusdb-10-to-11/)",
+        ));
+    }
+    if from.format != CURRENT_FORMAT_VERSION || to.format != CURRENT_FORMAT_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "cannot upgrade from '{}' to '{}'; only format version {} is supported",
+                from, to, CURRENT_FORMAT_VERSION
+            ),
+        ));
+    }
+
+    match (from.kind, to.kind) {
+        (StoreKind::Directory, StoreKind::Archive) => upgrade_directory_to_archive(&path).await?,
+        (StoreKind::Archive, StoreKind::Directory) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot convert an archive store into a directory store",
+            ))
+        }
+        _ => {}
+    }
+
+    write_store_version(&path, to).await
+}
+
+/// Write every directory layer as an archive in the same store, then remove the directories.
+async fn upgrade_directory_to_archive(path: &Path) -> io::Result<()> {
+    let labels = DirectoryLabelStore::new(path);
+    let directory_layers = DirectoryLayerStore::new(path);
+    let archive_backend = DirectoryArchiveBackend::new(path.to_path_buf());
+    let source = Store::new(labels.clone(), directory_layers.clone());
+    let target = Store::new(
+        labels,
+        ArchiveLayerStore::new(archive_backend.clone(), archive_backend),
+    );
+
+    // both stores share their labels, so only layers get copied
+    source.copy_into(&target, CopyOptions::new()).await?;
+    for layer in directory_layers.directories().await? {
+        directory_layers.discard_directory(layer).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::{Layer, ValueTriple};
+    use crate::store::{
+        open_archive_store, open_archive_store_checked, open_directory_store,
+        open_directory_store_checked,
+    };
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn new_stores_get_a_version() {
+        let dir = tempdir().unwrap();
+        open_archive_store_checked(dir.path().join("archive"), 512)
+            .await
+            .unwrap();
+        open_directory_store_checked(dir.path().join("directory"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            Some(StoreVersion::current(StoreKind::Archive)),
+            read_store_version(dir.path().join("archive"))
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            Some(StoreVersion::current(StoreKind::Directory)),
+            read_store_version(dir.path().join("directory"))
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn mismatched_kind_is_an_error() {
+        let dir = tempdir().unwrap();
+        let store = open_directory_store_checked(dir.path()).await.unwrap();
+        let builder = store.create_base_layer().await.unwrap();
+        builder.commit().await.unwrap();
+
+        let error = open_archive_store_checked(dir.path(), 512)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        assert!(error
+            .to_string()
+            .contains("is a directory store, open it with open_directory_store"));
+
+        // without a version file, the layers give away what kind of store it is
+        fs::remove_file(dir.path().join(STORE_VERSION_FILE))
+            .await
+            .unwrap();
+        assert!(open_archive_store_checked(dir.path(), 512).await.is_err());
+        assert!(open_directory_store_checked(dir.path()).await.is_ok());
+        assert!(dir.path().join(STORE_VERSION_FILE).is_file());
+
+        let dir = tempdir().unwrap();
+        open_archive_store_checked(dir.path(), 512).await.unwrap();
+        let error = open_directory_store_checked(dir.path())
+            .await
+            .err()
+            .unwrap();
+        assert!(error
+            .to_string()
+            .contains("is an archive store, open it with open_archive_store"));
+    }
+
+    #[tokio::test]
+    async fn unchecked_open_checks_on_first_access() {
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+        assert!(!dir.path().join(STORE_VERSION_FILE).exists());
+        store.create("foo").await.unwrap();
+        assert_eq!(
+            Some(StoreVersion::current(StoreKind::Directory)),
+            read_store_version(dir.path()).await.unwrap()
+        );
+
+        let store = open_archive_store(dir.path(), 512);
+        let error = store.labels().await.err().unwrap();
+        assert!(error
+            .to_string()
+            .contains("is a directory store, open it with open_directory_store"));
+        assert!(store.create_base_layer().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn newer_format_is_an_error() {
+        let dir = tempdir().unwrap();
+        write_store_version(
+            dir.path(),
+            StoreVersion::new(StoreKind::Archive, CURRENT_FORMAT_VERSION + 1),
+        )
+        .await
+        .unwrap();
+
+        let error = open_archive_store_checked(dir.path(), 512)
+            .await
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("newer version"));
+    }
+
+    #[tokio::test]
+    async fn legacy_store_is_detected() {
+        let dir = tempdir().unwrap();
+        let layer = dir
+            .path()
+            .join("abc")
+            .join("abcdef0123456789abcdef0123456789abcdef01");
+        fs::create_dir_all(&layer).await.unwrap();
+        for file in [
+            "node_dictionary_blocks.pfc",
+            "node_dictionary_offsets.logarray",
+            "value_dictionary_blocks.pfc",
+            "value_dictionary_offsets.logarray",
+        ] {
+            fs::write(layer.join(file), b"").await.unwrap();
+        }
+
+        assert_eq!(
+            Some(StoreVersion::new(
+                StoreKind::Directory,
+                LEGACY_FORMAT_VERSION
+            )),
+            read_store_version(dir.path()).await.unwrap()
+        );
+        let error = open_directory_store_checked(dir.path())
+            .await
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("0.19 or earlier"));
+        assert!(error.to_string().contains("upgrade_store"));
+        // the store is left alone
+        assert!(!dir.path().join(STORE_VERSION_FILE).exists());
+        let store = open_directory_store(dir.path());
+        let error = store
+            .get_layer_from_id([1, 2, 3, 4, 5])
+            .await
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("0.19 or earlier"));
+
+        let error = upgrade_store(
+            dir.path(),
+            StoreVersion::new(StoreKind::Directory, LEGACY_FORMAT_VERSION),
+            StoreVersion::current(StoreKind::Archive),
+        )
+        .await
+        .err()
+        .unwrap();
+        assert_eq!(io::ErrorKind::Unsupported, error.kind());
+        assert!(error.to_string().contains("terminusdb-10-to-11"));
+    }
+
+    #[tokio::test]
+    async fn upgrade_directory_store_to_archive_store() {
+        let dir = tempdir().unwrap();
+        let store = open_directory_store_checked(dir.path()).await.unwrap();
+        let graph = store.create("foo").await.unwrap();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("cow", "says", "moo"))
+            .unwrap();
+        let base = builder.commit().await.unwrap();
+        let builder = base.open_write().await.unwrap();
+        builder
+            .add_value_triple(ValueTriple::new_string_value("pig", "says", "oink"))
+            .unwrap();
+        let child = builder.commit().await.unwrap();
+        graph.set_head(&child).await.unwrap();
+        std::mem::drop(store);
+
+        let directory = StoreVersion::current(StoreKind::Directory);
+        let archive = StoreVersion::current(StoreKind::Archive);
+        assert!(upgrade_store(dir.path(), archive, directory).await.is_err());
+        upgrade_store(dir.path(), directory, archive).await.unwrap();
+        assert!(open_directory_store_checked(dir.path()).await.is_err());
+
+        let store = open_archive_store_checked(dir.path(), 512).await.unwrap();
+        assert_eq!(2, store.layers_info().await.unwrap().len());
+        let (layer, version) = store
+            .open("foo")
+            .await
+            .unwrap()
+            .unwrap()
+            .head_version()
+            .await
+            .unwrap();
+        let layer = layer.unwrap();
+        assert_eq!(child.name(), layer.name());
+        assert_eq!(1, version);
+        assert!(layer.value_triple_exists(&ValueTriple::new_string_value("cow", "says", "moo")));
+        assert!(layer.value_triple_exists(&ValueTriple::new_string_value("pig", "says", "oink")));
+    }
+}
@@ -0,0 +1,142 @@
+//! Watching labels for changes.
+//!
+//! Label stores that can notify about label writes do so through
+//! `LabelStore::watch_label`. For the others, the label is polled.
+//! Either way, `NamedGraph::watch` turns this into a stream of
+//! `LabelChange`s, one for every new version of the label.
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future;
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+
+use crate::storage::{Label, LabelChange, LabelStore};
+
+/// How often a label is polled if its store can't watch it.
+pub const LABEL_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Poll the given label, yielding it every time its version changed.
+///
+/// The stream ends when the label is deleted or can't be read.
+pub(super) fn poll_label(
+    label_store: Arc<dyn LabelStore>,
+    name: String,
+) -> BoxStream<'static, Label> {
+    stream::unfold(
+        (label_store, name, None),
+        |(label_store, name, last_version)| async move {
+            loop {
+                tokio::time::sleep(LABEL_POLL_INTERVAL).await;
+                let label = match label_store.get_label(&name).await {
+                    Ok(Some(label)) => label,
+                    Ok(None) => return None,
+                    Err(e) => {
+                        tracing::warn!(label = %name, error = %e, "stopped polling label");
+                        return None;
+                    }
+                };
+                if Some(label.version) != last_version {
+                    let version = label.version;
+                    return Some((label, (label_store, name, Some(version))));
+                }
+            }
+        },
+    )
+    .boxed()
+}
+
+/// Turn a stream of labels into the changes between them, starting from the current label.
+///
+/// Labels that are not newer than the last one seen are skipped, as
+/// watching a label may report the same version more than once.
+pub(super) fn label_changes(
+    current: Label,
+    labels: BoxStream<'static, Label>,
+) -> impl Stream<Item = LabelChange> + Send {
+    labels
+        .scan(current, |last, label| {
+            let change = if label.version > last.version {
+                let previous_layer = last.layer;
+                *last = label.clone();
+                Some(LabelChange {
+                    label,
+                    previous_layer,
+                })
+            } else {
+                None
+            };
+
+            future::ready(Some(change))
+        })
+        .filter_map(future::ready)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::Layer;
+    use crate::storage::directory::DirectoryLabelStore;
+    use crate::store::{open_directory_store, open_memory_store, Store};
+    use tempfile::tempdir;
+    use tokio::time::timeout;
+
+    const WAIT: Duration = Duration::from_secs(10);
+
+    async fn check_watch(store: &Store) {
+        let graph = store.create("foo").await.unwrap();
+        let mut changes = Box::pin(graph.watch().await.unwrap());
+
+        let base = store
+            .create_base_layer()
+            .await
+            .unwrap()
+            .commit()
+            .await
+            .unwrap();
+        graph.set_head(&base).await.unwrap();
+        let change = timeout(WAIT, changes.next()).await.unwrap().unwrap();
+        assert_eq!(None, change.previous_layer);
+        assert_eq!(Some(base.name()), change.label.layer);
+        assert_eq!(1, change.label.version);
+
+        let child = base.open_write().await.unwrap().commit().await.unwrap();
+        graph.set_head(&child).await.unwrap();
+        let change = timeout(WAIT, changes.next()).await.unwrap().unwrap();
+        assert_eq!(Some(base.name()), change.previous_layer);
+        assert_eq!(Some(child.name()), change.label.layer);
+        assert_eq!(2, change.label.version);
+
+        graph.delete().await.unwrap();
+        assert_eq!(None, timeout(WAIT, changes.next()).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn watch_memory_store() {
+        check_watch(&open_memory_store()).await;
+    }
+
+    #[tokio::test]
+    async fn watch_directory_store() {
+        let dir = tempdir().unwrap();
+        check_watch(&open_directory_store(dir.path())).await;
+    }
+
+    #[tokio::test]
+    async fn poll_directory_label_store() {
+        let dir = tempdir().unwrap();
+        let label_store = DirectoryLabelStore::new(dir.path());
+        let label = label_store.create_label("foo").await.unwrap();
+        let mut labels = poll_label(Arc::new(label_store.clone()), "foo".to_string());
+        assert_eq!(label, timeout(WAIT, labels.next()).await.unwrap().unwrap());
+
+        let label = label_store
+            .set_label(&label, [1, 2, 3, 4, 5])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(label, timeout(WAIT, labels.next()).await.unwrap().unwrap());
+
+        label_store.delete_label("foo").await.unwrap();
+        assert_eq!(None, timeout(WAIT, labels.next()).await.unwrap());
+    }
+}